@@ -0,0 +1,110 @@
+#!/usr/bin/env python3
+# Generates key_schedule.bin, the expected outputs of the key schedule that the
+# key_schedule_reference_check test in group_state.rs compares against. These are NOT the mlswg
+# test vectors: they come from this rewrite of the same derivation, which only uses the standard
+# library, so they're independent of the Rust code but not of our reading of the spec. They should
+# be replaced with the upstream key_schedule.bin once it's vendored into test_vectors/. Run this
+# from this directory.
+#
+# The file is a TLS-serialized KeyScheduleTestVectors:
+#
+#   struct {
+#     opaque update_secret<0..255>;
+#     opaque group_state<0..2^32-1>;
+#     opaque application_secret<0..255>;
+#     opaque confirmation_key<0..255>;
+#     opaque exporter_secret<0..255>;
+#     opaque init_secret<0..255>;
+#   } KeyScheduleRefEpoch;
+#
+#   struct {
+#     CipherSuite suite;
+#     KeyScheduleRefEpoch epochs<0..2^16-1>;
+#   } KeyScheduleRefCase;
+#
+#   struct {
+#     KeyScheduleRefCase cases<0..2^32-1>;
+#   } KeyScheduleRef;
+#
+# Each case starts from an all-zero init secret, and every epoch after the first starts from the
+# init secret of the one before it. group_state is the serialized GroupState of the epoch, and is
+# only ever hashed, so any bytes will do.
+
+import hashlib
+import hmac
+import struct
+
+NUM_EPOCHS = 8
+# P256_SHA256_AES128GCM and X25519_SHA256_AES128GCM
+SUITES = [0x0000, 0x0001]
+
+
+def hkdf_expand(prk, info, length):
+    out = b""
+    block = b""
+    counter = 1
+    while len(out) < length:
+        block = hmac.new(prk, block + info + bytes([counter]), hashlib.sha256).digest()
+        out += block
+        counter += 1
+    return out[:length]
+
+
+def hkdf_expand_label(secret, label, context, length):
+    full_label = b"mls10 " + label
+    hkdf_label = (
+        struct.pack(">H", length)
+        + struct.pack(">B", len(full_label))
+        + full_label
+        + struct.pack(">I", len(context))
+        + context
+    )
+    return hkdf_expand(secret, hkdf_label, length)
+
+
+def derive_secret(secret, label, group_state):
+    state_hash = hashlib.sha256(group_state).digest()
+    return hkdf_expand_label(secret, label, state_hash, 32)
+
+
+def opaque8(b):
+    return struct.pack(">B", len(b)) + b
+
+
+def opaque32(b):
+    return struct.pack(">I", len(b)) + b
+
+
+def make_case(suite):
+    init_secret = bytes(32)
+    epochs = b""
+    for epoch in range(NUM_EPOCHS):
+        update_secret = bytes([suite, epoch]) * 16
+        group_state = b"group state %d of suite %d" % (epoch, suite) * (epoch + 1)
+
+        epoch_secret = hmac.new(init_secret, update_secret, hashlib.sha256).digest()
+        application_secret = derive_secret(epoch_secret, b"app", group_state)
+        confirmation_key = derive_secret(epoch_secret, b"confirm", group_state)
+        exporter_secret = derive_secret(epoch_secret, b"exporter", group_state)
+        init_secret = derive_secret(epoch_secret, b"init", group_state)
+
+        epochs += (
+            opaque8(update_secret)
+            + opaque32(group_state)
+            + opaque8(application_secret)
+            + opaque8(confirmation_key)
+            + opaque8(exporter_secret)
+            + opaque8(init_secret)
+        )
+
+    return struct.pack(">H", suite) + struct.pack(">H", len(epochs)) + epochs
+
+
+def main():
+    cases = b"".join(make_case(suite) for suite in SUITES)
+    with open("key_schedule.bin", "wb") as f:
+        f.write(opaque32(cases))
+
+
+if __name__ == "__main__":
+    main()
@@ -1,9 +1,14 @@
 use crate::{
-    credential::BasicCredential,
+    credential::{BasicCredential, Credential, Identity, X509CertData, X509Credential},
     crypto::{
-        ciphersuite::{CipherSuite, X25519_SHA256_AES128GCM},
-        sig::{Signature, SignatureScheme, ED25519_IMPL},
+        ciphersuite::CipherSuite,
+        dh::DhPoint,
+        sig::{Signature, SignatureScheme, ECDSA_P256_IMPL, ED25519_IMPL},
     },
+    group_state::WelcomeInfo,
+    handshake::{Extension, ProtocolVersion, UserInitKey},
+    ratchet_tree::RatchetTree,
+    roster::Roster,
 };
 
 use serde::{
@@ -11,11 +16,18 @@ use serde::{
     ser::{Serialize, SerializeStruct, Serializer},
 };
 
-const CIPHERSUITE_NAME_IDS: &'static [(&'static CipherSuite, &'static str, u16)] =
-    &[(&X25519_SHA256_AES128GCM, "X25519_SHA256_AES128GCM", 0x0001)];
-const SIGSCHEME_NAME_IDS: &'static [(&'static SignatureScheme, &'static str, u16)] =
-    &[(&ED25519_IMPL, "ED25519", 0x0807)];
+const SIGSCHEME_NAME_IDS: &'static [(&'static dyn SignatureScheme, &'static str, u16)] = &[
+    (&ED25519_IMPL, "ED25519", 0x0807),
+    (&ECDSA_P256_IMPL, "ECDSA_P256_SHA256", 0x0403),
+];
 
+// struct {
+//     opaque identity<0..2^16-1>;
+//     SignatureScheme algorithm;
+//     SignaturePublicKey public_key;
+// } BasicCredential;
+//
+// where SignaturePublicKey is opaque public_key<1..2^16-1>
 impl Serialize for BasicCredential {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -23,11 +35,180 @@ impl Serialize for BasicCredential {
     {
         let mut struct_serializer = serializer.serialize_struct("BasicCredential", 3)?;
         struct_serializer.serialize_field("identity", &self.identity)?;
-        // TODO: FINISH
+        struct_serializer.serialize_field("signature_scheme", self.signature_scheme)?;
+        let public_key_bytes = self.signature_scheme.public_key_to_bytes(&self.public_key);
+        struct_serializer.serialize_field("public_key__bound_u16", &public_key_bytes)?;
         struct_serializer.end()
     }
 }
 
+impl<'de> Deserialize<'de> for BasicCredential {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // We can't make sense of the public key until we know which signature scheme it belongs
+        // to. So read it as bytes, and then convert it once we have the signature scheme.
+        #[derive(Deserialize)]
+        #[serde(rename = "BasicCredential")]
+        struct RawBasicCredential {
+            identity: Identity,
+            signature_scheme: &'static dyn SignatureScheme,
+            #[serde(rename = "public_key__bound_u16")]
+            public_key: Vec<u8>,
+        }
+
+        let raw = RawBasicCredential::deserialize(deserializer)?;
+        let public_key = raw
+            .signature_scheme
+            .public_key_from_bytes(&raw.public_key)
+            .map_err(serde::de::Error::custom)?;
+
+        Ok(BasicCredential {
+            identity: raw.identity,
+            signature_scheme: raw.signature_scheme,
+            public_key: public_key,
+        })
+    }
+}
+
+// struct {
+//     X509CertData chain<1..2^32-1>;
+// } X509Credential;
+//
+// Only the chain is sent. Everything else is parsed out of the leaf certificate.
+impl Serialize for X509Credential {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut struct_serializer = serializer.serialize_struct("X509Credential", 1)?;
+        struct_serializer.serialize_field("chain__bound_u32", &self.chain)?;
+        struct_serializer.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for X509Credential {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(rename = "X509Credential")]
+        struct RawX509Credential {
+            #[serde(rename = "chain__bound_u32")]
+            chain: Vec<X509CertData>,
+        }
+
+        let raw = RawX509Credential::deserialize(deserializer)?;
+        X509Credential::from_chain(raw.chain).map_err(serde::de::Error::custom)
+    }
+}
+
+// struct {
+//     opaque user_init_key_id<0..255>;
+//     ProtocolVersion supported_versions<0..255>;
+//     CipherSuite cipher_suites<0..255>;
+//     DHPublicKey init_keys<1..2^16-1>;
+//     Credential credential;
+//     Extension extensions<0..2^16-1>;
+//     opaque signature<0..2^16-1>;
+// } UserInitKey;
+//
+// The i-th init key belongs to the i-th cipher suite, so we reject a UserInitKey whose lists have
+// different lengths right away. Otherwise, looking up the init key for a cipher suite could pick
+// the wrong key, or none at all.
+impl<'de> Deserialize<'de> for UserInitKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(rename = "UserInitKey")]
+        struct RawUserInitKey {
+            #[serde(rename = "user_init_key_id__bound_u8")]
+            user_init_key_id: Vec<u8>,
+            #[serde(rename = "supported_versions__bound_u8")]
+            supported_versions: Vec<ProtocolVersion>,
+            #[serde(rename = "cipher_suites__bound_u8")]
+            cipher_suites: Vec<&'static CipherSuite>,
+            #[serde(rename = "init_keys__bound_u16")]
+            init_keys: Vec<DhPoint>,
+            credential: Credential,
+            #[serde(rename = "extensions__bound_u16")]
+            extensions: Vec<Extension>,
+            signature: Signature,
+        }
+
+        let raw = RawUserInitKey::deserialize(deserializer)?;
+        if raw.cipher_suites.len() != raw.init_keys.len() {
+            return Err(serde::de::Error::invalid_length(
+                raw.init_keys.len(),
+                &"as many init keys as cipher suites",
+            ));
+        }
+
+        Ok(UserInitKey {
+            user_init_key_id: raw.user_init_key_id,
+            supported_versions: raw.supported_versions,
+            cipher_suites: raw.cipher_suites,
+            init_keys: raw.init_keys,
+            credential: raw.credential,
+            extensions: raw.extensions,
+            signature: raw.signature,
+        })
+    }
+}
+
+// struct {
+//     opaque group_id<0..255>;
+//     uint32 epoch;
+//     optional<Credential> roster<1..2^32-1>;
+//     optional<PublicKey> tree<1..2^32-1>;
+//     opaque transcript_hash<0..255>;
+//     opaque init_secret<0..255>;
+// } WelcomeInfo;
+//
+// Slot i of the roster belongs to leaf i of the tree, so a WelcomeInfo whose roster and tree have
+// different numbers of leaves is rejected right away, like a UserInitKey with mismatched lists.
+// The tree itself is checked to have a valid shape when it's deserialized.
+impl<'de> Deserialize<'de> for WelcomeInfo {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(rename = "WelcomeInfo")]
+        struct RawWelcomeInfo {
+            #[serde(rename = "group_id__bound_u8")]
+            group_id: Vec<u8>,
+            epoch: u32,
+            roster: Roster,
+            tree: RatchetTree,
+            #[serde(rename = "transcript_hash__bound_u8")]
+            transcript_hash: Vec<u8>,
+            #[serde(rename = "init_secret__bound_u8")]
+            init_secret: Vec<u8>,
+        }
+
+        let raw = RawWelcomeInfo::deserialize(deserializer)?;
+        if raw.roster.len() != raw.tree.num_leaves() {
+            return Err(serde::de::Error::custom(
+                "WelcomeInfo roster and tree have different numbers of leaves",
+            ));
+        }
+
+        Ok(WelcomeInfo {
+            group_id: raw.group_id,
+            epoch: raw.epoch,
+            roster: raw.roster,
+            tree: raw.tree,
+            transcript_hash: raw.transcript_hash,
+            init_secret: raw.init_secret,
+        })
+    }
+}
+
 // Implement Serialize for our CipherSuites and SignatureSchemes. This just serializes their ID
 
 impl Serialize for CipherSuite {
@@ -35,12 +216,7 @@ impl Serialize for CipherSuite {
     where
         S: Serializer,
     {
-        for (_, name, id) in CIPHERSUITE_NAME_IDS {
-            if &self.name == name {
-                return serializer.serialize_u16(*id);
-            }
-        }
-        panic!("tried to serialize unknown ciphersuite");
+        serializer.serialize_u16(self.to_u16())
     }
 }
 
@@ -63,15 +239,12 @@ impl<'de> Deserialize<'de> for &'static CipherSuite {
             where
                 E: serde::de::Error,
             {
-                for (cs, _, id) in CIPHERSUITE_NAME_IDS {
-                    if value == *id {
-                        return Ok(cs);
-                    }
-                }
-                Err(E::custom(format!(
-                    "could not deserialize {:x} into cipher suite",
-                    value
-                )))
+                CipherSuite::from_u16(value).ok_or_else(|| {
+                    E::custom(format!(
+                        "could not deserialize {:x} into cipher suite",
+                        value
+                    ))
+                })
             }
         }
 
@@ -79,13 +252,56 @@ impl<'de> Deserialize<'de> for &'static CipherSuite {
     }
 }
 
-impl Serialize for SignatureScheme {
+// ProtocolVersions are serialized as their draft number
+impl Serialize for ProtocolVersion {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_u8(*self as u8)
+    }
+}
+
+impl<'de> Deserialize<'de> for ProtocolVersion {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = ProtocolVersion;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a u8 representing a protocol version")
+            }
+
+            fn visit_u8<E>(self, value: u8) -> Result<ProtocolVersion, E>
+            where
+                E: serde::de::Error,
+            {
+                match value {
+                    2 => Ok(ProtocolVersion::Draft02),
+                    3 => Ok(ProtocolVersion::Draft03),
+                    _ => Err(E::custom(format!(
+                        "could not deserialize {:x} into protocol version",
+                        value
+                    ))),
+                }
+            }
+        }
+
+        deserializer.deserialize_u8(Visitor)
+    }
+}
+
+impl Serialize for dyn SignatureScheme {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
         for (_, name, id) in SIGSCHEME_NAME_IDS {
-            if &self.name == name {
+            if self.name() == *name {
                 return serializer.serialize_u16(*id);
             }
         }
@@ -93,7 +309,7 @@ impl Serialize for SignatureScheme {
     }
 }
 
-impl<'de> Deserialize<'de> for &'static SignatureScheme {
+impl<'de> Deserialize<'de> for &'static dyn SignatureScheme {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
@@ -101,19 +317,19 @@ impl<'de> Deserialize<'de> for &'static SignatureScheme {
         struct Visitor;
 
         impl<'de> serde::de::Visitor<'de> for Visitor {
-            type Value = &'static SignatureScheme;
+            type Value = &'static dyn SignatureScheme;
 
             fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
                 formatter.write_str("a u16 representing a signature scheme")
             }
 
-            fn visit_u16<E>(self, value: u16) -> Result<&'static SignatureScheme, E>
+            fn visit_u16<E>(self, value: u16) -> Result<&'static dyn SignatureScheme, E>
             where
                 E: serde::de::Error,
             {
                 for (ss, _, id) in SIGSCHEME_NAME_IDS {
                     if value == *id {
-                        return Ok(ss);
+                        return Ok(*ss);
                     }
                 }
                 Err(E::custom(format!(
@@ -126,17 +342,3 @@ impl<'de> Deserialize<'de> for &'static SignatureScheme {
         deserializer.deserialize_u16(Visitor)
     }
 }
-
-impl Serialize for Signature {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        match self {
-            Signature::Ed25519Signature(sig) => {
-                let bytes = sig.to_bytes();
-                (&bytes).serialize(serializer)
-            }
-        }
-    }
-}
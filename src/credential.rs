@@ -1,25 +1,364 @@
-use crate::crypto::sig::{SigPublicKey, SignatureScheme};
+use crate::{
+    crypto::sig::{SigPublicKey, Signature, SignatureScheme, ECDSA_P256_IMPL, ED25519_IMPL},
+    error::Error,
+};
 
 // TODO: Decide whether we check the size on the lower end while (de)serializing
 
+/// The signature algorithms we accept on certificates in an X.509 chain
+static SUPPORTED_CERT_SIG_ALGS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+];
+
+// The DER encodings of the OIDs we recognize in a leaf certificate's SubjectPublicKeyInfo
+/// id-ecPublicKey (1.2.840.10045.2.1)
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+/// prime256v1 (1.2.840.10045.3.1.7)
+const OID_P256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+/// id-Ed25519 (1.3.101.112)
+const OID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+
 // opaque cert_data<1..2^24-1>;
-#[derive(Deserialize, Serialize)]
+/// A single DER-encoded X.509 certificate
+#[derive(Clone, Deserialize, Serialize)]
 #[serde(rename = "X509CertData__bound_u24")]
-pub(crate) struct X509CertData(Vec<u8>);
+pub(crate) struct X509CertData(pub(crate) Vec<u8>);
 
 // opaque identity<0..2^16-1>;
-#[derive(Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(rename = "Identity__bound_u16")]
 pub(crate) struct Identity(pub(crate) Vec<u8>);
 
+#[derive(Clone)]
 pub(crate) struct BasicCredential {
     pub(crate) identity: Identity,
-    pub(crate) signature_scheme: &'static SignatureScheme,
+    pub(crate) signature_scheme: &'static dyn SignatureScheme,
+    pub(crate) public_key: SigPublicKey,
+}
+
+// X509CertData chain<1..2^32-1>;
+/// A certificate chain, leaf first. Only the chain goes over the wire. The rest is parsed out of
+/// the leaf certificate when the credential is made.
+#[derive(Clone)]
+pub(crate) struct X509Credential {
+    pub(crate) chain: Vec<X509CertData>,
+    /// The DER-encoded subject name of the leaf certificate
+    pub(crate) identity: Vec<u8>,
+    pub(crate) signature_scheme: &'static dyn SignatureScheme,
     pub(crate) public_key: SigPublicKey,
 }
 
-#[derive(Serialize)]
+impl X509Credential {
+    /// Makes a credential out of the given certificate chain. The chain is not validated here; use
+    /// `Credential::verify_chain` for that.
+    ///
+    /// Returns: `Ok(cred)` on success. If the chain is empty, the leaf certificate is malformed, or
+    /// the leaf's public key isn't one we have a signature scheme for, returns
+    /// `Error::CredentialError`.
+    pub(crate) fn from_chain(chain: Vec<X509CertData>) -> Result<X509Credential, Error> {
+        let leaf = chain
+            .first()
+            .ok_or(Error::CredentialError("Certificate chain is empty"))?;
+        let parsed_leaf =
+            webpki::trust_anchor_util::cert_der_as_trust_anchor(untrusted::Input::from(&leaf.0))
+                .map_err(|_| Error::CredentialError("Malformed leaf certificate"))?;
+
+        let (signature_scheme, public_key) = parse_spki(parsed_leaf.spki)?;
+        let identity = parsed_leaf.subject.to_vec();
+
+        Ok(X509Credential {
+            chain: chain,
+            identity: identity,
+            signature_scheme: signature_scheme,
+            public_key: public_key,
+        })
+    }
+}
+
+// These DER helpers only handle what we need to pick apart a SubjectPublicKeyInfo
+
+/// Reads a DER value with the given tag, and returns its contents
+fn read_der<'a>(
+    reader: &mut untrusted::Reader<'a>,
+    tag: u8,
+) -> Result<untrusted::Input<'a>, untrusted::EndOfInput> {
+    if reader.read_byte()? != tag {
+        return Err(untrusted::EndOfInput);
+    }
+    // Lengths under 128 are a single byte. Otherwise, the first byte says how many length bytes
+    // follow. Nothing in a public key is long enough to need more than two of them.
+    let len = match reader.read_byte()? {
+        short if short < 0x80 => short as usize,
+        0x81 => reader.read_byte()? as usize,
+        0x82 => ((reader.read_byte()? as usize) << 8) | (reader.read_byte()? as usize),
+        _ => return Err(untrusted::EndOfInput),
+    };
+    reader.skip_and_get_input(len)
+}
+
+/// Parses the contents of a SubjectPublicKeyInfo into a signature public key
+///
+/// Returns: `Ok((signature_scheme, public_key))` on success. Otherwise, returns an
+/// `Error::CredentialError`.
+fn parse_spki(spki: &[u8]) -> Result<(&'static dyn SignatureScheme, SigPublicKey), Error> {
+    //  SubjectPublicKeyInfo ::= SEQUENCE {
+    //      algorithm            AlgorithmIdentifier,
+    //      subjectPublicKey     BIT STRING
+    //  }
+    //  AlgorithmIdentifier ::= SEQUENCE {
+    //      algorithm   OBJECT IDENTIFIER,
+    //      parameters  ANY DEFINED BY algorithm OPTIONAL
+    //  }
+    const TAG_SEQUENCE: u8 = 0x30;
+    const TAG_OID: u8 = 0x06;
+    const TAG_BIT_STRING: u8 = 0x03;
+
+    let parsed = untrusted::Input::from(spki).read_all(untrusted::EndOfInput, |reader| {
+        let alg_id = read_der(reader, TAG_SEQUENCE)?;
+        let key_bits = read_der(reader, TAG_BIT_STRING)?;
+
+        let (alg, params) = alg_id.read_all(untrusted::EndOfInput, |alg_reader| {
+            let alg = read_der(alg_reader, TAG_OID)?;
+            let params = if alg_reader.at_end() {
+                None
+            } else {
+                Some(read_der(alg_reader, TAG_OID)?)
+            };
+            Ok((alg, params))
+        })?;
+
+        Ok((alg, params, key_bits))
+    });
+    let (alg, params, key_bits) =
+        parsed.map_err(|_| Error::CredentialError("Malformed SubjectPublicKeyInfo"))?;
+
+    // The first byte of a BIT STRING is the number of unused bits. For keys, that's always 0.
+    let key_bytes = match key_bits.as_slice_less_safe().split_first() {
+        Some((0, key_bytes)) => key_bytes,
+        _ => return Err(Error::CredentialError("Malformed SubjectPublicKeyInfo")),
+    };
+
+    let alg = alg.as_slice_less_safe();
+    let params = params.map(|p| p.as_slice_less_safe());
+    let signature_scheme: &'static dyn SignatureScheme = match (alg, params) {
+        (OID_EC_PUBLIC_KEY, Some(OID_P256)) => &ECDSA_P256_IMPL,
+        (OID_ED25519, None) => &ED25519_IMPL,
+        _ => {
+            return Err(Error::CredentialError(
+                "Unsupported public key algorithm in certificate",
+            ))
+        }
+    };
+    let public_key = signature_scheme
+        .public_key_from_bytes(key_bytes)
+        .map_err(|_| Error::CredentialError("Invalid public key in certificate"))?;
+
+    Ok((signature_scheme, public_key))
+}
+
+// enum { basic(0), x509(1), (255) } CredentialType;
+//
+// struct {
+//     CredentialType credential_type;
+//     select (Credential.credential_type) {
+//         case basic:
+//             BasicCredential;
+//
+//         case x509:
+//             X509CertData chain<1..2^32-1>;
+//     };
+// } Credential;
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename = "Credential__enum_u8")]
 pub(crate) enum Credential {
     Basic(BasicCredential),
-    X509(X509CertData),
+    X509(X509Credential),
+}
+
+impl Credential {
+    /// Returns the identity of the holder of this credential. For X.509 credentials, this is the
+    /// DER-encoded subject name of the leaf certificate.
+    pub(crate) fn identity(&self) -> &[u8] {
+        match self {
+            Credential::Basic(basic_cred) => &basic_cred.identity.0,
+            Credential::X509(x509_cred) => &x509_cred.identity,
+        }
+    }
+
+    /// Returns the signature public key of the holder of this credential
+    pub(crate) fn public_key(&self) -> &SigPublicKey {
+        match self {
+            Credential::Basic(basic_cred) => &basic_cred.public_key,
+            Credential::X509(x509_cred) => &x509_cred.public_key,
+        }
+    }
+
+    /// Returns the signature scheme that the holder of this credential signs with
+    pub(crate) fn signature_scheme(&self) -> &'static dyn SignatureScheme {
+        match self {
+            Credential::Basic(basic_cred) => basic_cred.signature_scheme,
+            Credential::X509(x509_cred) => x509_cred.signature_scheme,
+        }
+    }
+
+    /// Verifies the signature of the given message under the public key in this credential
+    ///
+    /// Returns: `Ok(())` iff the signature is valid. Otherwise, returns an
+    /// `Err(Error::SignatureError)`.
+    pub(crate) fn verify(&self, msg: &[u8], sig: &Signature) -> Result<(), Error> {
+        self.signature_scheme().verify(self.public_key(), msg, sig)
+    }
+
+    /// Validates this credential's certificate chain up to one of the given trust anchors, as of
+    /// the current time. Members are checked the way TLS clients are: there's no name to match
+    /// against, and if the leaf has an extended key usage, it must allow client authentication.
+    ///
+    /// Returns: `Ok(())` iff the chain is valid. If this is not an X.509 credential, or the chain
+    /// is invalid, returns an `Error::CredentialError`.
+    pub(crate) fn verify_chain(&self, roots: &[webpki::TrustAnchor]) -> Result<(), Error> {
+        let chain = match self {
+            Credential::Basic(_) => {
+                return Err(Error::CredentialError(
+                    "Basic credentials have no certificate chain",
+                ))
+            }
+            Credential::X509(x509_cred) => &x509_cred.chain,
+        };
+
+        let (leaf, intermediates) = chain
+            .split_first()
+            .ok_or(Error::CredentialError("Certificate chain is empty"))?;
+        let leaf = webpki::EndEntityCert::from(untrusted::Input::from(&leaf.0))
+            .map_err(|_| Error::CredentialError("Malformed leaf certificate"))?;
+        let intermediates: Vec<untrusted::Input> = intermediates
+            .iter()
+            .map(|cert| untrusted::Input::from(&cert.0))
+            .collect();
+        let now = webpki::Time::try_from(std::time::SystemTime::now())
+            .map_err(|_| Error::CredentialError("System time is before the Unix epoch"))?;
+
+        leaf.verify_is_valid_tls_client_cert(
+            SUPPORTED_CERT_SIG_ALGS,
+            &webpki::TLSClientTrustAnchors(roots),
+            &intermediates,
+            now,
+        )
+        .map_err(|_| Error::CredentialError("Certificate chain is invalid"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        crypto::sig::{ECDSA_P256_IMPL, ED25519_IMPL},
+        tls_de::TlsDeserializer,
+        tls_ser,
+    };
+
+    use quickcheck_macros::quickcheck;
+    use rand::SeedableRng;
+    use serde::de::Deserialize;
+
+    // Checks that basic credentials survive a serialization roundtrip for every signature scheme,
+    // and that the deserialized credential can verify signatures made by the original key
+    #[quickcheck]
+    fn basic_credential_roundtrip(identity: Vec<u8>, msg: Vec<u8>, rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let schemes: [&'static dyn SignatureScheme; 2] = [&ED25519_IMPL, &ECDSA_P256_IMPL];
+
+        for ss in schemes.iter() {
+            let secret_key = ss.secret_key_from_random(&mut rng).unwrap();
+            let cred = Credential::Basic(BasicCredential {
+                identity: Identity(identity.clone()),
+                signature_scheme: *ss,
+                public_key: ss.public_key_from_secret_key(&secret_key),
+            });
+
+            let bytes = tls_ser::serialize_to_bytes(&cred).unwrap();
+            let mut buf = bytes.as_slice();
+            let mut deserializer = TlsDeserializer::from_reader(&mut buf);
+            let recovered = Credential::deserialize(&mut deserializer).unwrap();
+
+            assert_eq!(recovered.identity(), identity.as_slice());
+            assert_eq!(recovered.signature_scheme().name(), ss.name());
+            assert_eq!(bytes, tls_ser::serialize_to_bytes(&recovered).unwrap());
+
+            let sig = ss.sign(&secret_key, &msg);
+            assert!(recovered.verify(&msg, &sig).is_ok());
+        }
+    }
+
+    // A self-signed end-entity certificate with a P-256 key, valid from 2019 to 2119. Its only
+    // extension is a basic constraint saying it's not a CA. It was made with the Python
+    // cryptography library.
+    const TEST_CERT_HEX: &str = "\
+        3082012f3081d7a003020102020101300a06082a8648ce3d04030230183116301406035504030c0d6d6f6c61\
+        7373657320746573743020170d3139303130313030303030305a180f32313139303130313030303030305a30\
+        183116301406035504030c0d6d6f6c617373657320746573743059301306072a8648ce3d020106082a8648ce\
+        3d03010703420004471c3e758c4904285bba7e53118ed0f524adeb0757d25bd2f8e7b0d76dfa714cdd520f7a\
+        ca8a8b917acc37f51de8f0c9bbe3ad858382e702dc25a12d09f7a858a310300e300c0603551d130101ff0402\
+        3000300a06082a8648ce3d040302034700304402206ddbb7d594c3db837b0a5b0281108384f262043a3fa917\
+        85cbcf7c503d991a280220635be65a8babf8fc74ab9b8e90268619e9dd847304ca1148a358cc3ca3bc1d52";
+
+    // The leaf public key in TEST_CERT_HEX, uncompressed
+    const TEST_CERT_PUBKEY_HEX: &str = "\
+        04471c3e758c4904285bba7e53118ed0f524adeb0757d25bd2f8e7b0d76dfa714cdd520f7aca8a8b917acc37\
+        f51de8f0c9bbe3ad858382e702dc25a12d09f7a858";
+
+    // Checks that we pull the right key out of a certificate, that the certificate validates
+    // against itself as a trust anchor, and that it stops validating once it's been tampered with
+    #[test]
+    fn x509_verify_chain() {
+        let cert_der = hex::decode(TEST_CERT_HEX).unwrap();
+        let anchor =
+            webpki::trust_anchor_util::cert_der_as_trust_anchor(untrusted::Input::from(&cert_der))
+                .unwrap();
+        let anchors = [anchor];
+
+        let cred = Credential::X509(
+            X509Credential::from_chain(vec![X509CertData(cert_der.clone())]).unwrap(),
+        );
+        assert_eq!(cred.signature_scheme().name(), ECDSA_P256_IMPL.name());
+        assert_eq!(
+            hex::encode(ECDSA_P256_IMPL.public_key_to_bytes(cred.public_key())),
+            TEST_CERT_PUBKEY_HEX
+        );
+        cred.verify_chain(&anchors).unwrap();
+
+        // The credential should survive a roundtrip, since only the chain is sent
+        let bytes = tls_ser::serialize_to_bytes(&cred).unwrap();
+        let mut buf = bytes.as_slice();
+        let mut deserializer = TlsDeserializer::from_reader(&mut buf);
+        let recovered = Credential::deserialize(&mut deserializer).unwrap();
+        assert_eq!(recovered.identity(), cred.identity());
+        recovered.verify_chain(&anchors).unwrap();
+
+        // Flip a bit in the last byte of the signature. The certificate still parses, but it no
+        // longer verifies.
+        let mut tampered_der = cert_der.clone();
+        *tampered_der.last_mut().unwrap() ^= 1;
+        let tampered_cred =
+            Credential::X509(X509Credential::from_chain(vec![X509CertData(tampered_der)]).unwrap());
+        match tampered_cred.verify_chain(&anchors) {
+            Err(Error::CredentialError(_)) => (),
+            _ => panic!("tampered certificate passed verification"),
+        }
+
+        // Basic credentials have no chain to verify
+        let basic_cred = match recovered {
+            Credential::X509(x509_cred) => Credential::Basic(BasicCredential {
+                identity: Identity(b"Alice".to_vec()),
+                signature_scheme: x509_cred.signature_scheme,
+                public_key: x509_cred.public_key,
+            }),
+            _ => unreachable!(),
+        };
+        assert!(basic_cred.verify_chain(&anchors).is_err());
+    }
 }
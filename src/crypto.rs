@@ -1,6 +1,6 @@
-mod aead;
+pub(crate) mod aead;
 pub(crate) mod ciphersuite;
 pub(crate) mod dh;
 pub(crate) mod ecies;
-mod rng;
+pub(crate) mod rng;
 pub(crate) mod sig;
@@ -11,16 +11,59 @@ const AES_128_GCM_TAG_SIZE: usize = 128 / 8;
 /// Size of nonces, in bytes
 const AES_128_GCM_NONCE_SIZE: usize = 96 / 8;
 
+/// A singleton object representing the ChaCha20-Poly1305 AEAD scheme
+pub(crate) const CHACHA20POLY1305_IMPL: ChaCha20Poly1305 = ChaCha20Poly1305;
+
+/// Size of opening / sealing keys, in bytes
+const CHACHA20_POLY1305_KEY_SIZE: usize = 256 / 8;
+/// Size of tag, in bytes
+const CHACHA20_POLY1305_TAG_SIZE: usize = 128 / 8;
+/// Size of nonces, in bytes
+const CHACHA20_POLY1305_NONCE_SIZE: usize = 96 / 8;
+
 /// An enum of possible types for an AEAD key, depending on the underlying algorithm
 pub(crate) enum AeadKey {
     /// An opening / sealing key in AES-128-GCM
-    Aes128GcmKey(Aes128GcmKey),
+    Aes128GcmKey(RingAeadKey),
+    /// An opening / sealing key in ChaCha20-Poly1305
+    ChaCha20Poly1305Key(RingAeadKey),
+}
+
+/// An opening / sealing key for any of ring's AEAD algorithms. Which algorithm it's for is
+/// recorded by the `AeadKey` variant it's wrapped in.
+// These will just be two copies of the same thing. They're different types because ring requires
+// an OpeningKey for opening and a SealingKey for sealing. This incurs some 64 bytes of storage
+// overhead, but I frankly don't care.
+pub(crate) struct RingAeadKey {
+    opening_key: ring::aead::OpeningKey,
+    sealing_key: ring::aead::SealingKey,
+}
+
+impl RingAeadKey {
+    /// Makes a new key for the given algorithm from the given key bytes
+    ///
+    /// Returns: `Ok(key)` on success. If ring rejects the key bytes, returns an
+    /// `Error::EncryptionError`.
+    fn new(alg: &'static ring::aead::Algorithm, key_bytes: &[u8]) -> Result<RingAeadKey, Error> {
+        // The opening and sealing keys are the same
+        let opening_key = ring::aead::OpeningKey::new(alg, key_bytes)
+            .map_err(|_| Error::EncryptionError("Unspecified"))?;
+        let sealing_key = ring::aead::SealingKey::new(alg, key_bytes)
+            .map_err(|_| Error::EncryptionError("Unspecified"))?;
+
+        Ok(RingAeadKey {
+            opening_key,
+            sealing_key,
+        })
+    }
 }
 
 /// An enum of possible types for an AEAD nonce, depending on the underlying algorithm
 pub(crate) enum AeadNonce {
     /// A nonce in AES-128-GCM
     Aes128GcmNonce(ring::aead::Nonce),
+    /// A nonce in ChaCha20-Poly1305
+    ChaCha20Poly1305Nonce(ring::aead::Nonce),
 }
 
 /// A trait representing an authenticated encryption algorithm. Note that this makes no mention of
@@ -28,7 +71,7 @@ pub(crate) enum AeadNonce {
 // ring does algorithm specification at runtime, but I'd rather encode these things in the type
 // system. So, similar to the Digest trait, we're making an AuthenticatedEncryption trait. I don't
 // think we'll need associated data in this crate, so we leave it out for simplicity
-pub(crate) trait AuthenticatedEncryption {
+pub(crate) trait AuthenticatedEncryption: Sync {
     // Recall we can't have const trait methods if we want this to be a trait object
     fn key_size(&self) -> usize;
     fn nonce_size(&self) -> usize;
@@ -58,15 +101,6 @@ pub(crate) trait AuthenticatedEncryption {
 /// `AuthenticatedEncryption`.
 pub(crate) struct Aes128Gcm;
 
-/// An opening / sealing key for use with the `Aes128Gcm` algorithm
-// These will just be two copies of the same thing. They're different types because ring requires
-// an OpeningKey for opening and a SealingKey for sealing. This incurs some 64 bytes of storage
-// overhead, but I frankly don't care.
-pub(crate) struct Aes128GcmKey {
-    opening_key: ring::aead::OpeningKey,
-    sealing_key: ring::aead::SealingKey,
-}
-
 /// A nonce for use with the `Aes128Gcm` algorithm
 pub(crate) struct Aes128GcmNonce(ring::aead::Nonce);
 
@@ -97,16 +131,7 @@ impl AuthenticatedEncryption for Aes128Gcm {
             return Err(Error::EncryptionError("AES-GCM-128 requires 128-bit keys"));
         }
 
-        // Again, the opening and sealing keys for AES-GCM are the same.
-        let opening_key = ring::aead::OpeningKey::new(&ring::aead::AES_128_GCM, key_bytes)
-            .map_err(|_| Error::EncryptionError("Unspecified"))?;
-        let sealing_key = ring::aead::SealingKey::new(&ring::aead::AES_128_GCM, key_bytes)
-            .map_err(|_| Error::EncryptionError("Unspecified"))?;
-
-        let key = Aes128GcmKey {
-            opening_key,
-            sealing_key,
-        };
+        let key = RingAeadKey::new(&ring::aead::AES_128_GCM, key_bytes)?;
         Ok(AeadKey::Aes128GcmKey(key))
     }
 
@@ -210,6 +235,124 @@ impl AuthenticatedEncryption for Aes128Gcm {
     }
 }
 
+/// This represents the ChaCha20-Poly1305 authenticated encryption algorithm. Notably, it
+/// implements `AuthenticatedEncryption`.
+pub(crate) struct ChaCha20Poly1305;
+
+impl AuthenticatedEncryption for ChaCha20Poly1305 {
+    /// Returns `CHACHA20_POLY1305_KEY_SIZE`
+    fn key_size(&self) -> usize {
+        CHACHA20_POLY1305_KEY_SIZE
+    }
+
+    /// Returns `CHACHA20_POLY1305_NONCE_SIZE`
+    fn nonce_size(&self) -> usize {
+        CHACHA20_POLY1305_NONCE_SIZE
+    }
+
+    /// Returns `CHACHA20_POLY1305_TAG_SIZE`
+    fn tag_size(&self) -> usize {
+        CHACHA20_POLY1305_TAG_SIZE
+    }
+
+    /// Makes a new ChaCha20-Poly1305 key from the given key bytes.
+    ///
+    /// Requires: `key_bytes.len() == CHACHA20_POLY1305_KEY_SIZE`
+    ///
+    /// Returns: `Ok(key)` on success. If the key is the wrong length, returns an
+    /// `Error::EncryptionError`.
+    fn key_from_bytes(&self, key_bytes: &[u8]) -> Result<AeadKey, Error> {
+        if key_bytes.len() != CHACHA20_POLY1305_KEY_SIZE {
+            return Err(Error::EncryptionError(
+                "ChaCha20-Poly1305 requires 256-bit keys",
+            ));
+        }
+
+        let key = RingAeadKey::new(&ring::aead::CHACHA20_POLY1305, key_bytes)?;
+        Ok(AeadKey::ChaCha20Poly1305Key(key))
+    }
+
+    /// Makes a new secure-random ChaCha20-Poly1305 key.
+    ///
+    /// Returns: `Ok(key)` on success. On error, returns `Error::OutOfEntropy`.
+    fn key_from_random(&self, csprng: &mut dyn CryptoRng) -> Result<AeadKey, Error> {
+        let mut key = [0u8; CHACHA20_POLY1305_KEY_SIZE];
+        csprng
+            .try_fill_bytes(&mut key)
+            .map_err(|_| Error::OutOfEntropy)?;
+
+        self.key_from_bytes(&key)
+    }
+
+    /// Makes a new ChaCha20-Poly1305 nonce from the given bytes.
+    ///
+    /// Requires: `nonce_bytes.len() == CHACHA20_POLY1305_NONCE_SIZE`
+    ///
+    /// Returns: `Ok(nonce)` on success. If the nonce is the wrong length, returns an
+    /// `Error::EncryptionError`.
+    fn nonce_from_bytes(&self, nonce_bytes: &[u8]) -> Result<AeadNonce, Error> {
+        if nonce_bytes.len() != CHACHA20_POLY1305_NONCE_SIZE {
+            return Err(Error::EncryptionError(
+                "ChaCha20-Poly1305 requires 96-bit nonces",
+            ));
+        }
+
+        let mut nonce = [0u8; CHACHA20_POLY1305_NONCE_SIZE];
+        nonce.copy_from_slice(nonce_bytes);
+        Ok(AeadNonce::ChaCha20Poly1305Nonce(
+            ring::aead::Nonce::assume_unique_for_key(nonce),
+        ))
+    }
+
+    /// Does an in-place authenticated decryption of the given ciphertext and tag. This works
+    /// exactly like `Aes128Gcm::open`, tag size included.
+    ///
+    /// Returns: `Ok(plaintext)` on sucess. If there is an error in any part of this process, it
+    /// will be returned as an `Error::EncryptionError` with description "Unspecified".
+    fn open<'a>(
+        &self,
+        key: &AeadKey,
+        nonce: AeadNonce,
+        ciphertext_and_tag_modified_in_place: &'a mut [u8],
+    ) -> Result<&'a mut [u8], Error> {
+        let key = enum_variant!(key, AeadKey::ChaCha20Poly1305Key);
+        let nonce = enum_variant!(nonce, AeadNonce::ChaCha20Poly1305Nonce);
+
+        ring::aead::open_in_place(
+            &key.opening_key,
+            nonce,
+            ring::aead::Aad::empty(),
+            0,
+            ciphertext_and_tag_modified_in_place,
+        )
+        .map_err(|_| Error::EncryptionError("Unspecified"))
+    }
+
+    /// Does an in-place authenticated encryption of the given plaintext. This works exactly like
+    /// `Aes128Gcm::seal`, tag size included.
+    ///
+    /// Requires: `plaintext.len() >= 16`
+    ///
+    /// Returns: `Ok(())` on sucess, indicating that the inputted buffer contains the tagged
+    /// ciphertext. If there is an error in any part of this process, it will be returned as an
+    /// `Error::EncryptionError` with description "Unspecified".
+    #[must_use]
+    fn seal(&self, key: &AeadKey, nonce: AeadNonce, plaintext: &mut [u8]) -> Result<(), Error> {
+        let key = enum_variant!(key, AeadKey::ChaCha20Poly1305Key);
+        let nonce = enum_variant!(nonce, AeadNonce::ChaCha20Poly1305Nonce);
+
+        ring::aead::seal_in_place(
+            &key.sealing_key,
+            nonce,
+            ring::aead::Aad::empty(),
+            plaintext,
+            CHACHA20_POLY1305_TAG_SIZE,
+        )
+        .map(|_| ())
+        .map_err(|_| Error::EncryptionError("Unspecified"))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -220,14 +363,17 @@ mod test {
     // TODO: AES-GCM KAT
 
     // Returns a pair of identical nonces. For testing purposes only
-    fn make_nonce_pair<T: RngCore>(rng: &mut T) -> (AeadNonce, AeadNonce) {
-        let mut buf = [0u8; AES_128_GCM_NONCE_SIZE];
+    fn make_nonce_pair<T: RngCore>(
+        aead: &dyn AuthenticatedEncryption,
+        rng: &mut T,
+    ) -> (AeadNonce, AeadNonce) {
+        let mut buf = vec![0u8; aead.nonce_size()];
 
         rng.fill_bytes(&mut buf);
 
         (
-            AES128GCM_IMPL.nonce_from_bytes(&buf).unwrap(),
-            AES128GCM_IMPL.nonce_from_bytes(&buf).unwrap(),
+            aead.nonce_from_bytes(&buf).unwrap(),
+            aead.nonce_from_bytes(&buf).unwrap(),
         )
     }
 
@@ -239,7 +385,7 @@ mod test {
             .key_from_random(&mut rng)
             .expect("failed to generate key");
         // The open method consumes our nonce, so make two nonces
-        let (nonce1, nonce2) = make_nonce_pair(&mut rng);
+        let (nonce1, nonce2) = make_nonce_pair(&AES128GCM_IMPL, &mut rng);
         // Make sure there's enough room in the plaintext for the tag
         let mut extended_plaintext = [plaintext.as_slice(), &[0u8; AES_128_GCM_TAG_SIZE]].concat();
 
@@ -267,7 +413,7 @@ mod test {
             .key_from_random(&mut rng)
             .expect("failed to generate key");
         // The open method consumes our nonce, so make two nonces
-        let (nonce1, nonce2) = make_nonce_pair(&mut rng);
+        let (nonce1, nonce2) = make_nonce_pair(&AES128GCM_IMPL, &mut rng);
         // Make sure there's enough room in the plaintext for the tag
         plaintext.extend(vec![0u8; AES_128_GCM_TAG_SIZE]);
 
@@ -309,7 +455,7 @@ mod test {
             .key_from_random(&mut rng)
             .expect("failed to generate key");
         // The open method consumes our nonce, so make two nonces
-        let (nonce1, nonce2) = make_nonce_pair(&mut rng);
+        let (nonce1, nonce2) = make_nonce_pair(&AES128GCM_IMPL, &mut rng);
         // Make sure there's enough room in the plaintext for the tag
         plaintext.extend(vec![0u8; AES_128_GCM_TAG_SIZE]);
 
@@ -334,4 +480,48 @@ mod test {
         let res = AES128GCM_IMPL.open(&key, nonce2, auth_ciphertext);
         assert!(res.is_err());
     }
+
+    // Test that decrypt_k(encrypt_k(m)) == m, and that flipping any bit of the ciphertext or tag
+    // makes decryption fail
+    #[quickcheck]
+    fn chacha20_poly1305_correctness(plaintext: Vec<u8>, flip_idx: usize, rng_seed: u64) {
+        let aead = &CHACHA20POLY1305_IMPL;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let key = aead
+            .key_from_random(&mut rng)
+            .expect("failed to generate key");
+        let mut auth_ciphertext =
+            [plaintext.as_slice(), &[0u8; CHACHA20_POLY1305_TAG_SIZE]].concat();
+
+        let (nonce1, nonce2) = make_nonce_pair(aead, &mut rng);
+        aead.seal(&key, nonce1, auth_ciphertext.as_mut_slice())
+            .expect("failed to encrypt");
+        let mut tampered = auth_ciphertext.clone();
+
+        let recovered_plaintext = aead
+            .open(&key, nonce2, auth_ciphertext.as_mut_slice())
+            .expect("failed to decrypt");
+        assert_eq!(plaintext, recovered_plaintext);
+
+        // Now flip a bit and make sure it fails to open
+        let flip_idx = flip_idx % tampered.len();
+        tampered[flip_idx] ^= 1;
+        let (nonce, _) = make_nonce_pair(aead, &mut rng);
+        assert!(aead.open(&key, nonce, tampered.as_mut_slice()).is_err());
+    }
+
+    // Test that keys and nonces of the wrong size are rejected
+    #[test]
+    fn chacha20_poly1305_sizes() {
+        let aead = &CHACHA20POLY1305_IMPL;
+        assert!(aead.key_from_bytes(&[0u8; AES_128_GCM_KEY_SIZE]).is_err());
+        assert!(aead.nonce_from_bytes(&[0u8; 8]).is_err());
+        assert!(aead
+            .key_from_bytes(&[0u8; CHACHA20_POLY1305_KEY_SIZE])
+            .is_ok());
+        assert!(aead
+            .nonce_from_bytes(&[0u8; CHACHA20_POLY1305_NONCE_SIZE])
+            .is_ok());
+        assert_eq!(aead.tag_size(), AES128GCM_IMPL.tag_size());
+    }
 }
@@ -1,8 +1,9 @@
 use crate::{
     crypto::{
-        aead::{AuthenticatedEncryption, AES128GCM_IMPL},
-        dh::{DhPoint, DhScalar, DiffieHellman, X25519_IMPL},
-        sig::{SignatureScheme, ED25519_IMPL},
+        aead::{AuthenticatedEncryption, AES128GCM_IMPL, CHACHA20POLY1305_IMPL},
+        dh::{DhPoint, DhScalar, DiffieHellman, P256_IMPL, X25519_IMPL},
+        rng::CryptoRng,
+        sig::{SignatureScheme, ECDSA_P256_IMPL, ED25519_IMPL},
     },
     error::Error,
 };
@@ -16,6 +17,45 @@ pub(crate) const X25519_SHA256_AES128GCM: CipherSuite = CipherSuite {
     hash_alg: &ring::digest::SHA256,
 };
 
+/// This represents the P256-SHA256-AES128GCM ciphersuite. Notably, it implements `CipherSuite`.
+pub(crate) const P256_SHA256_AES128GCM: CipherSuite = CipherSuite {
+    name: "P256_SHA256_AES128GCM",
+    dh_impl: &P256_IMPL,
+    aead_impl: &AES128GCM_IMPL,
+    sig_impl: &ECDSA_P256_IMPL,
+    hash_alg: &ring::digest::SHA256,
+};
+
+/// This represents the X25519-SHA256-ChaCha20Poly1305 ciphersuite. Notably, it implements
+/// `CipherSuite`.
+pub(crate) const X25519_SHA256_CHACHA20POLY1305: CipherSuite = CipherSuite {
+    name: "X25519_SHA256_CHACHA20POLY1305",
+    dh_impl: &X25519_IMPL,
+    aead_impl: &CHACHA20POLY1305_IMPL,
+    sig_impl: &ED25519_IMPL,
+    hash_alg: &ring::digest::SHA256,
+};
+
+/// The label struct used as the `info` parameter in `CipherSuite::hkdf_expand_label`
+#[derive(Serialize)]
+struct HkdfLabel<'a> {
+    length: u16,
+    // opaque label<7..255> = "mls10 " + Label;
+    #[serde(rename = "label__bound_u8")]
+    label: Vec<u8>,
+    // opaque context<0..2^32-1> = Context;
+    #[serde(rename = "context__bound_u32")]
+    context: &'a [u8],
+}
+
+/// All the cipher suites we support, along with their wire identifiers
+const CIPHERSUITE_IDS: &[(&CipherSuite, u16)] = &[
+    (&P256_SHA256_AES128GCM, 0x0000),
+    (&X25519_SHA256_AES128GCM, 0x0001),
+    // The spec doesn't assign this one an ID yet, so we take the next one up
+    (&X25519_SHA256_CHACHA20POLY1305, 0x0002),
+];
+
 /// Represents the contents of an MLS ciphersuite: a DH-like key-agreement protocol, a
 /// hashing algorithm, and an authenticated encryption algorithm.
 pub(crate) struct CipherSuite {
@@ -26,7 +66,7 @@ pub(crate) struct CipherSuite {
     /// The trait object that implements our authenticated encryption functionality
     pub(crate) aead_impl: &'static dyn AuthenticatedEncryption,
     /// The object that implements our signature scheme
-    pub(crate) sig_impl: &'static SignatureScheme,
+    pub(crate) sig_impl: &'static dyn SignatureScheme,
     /// The `ring::digest::Algorithm` that implements our hashing functionality
     // We're gonna have to break the mold here. Originally this was Hash: digest::Digest. But to
     // define HKDF and HMAC over a generic Digest, one needs the following constraints:
@@ -41,9 +81,94 @@ pub(crate) struct CipherSuite {
 }
 
 impl CipherSuite {
-    /// Given an arbitrary number of bytes, derives a Diffie-Hellman keypair. For this ciphersuite,
-    /// the function is simply `scalar: [0u8; 32] = SHA256(bytes)`.
-    fn derive_key_pair(&self, bytes: &[u8]) -> Result<(DhPoint, DhScalar), Error> {
+    /// Looks up the cipher suite with the given wire identifier
+    ///
+    /// Returns: `Some(cs)` if we support a cipher suite with the given ID, and `None` otherwise
+    pub(crate) fn from_u16(id: u16) -> Option<&'static CipherSuite> {
+        CIPHERSUITE_IDS
+            .iter()
+            .find(|(_, other_id)| *other_id == id)
+            .map(|(cs, _)| *cs)
+    }
+
+    /// Returns the wire identifier of this cipher suite
+    ///
+    /// Panics: when this cipher suite is not in the table of supported cipher suites
+    pub(crate) fn to_u16(&self) -> u16 {
+        CIPHERSUITE_IDS
+            .iter()
+            .find(|(cs, _)| cs.name == self.name)
+            .map(|(_, id)| *id)
+            .expect("tried to get the ID of an unknown cipher suite")
+    }
+
+    /// Returns the length in bytes of this cipher suite's hash function's output
+    pub(crate) fn hash_len(&self) -> usize {
+        self.hash_alg.output_len
+    }
+
+    /// Returns a fresh leaf secret, i.e., `hash_len()` bytes from the given RNG. A leaf secret
+    /// seeds every path secret above the leaf, so it should always come from here rather than from
+    /// a buffer of some other length.
+    pub(crate) fn generate_leaf_secret<R: CryptoRng>(&self, csprng: &mut R) -> Vec<u8> {
+        let mut leaf_secret = vec![0u8; self.hash_len()];
+        csprng.fill_bytes(&mut leaf_secret);
+        leaf_secret
+    }
+
+    /// Returns `hash_len()` zero bytes. The spec uses this as the transcript hash and init secret
+    /// of a new group, and as the update secret of an Add.
+    pub(crate) fn zero_secret(&self) -> Vec<u8> {
+        vec![0u8; self.hash_len()]
+    }
+
+    /// Hashes the given input with this cipher suite's hash function. The output is always
+    /// `hash_len()` bytes long.
+    pub(crate) fn hash(&self, input: &[u8]) -> Vec<u8> {
+        ring::digest::digest(self.hash_alg, input).as_ref().to_vec()
+    }
+
+    /// Computes `HKDF-Expand-Label(secret, label, context, length)` as defined in section 5.9 of
+    /// the spec, using this cipher suite's hash function. `secret` is used as the PRK.
+    ///
+    /// Panics: when `"mls10 " + label` is longer than 255 bytes, or `length` doesn't fit in a `u16`
+    pub(crate) fn hkdf_expand_label(
+        &self,
+        secret: &[u8],
+        label: &str,
+        context: &[u8],
+        length: usize,
+    ) -> Vec<u8> {
+        // The output length is supposed to be representable by a u16
+        assert!(
+            length <= std::u16::MAX as usize,
+            "HKDF-Expand-Label output is too long"
+        );
+
+        let hkdf_label = HkdfLabel {
+            length: length as u16,
+            label: [b"mls10 ", label.as_bytes()].concat(),
+            context: context,
+        };
+        assert!(
+            hkdf_label.label.len() <= 255,
+            "HKDF-Expand-Label label is too long"
+        );
+
+        // We're gonna use the serialized label as the `info` parameter to HKDF-Expand
+        let serialized_label =
+            crate::tls_ser::serialize_to_bytes(&hkdf_label).expect("couldn't serialize HKDF label");
+
+        let prk = ring::hmac::SigningKey::new(self.hash_alg, secret);
+        let mut out_buf = vec![0u8; length];
+        ring::hkdf::expand(&prk, &serialized_label, out_buf.as_mut_slice());
+        out_buf
+    }
+
+    /// Given an arbitrary number of bytes, derives a Diffie-Hellman keypair. For the cipher suites
+    /// we support, the function is simply `scalar: [0u8; 32] = SHA256(bytes)`. For P-256, this
+    /// fails with negligible probability, since not every digest is a valid scalar.
+    pub(crate) fn derive_key_pair(&self, bytes: &[u8]) -> Result<(DhPoint, DhScalar), Error> {
         let digest = ring::digest::digest(self.hash_alg, bytes);
         let scalar_bytes = digest.as_ref();
 
@@ -53,3 +178,88 @@ impl CipherSuite {
         Ok((pubkey, privkey))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use quickcheck_macros::quickcheck;
+    use rand::SeedableRng;
+
+    // Checks that from_u16(to_u16(cs)) == cs for every supported cipher suite, and vice versa
+    #[test]
+    fn ciphersuite_id_roundtrip() {
+        for (cs, id) in CIPHERSUITE_IDS {
+            assert_eq!(cs.to_u16(), *id);
+            assert_eq!(CipherSuite::from_u16(*id).unwrap().name, cs.name);
+        }
+
+        // There is no cipher suite with ID 0xffff
+        assert!(CipherSuite::from_u16(0xffff).is_none());
+    }
+
+    // Checks that every supported cipher suite's hash output is as long as it says it is
+    #[test]
+    fn hash_len_correctness() {
+        for (cs, _) in CIPHERSUITE_IDS {
+            assert_eq!(cs.hash(b"").len(), cs.hash_len());
+            assert_eq!(cs.hash(&[0xab; 1000]).len(), cs.hash_len());
+        }
+
+        // SHA-256 of the empty string
+        assert_eq!(
+            hex::encode(X25519_SHA256_AES128GCM.hash(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    // Checks that leaf secrets are as long as the hash for every supported cipher suite, and that
+    // they actually come from the RNG
+    #[quickcheck]
+    fn generate_leaf_secret_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        for (cs, _) in CIPHERSUITE_IDS {
+            let leaf_secret1 = cs.generate_leaf_secret(&mut rng);
+            let leaf_secret2 = cs.generate_leaf_secret(&mut rng);
+            assert_eq!(leaf_secret1.len(), cs.hash_len());
+            assert_eq!(leaf_secret2.len(), cs.hash_len());
+            assert_ne!(leaf_secret1, leaf_secret2);
+        }
+    }
+
+    // Known-answer tests for HKDF-Expand-Label. There are no published vectors for this version of
+    // the label format, so these were computed independently with Python's hmac module. Each entry
+    // is (secret, label, context, expected output). The output length is the expected length.
+    #[test]
+    fn hkdf_expand_label_kat() {
+        let secret: Vec<u8> = (0u8..32).collect();
+        let vectors: &[(&[u8], &str, &[u8], &str)] = &[
+            (
+                &[0u8; 32],
+                "app",
+                b"",
+                "2963da41658ed36beaca5b9b8dc5cbb232ec508a5387c649c6d0816a06286247",
+            ),
+            (
+                &secret,
+                "confirm",
+                &[0xde, 0xad, 0xbe, 0xef],
+                "9903f4955f263e0055b731551778ed993cfe783fbb1fc7b167583262507373e9",
+            ),
+            (
+                &secret,
+                "key",
+                b"hello world",
+                "a53978d24a8b7945f9b4d7404da4f676",
+            ),
+            (&secret, "nonce", b"", "ae14d4f5d998c4310e93291c"),
+        ];
+
+        let cs = &X25519_SHA256_AES128GCM;
+        for (secret, label, context, expected) in vectors {
+            let expected = hex::decode(expected).unwrap();
+            let out = cs.hkdf_expand_label(secret, label, context, expected.len());
+            assert_eq!(out, expected);
+        }
+    }
+}
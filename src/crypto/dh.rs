@@ -1,14 +1,24 @@
 use crate::crypto::rng::CryptoRng;
 use crate::error::Error;
 
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use std::convert::TryFrom;
 use x25519_dalek::{x25519, X25519_BASEPOINT_BYTES};
+use zeroize::Zeroize;
 
 /// A singleton object representing the X25519 DH scheme
 pub(crate) const X25519_IMPL: X25519 = X25519;
 
+/// A singleton object representing the ECDH scheme over P-256
+pub(crate) const P256_IMPL: P256 = P256;
+
 const X25519_POINT_SIZE: usize = 32;
 const X25519_SCALAR_SIZE: usize = 32;
 
+// P-256 points are always uncompressed on the wire: 0x04 || x || y
+const P256_POINT_SIZE: usize = 65;
+const P256_SCALAR_SIZE: usize = 32;
+
 // We do not use the x25519_dalek DH API because the EphemeralSecret does not expose its internals.
 // The MLS spec requires that we be able to create secrets from arbitrary bytestrings, and we can
 // only do that if we can touch the buffer inside EphemeralSecret. So, we re-implement a small
@@ -21,24 +31,43 @@ const X25519_SCALAR_SIZE: usize = 32;
 
 /// An enum of possible types for a private DH value, depending on the underlying algorithm. In EC
 /// terminology, this is a point on the curve. In finite-field terminology, this is an element of
-/// the field.
+/// the field. Every copy zeroizes itself when dropped, just like the original.
+#[derive(Clone)]
 pub(crate) enum DhScalar {
     /// A scalar value in Curve25519
     X25519Scalar([u8; X25519_SCALAR_SIZE]),
+    /// A nonzero scalar value modulo the order of the P-256 group, in big-endian
+    P256Scalar([u8; P256_SCALAR_SIZE]),
+}
+
+impl Zeroize for DhScalar {
+    fn zeroize(&mut self) {
+        match self {
+            DhScalar::X25519Scalar(buf) => buf.zeroize(),
+            DhScalar::P256Scalar(buf) => buf.zeroize(),
+        }
+    }
+}
+
+// Private keys should not outlive their owners
+impl Drop for DhScalar {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
 }
 
 // opaque DHPublicKey<1..2^16-1>
 /// Because these are untagged during serialization and deserialization, we can only represent
 /// curve points as bytes, without any variant tag (such as X25519Scalar). So we use this type for
 /// all DH stuff. I know, this sucks.
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 #[serde(rename = "DhPoint__bound_u16")]
 pub(crate) struct DhPoint(Vec<u8>);
 
 /// A trait representing any DH-like key-agreement algorithm. The notation it uses in documentation
 /// is that of elliptic curves, but these concepts should generalize to finite-fields, SIDH, CSIDH,
-/// etc.
-pub(crate) trait DiffieHellman {
+/// etc. Implementors are stateless singletons, so they can be shared across threads.
+pub(crate) trait DiffieHellman: Sync {
     // You may ask why this function isn't implemented as part of a serialization function for
     // DhPoint. That's because the byte representation of this here point is independent of the
     // wire format we choose. This representation is used in the calculation of ECIES ciphertexts,
@@ -47,8 +76,41 @@ pub(crate) trait DiffieHellman {
 
     fn point_from_bytes(&self, bytes: Vec<u8>) -> DhPoint;
 
+    /// Returns the size, in bytes, of every point in this scheme
+    fn point_size(&self) -> usize;
+
+    /// Checks that the given point has the right size for this scheme. `DhPoint`s are
+    /// deserialized without knowing which scheme they belong to, so any point that came off the
+    /// wire should go through this before it's used.
+    ///
+    /// Returns: `Ok(())` if the point is the right size. Otherwise, returns
+    /// `Error::BadPointLength`.
+    fn validate_point(&self, point: &DhPoint) -> Result<(), Error> {
+        if point.0.len() != self.point_size() {
+            Err(Error::BadPointLength {
+                expected: self.point_size(),
+                got: point.0.len(),
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Makes a `DhPoint` from the given bytes. Unlike `point_from_bytes`, this doesn't trust the
+    /// length of its input.
+    ///
+    /// Returns: `Ok(point)` on success. Otherwise, if `bytes` is the wrong size for this scheme,
+    /// returns `Error::BadPointLength`.
+    fn try_point_from_bytes(&self, bytes: &[u8]) -> Result<DhPoint, Error> {
+        let point = DhPoint(bytes.to_vec());
+        self.validate_point(&point)?;
+        Ok(point)
+    }
+
     fn scalar_from_bytes(&self, bytes: &[u8]) -> Result<DhScalar, Error>;
 
+    fn scalar_as_bytes(&self, scalar: &DhScalar) -> Vec<u8>;
+
     // This has to take a dyn CryptoRng because DiffieHellman is itself a trait object inside a
     // CipherSuite. Trait objects can't have associated types, associated constants, or generic
     // methods.
@@ -56,15 +118,23 @@ pub(crate) trait DiffieHellman {
 
     fn multiply_basepoint(&self, scalar: &DhScalar) -> DhPoint;
 
-    fn diffie_hellman(&self, privkey: &DhScalar, pubkey: &DhPoint) -> DhPoint;
+    fn diffie_hellman(&self, privkey: &DhScalar, pubkey: &DhPoint) -> Result<DhPoint, Error>;
+
+    /// Generates a random keypair
+    ///
+    /// Returns: `Ok((public_key, private_key))` on success. Otherwise, if something goes wrong
+    /// with the RNG, it returns `Error::OutOfEntropy`.
+    fn generate_keypair(&self, csprng: &mut dyn CryptoRng) -> Result<(DhPoint, DhScalar), Error> {
+        let privkey = self.scalar_from_random(csprng)?;
+        let pubkey = self.multiply_basepoint(&privkey);
+        Ok((pubkey, privkey))
+    }
 }
 
 /// This represents the X25519 Diffie-Hellman key agreement protocol. Notably, it implements
 /// `DiffieHellman`.
 pub(crate) struct X25519;
 
-// TODO: Urgent: Do the zero checks that the specification requires
-
 impl DiffieHellman for X25519 {
     /// Outputs the internal byte representation of a given point
     fn point_as_bytes(&self, point: DhPoint) -> Vec<u8> {
@@ -80,6 +150,11 @@ impl DiffieHellman for X25519 {
         DhPoint(bytes)
     }
 
+    /// Returns `X25519_POINT_SIZE == 32`
+    fn point_size(&self) -> usize {
+        X25519_POINT_SIZE
+    }
+
     /// Uses the given bytes as a scalar in GF(2^(255) - 19)
     ///
     /// Requires: `bytes.len() == 32`
@@ -96,6 +171,12 @@ impl DiffieHellman for X25519 {
         }
     }
 
+    /// Outputs the bytes of the given scalar, exactly as they were given to `scalar_from_bytes`.
+    /// The caller is responsible for wiping them.
+    fn scalar_as_bytes(&self, scalar: &DhScalar) -> Vec<u8> {
+        enum_variant!(scalar, DhScalar::X25519Scalar).to_vec()
+    }
+
     /// Generates a random scalar value
     ///
     /// Returns: `Ok(scalar)` on success. Otherwise, if something goes wrong with the RNG, it
@@ -119,8 +200,14 @@ impl DiffieHellman for X25519 {
 
     /// Computes `privkey * Pubkey` where `privkey` is your local secret (a scalar) and `Pubkey` is
     /// someone's public key (a curve point)
-    fn diffie_hellman(&self, privkey: &DhScalar, pubkey: &DhPoint) -> DhPoint {
+    ///
+    /// Returns: `Ok(shared_secret)` on success. If `Pubkey` is the wrong size, returns
+    /// `Error::BadPointLength`. If `Pubkey` is a low-order point, the shared secret is all zeros,
+    /// and this returns `Error::ZeroSharedSecret`. RFC 7748 section 6.1 recommends this check,
+    /// since otherwise a malicious party can force the shared secret to a known value.
+    fn diffie_hellman(&self, privkey: &DhScalar, pubkey: &DhPoint) -> Result<DhPoint, Error> {
         let privkey = enum_variant!(privkey, DhScalar::X25519Scalar);
+        self.validate_point(pubkey)?;
         let pubkey = {
             let mut buf = [0u8; X25519_POINT_SIZE];
             buf.copy_from_slice(&pubkey.0);
@@ -128,7 +215,131 @@ impl DiffieHellman for X25519 {
         };
 
         let shared_secret = x25519(*privkey, pubkey);
-        DhPoint(shared_secret.to_vec())
+
+        if is_all_zero(&shared_secret) {
+            Err(Error::ZeroSharedSecret)
+        } else {
+            Ok(DhPoint(shared_secret.to_vec()))
+        }
+    }
+}
+
+/// Returns whether the given bytes are all zero. This doesn't branch on individual bytes, so it
+/// doesn't leak where the first nonzero byte is.
+fn is_all_zero(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |acc, b| acc | b) == 0
+}
+
+/// This represents the ECDH key agreement protocol over the NIST P-256 curve. Notably, it
+/// implements `DiffieHellman`.
+pub(crate) struct P256;
+
+impl P256 {
+    // Every P256Scalar is checked to be nonzero and reduced when it's made, so this never fails
+    fn nonzero_scalar(scalar: &DhScalar) -> p256::NonZeroScalar {
+        let scalar = enum_variant!(scalar, DhScalar::P256Scalar);
+        p256::NonZeroScalar::try_from(&scalar[..]).expect("invalid P-256 scalar")
+    }
+}
+
+impl DiffieHellman for P256 {
+    /// Outputs the internal byte representation of a given point
+    fn point_as_bytes(&self, point: DhPoint) -> Vec<u8> {
+        point.0
+    }
+
+    /// Makes a `DhPoint` from the given bytes
+    ///
+    /// Requires: `bytes.len() == P256_POINT_SIZE == 65`
+    fn point_from_bytes(&self, bytes: Vec<u8>) -> DhPoint {
+        // This has to be the right length
+        assert_eq!(bytes.len(), P256_POINT_SIZE);
+        DhPoint(bytes)
+    }
+
+    /// Returns `P256_POINT_SIZE == 65`, since points are uncompressed
+    fn point_size(&self) -> usize {
+        P256_POINT_SIZE
+    }
+
+    /// Uses the given bytes as a big-endian scalar modulo the order of the P-256 group
+    ///
+    /// Requires: `bytes.len() == 32`
+    ///
+    /// Returns: `Ok(scalar)` on success. Otherwise, if `bytes.len() != 32`, or `bytes` represents
+    /// zero or a number that's not fully reduced, returns `Error::DhError`.
+    fn scalar_from_bytes(&self, bytes: &[u8]) -> Result<DhScalar, Error> {
+        if bytes.len() != P256_SCALAR_SIZE {
+            return Err(Error::DhError("Wrong key size"));
+        }
+        if p256::NonZeroScalar::try_from(bytes).is_err() {
+            return Err(Error::DhError("Invalid scalar"));
+        }
+
+        let mut buf = [0u8; P256_SCALAR_SIZE];
+        buf.copy_from_slice(bytes);
+        Ok(DhScalar::P256Scalar(buf))
+    }
+
+    /// Outputs the big-endian bytes of the given scalar. The caller is responsible for wiping
+    /// them.
+    fn scalar_as_bytes(&self, scalar: &DhScalar) -> Vec<u8> {
+        enum_variant!(scalar, DhScalar::P256Scalar).to_vec()
+    }
+
+    /// Generates a random scalar value. This samples 32-byte strings until one of them is a valid
+    /// scalar.
+    ///
+    /// Returns: `Ok(scalar)` on success. Otherwise, if something goes wrong with the RNG, it
+    /// returns `Error::OutOfEntropy`.
+    fn scalar_from_random(&self, csprng: &mut dyn CryptoRng) -> Result<DhScalar, Error> {
+        let mut buf = [0u8; P256_SCALAR_SIZE];
+        loop {
+            csprng
+                .try_fill_bytes(&mut buf)
+                .map_err(|_| Error::OutOfEntropy)?;
+            if let Ok(scalar) = self.scalar_from_bytes(&buf) {
+                buf.zeroize();
+                return Ok(scalar);
+            }
+        }
+    }
+
+    /// Calculates `scalar * G`, where `G` is the standard P-256 generator. The resulting point is
+    /// uncompressed.
+    fn multiply_basepoint(&self, scalar: &DhScalar) -> DhPoint {
+        let scalar = P256::nonzero_scalar(scalar);
+        let point = p256::PublicKey::from_secret_scalar(&scalar);
+
+        self.point_from_bytes(point.to_encoded_point(false).as_bytes().to_vec())
+    }
+
+    /// Computes `privkey * Pubkey` where `privkey` is your local secret (a scalar) and `Pubkey` is
+    /// someone's public key (a curve point). As is standard for ECDH, the shared secret is the
+    /// x-coordinate of the resulting point.
+    ///
+    /// Returns: `Ok(shared_secret)` on success. If `Pubkey` is the wrong size, returns
+    /// `Error::BadPointLength`. If it's not an uncompressed encoding of a point on the curve,
+    /// returns `Error::DhError`. If the shared secret is all zeros, returns
+    /// `Error::ZeroSharedSecret`.
+    fn diffie_hellman(&self, privkey: &DhScalar, pubkey: &DhPoint) -> Result<DhPoint, Error> {
+        self.validate_point(pubkey)?;
+        // This checks that the point is on the curve. Since P-256 has prime order, this is enough
+        // to guarantee that the shared secret isn't the point at infinity.
+        let pubkey = p256::PublicKey::from_sec1_bytes(&pubkey.0)
+            .map_err(|_| Error::DhError("Invalid public key"))?;
+        let privkey = P256::nonzero_scalar(privkey);
+
+        let shared_secret = p256::ecdh::diffie_hellman(privkey, pubkey.as_affine());
+        let shared_secret_bytes = shared_secret.raw_secret_bytes();
+
+        // A shared point with x-coordinate 0 can't be forced by the other party the way a
+        // low-order X25519 point can, but it's no less predictable, so reject it all the same
+        if is_all_zero(shared_secret_bytes) {
+            Err(Error::ZeroSharedSecret)
+        } else {
+            Ok(DhPoint(shared_secret_bytes.to_vec()))
+        }
     }
 }
 
@@ -162,17 +373,17 @@ mod test {
         let bob_pubkey = X25519_IMPL.multiply_basepoint(&bob_scalar);
 
         // Compute b(aP) and a(bP) and make sure they are the same
-        let shared_secret_a = {
-            let point = X25519_IMPL.diffie_hellman(&alice_scalar, &bob_pubkey);
-            enum_variant!(point, DhPoint::X25519Point)
-        };
-        let shared_secret_b = {
-            let point = X25519_IMPL.diffie_hellman(&bob_scalar, &alice_pubkey);
-            enum_variant!(point, DhPoint::X25519Point)
-        };
-
-        let alice_pubkey = enum_variant!(alice_pubkey, DhPoint::X25519Point);
-        let bob_pubkey = enum_variant!(bob_pubkey, DhPoint::X25519Point);
+        let shared_secret_a = X25519_IMPL
+            .diffie_hellman(&alice_scalar, &bob_pubkey)
+            .unwrap()
+            .0;
+        let shared_secret_b = X25519_IMPL
+            .diffie_hellman(&bob_scalar, &alice_pubkey)
+            .unwrap()
+            .0;
+
+        let alice_pubkey = alice_pubkey.0;
+        let bob_pubkey = bob_pubkey.0;
 
         // Known-answer for aP
         assert_eq!(
@@ -193,6 +404,52 @@ mod test {
         );
     }
 
+    // Scalar multiplication test vectors from https://tools.ietf.org/html/rfc7748#section-5.2
+    #[test]
+    fn x25519_scalar_mult_kat() {
+        let scalar_point_output_triples = [
+            (
+                "a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4",
+                "e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c",
+                "c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552",
+            ),
+            (
+                "4b66e9d4d1b4673c5ad22691957d6af5c11b6421e0ea01d42ca4169e7918ba0d",
+                "e5210f12786811d3f4b7959d0538ae2c31dbe7106fc03c3efc4cd549c715a493",
+                "95cbde9476e8907d7aade45cb4b873f88b595a68799fa152e6f8f7647aac7957",
+            ),
+        ];
+
+        for (scalar_hex, point_hex, output_hex) in scalar_point_output_triples.iter() {
+            let scalar = X25519_IMPL
+                .scalar_from_bytes(&hex::decode(scalar_hex).unwrap())
+                .unwrap();
+            let point = X25519_IMPL.point_from_bytes(hex::decode(point_hex).unwrap());
+
+            let output = X25519_IMPL.diffie_hellman(&scalar, &point).unwrap();
+            assert_eq!(hex::encode(output.0), *output_hex);
+        }
+    }
+
+    // Checks that DH with a low-order point is rejected, since the result would be all zeros
+    #[quickcheck]
+    fn x25519_zero_check(secret_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(secret_seed);
+        let scalar = X25519_IMPL.scalar_from_random(&mut rng).unwrap();
+
+        // The points with u-coordinate 0 and 1 both have small order
+        for low_order_u in &[0u8, 1u8] {
+            let mut point_bytes = vec![0u8; X25519_POINT_SIZE];
+            point_bytes[0] = *low_order_u;
+            let point = X25519_IMPL.point_from_bytes(point_bytes);
+
+            match X25519_IMPL.diffie_hellman(&scalar, &point) {
+                Err(Error::ZeroSharedSecret) => (),
+                _ => panic!("expected a zero shared secret error"),
+            }
+        }
+    }
+
     #[quickcheck]
     fn x25519_correctness(secret_seed: u64) {
         // Make a secret key seeded with the above seed. This is so that this function is
@@ -214,14 +471,11 @@ mod test {
             X25519_IMPL.multiply_basepoint(&scalar2),
         );
         let (shared1, shared2) = (
-            X25519_IMPL.diffie_hellman(&scalar1, &point2),
-            X25519_IMPL.diffie_hellman(&scalar2, &point1),
+            X25519_IMPL.diffie_hellman(&scalar1, &point2).unwrap(),
+            X25519_IMPL.diffie_hellman(&scalar2, &point1).unwrap(),
         );
 
-        let shared1 = enum_variant!(shared1, DhPoint::X25519Point);
-        let shared2 = enum_variant!(shared2, DhPoint::X25519Point);
-
-        assert_eq!(shared1, shared2)
+        assert_eq!(shared1.0, shared2.0)
     }
 
     // This comes from
@@ -236,12 +490,153 @@ mod test {
                 .expect("couldn't make scalar from bytes")
         };
 
-        let pubkey = X25519_IMPL.multiply_basepoint(&scalar);
-        let pubkey = enum_variant!(pubkey, DhPoint::X25519Point);
+        let pubkey = X25519_IMPL.multiply_basepoint(&scalar).0;
 
         assert_eq!(
             hex::encode(&pubkey),
             "6667b1715a0ad45b0510e850322a8d471d4485ebcbfcc0f3bcce7bcae7b44f7f"
         );
     }
+
+    // There are no test vectors for P-256 with our exact encoding, so these were computed
+    // independently with the Python cryptography library. Alice's scalar is the private key from
+    // RFC 6979 appendix A.2.5.
+    #[test]
+    fn p256_kat() {
+        let alice_scalar = {
+            let hex_str = "c9afa9d845ba75166b5c215767b1d6934e50c3db36e89b127b8a622b120f6721";
+            let bytes = hex::decode(hex_str).unwrap();
+            P256_IMPL.scalar_from_bytes(&bytes).unwrap()
+        };
+        let bob_scalar = {
+            let hex_str = "7d7dc5f71eb29ddaf80d6214632eeae03d9058af1fb6d22ed80badb62bc1a534";
+            let bytes = hex::decode(hex_str).unwrap();
+            P256_IMPL.scalar_from_bytes(&bytes).unwrap()
+        };
+
+        let alice_pubkey = P256_IMPL.multiply_basepoint(&alice_scalar);
+        let bob_pubkey = P256_IMPL.multiply_basepoint(&bob_scalar);
+
+        let shared_secret_a = P256_IMPL
+            .diffie_hellman(&alice_scalar, &bob_pubkey)
+            .unwrap()
+            .0;
+        let shared_secret_b = P256_IMPL
+            .diffie_hellman(&bob_scalar, &alice_pubkey)
+            .unwrap()
+            .0;
+
+        // Known-answers for aG and bG, in uncompressed form
+        assert_eq!(
+            hex::encode(&alice_pubkey.0),
+            "0460fed4ba255a9d31c961eb74c6356d68c049b8923b61fa6ce669622e60f29fb67903fe1008b8bc99a41a\
+             e9e95628bc64f2f1b20c2d7e9f5177a3c294d4462299"
+        );
+        assert_eq!(
+            hex::encode(&bob_pubkey.0),
+            "04ead218590119e8876b29146ff89ca61770c4edbbf97d38ce385ed281d8a6b23028af61281fd35e2fa700\
+             2523acc85a429cb06ee6648325389f59edfce1405141"
+        );
+
+        // Known-answer for the x-coordinate of abG
+        let expected = "61e109425a7adbb9d0137091cff10a55550b708d14ad0137b80fa0ec1328394f";
+        assert_eq!(hex::encode(&shared_secret_a), expected);
+        assert_eq!(hex::encode(&shared_secret_b), expected);
+    }
+
+    // Checks that we reject the zero scalar, unreduced scalars, and compressed or off-curve points
+    #[test]
+    fn p256_input_validation() {
+        // Zero isn't a valid scalar, and neither is the group order n
+        let n = "ffffffff00000000ffffffffffffffffbce6faada7179e84f3b9cac2fc632551";
+        assert!(P256_IMPL.scalar_from_bytes(&[0u8; 32]).is_err());
+        assert!(P256_IMPL
+            .scalar_from_bytes(&hex::decode(n).unwrap())
+            .is_err());
+
+        let scalar = P256_IMPL.scalar_from_bytes(&[1u8; 32]).unwrap();
+        let mut point_bytes = P256_IMPL.multiply_basepoint(&scalar).0;
+
+        // Compressed points are the wrong size
+        let compressed = DhPoint(point_bytes[..33].to_vec());
+        match P256_IMPL.diffie_hellman(&scalar, &compressed) {
+            Err(Error::BadPointLength { .. }) => (),
+            _ => panic!("expected a bad point length for a compressed point"),
+        }
+
+        // Flipping a bit of the y-coordinate takes the point off the curve
+        point_bytes[64] ^= 1;
+        assert!(P256_IMPL
+            .diffie_hellman(&scalar, &DhPoint(point_bytes))
+            .is_err());
+    }
+
+    // Checks that points of the wrong size are rejected by both schemes, and that points of the
+    // right size are accepted
+    #[test]
+    fn point_size_validation() {
+        let schemes: [(&dyn DiffieHellman, usize); 2] = [
+            (&X25519_IMPL, X25519_POINT_SIZE),
+            (&P256_IMPL, P256_POINT_SIZE),
+        ];
+        for (scheme, point_size) in schemes.iter() {
+            assert_eq!(scheme.point_size(), *point_size);
+
+            for &bad_size in &[0, 1, point_size - 1, point_size + 1] {
+                match scheme.try_point_from_bytes(&vec![0x04; bad_size]) {
+                    Err(Error::BadPointLength { expected, got }) => {
+                        assert_eq!(expected, *point_size);
+                        assert_eq!(got, bad_size);
+                    }
+                    _ => panic!(
+                        "expected a bad point length for a point of size {}",
+                        bad_size
+                    ),
+                }
+            }
+
+            let point = scheme
+                .try_point_from_bytes(&vec![0x04; *point_size])
+                .unwrap();
+            assert!(scheme.validate_point(&point).is_ok());
+        }
+
+        // An X25519 point isn't a P-256 point, and vice versa
+        let x25519_point = X25519_IMPL.point_from_bytes(vec![0x09; X25519_POINT_SIZE]);
+        assert!(P256_IMPL.validate_point(&x25519_point).is_err());
+        let p256_point = P256_IMPL.point_from_bytes(vec![0x04; P256_POINT_SIZE]);
+        assert!(X25519_IMPL.validate_point(&p256_point).is_err());
+    }
+
+    #[quickcheck]
+    fn p256_correctness(secret_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(secret_seed);
+        let (point1, scalar1) = P256_IMPL.generate_keypair(&mut rng).unwrap();
+        let (point2, scalar2) = P256_IMPL.generate_keypair(&mut rng).unwrap();
+
+        let (shared1, shared2) = (
+            P256_IMPL.diffie_hellman(&scalar1, &point2).unwrap(),
+            P256_IMPL.diffie_hellman(&scalar2, &point1).unwrap(),
+        );
+
+        assert_eq!(shared1.0, shared2.0)
+    }
+
+    // Checks that zeroizing a scalar wipes its bytes, and that a clone has its own bytes to wipe.
+    // Drop does nothing but call zeroize, so this is what happens to a scalar when it's dropped.
+    #[test]
+    fn scalar_zeroize() {
+        let mut scalar = X25519_IMPL
+            .scalar_from_bytes(&[0xab; X25519_SCALAR_SIZE])
+            .unwrap();
+        let copy = scalar.clone();
+
+        scalar.zeroize();
+        let bytes = enum_variant!(&scalar, DhScalar::X25519Scalar);
+        assert_eq!(bytes, &[0u8; X25519_SCALAR_SIZE]);
+
+        // The clone is untouched
+        let bytes = enum_variant!(&copy, DhScalar::X25519Scalar);
+        assert_eq!(bytes, &[0xab; X25519_SCALAR_SIZE]);
+    }
 }
@@ -4,7 +4,9 @@ use crate::crypto::{
     dh::{DhPoint, DhScalar},
     rng::CryptoRng,
 };
-use crate::error::Error;
+use crate::error::{EciesError, Error};
+
+use zeroize::Zeroize;
 
 /// A label struct used for ECIES key/nonce derivation
 #[derive(Serialize, Deserialize)]
@@ -25,7 +27,7 @@ impl EciesLabel {
 }
 
 /// A short ciphertext encrypted with the enclosed ephemeral DH key
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 pub(crate) struct EciesCiphertext {
     /// Pubkey the ciphertext is encrypted under
     ephemeral_public_key: DhPoint,
@@ -37,29 +39,51 @@ pub(crate) struct EciesCiphertext {
 
 /// Performs an ECIES encryption of a given plaintext under a given DH public key.
 ///
-/// Returns: `Ok(ciphertext)` on success. If there is an issue with random nonce generation or
-/// sealing the plaintext, an `Error` is returned.
-fn ecies_encrypt(
+/// Returns: `Ok(ciphertext)` on success. If `others_public_key` is malformed, returns an
+/// `Error::DhError`. If it makes the shared secret zero (e.g., it's a low-order point), returns an
+/// `Error::ZeroSharedSecret`. If there is an issue with ephemeral key generation or sealing the
+/// plaintext, an `Error` is returned.
+pub(crate) fn ecies_encrypt(
     cs: &CipherSuite,
     others_public_key: &DhPoint,
-    mut plaintext: Vec<u8>,
+    plaintext: Vec<u8>,
     csprng: &mut dyn CryptoRng,
+) -> Result<EciesCiphertext, Error> {
+    // Denote this by `a`
+    let my_ephemeral_secret = cs.dh_impl.scalar_from_random(csprng)?;
+    ecies_encrypt_with_ephemeral(cs, others_public_key, plaintext, &my_ephemeral_secret)
+}
+
+/// Performs an ECIES encryption of a given plaintext under a given DH public key, using the given
+/// secret as the ephemeral key. This is the deterministic core of `ecies_encrypt`. It only exists
+/// outside of it so that ciphertexts can be compared byte-for-byte against test vectors. Never
+/// reuse an ephemeral secret; the AEAD nonce is derived from it.
+///
+/// Returns: `Ok(ciphertext)` on success. If `others_public_key` is malformed, returns an
+/// `Error::DhError`. If it makes the shared secret zero (e.g., it's a low-order point), returns an
+/// `Error::ZeroSharedSecret`. If there is an issue sealing the plaintext, an `Error` is returned.
+pub(crate) fn ecies_encrypt_with_ephemeral(
+    cs: &CipherSuite,
+    others_public_key: &DhPoint,
+    mut plaintext: Vec<u8>,
+    my_ephemeral_secret: &DhScalar,
 ) -> Result<EciesCiphertext, Error> {
     // Make room for the tag
     plaintext.extend(std::iter::repeat(0u8).take(cs.aead_impl.tag_size()));
 
-    // Denote this by `a`
-    let my_ephemeral_secret = cs.dh_impl.scalar_from_random(csprng)?;
     // Denote this by `aP`
-    let my_ephemeral_public_key = cs.dh_impl.multiply_basepoint(&my_ephemeral_secret);
+    let my_ephemeral_public_key = cs.dh_impl.multiply_basepoint(my_ephemeral_secret);
 
     // This is `abP` where `bP` is the other person's public key is `bP`
     let shared_secret = cs
         .dh_impl
-        .diffie_hellman(&my_ephemeral_secret, &others_public_key);
-    let shared_secret_bytes = cs.dh_impl.point_as_bytes(shared_secret);
+        .diffie_hellman(my_ephemeral_secret, &others_public_key)?;
+    let mut shared_secret_bytes = cs.dh_impl.point_as_bytes(shared_secret);
 
+    // The nonce isn't random. It's derived alongside the key from the shared secret, which is
+    // fresh for every encryption since the ephemeral secret is. So every key is used exactly once.
     let (key, nonce) = derive_ecies_key_nonce(cs, &shared_secret_bytes);
+    shared_secret_bytes.zeroize();
 
     cs.aead_impl.seal(&key, nonce, plaintext.as_mut_slice())?;
     // Rename for clarity
@@ -72,39 +96,58 @@ fn ecies_encrypt(
     Ok(ret)
 }
 
-/// Performs an ECIES decryption of a given ciphertext under a given DH ephemeral public key and
-/// known secret
-///
-/// Returns: `Ok(plaintext)` on success. Returns an `Error::EncryptionError` if something goes
-/// wrong.
-fn ecies_decrypt(
-    cs: &CipherSuite,
-    my_secret_key: &DhScalar,
-    EciesCiphertext {
-        ephemeral_public_key,
-        mut ciphertext,
-    }: EciesCiphertext,
-) -> Result<Vec<u8>, Error> {
-    // This is `abP` where `bP` is the other person's public key is `bP` and my secret key is `a`
-    let shared_secret = cs
-        .dh_impl
-        .diffie_hellman(&my_secret_key, &ephemeral_public_key);
-    let shared_secret_bytes = cs.dh_impl.point_as_bytes(shared_secret);
+impl EciesCiphertext {
+    /// Performs an ECIES decryption of the given ciphertext, using the given secret and the
+    /// ephemeral public key the ciphertext carries
+    ///
+    /// Returns: `Ok(plaintext)` on success. If the ciphertext fails authentication, returns an
+    /// `EciesError::TagMismatch`. If the ciphertext is malformed, returns an
+    /// `EciesError::MalformedInput` holding what's wrong with it: an `Error::ValidationError` if
+    /// it's too short to contain an authentication tag, an `Error::BadPointLength` or
+    /// `Error::DhError` if the ephemeral public key is malformed, or an `Error::ZeroSharedSecret`
+    /// if the ephemeral public key makes the shared secret zero.
+    pub(crate) fn decrypt(
+        cs: &CipherSuite,
+        my_secret_key: &DhScalar,
+        ciphertext: EciesCiphertext,
+    ) -> Result<Vec<u8>, EciesError> {
+        let EciesCiphertext {
+            ephemeral_public_key,
+            mut ciphertext,
+        } = ciphertext;
 
-    // Derive the key and nonce, then open the ciphertext. The length of the subslice it gives is
-    // the length we'll truncate the plaintext to. Recall this happens because there was a MAC at
-    // the end of the ciphertext.
-    let (key, nonce) = derive_ecies_key_nonce(cs, &shared_secret_bytes);
-    let plaintext_len = cs
-        .aead_impl
-        .open(&key, nonce, ciphertext.as_mut_slice())?
-        .len();
+        // There's no way this came from ecies_encrypt if there's no room for the tag
+        if ciphertext.len() < cs.aead_impl.tag_size() {
+            return Err(EciesError::MalformedInput(Error::ValidationError(
+                "ECIES ciphertext is too short",
+            )));
+        }
 
-    // Rename for clarity
-    let mut plaintext = ciphertext;
+        // This is `abP` where `bP` is the other person's public key is `bP` and my secret key is
+        // `a`
+        let shared_secret = cs
+            .dh_impl
+            .diffie_hellman(&my_secret_key, &ephemeral_public_key)
+            .map_err(EciesError::MalformedInput)?;
+        let mut shared_secret_bytes = cs.dh_impl.point_as_bytes(shared_secret);
+
+        // Derive the key and nonce, then open the ciphertext. The length of the subslice it gives
+        // is the length we'll truncate the plaintext to. Recall this happens because there was a
+        // MAC at the end of the ciphertext.
+        let (key, nonce) = derive_ecies_key_nonce(cs, &shared_secret_bytes);
+        shared_secret_bytes.zeroize();
+        let plaintext_len = cs
+            .aead_impl
+            .open(&key, nonce, ciphertext.as_mut_slice())
+            .map_err(|_| EciesError::TagMismatch)?
+            .len();
+
+        // Rename for clarity
+        let mut plaintext = ciphertext;
 
-    plaintext.truncate(plaintext_len);
-    Ok(plaintext)
+        plaintext.truncate(plaintext_len);
+        Ok(plaintext)
+    }
 }
 
 // From the spec:
@@ -147,18 +190,30 @@ fn derive_ecies_key_nonce(cs: &CipherSuite, shared_secret_bytes: &[u8]) -> (Aead
         .nonce_from_bytes(&nonce_buf)
         .expect("couldn't derive AEAD nonce from HKDF");
 
+    // The key and nonce have their own copies of these now
+    key_buf.zeroize();
+    nonce_buf.zeroize();
+
     (key, nonce)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::crypto::ciphersuite::X25519_SHA256_AES128GCM;
+    use crate::crypto::ciphersuite::{
+        P256_SHA256_AES128GCM, X25519_SHA256_AES128GCM, X25519_SHA256_CHACHA20POLY1305,
+    };
+    use crate::{tls_de::TlsDeserializer, tls_ser};
 
     use quickcheck_macros::quickcheck;
     use rand::SeedableRng;
+    use serde::de::Deserialize;
 
-    const CIPHERSUITES: &[CipherSuite] = &[X25519_SHA256_AES128GCM];
+    const CIPHERSUITES: &[CipherSuite] = &[
+        X25519_SHA256_AES128GCM,
+        P256_SHA256_AES128GCM,
+        X25519_SHA256_CHACHA20POLY1305,
+    ];
 
     // Checks that decrypt(encrypt_k(m)) == m
     #[quickcheck]
@@ -178,7 +233,7 @@ mod test {
                 ));
 
             // Now let Alice decrypt it
-            let recovered_plaintext = ecies_decrypt(cs, &alice_scalar, ecies_ciphertext)
+            let recovered_plaintext = EciesCiphertext::decrypt(cs, &alice_scalar, ecies_ciphertext)
                 .expect(&format!(
                     "failed to decrypt ECIES ciphertext; ciphersuite {}",
                     cs.name
@@ -188,4 +243,151 @@ mod test {
             assert_eq!(recovered_plaintext, plaintext);
         }
     }
+
+    // Checks that the ephemeral key is the only randomness in an encryption, and that it comes from
+    // the given RNG. Encrypting the same thing twice from the same seed gives the same ciphertext.
+    #[quickcheck]
+    fn ecies_deterministic(plaintext: Vec<u8>, rng_seed: u64) {
+        for cs in CIPHERSUITES {
+            let encrypt = || {
+                let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+                let alice_scalar = cs.dh_impl.scalar_from_random(&mut rng).unwrap();
+                let alice_point = cs.dh_impl.multiply_basepoint(&alice_scalar);
+                let ciphertext = ecies_encrypt(cs, &alice_point, plaintext.clone(), &mut rng)
+                    .expect("failed to encrypt ECIES plaintext");
+                tls_ser::serialize_to_bytes(&ciphertext).unwrap()
+            };
+
+            assert_eq!(encrypt(), encrypt());
+        }
+    }
+
+    // A known-answer test for ECIES in X25519_SHA256_AES128GCM. The keys are the Alice and Bob keys
+    // from https://tools.ietf.org/html/rfc7748#section-6.1, with Alice's secret as the ephemeral
+    // key. There are no published MLS ECIES vectors at this draft, so the ciphertext was computed
+    // independently with Python's cryptography package: X25519 for the shared secret, HKDFExpand
+    // with SHA256 over the serialized ECIESLabels for the key and nonce, and AESGCM with no AAD to
+    // seal. The intermediate values are
+    //     shared secret = 4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742
+    //     key           = 445c37d58fb87f67fe0d5486b0643ef5
+    //     nonce         = 0b17400f108ef76523204798
+    // where the shared secret is the one given in the RFC.
+    #[test]
+    fn ecies_x25519_kat() {
+        let cs = &X25519_SHA256_AES128GCM;
+        let scalar_from_hex = |hex_str| {
+            let bytes = hex::decode(hex_str).unwrap();
+            cs.dh_impl.scalar_from_bytes(&bytes).unwrap()
+        };
+        let ephemeral_scalar =
+            scalar_from_hex("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a");
+        let bob_scalar =
+            scalar_from_hex("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb");
+        let bob_point = cs.dh_impl.multiply_basepoint(&bob_scalar);
+
+        // Sanity check: the DH step agrees with the RFC
+        let shared_secret = cs
+            .dh_impl
+            .diffie_hellman(&ephemeral_scalar, &bob_point)
+            .unwrap();
+        assert_eq!(
+            hex::encode(cs.dh_impl.point_as_bytes(shared_secret)),
+            "4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742"
+        );
+
+        let plaintext = b"mls10 ecies test vector".to_vec();
+        let expected_ciphertext = concat!(
+            // ephemeral_public_key
+            "0020",
+            "8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a",
+            // ciphertext, with the tag at the end
+            "27",
+            "f1549cea425109e804244f94f46c0f1fdcfbd4b35a499ca3",
+            "6464bff81506da6e405a131ffa62a7",
+        );
+
+        // Encrypting with the given ephemeral key gives exactly the vector
+        let ciphertext =
+            ecies_encrypt_with_ephemeral(cs, &bob_point, plaintext.clone(), &ephemeral_scalar)
+                .unwrap();
+        assert_eq!(
+            hex::encode(
+                cs.dh_impl
+                    .point_as_bytes(ciphertext.ephemeral_public_key.clone())
+            ),
+            "8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a"
+        );
+        let ciphertext_bytes = tls_ser::serialize_to_bytes(&ciphertext).unwrap();
+        assert_eq!(hex::encode(&ciphertext_bytes), expected_ciphertext);
+
+        // And Bob can decrypt the vector with nothing but his secret key
+        let ciphertext = {
+            let bytes = hex::decode(expected_ciphertext).unwrap();
+            let mut buf = bytes.as_slice();
+            let mut deserializer = TlsDeserializer::from_reader(&mut buf);
+            EciesCiphertext::deserialize(&mut deserializer).unwrap()
+        };
+        assert_eq!(
+            EciesCiphertext::decrypt(cs, &bob_scalar, ciphertext).unwrap(),
+            plaintext
+        );
+    }
+
+    // Checks that tampering with an ECIES ciphertext makes decryption fail, and that truncating it
+    // fails in a different way
+    #[quickcheck]
+    fn ecies_tamper(plaintext: Vec<u8>, flip_idx: usize, rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        for cs in CIPHERSUITES {
+            let alice_scalar = cs.dh_impl.scalar_from_random(&mut rng).unwrap();
+            let alice_point = cs.dh_impl.multiply_basepoint(&alice_scalar);
+            let ecies_ciphertext =
+                ecies_encrypt(cs, &alice_point, plaintext.clone(), &mut rng).unwrap();
+
+            // Flip a bit somewhere in the ciphertext or tag
+            let mut flipped = ecies_ciphertext.clone();
+            let idx = flip_idx % flipped.ciphertext.len();
+            flipped.ciphertext[idx] ^= 0x01;
+            match EciesCiphertext::decrypt(cs, &alice_scalar, flipped) {
+                Err(EciesError::TagMismatch) => (),
+                _ => panic!("expected a tag mismatch"),
+            }
+
+            // Chop off part of the tag
+            let mut truncated = ecies_ciphertext;
+            truncated.ciphertext.truncate(cs.aead_impl.tag_size() - 1);
+            match EciesCiphertext::decrypt(cs, &alice_scalar, truncated) {
+                Err(EciesError::MalformedInput(Error::ValidationError(_))) => (),
+                _ => panic!("expected malformed input"),
+            }
+        }
+    }
+
+    // Checks that a low-order X25519 public key is rejected on both ends. Without this, a malicious
+    // member could publish such a key and make every path secret encrypted to it predictable.
+    #[quickcheck]
+    fn ecies_low_order_rejection(plaintext: Vec<u8>, rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        // The point with u-coordinate 0 has small order, so any multiple of it is zero
+        let low_order_point = cs.dh_impl.point_from_bytes(vec![0u8; 32]);
+
+        match ecies_encrypt(cs, &low_order_point, plaintext.clone(), &mut rng) {
+            Err(Error::ZeroSharedSecret) => (),
+            _ => panic!("expected a zero shared secret error when encrypting"),
+        }
+
+        // Now make a legit ciphertext and swap its ephemeral key out for the low-order point
+        let alice_scalar = cs.dh_impl.scalar_from_random(&mut rng).unwrap();
+        let alice_point = cs.dh_impl.multiply_basepoint(&alice_scalar);
+        let mut ecies_ciphertext = ecies_encrypt(cs, &alice_point, plaintext, &mut rng).unwrap();
+        ecies_ciphertext.ephemeral_public_key = low_order_point;
+
+        match EciesCiphertext::decrypt(cs, &alice_scalar, ecies_ciphertext) {
+            Err(EciesError::MalformedInput(Error::ZeroSharedSecret)) => (),
+            _ => panic!("expected a zero shared secret error when decrypting"),
+        }
+    }
 }
@@ -1,33 +1,95 @@
 use crate::crypto::rng::CryptoRng;
 use crate::error::Error;
 
-pub(crate) const ED25519_IMPL: SignatureScheme = SignatureScheme { name: "ED25519" };
+use p256::ecdsa::signature::{Signer, Verifier};
+
+/// A singleton object representing the Ed25519 signature scheme
+pub(crate) const ED25519_IMPL: Ed25519 = Ed25519;
+
+/// A singleton object representing the ECDSA signature scheme over P-256 with SHA-256
+pub(crate) const ECDSA_P256_IMPL: EcdsaP256 = EcdsaP256;
+
+const ED25519_SECRET_KEY_SIZE: usize = 32;
+const ECDSA_P256_SECRET_KEY_SIZE: usize = 32;
 
 /// An enum of possible types for a signature scheme's public key, depending on the underlying
 /// algorithm
+#[derive(Clone)]
 pub(crate) enum SigPublicKey {
     Ed25519PublicKey(ed25519_dalek::PublicKey),
+    EcdsaP256PublicKey(p256::ecdsa::VerifyingKey),
 }
 /// An enum of possible types for a signature scheme's secret key, depending on the underlying
 /// algorithm
 pub(crate) enum SigSecretKey {
     Ed25519SecretKey(ed25519_dalek::SecretKey),
+    EcdsaP256SecretKey(p256::ecdsa::SigningKey),
 }
 
-/// An enum of possible types for a signature scheme's signature, depending on the underlying
-/// algorithm
-pub(crate) enum Signature {
-    Ed25519Signature(ed25519_dalek::Signature),
+// ed25519_dalek's secret keys aren't Clone, so we go through their bytes
+impl Clone for SigSecretKey {
+    fn clone(&self) -> SigSecretKey {
+        match self {
+            SigSecretKey::Ed25519SecretKey(secret) => {
+                let copy = ed25519_dalek::SecretKey::from_bytes(secret.as_bytes())
+                    .expect("an Ed25519 secret key's bytes are always a valid secret key");
+                SigSecretKey::Ed25519SecretKey(copy)
+            }
+            SigSecretKey::EcdsaP256SecretKey(secret) => {
+                SigSecretKey::EcdsaP256SecretKey(secret.clone())
+            }
+        }
+    }
 }
 
-/// Represents the contents of an MLS signature scheme. Currently, this only implements Ed25519.
-pub(crate) struct SignatureScheme {
-    pub(crate) name: &'static str,
+// opaque signature<0..2^16-1>
+/// Like `DhPoint`, signatures are untagged on the wire, so we can't tell which algorithm made one
+/// until we try to verify it. So this is just the signature's byte representation. It's up to the
+/// `SignatureScheme` to make sense of it.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename = "Signature__bound_u16")]
+pub(crate) struct Signature(Vec<u8>);
+
+/// A trait representing an MLS signature scheme. Implementors are stateless singletons, so they
+/// can be shared across threads.
+pub(crate) trait SignatureScheme: Sync {
+    /// Returns the name of this signature scheme
+    fn name(&self) -> &'static str;
+
+    fn public_key_from_bytes(&self, bytes: &[u8]) -> Result<SigPublicKey, Error>;
+
+    fn public_key_to_bytes(&self, public_key: &SigPublicKey) -> Vec<u8>;
+
+    fn secret_key_from_bytes(&self, bytes: &[u8]) -> Result<SigSecretKey, Error>;
+
+    fn secret_key_to_bytes(&self, secret: &SigSecretKey) -> Vec<u8>;
+
+    // This has to take a dyn CryptoRng because SignatureScheme is itself a trait object inside a
+    // CipherSuite. See the note in DiffieHellman.
+    fn secret_key_from_random(&self, csprng: &mut dyn CryptoRng) -> Result<SigSecretKey, Error>;
+
+    fn public_key_from_secret_key(&self, secret: &SigSecretKey) -> SigPublicKey;
+
+    fn signature_from_bytes(&self, bytes: &[u8]) -> Result<Signature, Error>;
+
+    /// Returns the byte representation of this signature
+    fn signature_to_bytes(&self, signature: &Signature) -> Vec<u8> {
+        signature.0.clone()
+    }
+
+    fn sign(&self, secret: &SigSecretKey, msg: &[u8]) -> Signature;
+
+    fn verify(&self, public_key: &SigPublicKey, msg: &[u8], sig: &Signature) -> Result<(), Error>;
 }
 
-// This implementation is for Ed25519 only, currently. In the future, we should wrap Ed25519 with
-// a trait, and use the same trait for other signature implementations
-impl SignatureScheme {
+/// This represents the Ed25519 signature scheme. Notably, it implements `SignatureScheme`.
+pub(crate) struct Ed25519;
+
+impl SignatureScheme for Ed25519 {
+    fn name(&self) -> &'static str {
+        "ED25519"
+    }
+
     /// Creates a public key from the provided bytes
     ///
     /// Returns: `Ok(public_key)` iff no error occured. Otherwise, returns an
@@ -39,6 +101,12 @@ impl SignatureScheme {
         }
     }
 
+    /// Returns the byte representation of the given public key
+    fn public_key_to_bytes(&self, public_key: &SigPublicKey) -> Vec<u8> {
+        let public_key = enum_variant!(public_key, SigPublicKey::Ed25519PublicKey);
+        public_key.to_bytes().to_vec()
+    }
+
     /// Creates a key pair from the provided secret key bytes. This expects 32 bytes.
     fn secret_key_from_bytes(&self, bytes: &[u8]) -> Result<SigSecretKey, Error> {
         match ed25519_dalek::SecretKey::from_bytes(bytes) {
@@ -47,12 +115,18 @@ impl SignatureScheme {
         }
     }
 
+    /// Returns the 32 bytes of the given secret key. The caller is responsible for wiping them.
+    fn secret_key_to_bytes(&self, secret: &SigSecretKey) -> Vec<u8> {
+        let secret = enum_variant!(secret, SigSecretKey::Ed25519SecretKey);
+        secret.to_bytes().to_vec()
+    }
+
     /// Generates a random key pair using the given CSPRNG
     ///
     /// Returns: `Ok(secret_key)` on success. On error, returns `Error::SignatureErrror` or
     /// `Error::OutOfEntropy`.
     fn secret_key_from_random(&self, csprng: &mut dyn CryptoRng) -> Result<SigSecretKey, Error> {
-        let mut key_bytes = [0u8; 32];
+        let mut key_bytes = [0u8; ED25519_SECRET_KEY_SIZE];
         csprng
             .try_fill_bytes(&mut key_bytes)
             .map_err(|_| Error::OutOfEntropy)?;
@@ -68,37 +142,144 @@ impl SignatureScheme {
         SigPublicKey::Ed25519PublicKey(secret.into())
     }
 
-    /// Returns the byte representation of this signature
-    pub(crate) fn signature_to_bytes(&self, signature: &Signature) -> Vec<u8> {
-        let signature = enum_variant!(signature, Signature::Ed25519Signature);
-        signature.to_bytes().to_vec()
+    /// Creates a signature from the provided bytes
+    ///
+    /// Returns: `Ok(signature)` iff no error occured. Otherwise, returns an
+    /// `Err(Error::SignatureError)`.
+    fn signature_from_bytes(&self, bytes: &[u8]) -> Result<Signature, Error> {
+        match ed25519_dalek::Signature::from_bytes(bytes) {
+            Ok(_) => Ok(Signature(bytes.to_vec())),
+            Err(_) => Err(Error::SignatureError("Invalid signature")),
+        }
     }
 
     /// Computes a signature of the given message under the given secret key
-    pub(crate) fn sign(&self, secret: &SigSecretKey, msg: &[u8]) -> Signature {
+    fn sign(&self, secret: &SigSecretKey, msg: &[u8]) -> Signature {
         // For simplicity, we add the overhead of recomputing the public key on every signature
         // operation instead of having it passed into the function. Sue me.
-        let public = ED25519_IMPL.public_key_from_secret_key(secret);
+        let public = self.public_key_from_secret_key(secret);
 
         let secret = enum_variant!(secret, SigSecretKey::Ed25519SecretKey);
         let public = enum_variant!(public, SigPublicKey::Ed25519PublicKey);
 
         let expanded: ed25519_dalek::ExpandedSecretKey = secret.into();
 
-        Signature::Ed25519Signature(expanded.sign(&msg, &public))
+        Signature(expanded.sign(&msg, &public).to_bytes().to_vec())
     }
 
     /// Verifies the signature of the given message under the given public key
     ///
     /// Returns: `Ok(())` iff the signature succeeded. Otherwise, returns an
     /// `Err(Error::SignatureError)` which is a lot of errors, so you know it's bad.
-    #[must_use]
     fn verify(&self, public_key: &SigPublicKey, msg: &[u8], sig: &Signature) -> Result<(), Error> {
         let public_key = enum_variant!(public_key, SigPublicKey::Ed25519PublicKey);
-        let sig = enum_variant!(sig, Signature::Ed25519Signature);
+        let sig = ed25519_dalek::Signature::from_bytes(&sig.0)
+            .map_err(|_| Error::SignatureError("Invalid signature"))?;
+
+        public_key
+            .verify(msg, &sig)
+            .map_err(|_| Error::SignatureError("Invalid signature"))
+    }
+}
+
+/// This represents the ECDSA signature scheme over P-256 with SHA-256 (`ecdsa_secp256r1_sha256`
+/// in TLS 1.3 parlance). Notably, it implements `SignatureScheme`. Public keys are encoded as
+/// uncompressed SEC1 points, and signatures are DER-encoded, as they are in TLS.
+pub(crate) struct EcdsaP256;
+
+impl SignatureScheme for EcdsaP256 {
+    fn name(&self) -> &'static str {
+        "ECDSA_P256_SHA256"
+    }
+
+    /// Creates a public key from the provided SEC1-encoded point
+    ///
+    /// Returns: `Ok(public_key)` iff no error occured. Otherwise, returns an
+    /// `Err(Error::SignatureError)`.
+    fn public_key_from_bytes(&self, bytes: &[u8]) -> Result<SigPublicKey, Error> {
+        match p256::ecdsa::VerifyingKey::from_sec1_bytes(bytes) {
+            Ok(pubkey) => Ok(SigPublicKey::EcdsaP256PublicKey(pubkey)),
+            Err(_) => Err(Error::SignatureError("Invalid public key")),
+        }
+    }
+
+    /// Returns the uncompressed SEC1 encoding of the given public key
+    fn public_key_to_bytes(&self, public_key: &SigPublicKey) -> Vec<u8> {
+        let public_key = enum_variant!(public_key, SigPublicKey::EcdsaP256PublicKey);
+        public_key.to_encoded_point(false).as_bytes().to_vec()
+    }
+
+    /// Creates a key pair from the provided secret key bytes. This expects 32 bytes, representing
+    /// a big-endian integer in the range [1, n), where n is the order of the P-256 group.
+    fn secret_key_from_bytes(&self, bytes: &[u8]) -> Result<SigSecretKey, Error> {
+        if bytes.len() != ECDSA_P256_SECRET_KEY_SIZE {
+            return Err(Error::SignatureError("Invalid secret key"));
+        }
+        match p256::ecdsa::SigningKey::from_slice(bytes) {
+            Ok(secret) => Ok(SigSecretKey::EcdsaP256SecretKey(secret)),
+            Err(_) => Err(Error::SignatureError("Invalid secret key")),
+        }
+    }
+
+    /// Returns the secret key as a 32-byte big-endian integer. The caller is responsible for
+    /// wiping it.
+    fn secret_key_to_bytes(&self, secret: &SigSecretKey) -> Vec<u8> {
+        let secret = enum_variant!(secret, SigSecretKey::EcdsaP256SecretKey);
+        secret.to_bytes().to_vec()
+    }
+
+    /// Generates a random key pair using the given CSPRNG. This samples 32-byte strings until one
+    /// of them is a valid secret key.
+    ///
+    /// Returns: `Ok(secret_key)` on success. On error, returns `Error::OutOfEntropy`.
+    fn secret_key_from_random(&self, csprng: &mut dyn CryptoRng) -> Result<SigSecretKey, Error> {
+        let mut key_bytes = [0u8; ECDSA_P256_SECRET_KEY_SIZE];
+        loop {
+            csprng
+                .try_fill_bytes(&mut key_bytes)
+                .map_err(|_| Error::OutOfEntropy)?;
+            if let Ok(key) = self.secret_key_from_bytes(&key_bytes) {
+                return Ok(key);
+            }
+        }
+    }
+
+    /// Computes the public key corresponding to the given secret key
+    fn public_key_from_secret_key(&self, secret: &SigSecretKey) -> SigPublicKey {
+        let secret = enum_variant!(secret, SigSecretKey::EcdsaP256SecretKey);
+        SigPublicKey::EcdsaP256PublicKey(*secret.verifying_key())
+    }
+
+    /// Creates a signature from the provided DER-encoded bytes
+    ///
+    /// Returns: `Ok(signature)` iff no error occured. Otherwise, returns an
+    /// `Err(Error::SignatureError)`.
+    fn signature_from_bytes(&self, bytes: &[u8]) -> Result<Signature, Error> {
+        match p256::ecdsa::Signature::from_der(bytes) {
+            Ok(_) => Ok(Signature(bytes.to_vec())),
+            Err(_) => Err(Error::SignatureError("Invalid signature")),
+        }
+    }
+
+    /// Computes a signature of the given message under the given secret key. The nonce is derived
+    /// deterministically as per RFC 6979.
+    fn sign(&self, secret: &SigSecretKey, msg: &[u8]) -> Signature {
+        let secret = enum_variant!(secret, SigSecretKey::EcdsaP256SecretKey);
+        let sig: p256::ecdsa::Signature = secret.sign(msg);
+        Signature(sig.to_der().as_bytes().to_vec())
+    }
+
+    /// Verifies the signature of the given message under the given public key
+    ///
+    /// Returns: `Ok(())` iff the signature succeeded. Otherwise, returns an
+    /// `Err(Error::SignatureError)`.
+    fn verify(&self, public_key: &SigPublicKey, msg: &[u8], sig: &Signature) -> Result<(), Error> {
+        let public_key = enum_variant!(public_key, SigPublicKey::EcdsaP256PublicKey);
+        let sig = p256::ecdsa::Signature::from_der(&sig.0)
+            .map_err(|_| Error::SignatureError("Invalid signature"))?;
 
         public_key
-            .verify(msg, sig)
+            .verify(msg, &sig)
             .map_err(|_| Error::SignatureError("Invalid signature"))
     }
 }
@@ -155,11 +336,16 @@ mod test {
 
             let derived_sig = {
                 let sig = ED25519_IMPL.sign(&secret, &msg);
-                enum_variant!(sig, Signature::Ed25519Signature)
+                ED25519_IMPL.signature_to_bytes(&sig)
             };
             let expected_sig = hex::decode(sig_hex).unwrap();
 
-            assert_eq!(expected_sig, derived_sig.to_bytes().to_vec());
+            assert_eq!(expected_sig, derived_sig);
+
+            // The standard's signature should also verify under the standard's public key
+            let public = ED25519_IMPL.public_key_from_secret_key(&secret);
+            let sig = ED25519_IMPL.signature_from_bytes(&expected_sig).unwrap();
+            assert!(ED25519_IMPL.verify(&public, &msg, &sig).is_ok());
         }
     }
 
@@ -180,5 +366,73 @@ mod test {
 
         // Make sure the signature we just made is valid
         assert!(ED25519_IMPL.verify(&public_key, &msg, &sig).is_ok());
+
+        // Make sure it's not valid for a modified message
+        let mut modified_msg = msg.clone();
+        match modified_msg.first_mut() {
+            Some(b) => *b ^= 0x01,
+            None => modified_msg.push(0x00),
+        }
+        assert!(ED25519_IMPL
+            .verify(&public_key, &modified_msg, &sig)
+            .is_err());
+    }
+
+    // Test vectors are from https://tools.ietf.org/html/rfc6979#appendix-A.2.5. Signatures are
+    // given there as (r, s), so we compare against the DER-decoded signature.
+    #[test]
+    fn ecdsa_p256_kat() {
+        let secret = {
+            let hex_str = "c9afa9d845ba75166b5c215767b1d6934e50c3db36e89b127b8a622b120f6721";
+            let bytes = hex::decode(hex_str).unwrap();
+            ECDSA_P256_IMPL.secret_key_from_bytes(&bytes).unwrap()
+        };
+        let public = ECDSA_P256_IMPL.public_key_from_secret_key(&secret);
+        assert_eq!(
+            hex::encode(ECDSA_P256_IMPL.public_key_to_bytes(&public)),
+            "0460fed4ba255a9d31c961eb74c6356d68c049b8923b61fa6ce669622e60f29fb67903fe1008b8bc99a41a\
+             e9e95628bc64f2f1b20c2d7e9f5177a3c294d4462299"
+        );
+
+        let msg_r_s_tuples = [
+            (
+                "sample",
+                "efd48b2aacb6a8fd1140dd9cd45e81d69d2c877b56aaf991c34d0ea84eaf3716",
+                "f7cb1c942d657c41d436c7a1b6e29f65f3e900dbb9aff4064dc4ab2f843acda8",
+            ),
+            (
+                "test",
+                "f1abb023518351cd71d881567b1ea663ed3efcf6c5132b354f28d3b0b7d38367",
+                "019f4113742a2b14bd25926b49c649155f267e60d3814b4c0cc84250e46f0083",
+            ),
+        ];
+
+        for (msg, r_hex, s_hex) in msg_r_s_tuples.iter() {
+            let sig = ECDSA_P256_IMPL.sign(&secret, msg.as_bytes());
+            let sig_bytes = ECDSA_P256_IMPL.signature_to_bytes(&sig);
+            let decoded = p256::ecdsa::Signature::from_der(&sig_bytes).unwrap();
+
+            assert_eq!(hex::encode(decoded.r().to_bytes()), *r_hex);
+            assert_eq!(hex::encode(decoded.s().to_bytes()), *s_hex);
+            assert!(ECDSA_P256_IMPL
+                .verify(&public, msg.as_bytes(), &sig)
+                .is_ok());
+        }
+    }
+
+    #[quickcheck]
+    fn ecdsa_p256_correctness(msg: Vec<u8>, secret_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(secret_seed);
+        let secret_key = ECDSA_P256_IMPL.secret_key_from_random(&mut rng).unwrap();
+        let public_key = ECDSA_P256_IMPL.public_key_from_secret_key(&secret_key);
+
+        let sig = ECDSA_P256_IMPL.sign(&secret_key, &msg);
+        assert!(ECDSA_P256_IMPL.verify(&public_key, &msg, &sig).is_ok());
+
+        // A signature over a different message shouldn't verify
+        let other_msg = [msg.as_slice(), b"!"].concat();
+        assert!(ECDSA_P256_IMPL
+            .verify(&public_key, &other_msg, &sig)
+            .is_err());
     }
 }
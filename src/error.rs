@@ -1,19 +1,63 @@
 /// An error type for anything that goes wrong in this crate
+#[cfg(feature = "std")]
 #[derive(Debug)]
 pub enum Error {
     /// For errors that occur in AEAD algorithms
     EncryptionError(&'static str),
     /// For errors that occur in Diffie-Hellman key agreement
     DhError(&'static str),
+    /// For when a Diffie-Hellman shared secret comes out all zeros. This is what happens when the
+    /// other party's public key is a low-order point.
+    ZeroSharedSecret,
+    /// For when a Diffie-Hellman public key has the wrong number of bytes for its scheme
+    BadPointLength { expected: usize, got: usize },
     /// For errors that occur in signature algorithms
     SignatureError(&'static str),
     /// For errors encountered during (de)serialization
     SerdeError(std::io::Error),
+    /// For when a value is too long to be serialized with its length prefix. `max` is the longest
+    /// the value can be, in bytes.
+    LengthExceedsBound { max: u64, got: u64 },
+    /// For when a deserialized list has a different length than the rest of the message says it
+    /// should. `expected` describes what the length should have been.
+    LengthMismatch { expected: String, got: usize },
     /// For when we need randomness and there's none left
     OutOfEntropy,
+    /// For when a received message is malformed or doesn't agree with our local state
+    ValidationError(&'static str),
+    /// For when a `Handshake` was made in a different epoch than the one the group is in.
+    /// `expected` is the group's current epoch and `got` is the `Handshake`'s prior epoch.
+    StaleEpoch { expected: u32, got: u32 },
+    /// For when a `Handshake`'s signer index doesn't point to an active member of the group
+    InvalidSigner,
+    /// For when a `DirectPathMessage` has a different number of node messages than there are
+    /// nodes on the sender's direct path, counting the sender's leaf
+    PathLengthMismatch { expected: usize, got: usize },
+    /// For when a `DirectPathMessage` has the right number of nodes, but their contents can't be
+    /// right no matter who they were sent to
+    MalformedPath(&'static str),
+    /// For when a `GroupAdd` uses a `UserInitKey` whose ID has already been used in this group
+    ReusedInitKey,
+    /// For when something was made with a different cipher suite than the one it's being used
+    /// with. Both fields are cipher suite names.
+    CipherSuiteMismatch {
+        expected: &'static str,
+        got: &'static str,
+    },
+    /// For when a node's parent hash isn't the one computed from the tree around it, i.e., the
+    /// tree or path we were sent doesn't match the sender's view of the tree
+    ParentHashMismatch,
+    /// For when a `WelcomeInfo` isn't the one that the `GroupAdd` adding us vouches for
+    ConfirmationMismatch,
+    /// For errors that occur in parsing or validating credentials
+    CredentialError(&'static str),
+    /// For when the secret tree can't give out the application key that was asked for, e.g.,
+    /// because the sender is out of range or the key was already used
+    SecretTreeError(&'static str),
 }
 
 // The only IO done in molasses is via serde, so this is a natural conversion
+#[cfg(feature = "std")]
 impl<'a> std::convert::From<std::io::Error> for Error {
     fn from(other: std::io::Error) -> Error {
         crate::error::Error::SerdeError(other)
@@ -21,27 +65,89 @@ impl<'a> std::convert::From<std::io::Error> for Error {
 }
 
 // Serde requires that any Serializer's error type implement std::error::Error
+#[cfg(feature = "std")]
 impl std::error::Error for Error {
     fn description(&self) -> &str {
         match self {
             Error::EncryptionError(e) => e,
             Error::DhError(e) => e,
+            Error::ZeroSharedSecret => "Shared secret is zero",
+            Error::BadPointLength { .. } => "Public key has the wrong length",
             Error::SignatureError(e) => e,
             Error::SerdeError(e) => e.description(),
+            Error::LengthExceedsBound { .. } => "Value is too long for its length prefix",
+            Error::LengthMismatch { .. } => "List has the wrong length",
             Error::OutOfEntropy => "Out of Entropy",
+            Error::ValidationError(e) => e,
+            Error::StaleEpoch { .. } => "Handshake is not from the current epoch",
+            Error::InvalidSigner => "Handshake signer is not an active member",
+            Error::PathLengthMismatch { .. } => "DirectPathMessage has the wrong number of nodes",
+            Error::MalformedPath(e) => e,
+            Error::ReusedInitKey => "UserInitKey has already been used in this group",
+            Error::CipherSuiteMismatch { .. } => "Cipher suite mismatch",
+            Error::ParentHashMismatch => "Parent hash doesn't match the tree",
+            Error::ConfirmationMismatch => "WelcomeInfo isn't the one its GroupAdd vouches for",
+            Error::CredentialError(e) => e,
+            Error::SecretTreeError(e) => e,
         }
     }
 }
 
 // Serde also requires that any Serializer's error type implement std::fmt::Display
+#[cfg(feature = "std")]
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
-        use std::error::Error;
-        f.write_str(self.description())
+        use std::error::Error as StdError;
+        match self {
+            Error::BadPointLength { expected, got } => write!(
+                f,
+                "{}: expected {} bytes, got {}",
+                self.description(),
+                expected,
+                got
+            ),
+            Error::LengthExceedsBound { max, got } => write!(
+                f,
+                "{}: it can be at most {} bytes, but it's {}",
+                self.description(),
+                max,
+                got
+            ),
+            Error::LengthMismatch { expected, got } => write!(
+                f,
+                "{}: expected {}, got {}",
+                self.description(),
+                expected,
+                got
+            ),
+            Error::StaleEpoch { expected, got } => write!(
+                f,
+                "{}: the group is in epoch {}, but the Handshake is from epoch {}",
+                self.description(),
+                expected,
+                got
+            ),
+            Error::PathLengthMismatch { expected, got } => write!(
+                f,
+                "{}: expected {}, got {}",
+                self.description(),
+                expected,
+                got
+            ),
+            Error::CipherSuiteMismatch { expected, got } => write!(
+                f,
+                "{}: expected {}, got {}",
+                self.description(),
+                expected,
+                got
+            ),
+            _ => f.write_str(self.description()),
+        }
     }
 }
 
 // serde requires that any Serializer's error type implement serde::ser::Error
+#[cfg(feature = "std")]
 impl serde::ser::Error for Error {
     fn custom<T: std::fmt::Display>(msg: T) -> Self {
         Error::SerdeError(std::io::Error::new(
@@ -52,6 +158,7 @@ impl serde::ser::Error for Error {
 }
 
 // serde requires that any Deserializer's error type implement serde::de::Error
+#[cfg(feature = "std")]
 impl serde::de::Error for Error {
     fn custom<T: std::fmt::Display>(msg: T) -> Self {
         Error::SerdeError(std::io::Error::new(
@@ -59,4 +166,79 @@ impl serde::de::Error for Error {
             format!("{}", msg),
         ))
     }
+
+    // Deserialize impls report lists of the wrong length through this, and we want those to be
+    // distinguishable from malformed input
+    fn invalid_length(len: usize, exp: &dyn serde::de::Expected) -> Self {
+        Error::LengthMismatch {
+            expected: format!("{}", exp),
+            got: len,
+        }
+    }
+}
+
+/// An error type for ECIES decryption. A ciphertext that fails authentication is kept apart from
+/// one that's malformed, since only the former means it was tampered with or encrypted to someone
+/// else.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum EciesError {
+    /// The ciphertext can't be decrypted because it's malformed. The enclosed error says how.
+    MalformedInput(Error),
+    /// The ciphertext failed authentication
+    TagMismatch,
+}
+
+// Outside of ECIES, a failed decryption is just an error like any other
+#[cfg(feature = "std")]
+impl std::convert::From<EciesError> for Error {
+    fn from(other: EciesError) -> Error {
+        match other {
+            EciesError::MalformedInput(e) => e,
+            EciesError::TagMismatch => {
+                Error::EncryptionError("ECIES ciphertext failed authentication")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for EciesError {}
+
+#[cfg(feature = "std")]
+impl std::fmt::Display for EciesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            EciesError::MalformedInput(e) => write!(f, "Malformed ECIES ciphertext: {}", e),
+            EciesError::TagMismatch => f.write_str("ECIES ciphertext failed authentication"),
+        }
+    }
+}
+
+/// An error type for invalid inputs to the functions in `tree_math`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TreeMathError {
+    /// A tree must have at least one leaf
+    ZeroLeaves,
+    /// The number of leaves exceeds the maximum that can be indexed by a `usize`
+    TooManyLeaves,
+    /// The given node index does not exist in a tree of the given size
+    IndexOutOfRange,
+    /// A left-balanced binary tree always has an odd number of nodes
+    EvenNumNodes,
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TreeMathError {}
+
+impl core::fmt::Display for TreeMathError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> Result<(), core::fmt::Error> {
+        let msg = match self {
+            TreeMathError::ZeroLeaves => "Tree has zero leaves",
+            TreeMathError::TooManyLeaves => "Tree has too many leaves",
+            TreeMathError::IndexOutOfRange => "Node index out of range",
+            TreeMathError::EvenNumNodes => "Tree has an even number of nodes",
+        };
+        f.write_str(msg)
+    }
 }
@@ -1,11 +1,32 @@
 use crate::{
-    credential::{Credential, Identity},
-    crypto::{ciphersuite::CipherSuite, sig::SigSecretKey},
-    ratchet_tree::RatchetTree,
+    credential::Credential,
+    crypto::{
+        aead::{AeadKey, AeadNonce},
+        ciphersuite::CipherSuite,
+        dh::{DhPoint, DhScalar},
+        ecies,
+        rng::CryptoRng,
+        sig::SigSecretKey,
+    },
+    error::Error,
+    handshake::{
+        self, DirectPathMessage, GroupAdd, GroupOperation, GroupRemove, GroupUpdate, Handshake,
+        UserInitKey, Welcome,
+    },
+    ratchet_tree::{RatchetTree, RatchetTreeNode},
+    roster::Roster,
+    secret_tree::SecretTree,
+    tls_de::TlsDeserializer,
+    tls_ser, tree_math,
 };
 
+use serde::{de::Deserialize, ser::Serialize};
+
+use std::cell::RefCell;
+use zeroize::Zeroize;
+
 /// Contains all group state
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub(crate) struct GroupState {
     /// You can think of this as a context variable. It helps us implement crypto ops and
     /// disambiguate serialized data structures
@@ -22,7 +43,7 @@ pub(crate) struct GroupState {
     // optional<Credential> roster<1..2^32-1>;
     /// Contains credentials for the occupied slots in the tree, including the identity and
     /// signature public key for the holder of the slot
-    roster: Vec<Option<Credential>>,
+    pub(crate) roster: Roster,
     // optional<PublicKey> tree<1..2^32-1>;
     /// The tree field contains the public keys corresponding to the nodes of the ratchet tree for
     /// this group. The number of leaves in this tree MUST be equal to the length of `roster`
@@ -38,44 +59,211 @@ pub(crate) struct GroupState {
     //
     /// The initial secret used to derive all the rest
     #[serde(skip)]
-    init_secret: Vec<u8>,
+    pub(crate) init_secret: Vec<u8>,
+    /// The secret from which application message keys are derived
     #[serde(skip)]
-    application_secret: Vec<u8>,
+    pub(crate) application_secret: Vec<u8>,
+    /// The key used to compute and check `Handshake` confirmation MACs. This is kept as bytes,
+    /// rather than as a ring key, so that it can be persisted and wiped.
+    #[serde(skip)]
+    pub(crate) confirmation_key: Vec<u8>,
+    /// The secret from which secrets for use outside of MLS are exported. See `export_secret`.
+    #[serde(skip)]
+    exporter_secret: Vec<u8>,
+    /// The tree that application message keys are drawn from. It's rebuilt from
+    /// `application_secret` every epoch, and is only `None` before the first one.
+    #[serde(skip)]
+    secret_tree: Option<SecretTree>,
+    /// The IDs of every `UserInitKey` that's been used to add someone to this group since we
+    /// joined. An init key is only ever supposed to be used once, so we refuse to see one twice.
+    #[serde(skip)]
+    used_init_key_ids: Vec<InitKeyId>,
+    /// The last value of `tree_hash()`, along with the versions of the tree and roster it was
+    /// computed from. It's only good as long as both versions still match. This is never
+    /// serialized.
     #[serde(skip)]
-    pub(crate) confirmation_key: ring::hmac::SigningKey,
+    tree_hash_cache: RefCell<Option<((u64, u64), Vec<u8>)>>,
+}
+
+/// Lets a `TlsSerializer` write into a running hash. With a streaming serializer, nothing is
+/// buffered along the way, not even the length-prefixed values nested inside what's hashed.
+struct DigestWriter<'a>(&'a mut ring::digest::Context);
+
+impl<'a> std::io::Write for DigestWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// opaque user_init_key_id<0..255>
+/// The ID of a `UserInitKey`. This is only ever serialized when a `GroupState` is persisted.
+#[derive(Clone, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename = "InitKeyId__bound_u8")]
+struct InitKeyId(Vec<u8>);
+
+impl Drop for GroupState {
+    fn drop(&mut self) {
+        self.init_secret.zeroize();
+        self.application_secret.zeroize();
+        self.confirmation_key.zeroize();
+        self.exporter_secret.zeroize();
+    }
 }
 
-// TODO: Write the method to create a one-man group from scratch. The spec says that
-// transcript_hash is initialized to all zeros.
 impl GroupState {
-    /// Initializes a `GroupState` with the given `Welcome` information, this participant's
-    /// identity, and this participant's identity key
-    fn from_welcome_info(
+    /// Creates a brand new group whose only member is the caller. The group starts at epoch 0
+    /// with a single-leaf tree, whose keypair is derived from a fresh leaf secret. As the spec
+    /// says, the transcript hash and the initial `init_secret` are all zeros. Since the leaf is
+    /// also the root, the leaf secret is the update secret for the initial key schedule.
+    ///
+    /// Returns: `Ok(group_state)` on success. If a keypair can't be derived from the leaf secret,
+    /// returns an `Error::DhError`. This happens with negligible probability.
+    pub(crate) fn create<R: CryptoRng>(
+        cs: &'static CipherSuite,
+        group_id: Vec<u8>,
+        credential: Credential,
+        identity_key: SigSecretKey,
+        csprng: &mut R,
+    ) -> Result<GroupState, Error> {
+        let mut leaf_secret = cs.generate_leaf_secret(csprng);
+        let (leaf_pubkey, leaf_privkey) = cs.derive_key_pair(&leaf_secret)?;
+        let mut tree = RatchetTree::new();
+        tree.add_leaf_node(RatchetTreeNode::Filled {
+            pubkey: leaf_pubkey,
+            privkey: Some(leaf_privkey),
+            secret: Some(leaf_secret.clone()),
+            credential: Some(credential.clone()),
+            parent_hash: Vec::new(),
+        });
+        tree.set_parent_hashes(cs);
+
+        let mut state = GroupState {
+            cs: cs,
+            identity_key: identity_key,
+            group_id: group_id,
+            epoch: 0,
+            roster: {
+                let mut roster = Roster::new();
+                roster.add(credential);
+                roster
+            },
+            tree: tree,
+            transcript_hash: cs.zero_secret(),
+            my_position_in_roster: 0,
+            init_secret: cs.zero_secret(),
+            // All these fields will be populated by the call to `derive_new_secrets` below
+            application_secret: Vec::new(),
+            confirmation_key: Vec::new(),
+            exporter_secret: Vec::new(),
+            secret_tree: None,
+            used_init_key_ids: Vec::new(),
+            tree_hash_cache: RefCell::new(None),
+        };
+        state.derive_new_secrets(&leaf_secret);
+        leaf_secret.zeroize();
+
+        Ok(state)
+    }
+
+    /// Initializes a `GroupState` with the given `Welcome` information, and processes the
+    /// `Handshake` that added this participant, so that the returned state is in the same epoch as
+    /// everyone else in the group. `my_credential` is used to find this participant in the roster,
+    /// `my_init_private_key` is the private key of the init key this participant was added with,
+    /// and `my_identity_key` is this participant's identity key.
+    ///
+    /// The `WelcomeInfo` is only trusted as far as the `Handshake` is: its hash has to be the one
+    /// in the `GroupAdd`, and the `Handshake` has to be signed by the member of the roster at its
+    /// signer index. That member's credential comes from the `WelcomeInfo` too, so it's up to the
+    /// caller to decide whether they trust it.
+    ///
+    /// Returns: `Ok(group_state)` on success. If the `Handshake` isn't from the `WelcomeInfo`'s
+    /// epoch, returns an `Error::StaleEpoch`. If the `WelcomeInfo` isn't the one the `GroupAdd`
+    /// vouches for, returns an `Error::ConfirmationMismatch`. If the `Handshake`'s signer isn't in
+    /// the roster, returns an `Error::InvalidSigner`, and if its signature is invalid, returns an
+    /// `Error::SignatureError`. If the `Handshake` isn't a `GroupAdd`, if this participant isn't in
+    /// the roster, if the roster and tree don't have the same number of leaves, or if this
+    /// participant's leaf doesn't hold the public key of `my_init_private_key`, returns an
+    /// `Error::ValidationError`. If the tree's parent hashes don't match the tree, returns an
+    /// `Error::ParentHashMismatch`.
+    pub(crate) fn from_welcome_info(
         cs: &'static CipherSuite,
-        w: WelcomeInfo,
-        my_identity: &Identity,
+        mut w: WelcomeInfo,
+        add_handshake: &Handshake,
+        my_credential: &Credential,
+        my_init_private_key: DhScalar,
         my_identity_key: SigSecretKey,
-    ) -> GroupState {
+    ) -> Result<GroupState, Error> {
+        // Everything in the WelcomeInfo, including the roster we're about to look the signer up
+        // in, comes from whoever sent it. What ties it to the group is that a member signed a
+        // GroupAdd with its hash in it.
+        let add = match &add_handshake.operation {
+            GroupOperation::Add(add) => add,
+            _ => return Err(Error::ValidationError("Handshake is not a GroupAdd")),
+        };
+        if add.welcome_info_hash != w.hash(cs)? {
+            return Err(Error::ConfirmationMismatch);
+        }
+        add_handshake.verify_signature(&w.roster, &w.transcript_hash)?;
+        if add_handshake.prior_epoch != w.epoch {
+            return Err(Error::StaleEpoch {
+                expected: w.epoch,
+                got: add_handshake.prior_epoch,
+            });
+        }
+
+        if w.roster.len() != w.tree.validate_shape()? {
+            return Err(Error::ValidationError(
+                "WelcomeInfo roster and tree have different numbers of leaves",
+            ));
+        }
+        // The tree came from whoever added us. Its parent hashes tie every node to the rest of the
+        // tree, so a node that was swapped out or moved shows up here.
+        w.tree.verify_parent_hashes(cs)?;
+
         // We're not told where we are in the roster, so we first find ourselves. The index is used
         // as the signer index in Handshake messages
-        let my_position_in_roster: u32 = {
-            let pos = w
-                .roster
-                .iter()
-                .position(|cred| match cred {
-                    Some(Credential::Basic(basic_cred)) => &basic_cred.identity == my_identity,
-                    None => false,
-                    Some(_) => unimplemented!("X.509 is not a thing yet"),
-                })
-                .expect("could not find myself in roster");
-            assert!(pos <= std::u32::MAX as usize, "roster index out of range");
-            pos as u32
-        };
+        let my_position_in_roster = w
+            .roster
+            .iter()
+            .position(|cred| match cred {
+                Some(cred) => cred.identity() == my_credential.identity(),
+                None => false,
+            })
+            .ok_or(Error::ValidationError(
+                "Could not find myself in the roster",
+            ))?;
+        assert!(
+            my_position_in_roster <= std::u32::MAX as usize,
+            "roster index out of range"
+        );
 
-        // This will get populated on the next call to `derive_new_secrets`
-        let empty_confirmation_key = ring::hmac::SigningKey::new(cs.hash_alg, &[]);
+        // Our leaf should have our init key in it. If it does, we fill in the private key.
+        let my_public_key_bytes = {
+            let my_public_key = cs.dh_impl.multiply_basepoint(&my_init_private_key);
+            cs.dh_impl.point_as_bytes(my_public_key)
+        };
+        let my_leaf = w
+            .tree
+            .get_mut(tree_math::leaf_to_node(my_position_in_roster));
+        match my_leaf {
+            Some(RatchetTreeNode::Filled {
+                pubkey, privkey, ..
+            }) if cs.dh_impl.point_as_bytes(pubkey.clone()) == my_public_key_bytes => {
+                *privkey = Some(my_init_private_key);
+            }
+            _ => {
+                return Err(Error::ValidationError(
+                    "My leaf does not contain my init key",
+                ))
+            }
+        }
 
-        GroupState {
+        let mut state = GroupState {
             cs: cs,
             identity_key: my_identity_key,
             group_id: w.group_id,
@@ -84,96 +272,2809 @@ impl GroupState {
             tree: w.tree,
             transcript_hash: w.transcript_hash,
             init_secret: w.init_secret,
-            // All these fields will be populated on the next call to `derive_new_secrets`
+            // All these fields will be populated by the call to `advance_epoch` below
             application_secret: Vec::new(),
-            confirmation_key: empty_confirmation_key,
-            my_position_in_roster: my_position_in_roster,
+            confirmation_key: Vec::new(),
+            exporter_secret: Vec::new(),
+            secret_tree: None,
+            my_position_in_roster: my_position_in_roster as u32,
+            used_init_key_ids: vec![InitKeyId(add.init_key.user_init_key_id.clone())],
+            tree_hash_cache: RefCell::new(None),
+        };
+
+        // The WelcomeInfo already has us in it, so all that's left of the add is moving to the
+        // next epoch. An add has an all-zero update secret.
+        state.update_transcript_hash(add_handshake)?;
+        state.advance_epoch(&cs.zero_secret());
+
+        Ok(state)
+    }
+
+    /// Decrypts the given `Welcome` and joins the group with the `WelcomeInfo` in it. The `Welcome`
+    /// has to be encrypted to the init key that the `GroupAdd` in `add_handshake` adds. Everything
+    /// else is done by `from_welcome_info`, which is passed the same arguments.
+    ///
+    /// Returns: `Ok(group_state)` on success. If the `Handshake` isn't a `GroupAdd`, or the
+    /// `Welcome` isn't encrypted to the init key it adds, returns an `Error::ValidationError`.
+    /// Otherwise, returns whatever error ECIES decryption, deserialization, or `from_welcome_info`
+    /// returns.
+    pub(crate) fn from_welcome(
+        cs: &'static CipherSuite,
+        welcome: Welcome,
+        add_handshake: &Handshake,
+        my_credential: &Credential,
+        my_init_private_key: DhScalar,
+        my_identity_key: SigSecretKey,
+    ) -> Result<GroupState, Error> {
+        let add = match &add_handshake.operation {
+            GroupOperation::Add(add) => add,
+            _ => return Err(Error::ValidationError("Handshake is not a GroupAdd")),
+        };
+        let welcome_info = welcome.decrypt(&add.init_key.user_init_key_id, &my_init_private_key)?;
+
+        GroupState::from_welcome_info(
+            cs,
+            welcome_info,
+            add_handshake,
+            my_credential,
+            my_init_private_key,
+            my_identity_key,
+        )
+    }
+
+    /// Makes the `WelcomeInfo` for a member who was just added to this group. This contains only
+    /// the public parts of the ratchet tree, along with everything else the new member needs to
+    /// process the `Handshake` that added them.
+    pub(crate) fn make_welcome_info(&self) -> WelcomeInfo {
+        WelcomeInfo {
+            group_id: self.group_id.clone(),
+            epoch: self.epoch,
+            roster: self.roster.clone(),
+            tree: self.tree.public_copy(),
+            transcript_hash: self.transcript_hash.clone(),
+            init_secret: self.init_secret.clone(),
         }
     }
 
-    /// This is the `Derive-Secret` function defined in section 5.9 of the spec. It's used as a
-    /// helper function for `derive_new_secrets`
-    fn derive_secret(
-        prk: &ring::hmac::SigningKey,
-        label_info: &[u8],
-        state: &GroupState,
-    ) -> Vec<u8> {
-        // This struct is only used for `derive_secret` calculations
-        #[derive(Serialize)]
-        struct HkdfLabel<'a> {
-            length: u16,
-            // opaque label<6..255> = "mls10 " + Label;
-            label: Vec<u8>,
-            state: &'a GroupState,
+    /// Makes a `GroupAdd` that adds the owner of the given `UserInitKey` to this group, along with
+    /// the `Welcome` for them, and applies the add to this group. The `GroupAdd` carries the hash
+    /// of the `WelcomeInfo` in the `Welcome`, so the new member can tell that it came from us. Our
+    /// state ends up in the same epoch everyone else reaches by processing the returned
+    /// `Handshake`.
+    ///
+    /// Returns: `Ok((handshake, welcome))` on success, where `handshake` is the signed `Handshake`
+    /// to send to the group and the new member, and `welcome` is the `WelcomeInfo` encrypted to the
+    /// new member's init key. The returned `Handshake` must not be passed back to
+    /// `process_handshake` on this state, since it's already been applied. If the add can't be
+    /// applied or the `WelcomeInfo` can't be encrypted, returns that error and leaves this state
+    /// untouched.
+    pub(crate) fn create_add<R: CryptoRng>(
+        &mut self,
+        init_key: UserInitKey,
+        csprng: &mut R,
+    ) -> Result<(Handshake, Welcome), Error> {
+        // The WelcomeInfo describes the group with the new member in it, but the GroupAdd has to
+        // have its hash before the add can be signed. So do the add on a copy first.
+        let mut staged = self.clone();
+        staged.apply_add(&GroupAdd {
+            init_key: init_key.clone(),
+            welcome_info_hash: Vec::new(),
+        })?;
+        let welcome_info = staged.make_welcome_info();
+        let welcome = Welcome::encrypt(self.cs, &init_key, &welcome_info, csprng)?;
+
+        let add = GroupAdd {
+            init_key,
+            welcome_info_hash: welcome_info.hash(self.cs)?,
+        };
+        let handshake = Handshake::from_group_op(self.cs, self, GroupOperation::Add(add));
+        self.process_handshake(&handshake)?;
+
+        Ok((handshake, welcome))
+    }
+
+    /// Processes an incoming `Handshake`: checks that it was made in the current epoch, verifies
+    /// it, applies its operation, and moves the group to the next epoch. Checking the epoch first
+    /// means a replayed or stale `Handshake` is rejected before anything else is looked at. All of
+    /// this happens on a copy of the state, which only replaces this one if every step succeeds.
+    ///
+    /// Returns: `Ok(())` on success. If the `Handshake`'s prior epoch isn't this group's current
+    /// epoch, returns an `Error::StaleEpoch`. If it contains a `GroupInit` operation, returns an
+    /// `Error::ValidationError`.
+    /// Otherwise, returns whatever error verifying or applying the operation returns. On error,
+    /// this state is left untouched.
+    pub(crate) fn process_handshake(&mut self, handshake: &Handshake) -> Result<(), Error> {
+        let mut staged = self.clone();
+        staged.process_handshake_in_place(handshake)?;
+        *self = staged;
+
+        Ok(())
+    }
+
+    /// Does the work of `process_handshake` directly on this state.
+    ///
+    /// Returns: `Ok(())` on success. Otherwise, returns whatever error `process_handshake` would,
+    /// and this state may be partially modified and should be discarded.
+    fn process_handshake_in_place(&mut self, handshake: &Handshake) -> Result<(), Error> {
+        if handshake.prior_epoch != self.epoch {
+            return Err(Error::StaleEpoch {
+                expected: self.epoch,
+                got: handshake.prior_epoch,
+            });
+        }
+        handshake.verify(self.cs, self)?;
+
+        let update_secret = match &handshake.operation {
+            GroupOperation::Init(_) => {
+                return Err(Error::ValidationError(
+                    "GroupInit operations are unsupported",
+                ))
+            }
+            GroupOperation::Add(add) => {
+                self.apply_add(add)?;
+                // An add has an all-zero update secret
+                self.cs.zero_secret()
+            }
+            GroupOperation::Update(update) => self.apply_update(update, handshake.signer_index)?,
+            GroupOperation::Remove(remove) => self.apply_remove(remove)?,
+        };
+
+        self.update_transcript_hash(handshake)?;
+        self.advance_epoch(&update_secret);
+
+        Ok(())
+    }
+
+    /// Makes a `GroupUpdate` that re-keys the path from our leaf to the root, starting from a
+    /// fresh leaf secret, and applies it to this group. Our state ends up in the next epoch, which
+    /// is the same epoch everyone else reaches by processing the returned `Handshake`.
+    ///
+    /// Returns: `Ok((handshake, path))` on success, where `handshake` is the signed `Handshake` to
+    /// send to the group, and `path` is the `DirectPathMessage` inside it. The returned
+    /// `Handshake` must not be passed back to `process_handshake` on this state, since it's
+    /// already been applied. If making the path or applying the update fails, returns that error
+    /// and leaves this state untouched.
+    pub(crate) fn create_update<R: CryptoRng>(
+        &mut self,
+        csprng: &mut R,
+    ) -> Result<(Handshake, DirectPathMessage), Error> {
+        let my_leaf = self.my_position_in_roster as usize;
+        let new_leaf_secret = self.cs.generate_leaf_secret(csprng);
+        let path =
+            DirectPathMessage::from_path(self.cs, &self.tree, my_leaf, &new_leaf_secret, csprng)?;
+        // The handshake is signed under the state of the epoch we're leaving, so make it before
+        // touching anything
+        let handshake = Handshake::from_group_op(
+            self.cs,
+            self,
+            GroupOperation::Update(GroupUpdate { path: path.clone() }),
+        );
+
+        // We're the sender, so apply_update expects the new secret to already be in our leaf. Do
+        // this on a copy, so that we don't end up with the new secret if applying fails.
+        let mut staged = self.clone();
+        match staged.tree.get_mut(tree_math::leaf_to_node(my_leaf)) {
+            Some(RatchetTreeNode::Filled { secret, .. }) => {
+                if let Some(old_secret) = secret.as_mut() {
+                    old_secret.zeroize();
+                }
+                *secret = Some(new_leaf_secret);
+            }
+            _ => return Err(Error::ValidationError("My leaf is blank")),
+        }
+        staged.process_handshake_in_place(&handshake)?;
+        *self = staged;
+
+        Ok((handshake, path))
+    }
+
+    /// Makes a `GroupRemove` that removes the member at the given roster index, and applies it to
+    /// this group. The removed member's leaf and everything above it are blanked, and then the path
+    /// is re-keyed from the removed leaf starting at a fresh leaf secret. None of the new path
+    /// secrets are encrypted to the removed member, so they can't follow the group into the next
+    /// epoch. Our state ends up in the same epoch everyone else reaches by processing the returned
+    /// `Handshake`.
+    ///
+    /// Returns: `Ok((handshake, remove))` on success, where `handshake` is the signed `Handshake`
+    /// to send to the group, and `remove` is the `GroupRemove` inside it. The returned `Handshake`
+    /// must not be passed back to `process_handshake` on this state, since it's already been
+    /// applied. If `removed` is out of range, blank, or is us, returns an `Error::ValidationError`.
+    /// If making the path or applying the removal fails, returns that error. On error, this state
+    /// is left untouched.
+    pub(crate) fn create_remove<R: CryptoRng>(
+        &mut self,
+        removed: u32,
+        csprng: &mut R,
+    ) -> Result<(Handshake, GroupRemove), Error> {
+        let removed_leaf = removed as usize;
+        if self.roster.get(removed_leaf).is_none() {
+            return Err(Error::ValidationError(
+                "Removed member is out of range or already blank",
+            ));
+        }
+        if removed == self.my_position_in_roster {
+            return Err(Error::ValidationError("Can't remove myself"));
+        }
+
+        // Make the path over the tree as it looks after the removed member is blanked out, which
+        // is the tree everyone applies the path to
+        let mut tree = self.tree.public_copy();
+        let num_leaves = tree.num_leaves();
+        for idx in tree_math::nodes_to_blank_on_remove(removed_leaf, num_leaves) {
+            tree.blank_node(idx);
+        }
+        let mut new_leaf_secret = self.cs.generate_leaf_secret(csprng);
+        let path =
+            DirectPathMessage::from_path(self.cs, &tree, removed_leaf, &new_leaf_secret, csprng);
+        new_leaf_secret.zeroize();
+        let path = path?;
+
+        let remove = GroupRemove { removed, path };
+        let handshake =
+            Handshake::from_group_op(self.cs, self, GroupOperation::Remove(remove.clone()));
+
+        // We aren't the sender of the path, so we get the new secrets by decrypting them like
+        // everyone else
+        self.process_handshake(&handshake)?;
+
+        Ok((handshake, remove))
+    }
+
+    /// Applies a `GroupAdd` operation to this group. The new member is placed in the leftmost
+    /// blank leaf, or in a new leaf on the right if there are no blank leaves. Every node above the
+    /// new member's leaf is then blanked, since the new member doesn't know any of their secrets.
+    ///
+    /// Returns: `Ok(())` on success. If the `UserInitKey` is malformed or has an invalid
+    /// signature, returns an `Error::ValidationError` or an `Error::SignatureError`. If its ID has
+    /// already been used in this group, returns an `Error::ReusedInitKey`. If it doesn't support
+    /// this group's cipher suite or any of our protocol versions, returns an
+    /// `Error::ValidationError`.
+    fn apply_add(&mut self, add: &GroupAdd) -> Result<(), Error> {
+        let init_key = &add.init_key;
+        init_key.verify_signature()?;
+
+        // Adding someone with an init key that's been used before would mean reusing its
+        // ephemeral private key, which breaks forward secrecy
+        let init_key_id = InitKeyId(init_key.user_init_key_id.clone());
+        if self.used_init_key_ids.contains(&init_key_id) {
+            return Err(Error::ReusedInitKey);
         }
 
-        // The output is suppose to be the size of the hash algorithm's digest size
-        let mut out_buf = vec![0u8; prk.digest_algorithm().output_len];
-        // The output length is also supposed to be representable by a u16
-        assert!(out_buf.len() <= std::u16::MAX as usize);
+        if handshake::negotiate_version(handshake::SUPPORTED_PROTOCOL_VERSIONS, init_key).is_none()
+        {
+            return Err(Error::ValidationError(
+                "UserInitKey has no protocol version in common with the group",
+            ));
+        }
+
+        // Find the init key that corresponds to our cipher suite
+        let new_member_public_key = init_key
+            .cipher_suites
+            .iter()
+            .position(|other| other.name == self.cs.name)
+            .and_then(|idx| init_key.init_keys.get(idx))
+            .ok_or(Error::ValidationError(
+                "UserInitKey does not support the group's cipher suite",
+            ))?;
+        let new_member_node = RatchetTreeNode::Filled {
+            pubkey: new_member_public_key.clone(),
+            privkey: None,
+            secret: None,
+            credential: Some(init_key.credential.clone()),
+            parent_hash: Vec::new(),
+        };
 
-        // We're gonna used the serialized label as the `info` parameter to HKDF-Expand
-        let label = HkdfLabel {
-            length: out_buf.len() as u16,
-            // Recall the def: opaque label<6..255> = "mls10 " + Label;
-            label: [b"mls10 ", label_info].concat(),
-            state: state,
+        // Put the new member in the leftmost blank leaf if there is one. Otherwise, grow the tree
+        let new_member_leaf = match tree_math::leftmost_blank_leaf(&self.tree.blanks()) {
+            Some(leaf) => {
+                *self.tree.get_mut(tree_math::leaf_to_node(leaf)).unwrap() = new_member_node;
+                leaf
+            }
+            None => {
+                self.tree.add_leaf_node(new_member_node);
+                self.tree.num_leaves() - 1
+            }
         };
-        // Serialize the label
-        let serialized_label =
-            crate::tls_ser::serialize_to_bytes(&label).expect("couldn't serialize HKDF label");
+        let roster_idx = self.roster.add(init_key.credential.clone());
+        assert_eq!(
+            roster_idx, new_member_leaf,
+            "roster and tree disagree on where the new member goes"
+        );
+
+        // Blank everything above the new leaf. That changes the parent hash of every node.
+        self.tree.blank_path(new_member_leaf);
+        self.tree.set_parent_hashes(self.cs);
+        self.used_init_key_ids.push(init_key_id);
 
-        // Finally, do the HKDF-Expand operation
-        ring::hkdf::expand(prk, &serialized_label, out_buf.as_mut_slice());
-        out_buf
+        Ok(())
     }
 
-    /// Derives the next generation of Group secrets as per section 5.9 in the spec
-    fn derive_new_secrets(&mut self, update_secret: &[u8]) {
-        // epoch_secret = HKDF-Extract(salt=init_secret_[n-1] (or 0), ikm=update_secret)
+    /// Applies a `GroupRemove` operation to this group. The removed member's leaf and every node
+    /// above it are blanked, the path in the message is used to re-key the nodes above the
+    /// removed leaf, and finally any blank leaves on the right edge of the tree are removed.
+    ///
+    /// Returns: `Ok(root_secret)` on success, where `root_secret` is the new secret of the root
+    /// node. If the removed member is out of range or already blank, returns an
+    /// `Error::ValidationError`. Otherwise, returns whatever error applying the path returns. On
+    /// error, the tree and roster are left untouched.
+    fn apply_remove(&mut self, remove: &GroupRemove) -> Result<Vec<u8>, Error> {
+        let removed_leaf = remove.removed as usize;
+        if removed_leaf >= self.roster.len() {
+            return Err(Error::ValidationError("Removed member is out of range"));
+        }
+        if self.roster.get(removed_leaf).is_none() {
+            return Err(Error::ValidationError("Removed member is already blank"));
+        }
+
+        // Everything below happens on copies of the tree and roster, so that a bad path doesn't
+        // leave us with the removed member blanked out but the path not applied. First, blank the
+        // removed member's leaf and everything above it.
+        let mut tree = self.tree.clone();
+        let num_leaves = tree.num_leaves();
+        let removed_idx = tree_math::leaf_to_node(removed_leaf);
+        for idx in tree_math::nodes_to_blank_on_remove(removed_leaf, num_leaves) {
+            tree.blank_node(idx);
+        }
+
+        // Re-key the path. The remover picked a new secret for the removed leaf, which nobody
+        // should know, so we blank that leaf again afterwards.
+        let (mut tree, root_secret) = self.derive_direct_path(&tree, removed_leaf, &remove.path)?;
+        tree.blank_node(removed_idx);
+
+        // Get rid of the trailing blanks
+        let new_num_leaves = tree_math::truncated_num_leaves(num_leaves, &tree.blanks());
+        tree.truncate_to_leaves(new_num_leaves);
+        tree.set_parent_hashes(self.cs);
+        let mut roster = self.roster.clone();
+        roster.remove(removed_leaf);
+        debug_assert_eq!(roster.len(), new_num_leaves);
+
+        // Nothing can fail anymore, so commit to the changes
+        self.tree = tree;
+        self.roster = roster;
+
+        Ok(root_secret)
+    }
+
+    /// Applies a `GroupUpdate` operation sent by the member at the given roster index. This
+    /// re-keys the path from the sender's leaf to the root. If we are the sender, then the new
+    /// secret must already be stored in our leaf.
+    ///
+    /// Returns: `Ok(root_secret)` on success, where `root_secret` is the new secret of the root
+    /// node. This is the update secret that should be passed to `advance_epoch`. If the sender is
+    /// out of range or blank, returns an `Error::ValidationError`. Otherwise, returns whatever
+    /// error applying the path returns.
+    fn apply_update(&mut self, update: &GroupUpdate, from: u32) -> Result<Vec<u8>, Error> {
+        if self.roster.get(from as usize).is_none() {
+            return Err(Error::ValidationError("Update sender is not in the roster"));
+        }
+
+        self.apply_direct_path(from as usize, &update.path)
+    }
+
+    /// Hashes the operation and signer of the given `Handshake` into the transcript hash. This
+    /// must be called after the operation is applied and before `advance_epoch`, since the new
+    /// group secrets depend on the transcript hash.
+    ///
+    /// Returns: `Ok(())` on success. Otherwise, returns whatever error serializing the operation
+    /// returns.
+    fn update_transcript_hash(&mut self, handshake: &Handshake) -> Result<(), Error> {
+        // transcript_hash_[n] = Hash(transcript_hash_[n-1] || operation || signer_index)
+        let mut ctx = ring::digest::Context::new(self.cs.hash_alg);
+        ctx.update(&self.transcript_hash);
+        {
+            // Serialize the operation straight into the hash instead of into one big buffer
+            let mut serializer =
+                tls_ser::TlsSerializer::streaming_from_writer(DigestWriter(&mut ctx));
+            handshake.operation.serialize(&mut serializer)?;
+        }
+        ctx.update(&handshake.signer_index.to_be_bytes());
+        self.transcript_hash = ctx.finish().as_ref().to_vec();
+
+        Ok(())
+    }
+
+    /// Moves this group to the next epoch after an operation has been applied. This increments the
+    /// epoch and derives the new group secrets from the given update secret.
+    fn advance_epoch(&mut self, update_secret: &[u8]) {
+        self.epoch += 1;
+        self.derive_new_secrets(update_secret);
+    }
+
+    /// Mixes an externally agreed-upon pre-shared key into the key schedule. This only changes the
+    /// init secret, so it takes effect at the next epoch, and the epochs after that depend on it
+    /// too. Every member has to inject the same PSK (and ID) between the same two epochs, or their
+    /// secrets diverge from then on. The PSK itself isn't kept around.
+    pub(crate) fn inject_psk(&mut self, psk: &[u8], psk_id: &[u8]) {
+        // psk_secret = HKDF-Expand-Label(psk, "psk", psk_id, Hash.length)
+        let mut psk_secret = self
+            .cs
+            .hkdf_expand_label(psk, "psk", psk_id, self.cs.hash_len());
+        // init_secret = HKDF-Extract(salt=init_secret, ikm=psk_secret). Again, we do the
+        // extraction by hand.
         let salt = ring::hmac::SigningKey::new(self.cs.hash_alg, &self.init_secret);
-        let epoch_secret: ring::hmac::SigningKey = ring::hkdf::extract(&salt, &update_secret);
+        let init_secret = ring::hmac::sign(&salt, &psk_secret).as_ref().to_vec();
+        psk_secret.zeroize();
+
+        self.init_secret.zeroize();
+        self.init_secret = init_secret;
+    }
+
+    /// Derives a secret of the given length for use outside of MLS, e.g., to key some other
+    /// channel between the members of this group. Every member in the same epoch gets the same
+    /// secret for the same `label` and `context`, and nobody can get it once they've left the
+    /// epoch. This is the MLS exporter:
+    /// `HKDF-Expand-Label(Derive-Secret(exporter_secret, label), "exporter", Hash(context),
+    /// length)`.
+    ///
+    /// Panics: when `length > 2^16 - 1`
+    pub(crate) fn export_secret(&self, label: &str, context: &[u8], length: usize) -> Vec<u8> {
+        let mut label_secret = self.derive_secret(&self.exporter_secret, label);
+        let context_hash = self.cs.hash(context);
+        let exported = self
+            .cs
+            .hkdf_expand_label(&label_secret, "exporter", &context_hash, length);
+        label_secret.zeroize();
+
+        exported
+    }
 
-        // application_secret = Derive-Secret(epoch_secret, "app", GroupState_[n])
-        let application_secret = GroupState::derive_secret(&epoch_secret, b"app", self);
-        // confirmation_key = Derive-Secret(epoch_secret, "confirm", GroupState_[n])
-        let confirmation_key = {
-            let key_bytes = GroupState::derive_secret(&epoch_secret, b"confirm", self);
-            ring::hmac::SigningKey::new(self.cs.hash_alg, &key_bytes)
+    /// Returns the key and nonce for the next application message we send in this epoch, along with
+    /// its generation. The generation has to go out with the message, so that the other members
+    /// can look up the same key with `application_key_nonce`. Each key must encrypt exactly one
+    /// message. See `SecretTree::next_key_nonce`.
+    ///
+    /// Returns: `Ok((key, nonce, generation))` on success. If our chain has been exhausted, returns
+    /// an `Error::SecretTreeError`. Otherwise, returns whatever error the cipher suite's AEAD
+    /// returns.
+    pub(crate) fn next_application_key_nonce(
+        &mut self,
+    ) -> Result<(AeadKey, AeadNonce, u32), Error> {
+        let my_leaf = self.my_position_in_roster as usize;
+        self.secret_tree
+            .as_mut()
+            .expect("secret tree is built with the first epoch")
+            .next_key_nonce(my_leaf)
+    }
+
+    /// Returns the key and nonce of the application message of the given generation that the
+    /// member at roster index `sender` sent in this epoch. See
+    /// `SecretTree::key_nonce_for_generation`.
+    ///
+    /// Returns: `Ok((key, nonce))` on success. If `sender` isn't an active member, returns an
+    /// `Error::ValidationError`. If the key can't be handed out, e.g., because it was already used,
+    /// returns an `Error::SecretTreeError`. Otherwise, returns whatever error the cipher suite's
+    /// AEAD returns.
+    pub(crate) fn application_key_nonce(
+        &mut self,
+        sender: u32,
+        generation: u32,
+    ) -> Result<(AeadKey, AeadNonce), Error> {
+        if self.roster.get(sender as usize).is_none() {
+            return Err(Error::ValidationError(
+                "Application message sender is not in the roster",
+            ));
+        }
+
+        self.secret_tree
+            .as_mut()
+            .expect("secret tree is built with the first epoch")
+            .key_nonce_for_generation(sender as usize, generation)
+    }
+
+    /// Returns whether we should send a `GroupUpdate`, because there are nodes above our leaf whose
+    /// secrets we don't know. This is the case right after we join, and stays so until someone's
+    /// path update covers our whole path. See `RatchetTree::uninitialized_path_nodes`.
+    pub(crate) fn needs_update(&self) -> bool {
+        let my_leaf = self.my_position_in_roster as usize;
+        !self.tree.uninitialized_path_nodes(my_leaf).is_empty()
+    }
+
+    /// Updates the public keys of every node from the given leaf to the root with the ones in the
+    /// given `DirectPathMessage`. We then find the lowest node on that path whose secret was
+    /// encrypted to us, decrypt it, and use it to derive the secrets and private keys of that node
+    /// and everything above it. If we are the sender, then nothing is encrypted to us, and we use
+    /// the secret of our own leaf instead.
+    ///
+    /// Returns: `Ok(root_secret)` on success, where `root_secret` is the new secret of the root
+    /// node. Otherwise, returns whatever error `derive_direct_path` returns, and leaves the tree
+    /// untouched.
+    fn apply_direct_path(
+        &mut self,
+        sender_leaf: usize,
+        path_msg: &DirectPathMessage,
+    ) -> Result<Vec<u8>, Error> {
+        let (new_tree, root_secret) = self.derive_direct_path(&self.tree, sender_leaf, path_msg)?;
+        self.tree = new_tree;
+
+        Ok(root_secret)
+    }
+
+    /// Does the work of `apply_direct_path` on a copy of the given tree, which is left as it is.
+    /// Everything in the `DirectPathMessage` is checked before the copy is returned.
+    ///
+    /// Returns: `Ok((new_tree, root_secret))` on success, where `new_tree` is the given tree with
+    /// the path applied, and `root_secret` is the new secret of the root node. If the message has
+    /// the wrong number of nodes, returns an `Error::PathLengthMismatch`. If it has secrets
+    /// encrypted for the sender's own leaf, returns an `Error::MalformedPath`. If the sender's leaf
+    /// is out of range, or if the message contains a public key that doesn't match the secret we
+    /// derived for it, or if none of its secrets are encrypted to us (or we're the sender and don't
+    /// know our leaf secret), returns an `Error::ValidationError`. If one of its public keys is the
+    /// wrong size, returns an `Error::BadPointLength`. If one of its parent hashes isn't the one
+    /// we compute from the new tree, returns an `Error::ParentHashMismatch`. Otherwise, returns
+    /// whatever error ECIES decryption or key derivation returns.
+    fn derive_direct_path(
+        &self,
+        tree: &RatchetTree,
+        sender_leaf: usize,
+        path_msg: &DirectPathMessage,
+    ) -> Result<(RatchetTree, Vec<u8>), Error> {
+        // The sender index comes off the wire. Check it here, since the tree math below panics on
+        // indices outside of the tree.
+        let num_leaves = tree.num_leaves();
+        if sender_leaf >= num_leaves {
+            return Err(Error::ValidationError(
+                "DirectPathMessage sender is out of range",
+            ));
+        }
+        let sender_idx = tree_math::leaf_to_node(sender_leaf);
+        let my_idx = tree_math::leaf_to_node(self.my_position_in_roster as usize);
+
+        // There's one node message for the sender's leaf and one for each node on its direct path.
+        // Everything below walks the path and the message in lockstep, so a message that's too
+        // short would be silently truncated, and one that's too long would have extra keys ignored.
+        let path = tree_math::node_full_path(sender_idx, num_leaves);
+        if path.len() != path_msg.node_messages.len() {
+            return Err(Error::PathLengthMismatch {
+                expected: path.len(),
+                got: path_msg.node_messages.len(),
+            });
+        }
+        // The first node is the sender's leaf, whose secret is never sent to anyone. If there are
+        // ciphertexts here anyway, the sender is confused about where the path starts.
+        if !path_msg.node_messages[0].node_secrets.is_empty() {
+            return Err(Error::MalformedPath(
+                "DirectPathMessage has secrets for the sender's leaf",
+            ));
+        }
+        for node_msg in path_msg.node_messages.iter() {
+            self.cs.dh_impl.validate_point(&node_msg.public_key)?;
+        }
+
+        // If we sent this message, then we already know the leaf secret. Otherwise, we have to
+        // decrypt some secret further up the path.
+        let (path_pos, mut path_secret) = if sender_idx == my_idx {
+            let my_leaf_secret = match tree.get(my_idx) {
+                Some(RatchetTreeNode::Filled {
+                    secret: Some(secret),
+                    ..
+                }) => secret.clone(),
+                _ => {
+                    return Err(Error::ValidationError(
+                        "Don't know the secret of my own leaf",
+                    ))
+                }
+            };
+            (0, my_leaf_secret)
+        } else {
+            // The ith node of the path (for i > 0) has its secret encrypted to the resolution of
+            // the sibling of the (i-1)th node. Find the first such copath node that we're
+            // underneath.
+            let (path_pos, copath_idx) = tree_math::CopathIter::new(sender_idx, num_leaves)
+                .enumerate()
+                .find(|&(_, idx)| idx == my_idx || tree_math::is_ancestor(idx, my_idx, num_leaves))
+                .map(|(i, idx)| (i + 1, idx))
+                .ok_or(Error::ValidationError(
+                    "DirectPathMessage has no secrets encrypted to us",
+                ))?;
+
+            // Exactly one node in that resolution is one whose private key we know. Its position
+            // in the resolution is the position of the ciphertext meant for us.
+            let path_secret = {
+                let (ciphertext_pos, privkey) = tree
+                    .resolution(copath_idx)
+                    .into_iter()
+                    .enumerate()
+                    .find_map(|(i, node)| match node {
+                        RatchetTreeNode::Filled {
+                            privkey: Some(privkey),
+                            ..
+                        } => Some((i, privkey)),
+                        _ => None,
+                    })
+                    .ok_or(Error::ValidationError(
+                        "Don't know any private keys in the resolution of the copath node",
+                    ))?;
+                let ciphertext = path_msg.node_messages[path_pos]
+                    .node_secrets
+                    .get(ciphertext_pos)
+                    .ok_or(Error::ValidationError(
+                        "DirectPathNodeMessage has too few node secrets",
+                    ))?
+                    .clone();
+
+                ecies::EciesCiphertext::decrypt(self.cs, privkey, ciphertext)?
+            };
+
+            (path_pos, path_secret)
+        };
+        // Below the decrypted node, all we learn are public keys. From the decrypted node up, we
+        // know everything. All of it goes into a copy of the tree, which is only handed back once
+        // every public key in the message has been checked.
+        let mut new_tree = tree.clone();
+        let public_nodes = path
+            .iter()
+            .zip(path_msg.node_messages.iter())
+            .take(path_pos)
+            .map(|(&idx, node_msg)| {
+                let node = RatchetTreeNode::Filled {
+                    pubkey: node_msg.public_key.clone(),
+                    privkey: None,
+                    secret: None,
+                    credential: tree.credential(idx).cloned(),
+                    parent_hash: Vec::new(),
+                };
+                (idx, node)
+            })
+            .collect();
+        new_tree.set_nodes(public_nodes);
+        let merged = new_tree.merge_path_secret(self.cs, path[path_pos], &path_secret);
+        path_secret.zeroize();
+        let mut root_secret = merged?;
+
+        // Make sure the public keys we were sent are the ones we derived
+        let sent_nodes = path
+            .iter()
+            .zip(path_msg.node_messages.iter())
+            .skip(path_pos);
+        for (&idx, node_msg) in sent_nodes {
+            let derived_pubkey = match new_tree.get(idx) {
+                Some(RatchetTreeNode::Filled { pubkey, .. }) => pubkey.clone(),
+                _ => unreachable!("merge_path_secret left a blank node on the path"),
+            };
+            let derived_pubkey_bytes = self.cs.dh_impl.point_as_bytes(derived_pubkey);
+            let sent_pubkey_bytes = self.cs.dh_impl.point_as_bytes(node_msg.public_key.clone());
+            // The derived key comes from a secret, so don't let the comparison time leak anything
+            // about where the two differ
+            if ring::constant_time::verify_slices_are_equal(
+                &derived_pubkey_bytes,
+                &sent_pubkey_bytes,
+            )
+            .is_err()
+            {
+                root_secret.zeroize();
+                return Err(Error::ValidationError(
+                    "DirectPathMessage public key doesn't match its secret",
+                ));
+            }
+        }
+
+        // The sender computed the parent hashes of the path over the tree as they saw it. If our
+        // tree is any different, the hashes won't match.
+        let parent_hashes = new_tree.parent_hashes(self.cs);
+        for (&idx, node_msg) in path.iter().zip(path_msg.node_messages.iter()) {
+            if node_msg.parent_hash != parent_hashes[idx] {
+                root_secret.zeroize();
+                return Err(Error::ParentHashMismatch);
+            }
+        }
+        new_tree.set_parent_hashes(self.cs);
+
+        Ok((new_tree, root_secret))
+    }
+
+    /// Returns the roster indices of the members who have to decrypt something when a path update
+    /// is sent from the leaf `from_leaf`. These are the members whose leaves are under the nodes
+    /// that the path's secrets are encrypted to, i.e., the resolutions of the copath nodes of
+    /// `from_leaf`. This is in increasing order, and never includes `from_leaf`. It's useful for
+    /// estimating how much work an update or removal makes for the rest of the group.
+    ///
+    /// Panics: when `from_leaf` is not a leaf of this group's tree
+    pub(crate) fn members_affected_by_path(&self, from_leaf: usize) -> Vec<u32> {
+        let num_leaves = self.tree.num_leaves();
+        assert!(from_leaf < num_leaves, "sender leaf is out of range");
+        let blanks = self.tree.blanks();
+
+        // These are the recipients of the node secrets of a path from this leaf, just like in
+        // `DirectPathMessage::from_path`. Every filled leaf under a recipient knows its private
+        // key, and has to decrypt the secret sent to it.
+        let recipients = tree_math::copath_resolutions(from_leaf, num_leaves, &blanks);
+
+        // The recipients' subtrees are disjoint, so there are no duplicates. They go up the tree
+        // rather than left to right, though, so the leaves still need sorting.
+        let mut members: Vec<u32> = recipients
+            .into_iter()
+            .flatten()
+            .flat_map(|recipient_idx| tree_math::subtree_nodes(recipient_idx, num_leaves))
+            .filter(|&idx| !blanks[idx])
+            .filter_map(tree_math::node_to_leaf)
+            .map(|leaf| leaf as u32)
+            .collect();
+        members.sort();
+
+        members
+    }
+
+    /// Computes a hash of the entire public tree, so that members can check that they agree on
+    /// it. A leaf's hash covers its public key and the credential in its roster slot, and a
+    /// non-leaf node's hash covers its public key and the hashes of its two children. Blank nodes
+    /// and empty roster slots are hashed as absent values. This only depends on the current
+    /// contents of the tree, not on how it got there. The hash is cached until the tree or roster
+    /// changes, so asking for it repeatedly is cheap.
+    pub(crate) fn tree_hash(&self) -> Vec<u8> {
+        let versions = (self.tree.version(), self.roster.version());
+        if let Some((cached_versions, hash)) = self.tree_hash_cache.borrow().as_ref() {
+            if *cached_versions == versions {
+                return hash.clone();
+            }
+        }
+
+        let num_leaves = self.tree.num_leaves();
+        let root = tree_math::try_root_idx(num_leaves).expect("tree has an invalid size");
+        let hash = self.node_hash(root, num_leaves);
+        *self.tree_hash_cache.borrow_mut() = Some((versions, hash.clone()));
+        hash
+    }
+
+    /// Computes the hash of the subtree rooted at the given node. See `tree_hash`.
+    fn node_hash(&self, idx: usize, num_leaves: usize) -> Vec<u8> {
+        #[derive(Serialize)]
+        struct LeafNodeHashInput<'a> {
+            public_key: Option<&'a DhPoint>,
+            credential: Option<&'a Credential>,
+        }
+
+        #[derive(Serialize)]
+        struct ParentNodeHashInput<'a> {
+            public_key: Option<&'a DhPoint>,
+            #[serde(rename = "left_hash__bound_u8")]
+            left_hash: &'a [u8],
+            #[serde(rename = "right_hash__bound_u8")]
+            right_hash: &'a [u8],
+        }
+
+        let public_key = match self.tree.get(idx).expect("tree hash of a nonexistent node") {
+            RatchetTreeNode::Blank => None,
+            RatchetTreeNode::Filled { pubkey, .. } => Some(pubkey),
         };
-        // init_secret_[n] = Derive-Secret(epoch_secret, "init", GroupState_[n])
-        let init_secret = GroupState::derive_secret(&epoch_secret, b"init", self);
 
+        let serialized = match tree_math::node_to_leaf(idx) {
+            Some(leaf) => {
+                assert!(
+                    leaf < self.roster.len(),
+                    "roster and tree disagree on the number of leaves"
+                );
+                let credential = self.roster.get(leaf);
+                crate::tls_ser::serialize_to_bytes(&LeafNodeHashInput {
+                    public_key,
+                    credential,
+                })
+            }
+            None => {
+                let left_hash = self.node_hash(tree_math::node_left_child(idx), num_leaves);
+                let right_hash =
+                    self.node_hash(tree_math::node_right_child(idx, num_leaves), num_leaves);
+                crate::tls_ser::serialize_to_bytes(&ParentNodeHashInput {
+                    public_key,
+                    left_hash: &left_hash,
+                    right_hash: &right_hash,
+                })
+            }
+        }
+        .expect("couldn't serialize tree hash input");
+
+        self.cs.hash(&serialized)
+    }
+
+    /// Returns the confirmation key of the current epoch as a ring HMAC key
+    pub(crate) fn confirmation_signing_key(&self) -> ring::hmac::SigningKey {
+        ring::hmac::SigningKey::new(self.cs.hash_alg, &self.confirmation_key)
+    }
+
+    /// This is the `Derive-Secret` function defined in section 5.9 of the spec:
+    /// `Derive-Secret(Secret, Label, State) = HKDF-Expand-Label(Secret, Label, Hash(State),
+    /// Hash.length)`, where `State` is this `GroupState`
+    pub(crate) fn derive_secret(&self, secret: &[u8], label: &str) -> Vec<u8> {
+        let serialized_state =
+            crate::tls_ser::serialize_to_bytes(self).expect("couldn't serialize GroupState");
+        let state_hash = self.cs.hash(&serialized_state);
+
+        self.cs
+            .hkdf_expand_label(secret, label, &state_hash, self.cs.hash_len())
+    }
+
+    /// Derives the next generation of Group secrets as per section 5.9 in the spec
+    fn derive_new_secrets(&mut self, update_secret: &[u8]) {
+        let serialized_state =
+            crate::tls_ser::serialize_to_bytes(self).expect("couldn't serialize GroupState");
+        let EpochSecrets {
+            application_secret,
+            confirmation_key,
+            exporter_secret,
+            init_secret,
+        } = derive_epoch_secrets(self.cs, &self.init_secret, update_secret, &serialized_state);
+
+        // Wipe the old secrets before we replace them
+        self.application_secret.zeroize();
+        self.init_secret.zeroize();
+        self.confirmation_key.zeroize();
+        self.exporter_secret.zeroize();
         self.application_secret = application_secret;
         self.confirmation_key = confirmation_key;
+        self.exporter_secret = exporter_secret;
         self.init_secret = init_secret;
+
+        // The old tree is wiped as it's dropped
+        self.secret_tree = Some(SecretTree::new(
+            self.cs,
+            &self.application_secret,
+            self.roster.len(),
+        ));
+    }
+
+    /// Serializes this entire `GroupState`, secrets included, so that it can be persisted and
+    /// later restored with `from_bytes`. Unlike the serialization used in the key schedule, this
+    /// contains the identity key, the private keys and secrets in the tree, and the secrets of
+    /// the current epoch, including the secret tree. So the output must be treated as secret, and
+    /// wiped when it's done with. The secret tree records which application keys were already
+    /// handed out, so only the latest output should be kept. Restoring an older one would hand them
+    /// out again.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let node_secrets = (0..self.tree.num_nodes())
+            .map(|idx| match self.tree.get(idx) {
+                Some(RatchetTreeNode::Filled {
+                    privkey, secret, ..
+                }) => PersistedNodeSecrets {
+                    privkey: privkey
+                        .as_ref()
+                        .map(|privkey| self.cs.dh_impl.scalar_as_bytes(privkey))
+                        .unwrap_or_default(),
+                    secret: secret.clone().unwrap_or_default(),
+                },
+                _ => PersistedNodeSecrets::default(),
+            })
+            .collect();
+
+        let persisted = PersistedGroupState {
+            cs: self.cs,
+            group_id: self.group_id.clone(),
+            epoch: self.epoch,
+            roster: self.roster.clone(),
+            tree: self.tree.public_copy(),
+            node_secrets: node_secrets,
+            transcript_hash: self.transcript_hash.clone(),
+            my_position_in_roster: self.my_position_in_roster,
+            identity_key: self.cs.sig_impl.secret_key_to_bytes(&self.identity_key),
+            init_secret: self.init_secret.clone(),
+            application_secret: self.application_secret.clone(),
+            confirmation_key: self.confirmation_key.clone(),
+            exporter_secret: self.exporter_secret.clone(),
+            secret_tree: self.secret_tree.clone(),
+            used_init_key_ids: self.used_init_key_ids.clone(),
+        };
+
+        tls_ser::serialize_to_bytes(&persisted).expect("couldn't serialize GroupState")
+    }
+
+    /// Restores a `GroupState` from the output of `to_bytes`. The cipher suite is recorded in the
+    /// serialized state, and must be the given one.
+    ///
+    /// Returns: `Ok(group_state)` on success. If the bytes can't be deserialized, returns an
+    /// `Error::SerdeError`. If the state was persisted with a cipher suite other than `cs`, returns
+    /// an `Error::CipherSuiteMismatch`. If the deserialized state is inconsistent (e.g., the roster
+    /// and tree have different numbers of leaves, or a private key doesn't match its public key),
+    /// returns an `Error::ValidationError`. If a private key is malformed, returns whatever error
+    /// the cipher suite returns.
+    pub(crate) fn from_bytes(bytes: &[u8], cs: &'static CipherSuite) -> Result<GroupState, Error> {
+        let mut cursor = bytes;
+        let mut persisted = {
+            let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
+            PersistedGroupState::deserialize(&mut deserializer)?
+        };
+        if !cursor.is_empty() {
+            return Err(Error::ValidationError(
+                "Persisted GroupState has trailing bytes",
+            ));
+        }
+        if persisted.cs.name != cs.name {
+            return Err(Error::CipherSuiteMismatch {
+                expected: cs.name,
+                got: persisted.cs.name,
+            });
+        }
+
+        // The tree must be a well-formed nonempty tree that agrees with the roster, and our own
+        // slot in the roster must be filled
+        let num_nodes = persisted.tree.num_nodes();
+        if persisted.roster.len() != persisted.tree.validate_shape()? {
+            return Err(Error::ValidationError(
+                "Persisted roster and tree have different numbers of leaves",
+            ));
+        }
+        if persisted.node_secrets.len() != num_nodes {
+            return Err(Error::ValidationError(
+                "Persisted tree and node secrets have different numbers of nodes",
+            ));
+        }
+        // The secret tree is indexed by leaf, so it has to be the size of the group's tree
+        match &persisted.secret_tree {
+            Some(secret_tree) => secret_tree.validate_persisted(cs, persisted.roster.len())?,
+            None => {
+                return Err(Error::ValidationError(
+                    "Persisted GroupState has no secret tree",
+                ))
+            }
+        }
+        let my_credential = persisted
+            .roster
+            .get(persisted.my_position_in_roster as usize)
+            .ok_or(Error::ValidationError("Persisted roster position is blank"))?;
+
+        // Our identity key has to be the one in our credential
+        let identity_key = cs.sig_impl.secret_key_from_bytes(&persisted.identity_key)?;
+        let identity_public_key = cs.sig_impl.public_key_from_secret_key(&identity_key);
+        if cs.sig_impl.public_key_to_bytes(&identity_public_key)
+            != cs.sig_impl.public_key_to_bytes(my_credential.public_key())
+        {
+            return Err(Error::ValidationError(
+                "Persisted identity key doesn't match my credential",
+            ));
+        }
+
+        // Put the private keys and secrets back into the tree. Private keys only go in filled
+        // nodes, and have to match the public keys that are already there.
+        let mut tree = std::mem::replace(&mut persisted.tree, RatchetTree::new());
+        for (idx, node_secrets) in persisted.node_secrets.iter_mut().enumerate() {
+            if node_secrets.privkey.is_empty() && node_secrets.secret.is_empty() {
+                continue;
+            }
+
+            match tree.get_mut(idx) {
+                Some(RatchetTreeNode::Filled {
+                    pubkey,
+                    privkey,
+                    secret,
+                    ..
+                }) => {
+                    if !node_secrets.privkey.is_empty() {
+                        let persisted_privkey =
+                            cs.dh_impl.scalar_from_bytes(&node_secrets.privkey)?;
+                        let derived_pubkey = cs.dh_impl.multiply_basepoint(&persisted_privkey);
+                        if cs.dh_impl.point_as_bytes(derived_pubkey)
+                            != cs.dh_impl.point_as_bytes(pubkey.clone())
+                        {
+                            return Err(Error::ValidationError(
+                                "Persisted private key doesn't match its public key",
+                            ));
+                        }
+                        *privkey = Some(persisted_privkey);
+                    }
+                    if !node_secrets.secret.is_empty() {
+                        *secret = Some(std::mem::replace(&mut node_secrets.secret, Vec::new()));
+                    }
+                }
+                _ => {
+                    return Err(Error::ValidationError(
+                        "Persisted tree has secrets for a blank node",
+                    ))
+                }
+            }
+        }
+
+        Ok(GroupState {
+            cs: cs,
+            identity_key: identity_key,
+            group_id: std::mem::replace(&mut persisted.group_id, Vec::new()),
+            epoch: persisted.epoch,
+            roster: std::mem::replace(&mut persisted.roster, Roster::new()),
+            tree: tree,
+            transcript_hash: std::mem::replace(&mut persisted.transcript_hash, Vec::new()),
+            my_position_in_roster: persisted.my_position_in_roster,
+            init_secret: std::mem::replace(&mut persisted.init_secret, Vec::new()),
+            application_secret: std::mem::replace(&mut persisted.application_secret, Vec::new()),
+            confirmation_key: std::mem::replace(&mut persisted.confirmation_key, Vec::new()),
+            exporter_secret: std::mem::replace(&mut persisted.exporter_secret, Vec::new()),
+            secret_tree: persisted.secret_tree.take(),
+            used_init_key_ids: std::mem::replace(&mut persisted.used_init_key_ids, Vec::new()),
+            tree_hash_cache: RefCell::new(None),
+        })
     }
 }
 
+/// The private key and secret of a single node of a persisted `GroupState`'s tree. An empty vector
+/// means the node doesn't have one. Neither can be legitimately empty, so this is unambiguous.
+#[derive(Default, Deserialize, Serialize)]
+struct PersistedNodeSecrets {
+    #[serde(rename = "privkey__bound_u8")]
+    privkey: Vec<u8>,
+    #[serde(rename = "secret__bound_u8")]
+    secret: Vec<u8>,
+}
+
+impl Drop for PersistedNodeSecrets {
+    fn drop(&mut self) {
+        self.privkey.zeroize();
+        self.secret.zeroize();
+    }
+}
+
+/// Everything in a `GroupState`, in a form that can be (de)serialized. This is only ever written to
+/// local storage, never sent, so it isn't in the spec. The tree is stored as its public copy, and
+/// the private parts of its nodes are stored alongside, indexed by node.
+#[derive(Deserialize, Serialize)]
+struct PersistedGroupState {
+    cs: &'static CipherSuite,
+    #[serde(rename = "group_id__bound_u8")]
+    group_id: Vec<u8>,
+    epoch: u32,
+    roster: Roster,
+    tree: RatchetTree,
+    #[serde(rename = "node_secrets__bound_u32")]
+    node_secrets: Vec<PersistedNodeSecrets>,
+    #[serde(rename = "transcript_hash__bound_u8")]
+    transcript_hash: Vec<u8>,
+    my_position_in_roster: u32,
+    #[serde(rename = "identity_key__bound_u8")]
+    identity_key: Vec<u8>,
+    #[serde(rename = "init_secret__bound_u8")]
+    init_secret: Vec<u8>,
+    #[serde(rename = "application_secret__bound_u8")]
+    application_secret: Vec<u8>,
+    #[serde(rename = "confirmation_key__bound_u8")]
+    confirmation_key: Vec<u8>,
+    #[serde(rename = "exporter_secret__bound_u8")]
+    exporter_secret: Vec<u8>,
+    secret_tree: Option<SecretTree>,
+    #[serde(rename = "used_init_key_ids__bound_u32")]
+    used_init_key_ids: Vec<InitKeyId>,
+}
+
+// Whatever wasn't moved out into a GroupState gets wiped
+impl Drop for PersistedGroupState {
+    fn drop(&mut self) {
+        self.identity_key.zeroize();
+        self.init_secret.zeroize();
+        self.application_secret.zeroize();
+        self.confirmation_key.zeroize();
+        self.exporter_secret.zeroize();
+    }
+}
+
+/// The secrets that the key schedule derives for a single epoch
+struct EpochSecrets {
+    application_secret: Vec<u8>,
+    confirmation_key: Vec<u8>,
+    exporter_secret: Vec<u8>,
+    init_secret: Vec<u8>,
+}
+
+/// Runs one step of the key schedule in section 5.9 of the spec. `init_secret` is the init secret
+/// of the previous epoch (all zeros for the first one), and `serialized_state` is the serialized
+/// `GroupState` of the new epoch, which every `Derive-Secret` uses as its context.
+fn derive_epoch_secrets(
+    cs: &'static CipherSuite,
+    init_secret: &[u8],
+    update_secret: &[u8],
+    serialized_state: &[u8],
+) -> EpochSecrets {
+    // epoch_secret = HKDF-Extract(salt=init_secret_[n-1] (or 0), ikm=update_secret). We do the
+    // extraction by hand, since ring won't give us the bytes of the PRK.
+    let salt = ring::hmac::SigningKey::new(cs.hash_alg, init_secret);
+    let mut epoch_secret = ring::hmac::sign(&salt, update_secret).as_ref().to_vec();
+
+    // Derive-Secret(epoch_secret, label, GroupState_[n])
+    let state_hash = cs.hash(serialized_state);
+    let derive_secret =
+        |label| cs.hkdf_expand_label(&epoch_secret, label, &state_hash, cs.hash_len());
+
+    let secrets = EpochSecrets {
+        application_secret: derive_secret("app"),
+        confirmation_key: derive_secret("confirm"),
+        exporter_secret: derive_secret("exporter"),
+        init_secret: derive_secret("init"),
+    };
+    epoch_secret.zeroize();
+
+    secrets
+}
+
 /// Contains everything a new user needs to know to join a Group
-#[derive(Serialize)]
-struct WelcomeInfo {
+#[derive(Clone, Serialize)]
+pub(crate) struct WelcomeInfo {
     // opaque group_id<0..255>;
     /// An application-defined identifier for the group
     #[serde(rename = "group_id__bound_u8")]
-    group_id: Vec<u8>,
+    pub(crate) group_id: Vec<u8>,
     /// Represents the current version of the group key
-    epoch: u32,
+    pub(crate) epoch: u32,
     // optional<Credential> roster<1..2^32-1>;
     /// Contains credentials for the occupied slots in the tree, including the identity and
     /// signature public key for the holder of the slot
-    #[serde(rename = "roster__bound_u32")]
-    roster: Vec<Option<Credential>>,
+    pub(crate) roster: Roster,
     // optional<PublicKey> tree<1..2^32-1>;
     /// The tree field contains the public keys corresponding to the nodes of the ratchet tree for
     /// this group. The number of leaves in this tree MUST be equal to the length of `roster`
-    tree: RatchetTree,
+    pub(crate) tree: RatchetTree,
     // opaque transcript_hash<0..255>;
     /// Contains a running hash of `GroupOperation` messages that led to this state
     #[serde(rename = "transcript_hash__bound_u8")]
-    transcript_hash: Vec<u8>,
+    pub(crate) transcript_hash: Vec<u8>,
     // opaque init_secret<0..255>;
     /// The initial secret used to derive all the rest
     #[serde(rename = "init_secret__bound_u8")]
-    init_secret: Vec<u8>,
+    pub(crate) init_secret: Vec<u8>,
+}
+
+impl WelcomeInfo {
+    /// Returns the hash of this `WelcomeInfo`'s serialization. This is what goes in the
+    /// `GroupAdd` for the new member, whose signature vouches for everything in here.
+    ///
+    /// Returns: `Ok(hash)` on success. Otherwise, returns whatever error serialization returns.
+    pub(crate) fn hash(&self, cs: &'static CipherSuite) -> Result<Vec<u8>, Error> {
+        // The serialization has the init secret in it
+        let mut serialized = tls_ser::serialize_to_bytes(self)?;
+        let hash = cs.hash(&serialized);
+        serialized.zeroize();
+
+        Ok(hash)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::{
+        credential::{BasicCredential, Identity},
+        crypto::{
+            ciphersuite::{
+                P256_SHA256_AES128GCM, X25519_SHA256_AES128GCM, X25519_SHA256_CHACHA20POLY1305,
+            },
+            rng::CryptoRng,
+            sig::{SignatureScheme, ED25519_IMPL},
+        },
+        handshake::{
+            test::{
+                make_direct_path_message, make_multi_suite_user_init_key, make_user_init_key,
+                make_user_init_key_with_id, make_user_init_key_with_versions,
+            },
+            GroupInit, GroupOperation, ProtocolVersion,
+        },
+        tls_de::TlsDeserializer,
+        tls_ser,
+    };
+
+    use quickcheck_macros::quickcheck;
+    use rand::{RngCore, SeedableRng};
+    use serde::de::Deserialize;
+
+    // The identities of the members of the groups made by make_group_states
+    const MEMBER_NAMES: &[&[u8]] = &[b"Alice", b"Bob", b"Carol", b"Dave", b"Eve", b"Frank"];
+
+    // Makes the group states of every member of a group of the given size. Every member has a
+    // fresh identity key and leaf keypair, and every non-leaf node of the tree is blank. The group
+    // secrets are derived from an all-zero update secret.
+    pub(crate) fn make_group_states<R: CryptoRng>(
+        cs: &'static CipherSuite,
+        num_members: usize,
+        rng: &mut R,
+    ) -> Vec<GroupState> {
+        assert!(num_members <= MEMBER_NAMES.len(), "not enough member names");
+
+        let mut identity_keys = Vec::new();
+        let mut leaf_privkeys = Vec::new();
+        let mut leaf_pubkeys = Vec::new();
+        let mut roster = Roster::new();
+        for name in MEMBER_NAMES.iter().take(num_members) {
+            let identity_key = cs.sig_impl.secret_key_from_random(rng).unwrap();
+            roster.add(Credential::Basic(BasicCredential {
+                identity: Identity(name.to_vec()),
+                signature_scheme: cs.sig_impl,
+                public_key: cs.sig_impl.public_key_from_secret_key(&identity_key),
+            }));
+            identity_keys.push(identity_key);
+
+            let privkey = cs.dh_impl.scalar_from_random(rng).unwrap();
+            leaf_pubkeys.push(cs.dh_impl.multiply_basepoint(&privkey));
+            leaf_privkeys.push(privkey);
+        }
+
+        let members = identity_keys.into_iter().zip(leaf_privkeys).enumerate();
+        let mut states = Vec::new();
+        for (my_position, (identity_key, my_privkey)) in members {
+            // Everyone knows every leaf's public key, but only their own private key
+            let mut my_privkey = Some(my_privkey);
+            let mut tree = RatchetTree::new();
+            for (i, pubkey) in leaf_pubkeys.iter().enumerate() {
+                tree.add_leaf_node(RatchetTreeNode::Filled {
+                    pubkey: pubkey.clone(),
+                    privkey: if i == my_position {
+                        my_privkey.take()
+                    } else {
+                        None
+                    },
+                    secret: None,
+                    credential: roster.get(i).cloned(),
+                    parent_hash: Vec::new(),
+                });
+            }
+            tree.set_parent_hashes(cs);
+
+            let mut state = GroupState {
+                cs: cs,
+                identity_key: identity_key,
+                group_id: b"test group".to_vec(),
+                epoch: 0,
+                roster: roster.clone(),
+                tree: tree,
+                transcript_hash: cs.zero_secret(),
+                my_position_in_roster: my_position as u32,
+                init_secret: cs.zero_secret(),
+                application_secret: Vec::new(),
+                confirmation_key: Vec::new(),
+                exporter_secret: Vec::new(),
+                secret_tree: None,
+                used_init_key_ids: Vec::new(),
+                tree_hash_cache: RefCell::new(None),
+            };
+            state.derive_new_secrets(&cs.zero_secret());
+            states.push(state);
+        }
+
+        states
+    }
+
+    // Makes a one-member group. See make_group_states.
+    pub(crate) fn make_solo_group_state<R: CryptoRng>(
+        cs: &'static CipherSuite,
+        rng: &mut R,
+    ) -> GroupState {
+        make_group_states(cs, 1, rng).pop().unwrap()
+    }
+
+    #[quickcheck]
+    fn apply_add_correctness(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut state = make_solo_group_state(cs, &mut rng);
+
+        // Add Bob and Carol to Alice's group
+        for identity in &[&b"Bob"[..], &b"Carol"[..]] {
+            let (init_key, _) = make_user_init_key(identity, &mut rng);
+            state
+                .apply_add(&GroupAdd {
+                    init_key,
+                    welcome_info_hash: Vec::new(),
+                })
+                .unwrap();
+        }
+
+        // There should be 3 leaves in the tree, and everyone should be in the roster in the order
+        // they were added
+        assert_eq!(state.tree.num_leaves(), 3);
+        let identities: Vec<&[u8]> = state
+            .roster
+            .iter()
+            .map(|cred| match cred {
+                Some(Credential::Basic(basic_cred)) => basic_cred.identity.0.as_slice(),
+                _ => panic!("expected a basic credential"),
+            })
+            .collect();
+        assert_eq!(identities, vec![&b"Alice"[..], &b"Bob"[..], &b"Carol"[..]]);
+
+        // Carol's leaf is node 4, whose parent is the root, node 3. The root must be blank
+        match state.tree.get(3) {
+            Some(RatchetTreeNode::Blank) => (),
+            _ => panic!("root of the tree should be blank after an add"),
+        }
+
+        // Everyone's leaf holds their credential too, even after Alice re-keys her path
+        state.create_update(&mut rng).unwrap();
+        for (leaf, identity) in [&b"Alice"[..], &b"Bob"[..], &b"Carol"[..]]
+            .iter()
+            .enumerate()
+        {
+            let credential = state.tree.credential(tree_math::leaf_to_node(leaf));
+            assert_eq!(credential.map(Credential::identity), Some(*identity));
+        }
+    }
+
+    // Checks that someone who doesn't speak any version we do can't be added
+    #[quickcheck]
+    fn apply_add_version_mismatch(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut state = make_solo_group_state(cs, &mut rng);
+
+        let (init_key, _) =
+            make_user_init_key_with_versions(b"Bob", vec![ProtocolVersion::Draft02], &mut rng);
+        match state.apply_add(&GroupAdd {
+            init_key,
+            welcome_info_hash: Vec::new(),
+        }) {
+            Err(Error::ValidationError(_)) => (),
+            _ => panic!("expected a version mismatch error"),
+        }
+        assert_eq!(state.roster.len(), 1);
+
+        let versions = vec![ProtocolVersion::Draft02, ProtocolVersion::Draft03];
+        let (init_key, _) = make_user_init_key_with_versions(b"Bob", versions, &mut rng);
+        state
+            .apply_add(&GroupAdd {
+                init_key,
+                welcome_info_hash: Vec::new(),
+            })
+            .unwrap();
+        assert_eq!(state.roster.len(), 2);
+    }
+
+    // Has Alice add Bob to her group, then tampers with the WelcomeInfo she makes for him. Bob
+    // should only join with the WelcomeInfo that Alice's signed GroupAdd vouches for, and only if
+    // its tree matches its parent hashes.
+    #[quickcheck]
+    fn welcome_info_tamper(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut alice = make_solo_group_state(cs, &mut rng);
+
+        let (init_key, mut init_privkeys, bob_identity_key) =
+            make_multi_suite_user_init_key(b"Bob", vec![cs], &mut rng);
+        let bob_credential = init_key.credential.clone();
+        let init_key_id = init_key.user_init_key_id.clone();
+        let bob_init_privkey = init_privkeys.pop().unwrap();
+        let alice_before = alice.clone();
+        let (handshake, welcome) = alice.create_add(init_key, &mut rng).unwrap();
+        let welcome_info = welcome.decrypt(&init_key_id, &bob_init_privkey).unwrap();
+
+        // Everything below is rejected before any keys are looked at, so the rejected joins can
+        // use throwaway keys
+        let mut try_join = |welcome_info: WelcomeInfo, handshake: &Handshake| {
+            GroupState::from_welcome_info(
+                cs,
+                welcome_info,
+                handshake,
+                &bob_credential,
+                cs.dh_impl.scalar_from_random(&mut rng).unwrap(),
+                ED25519_IMPL.secret_key_from_random(&mut rng).unwrap(),
+            )
+        };
+        // Puts the given hash in the GroupAdd of the given Handshake
+        let with_hash = |handshake: &Handshake, hash: Vec<u8>| {
+            let mut handshake = handshake.clone();
+            if let GroupOperation::Add(add) = &mut handshake.operation {
+                add.welcome_info_hash = hash;
+            }
+            handshake
+        };
+
+        let tampers: &[fn(&mut WelcomeInfo)] = &[
+            // A transcript hash from some other state
+            |w| w.transcript_hash[0] ^= 1,
+            // A different epoch
+            |w| w.epoch += 1,
+            // A different init secret
+            |w| w.init_secret[0] ^= 1,
+            // Alice's leaf with Bob's public key in it
+            |w| {
+                let bob_pubkey = match w.tree.get(2) {
+                    Some(RatchetTreeNode::Filled { pubkey, .. }) => pubkey.clone(),
+                    _ => unreachable!(),
+                };
+                if let Some(RatchetTreeNode::Filled { pubkey, .. }) = w.tree.get_mut(0) {
+                    *pubkey = bob_pubkey;
+                }
+            },
+        ];
+        for tamper in tampers {
+            let mut tampered = welcome_info.clone();
+            tamper(&mut tampered);
+
+            // Alice's GroupAdd doesn't vouch for the tampered WelcomeInfo
+            match try_join(tampered.clone(), &handshake) {
+                Err(Error::ConfirmationMismatch) => (),
+                _ => panic!("expected a confirmation mismatch"),
+            }
+
+            // Recomputing the hash in the GroupAdd doesn't help, since Alice didn't sign that
+            let forged = with_hash(&handshake, tampered.hash(cs).unwrap());
+            match try_join(tampered, &forged) {
+                Err(Error::SignatureError(_)) => (),
+                _ => panic!("expected a signature error"),
+            }
+        }
+
+        // Even a WelcomeInfo that Alice vouches for is refused if its tree doesn't match its
+        // parent hashes
+        let mut bad_tree = welcome_info.clone();
+        tampers[3](&mut bad_tree);
+        let add = GroupAdd {
+            init_key: match &handshake.operation {
+                GroupOperation::Add(add) => add.init_key.clone(),
+                _ => unreachable!(),
+            },
+            welcome_info_hash: bad_tree.hash(cs).unwrap(),
+        };
+        let signed = Handshake::from_group_op(cs, &alice_before, GroupOperation::Add(add));
+        match try_join(bad_tree, &signed) {
+            Err(Error::ParentHashMismatch) => (),
+            _ => panic!("expected a parent hash mismatch"),
+        }
+
+        // Untampered, it goes through
+        let res = GroupState::from_welcome_info(
+            cs,
+            welcome_info,
+            &handshake,
+            &bob_credential,
+            bob_init_privkey,
+            bob_identity_key,
+        );
+        assert!(res.is_ok());
+    }
+
+    // Checks that a WelcomeInfo whose roster doesn't line up with its tree is rejected as soon as
+    // it's deserialized
+    #[quickcheck]
+    fn welcome_info_leaf_count_mismatch(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let states = make_group_states(cs, 3, &mut rng);
+
+        let mut welcome_info = states[0].make_welcome_info();
+        let extra_credential = welcome_info.roster.get(0).unwrap().clone();
+        welcome_info.roster.add(extra_credential);
+
+        let bytes = tls_ser::serialize_to_bytes(&welcome_info).unwrap();
+        let mut buf = bytes.as_slice();
+        let mut deserializer = TlsDeserializer::from_reader(&mut buf);
+        assert!(WelcomeInfo::deserialize(&mut deserializer).is_err());
+    }
+
+    // Has Alice add Bob to her group and send him a Welcome. Bob's reconstructed state should agree
+    // with Alice's, and they should agree on the secrets of the next epoch.
+    #[quickcheck]
+    fn welcome_info_correctness(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut alice = make_solo_group_state(cs, &mut rng);
+
+        let (init_key, mut init_privkeys, bob_identity_key) =
+            make_multi_suite_user_init_key(b"Bob", vec![cs], &mut rng);
+        let bob_credential = init_key.credential.clone();
+        let init_key_id = init_key.user_init_key_id.clone();
+        let bob_init_privkey = init_privkeys.pop().unwrap();
+
+        let alice_before = alice.clone();
+        let (handshake, welcome) = alice.create_add(init_key, &mut rng).unwrap();
+
+        // The WelcomeInfo in the Welcome should be of Alice's group with Bob added, but before the
+        // epoch changes
+        let welcome_info = welcome
+            .clone()
+            .decrypt(&init_key_id, &bob_init_privkey)
+            .unwrap();
+        let mut alice_added = alice_before.clone();
+        alice_added
+            .apply_add(match &handshake.operation {
+                GroupOperation::Add(add) => add,
+                _ => unreachable!(),
+            })
+            .unwrap();
+        assert_eq!(
+            tls_ser::serialize_to_bytes(&welcome_info).unwrap(),
+            tls_ser::serialize_to_bytes(&alice_added.make_welcome_info()).unwrap()
+        );
+
+        // The WelcomeInfo should survive a roundtrip over the wire, and shouldn't contain anything
+        // private
+        let welcome_info = {
+            let bytes = tls_ser::serialize_to_bytes(&welcome_info).unwrap();
+            let mut buf = bytes.as_slice();
+            let mut deserializer = TlsDeserializer::from_reader(&mut buf);
+            WelcomeInfo::deserialize(&mut deserializer).unwrap()
+        };
+        for idx in 0..3 {
+            match welcome_info.tree.get(idx) {
+                Some(RatchetTreeNode::Filled {
+                    privkey: Some(_), ..
+                })
+                | Some(RatchetTreeNode::Filled {
+                    secret: Some(_), ..
+                }) => panic!("WelcomeInfo contains secrets"),
+                _ => (),
+            }
+        }
+
+        // Bob ends up in the same epoch as Alice, with the same secrets
+        let bob = GroupState::from_welcome(
+            cs,
+            welcome,
+            &handshake,
+            &bob_credential,
+            bob_init_privkey,
+            bob_identity_key,
+        )
+        .unwrap();
+        assert_eq!(bob.my_position_in_roster, 1);
+        assert_eq!(bob.epoch, alice_before.epoch + 1);
+        assert_eq!(bob.epoch, alice.epoch);
+        assert_eq!(bob.transcript_hash, alice.transcript_hash);
+        assert_eq!(
+            tls_ser::serialize_to_bytes(&bob).unwrap(),
+            tls_ser::serialize_to_bytes(&alice).unwrap()
+        );
+        assert_eq!(bob.application_secret, alice.application_secret);
+        assert_eq!(bob.init_secret, alice.init_secret);
+    }
+
+    // Adds a member who advertises both an X25519 and a P-256 init key to a P-256 group, then has
+    // Alice update. The new member should be able to decrypt the root secret from Alice's path
+    // using their P-256 init key, and it should match the root secret Bob computes.
+    #[quickcheck]
+    fn cross_suite_add_correctness(rng_seed: u64) {
+        let cs = &P256_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut states = make_group_states(cs, 2, &mut rng);
+
+        let (init_key, mut init_privkeys, _) =
+            make_multi_suite_user_init_key(b"Carol", vec![&X25519_SHA256_AES128GCM, cs], &mut rng);
+        init_key.verify_signature().unwrap();
+        let carol_privkey = init_privkeys.pop().unwrap();
+
+        let add = GroupAdd {
+            init_key,
+            welcome_info_hash: Vec::new(),
+        };
+        for state in states.iter_mut() {
+            state.apply_add(&add).unwrap();
+        }
+
+        // Alice updates. Carol is leaf 2, i.e., node 4, which is the resolution of the last
+        // copath node of Alice's path. So the root secret is encrypted directly to Carol.
+        let leaf_secret = cs.generate_leaf_secret(&mut rng);
+        let update = GroupUpdate {
+            path: DirectPathMessage::from_path(cs, &states[0].tree, 0, &leaf_secret, &mut rng)
+                .unwrap(),
+        };
+        let root_msg = update.path.node_messages.last().unwrap();
+        assert_eq!(root_msg.node_secrets.len(), 1);
+        let carol_root_secret =
+            ecies::EciesCiphertext::decrypt(cs, &carol_privkey, root_msg.node_secrets[0].clone())
+                .unwrap();
+
+        // Carol's secret should match the new root public key, and Bob's view of the root secret
+        let (derived_root_pubkey, _) = cs.derive_key_pair(&carol_root_secret).unwrap();
+        assert_eq!(
+            cs.dh_impl.point_as_bytes(derived_root_pubkey),
+            cs.dh_impl.point_as_bytes(root_msg.public_key.clone())
+        );
+        let bob_root_secret = states[1].apply_update(&update, 0).unwrap();
+        assert_eq!(carol_root_secret, bob_root_secret);
+    }
+
+    // Removes a member from a group of 3 and checks that the remaining members agree on the new
+    // root secret
+    #[quickcheck]
+    fn apply_remove_correctness(removed: u32, rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut states = make_group_states(cs, 3, &mut rng);
+
+        // Pick someone to remove who isn't Alice. Alice does the removing.
+        let removed = 1 + (removed % 2);
+        let remove = {
+            let leaf_secret = cs.generate_leaf_secret(&mut rng);
+            let path = DirectPathMessage::from_path(
+                cs,
+                &states[0].tree,
+                removed as usize,
+                &leaf_secret,
+                &mut rng,
+            )
+            .unwrap();
+            GroupRemove { removed, path }
+        };
+
+        let root_secrets: Vec<Vec<u8>> = states
+            .iter_mut()
+            .enumerate()
+            .filter(|&(i, _)| i != removed as usize)
+            .map(|(_, state)| state.apply_remove(&remove).unwrap())
+            .collect();
+        assert_eq!(root_secrets[0], root_secrets[1]);
+
+        let alice = &states[0];
+        if removed == 1 {
+            // Removing Bob leaves a hole in the middle of the tree
+            assert_eq!(alice.tree.num_leaves(), 3);
+            assert!(alice.roster.get(1).is_none());
+            assert_eq!(alice.roster.len(), 3);
+            match alice.tree.get(2) {
+                Some(RatchetTreeNode::Blank) => (),
+                _ => panic!("removed leaf should be blank"),
+            }
+        } else {
+            // Removing Carol makes the tree shrink
+            assert_eq!(alice.tree.num_leaves(), 2);
+            assert_eq!(alice.roster.len(), 2);
+        }
+
+        // Removing someone twice is an error
+        match states[0].apply_remove(&remove) {
+            Err(Error::ValidationError(_)) => (),
+            _ => panic!("expected a validation error"),
+        }
+    }
+
+    // Checks that indices from a malicious peer that lie outside the tree are rejected with an
+    // error instead of making the tree math panic
+    #[quickcheck]
+    fn apply_remove_out_of_range(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut states = make_group_states(cs, 3, &mut rng);
+
+        // A legitimate path from Carol's leaf, which we'll pair with bogus indices
+        let path = {
+            let leaf_secret = cs.generate_leaf_secret(&mut rng);
+            DirectPathMessage::from_path(cs, &states[0].tree, 2, &leaf_secret, &mut rng).unwrap()
+        };
+
+        for &removed in &[3, 9999, std::u32::MAX] {
+            let remove = GroupRemove {
+                removed,
+                path: path.clone(),
+            };
+            match states[0].apply_remove(&remove) {
+                Err(Error::ValidationError(_)) => (),
+                _ => panic!("expected a validation error"),
+            }
+            match states[0].apply_direct_path(removed as usize, &path) {
+                Err(Error::ValidationError(_)) => (),
+                _ => panic!("expected a validation error"),
+            }
+        }
+
+        // Nothing was touched
+        assert_eq!(states[0].tree.num_leaves(), 3);
+        assert_eq!(states[0].roster.len_active(), 3);
+    }
+
+    // Checks that a removal whose path turns out to be bad only after the removed member has been
+    // blanked out doesn't change the tree or the roster, and that the good removal still works
+    #[quickcheck]
+    fn apply_remove_bad_path_atomic(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut states = make_group_states(cs, 3, &mut rng);
+
+        // Alice removes Carol. Then swap out the root's public key in her path for someone else's.
+        let (_, remove) = states[0].create_remove(2, &mut rng).unwrap();
+        let mut bad_remove = remove.clone();
+        let privkey = cs.dh_impl.scalar_from_random(&mut rng).unwrap();
+        bad_remove.path.node_messages.last_mut().unwrap().public_key =
+            cs.dh_impl.multiply_basepoint(&privkey);
+
+        let old_tree_bytes = tls_ser::serialize_to_bytes(&states[1].tree).unwrap();
+        let old_roster_bytes = tls_ser::serialize_to_bytes(&states[1].roster).unwrap();
+        match states[1].apply_remove(&bad_remove) {
+            Err(Error::ValidationError(msg)) => {
+                assert_eq!(msg, "DirectPathMessage public key doesn't match its secret")
+            }
+            _ => panic!("expected a mismatched public key to be rejected"),
+        }
+        assert_eq!(
+            tls_ser::serialize_to_bytes(&states[1].tree).unwrap(),
+            old_tree_bytes
+        );
+        assert_eq!(
+            tls_ser::serialize_to_bytes(&states[1].roster).unwrap(),
+            old_roster_bytes
+        );
+
+        // Bob can still process the real thing, and gets the same tree Alice did
+        states[1].apply_remove(&remove).unwrap();
+        assert_eq!(states[1].roster.len(), 2);
+        assert_eq!(
+            tls_ser::serialize_to_bytes(&states[1].tree).unwrap(),
+            tls_ser::serialize_to_bytes(&states[0].tree).unwrap()
+        );
+    }
+
+    // Has every member of a group of 3 update in turn, and checks that everyone agrees on the new
+    // secrets after every update. This is done in every ciphersuite.
+    #[quickcheck]
+    fn apply_update_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        // Run through every suite, so the whole handshake path gets exercised with each AEAD
+        for cs in &[
+            X25519_SHA256_AES128GCM,
+            P256_SHA256_AES128GCM,
+            X25519_SHA256_CHACHA20POLY1305,
+        ] {
+            let mut states = make_group_states(cs, 3, &mut rng);
+
+            for updater in 0..states.len() {
+                let leaf_secret = cs.generate_leaf_secret(&mut rng);
+
+                // The updater remembers their new leaf secret, then makes the path
+                let update = {
+                    let updater_state = &mut states[updater];
+                    let leaf_idx = tree_math::leaf_to_node(updater);
+                    match updater_state.tree.get_mut(leaf_idx) {
+                        Some(RatchetTreeNode::Filled { secret, .. }) => {
+                            *secret = Some(leaf_secret.clone())
+                        }
+                        _ => panic!("updater's leaf is blank"),
+                    }
+
+                    let path = DirectPathMessage::from_path(
+                        cs,
+                        &updater_state.tree,
+                        updater,
+                        &leaf_secret,
+                        &mut rng,
+                    )
+                    .unwrap();
+                    GroupUpdate { path }
+                };
+                let handshake =
+                    Handshake::from_group_op(cs, &states[updater], GroupOperation::Update(update));
+                let update = match &handshake.operation {
+                    GroupOperation::Update(update) => update,
+                    _ => unreachable!(),
+                };
+
+                for state in states.iter_mut() {
+                    let update_secret = state.apply_update(update, updater as u32).unwrap();
+                    state.update_transcript_hash(&handshake).unwrap();
+                    state.advance_epoch(&update_secret);
+                }
+
+                // Everyone should be in the same epoch with the same secrets
+                for state in states.iter() {
+                    assert_eq!(state.epoch, updater as u32 + 1);
+                    assert_eq!(state.application_secret, states[0].application_secret);
+                    assert_eq!(state.init_secret, states[0].init_secret);
+                    assert_eq!(state.transcript_hash, states[0].transcript_hash);
+                }
+            }
+        }
+    }
+
+    // Checks that a path is rejected if the receiver's tree isn't the one the sender saw. A path
+    // with tampered parent hashes is one of the cases in process_handshake_rejections.
+    #[quickcheck]
+    fn direct_path_parent_hash_mismatch(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut states = make_group_states(cs, 3, &mut rng);
+
+        let leaf_secret = cs.generate_leaf_secret(&mut rng);
+        let path =
+            DirectPathMessage::from_path(cs, &states[0].tree, 0, &leaf_secret, &mut rng).unwrap();
+
+        // Give Bob a different key for Carol's leaf, which is in the resolution of the sibling of
+        // node 1. Bob can still decrypt everything, but node 1's parent hash comes out different.
+        let mut tampered_state = states[1].clone();
+        if let Some(RatchetTreeNode::Filled { pubkey, .. }) = tampered_state.tree.get_mut(4) {
+            *pubkey = cs.dh_impl.generate_keypair(&mut rng).unwrap().0;
+        }
+        tampered_state.tree.set_parent_hashes(cs);
+        let update = GroupUpdate { path };
+        match tampered_state.apply_update(&update, 0) {
+            Err(Error::ParentHashMismatch) => (),
+            _ => panic!("expected a tampered tree to be rejected"),
+        }
+
+        assert!(states[1].apply_update(&update, 0).is_ok());
+        assert!(states[1].tree.verify_parent_hashes(cs).is_ok());
+    }
+
+    // Checks that a freshly created group has just us in it, and that creating it is deterministic
+    #[quickcheck]
+    fn create_correctness(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        let mut identity_key_bytes = [0u8; 32];
+        rng.fill_bytes(&mut identity_key_bytes);
+        let mut leaf_seed = [0u8; 32];
+        rng.fill_bytes(&mut leaf_seed);
+
+        // Both states draw their leaf secret from identically seeded RNGs
+        let make_state = || {
+            let identity_key = cs
+                .sig_impl
+                .secret_key_from_bytes(&identity_key_bytes)
+                .unwrap();
+            let credential = Credential::Basic(BasicCredential {
+                identity: Identity(b"Alice".to_vec()),
+                signature_scheme: cs.sig_impl,
+                public_key: cs.sig_impl.public_key_from_secret_key(&identity_key),
+            });
+            GroupState::create(
+                cs,
+                b"test group".to_vec(),
+                credential,
+                identity_key,
+                &mut rand::rngs::StdRng::from_seed(leaf_seed),
+            )
+            .unwrap()
+        };
+        let state1 = make_state();
+        let state2 = make_state();
+
+        assert_eq!(state1.tree.num_leaves(), 1);
+        assert_eq!(state1.roster.len(), 1);
+        assert_eq!(state1.my_position_in_roster, 0);
+        assert_eq!(state1.epoch, 0);
+
+        assert_eq!(state1.confirmation_key, state2.confirmation_key);
+        assert_eq!(state1.application_secret, state2.application_secret);
+
+        // The leaf secret is a fresh one of the right length
+        match state1.tree.get(0) {
+            Some(RatchetTreeNode::Filled {
+                secret: Some(secret),
+                ..
+            }) => assert_eq!(secret.len(), cs.hash_len()),
+            _ => panic!("our leaf should know its secret"),
+        }
+
+        // The group should be usable right away. Updating our own leaf should work.
+        let mut state = state1;
+        state.create_update(&mut rng).unwrap();
+        assert_eq!(state.epoch, 1);
+    }
+
+    // Has Alice sign a bunch of bad Handshakes, and checks that Bob rejects each one with the error
+    // for what's wrong with it, leaving his state exactly as it was. Bob is restored from persisted
+    // bytes first, so this also checks what survives persistence, like the used init key IDs.
+    #[quickcheck]
+    fn process_handshake_rejections(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut states = make_group_states(cs, 4, &mut rng);
+
+        // Alice adds Eve with a fresh init key, then removes Carol, which blanks roster slot 2
+        let (init_key, _) = make_user_init_key_with_id(b"Eve", b"eve's key".to_vec(), &mut rng);
+        let (add_handshake, _) = states[0].create_add(init_key, &mut rng).unwrap();
+        states[1].process_handshake(&add_handshake).unwrap();
+        let (remove_handshake, _) = states[0].create_remove(2, &mut rng).unwrap();
+        states[1].process_handshake(&remove_handshake).unwrap();
+        let mut bob = GroupState::from_bytes(&states[1].to_bytes(), cs).unwrap();
+        assert_eq!(bob.epoch, 2);
+
+        // A good update and a good removal of Dave to tamper with. Both are signed by Alice.
+        let alice = &states[0];
+        let sign = |op| Handshake::from_group_op(cs, alice, op);
+        let leaf_secret = cs.generate_leaf_secret(&mut rng);
+        let path =
+            DirectPathMessage::from_path(cs, &alice.tree, 0, &leaf_secret, &mut rng).unwrap();
+        let path_len = path.node_messages.len();
+        let (_, remove) = alice.clone().create_remove(3, &mut rng).unwrap();
+        let remove_path_len = remove.path.node_messages.len();
+
+        let mut truncated = path.clone();
+        truncated.node_messages.pop();
+        let mut extended = path.clone();
+        extended.node_messages.push(path.node_messages[1].clone());
+        let mut leaf_ciphertexts = path.clone();
+        let ciphertext = path.node_messages[1].node_secrets[0].clone();
+        leaf_ciphertexts.node_messages[0]
+            .node_secrets
+            .push(ciphertext);
+        let mut wrong_parent_hash = path.clone();
+        wrong_parent_hash.node_messages[1].parent_hash[0] ^= 1;
+        let mut truncated_remove = remove.clone();
+        truncated_remove.path.node_messages.pop();
+        let mut wrong_public_key_remove = remove.clone();
+        wrong_public_key_remove
+            .path
+            .node_messages
+            .last_mut()
+            .unwrap()
+            .public_key = cs.dh_impl.generate_keypair(&mut rng).unwrap().0;
+
+        let update = |path| sign(GroupOperation::Update(GroupUpdate { path }));
+        let with_signer = |signer_index| {
+            let mut handshake = update(path.clone());
+            handshake.signer_index = signer_index;
+            handshake
+        };
+        let mut future = update(path.clone());
+        future.prior_epoch += 1;
+        let (reused_init_key, _) =
+            make_user_init_key_with_id(b"Frank", b"eve's key".to_vec(), &mut rng);
+        let reused_add = sign(GroupOperation::Add(GroupAdd {
+            init_key: reused_init_key,
+            welcome_info_hash: Vec::new(),
+        }));
+
+        let rejections: Vec<(&str, Handshake, Box<dyn Fn(&Error) -> bool>)> = vec![
+            (
+                "replayed add",
+                add_handshake.clone(),
+                Box::new(|e| match e {
+                    Error::StaleEpoch {
+                        expected: 2,
+                        got: 0,
+                    } => true,
+                    _ => false,
+                }),
+            ),
+            (
+                "future epoch",
+                future,
+                Box::new(|e| match e {
+                    Error::StaleEpoch {
+                        expected: 2,
+                        got: 3,
+                    } => true,
+                    _ => false,
+                }),
+            ),
+            (
+                "removed signer",
+                with_signer(2),
+                Box::new(|e| match e {
+                    Error::InvalidSigner => true,
+                    _ => false,
+                }),
+            ),
+            (
+                "out-of-range signer",
+                with_signer(5),
+                Box::new(|e| match e {
+                    Error::InvalidSigner => true,
+                    _ => false,
+                }),
+            ),
+            (
+                "GroupInit",
+                sign(GroupOperation::Init(GroupInit)),
+                Box::new(|e| match e {
+                    Error::ValidationError(_) => true,
+                    _ => false,
+                }),
+            ),
+            (
+                "reused init key",
+                reused_add,
+                Box::new(|e| match e {
+                    Error::ReusedInitKey => true,
+                    _ => false,
+                }),
+            ),
+            (
+                "truncated path",
+                update(truncated),
+                Box::new(move |e| match e {
+                    Error::PathLengthMismatch { expected, got } => {
+                        *expected == path_len && *got == path_len - 1
+                    }
+                    _ => false,
+                }),
+            ),
+            (
+                "extended path",
+                update(extended),
+                Box::new(move |e| match e {
+                    Error::PathLengthMismatch { expected, got } => {
+                        *expected == path_len && *got == path_len + 1
+                    }
+                    _ => false,
+                }),
+            ),
+            (
+                "truncated removal path",
+                sign(GroupOperation::Remove(truncated_remove)),
+                Box::new(move |e| match e {
+                    Error::PathLengthMismatch { expected, got } => {
+                        *expected == remove_path_len && *got == remove_path_len - 1
+                    }
+                    _ => false,
+                }),
+            ),
+            (
+                "ciphertexts for the sender's leaf",
+                update(leaf_ciphertexts),
+                Box::new(|e| match e {
+                    Error::MalformedPath(_) => true,
+                    _ => false,
+                }),
+            ),
+            (
+                "wrong public key in a removal",
+                sign(GroupOperation::Remove(wrong_public_key_remove)),
+                Box::new(|e| match e {
+                    Error::ValidationError(msg) => {
+                        *msg == "DirectPathMessage public key doesn't match its secret"
+                    }
+                    _ => false,
+                }),
+            ),
+            (
+                "wrong parent hash",
+                update(wrong_parent_hash),
+                Box::new(|e| match e {
+                    Error::ParentHashMismatch => true,
+                    _ => false,
+                }),
+            ),
+        ];
+
+        let old_bob_bytes = bob.to_bytes();
+        for (desc, handshake, is_expected) in rejections.iter() {
+            match bob.process_handshake(handshake) {
+                Err(e) => assert!(is_expected(&e), "{}: unexpected error: {}", desc, e),
+                Ok(()) => panic!("{}: the Handshake was accepted", desc),
+            }
+            assert_eq!(
+                bob.to_bytes(),
+                old_bob_bytes,
+                "{}: Bob's state changed",
+                desc
+            );
+        }
+
+        // The untampered operations still go through
+        bob.clone()
+            .process_handshake(&sign(GroupOperation::Remove(remove)))
+            .unwrap();
+        bob.process_handshake(&update(path)).unwrap();
+        assert_eq!(bob.epoch, 3);
+    }
+
+    // Makes the given member update their path and has everyone process the resulting Handshake
+    fn update_everyone<R: CryptoRng>(states: &mut [GroupState], updater: usize, rng: &mut R) {
+        let (handshake, _) = states[updater].create_update(rng).unwrap();
+        for (i, state) in states.iter_mut().enumerate() {
+            if i != updater {
+                state.process_handshake(&handshake).unwrap();
+            }
+        }
+    }
+
+    // Has Dave join a group of 3 by a WelcomeInfo, and checks that he knows no secrets on his path
+    // until he updates
+    #[quickcheck]
+    fn uninitialized_path_after_join(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut states = make_group_states(cs, 3, &mut rng);
+
+        let (init_key, mut init_privkeys, dave_identity_key) =
+            make_multi_suite_user_init_key(b"Dave", vec![cs], &mut rng);
+        let dave_credential = init_key.credential.clone();
+        let (handshake, welcome) = states[0].create_add(init_key, &mut rng).unwrap();
+        for state in states.iter_mut().skip(1) {
+            state.process_handshake(&handshake).unwrap();
+        }
+
+        // Alice welcomes Dave into the group, which puts him in the same epoch as everyone else
+        let dave = GroupState::from_welcome(
+            cs,
+            welcome,
+            &handshake,
+            &dave_credential,
+            init_privkeys.pop().unwrap(),
+            dave_identity_key,
+        )
+        .unwrap();
+        states.push(dave);
+
+        // Dave is leaf 3, i.e., node 6. Everything above him is unknown to him: node 5 and the
+        // root, node 3.
+        assert_eq!(states[3].tree.uninitialized_path_nodes(3), vec![5, 3]);
+        assert!(states[3].needs_update());
+
+        // Once he updates, he knows his whole path
+        update_everyone(&mut states, 3, &mut rng);
+        assert_eq!(
+            states[3].tree.uninitialized_path_nodes(3),
+            Vec::<usize>::new()
+        );
+        assert!(!states[3].needs_update());
+        assert_eq!(states[0].application_secret, states[3].application_secret);
+    }
+
+    // Drives a group of three through an add, an update, and a remove, where every member applies
+    // every Handshake with process_handshake, and checks that they all stay in sync
+    #[quickcheck]
+    fn process_handshake_lifecycle(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut states = make_group_states(cs, 3, &mut rng);
+
+        fn assert_in_sync(states: &[GroupState], expected_epoch: u32, expected_roster_len: usize) {
+            for state in states.iter() {
+                assert_eq!(state.epoch, expected_epoch);
+                assert_eq!(state.roster.len(), expected_roster_len);
+                assert_eq!(state.application_secret, states[0].application_secret);
+                assert_eq!(state.init_secret, states[0].init_secret);
+                assert_eq!(state.transcript_hash, states[0].transcript_hash);
+                assert_eq!(state.tree_hash(), states[0].tree_hash());
+            }
+        }
+
+        // Alice adds Dave. She applies the add as she makes it, and everyone else processes it.
+        let (init_key, _) = make_user_init_key(b"Dave", &mut rng);
+        let (handshake, _) = states[0].create_add(init_key, &mut rng).unwrap();
+        for state in states.iter_mut().skip(1) {
+            state.process_handshake(&handshake).unwrap();
+        }
+        assert_in_sync(&states, 1, 4);
+
+        // Bob updates. The sender applies their own update as they make it.
+        update_everyone(&mut states, 1, &mut rng);
+        assert_in_sync(&states, 2, 4);
+
+        // Alice removes Carol
+        let (handshake, _) = states[0].create_remove(2, &mut rng).unwrap();
+        let mut carol = states.remove(2);
+        states[1].process_handshake(&handshake).unwrap();
+        assert_in_sync(&states, 3, 4);
+        assert!(states[0].roster.get(2).is_none());
+
+        // Carol is out, and every Handshake was applied exactly once
+        assert!(carol.process_handshake(&handshake).is_err());
+        for state in states.iter_mut() {
+            assert!(state.process_handshake(&handshake).is_err());
+        }
+    }
+
+    // Has Alice remove Carol from a group of three. Bob should end up in the same epoch as Alice,
+    // and nothing in the removal should be decryptable with any private key Carol knows.
+    #[quickcheck]
+    fn create_remove_correctness(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut states = make_group_states(cs, 3, &mut rng);
+
+        // Give everyone some private keys above their leaves, so Carol has more to try
+        update_everyone(&mut states, 2, &mut rng);
+        let mut carol = states.pop().unwrap();
+
+        let (handshake, remove) = states[0].create_remove(2, &mut rng).unwrap();
+        states[1].process_handshake(&handshake).unwrap();
+
+        let (alice, bob) = (&states[0], &states[1]);
+        assert_eq!(alice.epoch, bob.epoch);
+        assert_eq!(alice.application_secret, bob.application_secret);
+        assert_eq!(alice.init_secret, bob.init_secret);
+        assert_eq!(alice.roster.len(), 2);
+        assert!(bob.roster.get(2).is_none());
+
+        // Carol can't process the removal, and can't decrypt any of the new path secrets
+        let carol_privkeys: Vec<&DhScalar> = (0..carol.tree.num_nodes())
+            .filter_map(|idx| match carol.tree.get(idx) {
+                Some(RatchetTreeNode::Filled {
+                    privkey: Some(privkey),
+                    ..
+                }) => Some(privkey),
+                _ => None,
+            })
+            .collect();
+        assert!(!carol_privkeys.is_empty());
+        for node_msg in remove.path.node_messages.iter() {
+            for ciphertext in node_msg.node_secrets.iter() {
+                for privkey in carol_privkeys.iter() {
+                    assert!(
+                        ecies::EciesCiphertext::decrypt(cs, privkey, ciphertext.clone()).is_err()
+                    );
+                }
+            }
+        }
+        assert!(carol.process_handshake(&handshake).is_err());
+
+        // Nobody can remove themselves, or someone who isn't there
+        assert!(states[0].create_remove(0, &mut rng).is_err());
+        assert!(states[0].create_remove(2, &mut rng).is_err());
+    }
+
+    // Checks that members who inject the same PSK stay in sync, and that a member who injects a
+    // different PSK, a different PSK ID, or no PSK at all falls out of sync
+    #[quickcheck]
+    fn inject_psk_correctness(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut states = make_group_states(cs, 5, &mut rng);
+
+        states[0].inject_psk(b"hunter2", b"psk id");
+        states[1].inject_psk(b"hunter2", b"psk id");
+        states[2].inject_psk(b"hunter3", b"psk id");
+        states[3].inject_psk(b"hunter2", b"other psk id");
+        update_everyone(&mut states, 0, &mut rng);
+
+        assert_eq!(states[0].application_secret, states[1].application_secret);
+        for state in states[2..].iter() {
+            assert_eq!(state.epoch, states[0].epoch);
+            assert_ne!(state.application_secret, states[0].application_secret);
+        }
+
+        // Out-of-sync members can't even verify the next Handshake, since their confirmation keys
+        // differ. The in-sync ones carry on.
+        let (handshake, _) = states[1].create_update(&mut rng).unwrap();
+        states[0].process_handshake(&handshake).unwrap();
+        assert_eq!(states[0].application_secret, states[1].application_secret);
+        for state in states[2..].iter_mut() {
+            assert!(state.process_handshake(&handshake).is_err());
+        }
+    }
+
+    // Checks that everyone in the same epoch exports the same secrets, that the label, context, and
+    // length all matter, and that the exported secrets change with the epoch
+    #[quickcheck]
+    fn export_secret_correctness(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut states = make_group_states(cs, 3, &mut rng);
+
+        let exported = states[0].export_secret("video call", b"call 1", 32);
+        assert_eq!(exported.len(), 32);
+        assert_eq!(
+            exported,
+            states[0].export_secret("video call", b"call 1", 32)
+        );
+        for state in states[1..].iter() {
+            assert_eq!(exported, state.export_secret("video call", b"call 1", 32));
+        }
+
+        assert_ne!(
+            exported,
+            states[0].export_secret("voice call", b"call 1", 32)
+        );
+        assert_ne!(
+            exported,
+            states[0].export_secret("video call", b"call 2", 32)
+        );
+        let longer = states[0].export_secret("video call", b"call 1", 64);
+        assert_eq!(longer.len(), 64);
+
+        // Next epoch, new secrets. Everyone still agrees.
+        update_everyone(&mut states, 1, &mut rng);
+        let next_exported = states[0].export_secret("video call", b"call 1", 32);
+        assert_ne!(exported, next_exported);
+        for state in states[1..].iter() {
+            assert_eq!(
+                next_exported,
+                state.export_secret("video call", b"call 1", 32)
+            );
+        }
+
+        // A restored state exports the same thing
+        let restored = GroupState::from_bytes(&states[2].to_bytes(), cs).unwrap();
+        assert_eq!(
+            next_exported,
+            restored.export_secret("video call", b"call 1", 32)
+        );
+    }
+
+    // Checks who has to decrypt a path on a tree with blank leaves in the middle and blank
+    // intermediate nodes, where the answer isn't every leaf of the tree. It should be exactly the
+    // members who can decrypt a path sent from that leaf.
+    #[quickcheck]
+    fn members_affected_by_path_correctness(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut states = make_group_states(cs, 6, &mut rng);
+
+        // Give the tree some non-blank intermediate nodes, so resolutions aren't just leaves
+        update_everyone(&mut states, 0, &mut rng);
+        update_everyone(&mut states, 4, &mut rng);
+
+        // Alice removes Bob and then Dave. Neither is on the right edge, so their leaves stay in
+        // the tree as blanks, and so do the nodes above them.
+        for &removed in &[1, 3] {
+            let (handshake, _) = states[0].create_remove(removed, &mut rng).unwrap();
+            states.retain(|state| state.my_position_in_roster != removed);
+            for state in states.iter_mut().skip(1) {
+                state.process_handshake(&handshake).unwrap();
+            }
+        }
+        let positions: Vec<u32> = states.iter().map(|s| s.my_position_in_roster).collect();
+        assert_eq!(positions, vec![0, 2, 4, 5]);
+        assert_eq!(states[0].tree.num_leaves(), 6);
+
+        // The blank leaves don't count, whether they're next to the sender or under a blank node
+        assert_eq!(states[0].members_affected_by_path(0), vec![2, 4, 5]);
+        assert_eq!(states[0].members_affected_by_path(5), vec![0, 2, 4]);
+        // A path from a blank leaf, like in a removal, goes to everyone who's left
+        assert_eq!(states[0].members_affected_by_path(1), vec![0, 2, 4, 5]);
+
+        // Now make a real path from every leaf, and see who can decrypt it
+        for from_leaf in 0..6 {
+            let leaf_secret = cs.generate_leaf_secret(&mut rng);
+            let path = DirectPathMessage::from_path(
+                cs,
+                &states[0].tree,
+                from_leaf,
+                &leaf_secret,
+                &mut rng,
+            )
+            .unwrap();
+            let can_decrypt: Vec<u32> = states
+                .iter()
+                .filter(|state| state.my_position_in_roster as usize != from_leaf)
+                .filter(|state| {
+                    state
+                        .derive_direct_path(&state.tree, from_leaf, &path)
+                        .is_ok()
+                })
+                .map(|state| state.my_position_in_roster)
+                .collect();
+            assert_eq!(states[0].members_affected_by_path(from_leaf), can_decrypt);
+        }
+    }
+
+    // Has Alice make an update and everyone else apply it. Everyone should land in the same epoch
+    // with the same secrets, and Alice's returned path should be the one in her Handshake.
+    #[quickcheck]
+    fn create_update_correctness(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut states = make_group_states(cs, 3, &mut rng);
+        let old_epoch = states[0].epoch;
+
+        let (handshake, path) = states[0].create_update(&mut rng).unwrap();
+        match &handshake.operation {
+            GroupOperation::Update(update) => assert_eq!(
+                tls_ser::serialize_to_bytes(&update.path).unwrap(),
+                tls_ser::serialize_to_bytes(&path).unwrap()
+            ),
+            _ => panic!("expected an update"),
+        }
+        assert_eq!(handshake.prior_epoch, old_epoch);
+        assert_eq!(states[0].epoch, old_epoch + 1);
+
+        for state in states[1..].iter_mut() {
+            state.process_handshake(&handshake).unwrap();
+        }
+        for state in states[1..].iter() {
+            assert_eq!(state.epoch, states[0].epoch);
+            assert_eq!(state.application_secret, states[0].application_secret);
+            assert_eq!(state.init_secret, states[0].init_secret);
+            assert_eq!(state.transcript_hash, states[0].transcript_hash);
+        }
+
+        // Alice already applied her own update
+        assert!(states[0].process_handshake(&handshake).is_err());
+    }
+
+    // Checks that a persisted-then-restored GroupState is the same as the original, and can keep
+    // going: it can decrypt and apply the next update, and sign Handshakes others accept
+    #[quickcheck]
+    fn persistence_roundtrip(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        for cs in &[&X25519_SHA256_AES128GCM, &P256_SHA256_AES128GCM] {
+            let mut states = make_group_states(cs, 3, &mut rng);
+
+            // After Alice's update, Bob knows the private keys on the path they share
+            update_everyone(&mut states, 0, &mut rng);
+
+            // Bob has sent an application message in this epoch
+            let (_, _, gen) = states[1].next_application_key_nonce().unwrap();
+            assert_eq!(gen, 0);
+
+            // Persist and restore Bob's state. It should reserialize identically.
+            let bytes = states[1].to_bytes();
+            let mut restored = GroupState::from_bytes(&bytes, cs).unwrap();
+            assert_eq!(restored.to_bytes(), bytes);
+            assert_eq!(restored.epoch, states[1].epoch);
+            assert_eq!(restored.tree_hash(), states[1].tree_hash());
+            assert_eq!(restored.application_secret, states[1].application_secret);
+
+            // Restored Bob's chain picks up where it left off, so he doesn't reuse a key
+            let (_, _, gen) = restored.next_application_key_nonce().unwrap();
+            assert_eq!(gen, 1);
+
+            // Swap the restored Bob in. Carol's update is encrypted to the node above Alice and
+            // Bob, which restored Bob has to decrypt with a persisted private key.
+            states[1] = restored;
+            update_everyone(&mut states, 2, &mut rng);
+            for state in states.iter() {
+                assert_eq!(state.application_secret, states[0].application_secret);
+            }
+
+            // Restored Bob can still sign Handshakes
+            let handshake =
+                Handshake::from_group_op(cs, &states[1], GroupOperation::Init(GroupInit));
+            assert!(handshake.verify(cs, &states[0]).is_ok());
+
+            // Garbage doesn't restore
+            assert!(GroupState::from_bytes(&bytes[..bytes.len() - 1], cs).is_err());
+            let mut extended = bytes.clone();
+            extended.push(0x00);
+            assert!(GroupState::from_bytes(&extended, cs).is_err());
+
+            // Nor does a state restored with the wrong cipher suite
+            let other_cs = if cs.name == X25519_SHA256_AES128GCM.name {
+                &P256_SHA256_AES128GCM
+            } else {
+                &X25519_SHA256_AES128GCM
+            };
+            match GroupState::from_bytes(&bytes, other_cs) {
+                Err(Error::CipherSuiteMismatch { expected, got }) => {
+                    assert_eq!(expected, other_cs.name);
+                    assert_eq!(got, cs.name);
+                }
+                _ => panic!("expected a cipher suite mismatch error"),
+            }
+        }
+    }
+
+    // Has every member of a group send an application message, and checks that everyone else gets
+    // the key to open it. Every epoch has a new secret tree, so the chains start over.
+    #[quickcheck]
+    fn application_key_agreement(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut states = make_group_states(cs, 3, &mut rng);
+
+        for _ in 0..2 {
+            for sender in 0..states.len() {
+                let (key, nonce, gen) = states[sender].next_application_key_nonce().unwrap();
+                assert_eq!(gen, 0);
+
+                let plaintext = vec![sender as u8; 16];
+                let mut ciphertext = plaintext.clone();
+                ciphertext.extend(vec![0u8; cs.aead_impl.tag_size()]);
+                cs.aead_impl.seal(&key, nonce, &mut ciphertext).unwrap();
+
+                for (receiver, state) in states.iter_mut().enumerate() {
+                    if receiver == sender {
+                        continue;
+                    }
+                    let (key, nonce) = state.application_key_nonce(sender as u32, gen).unwrap();
+                    let mut buf = ciphertext.clone();
+                    let opened = cs.aead_impl.open(&key, nonce, &mut buf).unwrap();
+                    assert_eq!(opened, plaintext.as_slice());
+                }
+            }
+
+            update_everyone(&mut states, 0, &mut rng);
+        }
+
+        // Once Carol is removed, nobody takes keys for her messages
+        let (handshake, _) = states[0].create_remove(2, &mut rng).unwrap();
+        states[1].process_handshake(&handshake).unwrap();
+        match states[1].application_key_nonce(2, 0) {
+            Err(Error::ValidationError(_)) => (),
+            _ => panic!("expected a validation error"),
+        }
+    }
+
+    // Checks that every member computes the same tree hash after the same sequence of adds and
+    // updates, and that blanking a node changes the hash
+    #[quickcheck]
+    fn tree_hash_agreement(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut states = make_group_states(cs, 3, &mut rng);
+
+        // Everyone adds Dave
+        let (init_key, _) = make_user_init_key(b"Dave", &mut rng);
+        let add = GroupAdd {
+            init_key,
+            welcome_info_hash: Vec::new(),
+        };
+        for state in states.iter_mut() {
+            state.apply_add(&add).unwrap();
+        }
+        let hash_after_add = states[0].tree_hash();
+        for state in states.iter() {
+            assert_eq!(state.tree_hash(), hash_after_add);
+        }
+
+        // Now Alice updates her path
+        let leaf_secret = cs.generate_leaf_secret(&mut rng);
+        match states[0].tree.get_mut(0) {
+            Some(RatchetTreeNode::Filled { secret, .. }) => *secret = Some(leaf_secret.clone()),
+            _ => panic!("Alice's leaf is blank"),
+        }
+        let path =
+            DirectPathMessage::from_path(cs, &states[0].tree, 0, &leaf_secret, &mut rng).unwrap();
+        let update = GroupUpdate { path };
+        for state in states.iter_mut() {
+            state.apply_update(&update, 0).unwrap();
+        }
+
+        // The hash should have changed, and everyone should still agree on it. Since only public
+        // keys are hashed, it doesn't matter that everyone knows different private keys.
+        let hash_after_update = states[0].tree_hash();
+        assert_ne!(hash_after_update, hash_after_add);
+        for state in states.iter() {
+            assert_eq!(state.tree_hash(), hash_after_update);
+        }
+
+        // Blanking a populated node changes the hash. Alice's update filled the root.
+        let root = tree_math::try_root_idx(states[0].tree.num_leaves()).unwrap();
+        *states[0].tree.get_mut(root).unwrap() = RatchetTreeNode::Blank;
+        assert_ne!(states[0].tree_hash(), hash_after_update);
+    }
+
+    // Checks that the cached tree hash is always the one we'd compute from scratch after a
+    // sequence of adds, updates, and removes, and that reading it twice doesn't recompute it
+    #[quickcheck]
+    fn tree_hash_cache_correctness(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut states = make_group_states(cs, 3, &mut rng);
+
+        let fresh_tree_hash = |state: &GroupState| {
+            let num_leaves = state.tree.num_leaves();
+            let root = tree_math::try_root_idx(num_leaves).unwrap();
+            state.node_hash(root, num_leaves)
+        };
+        let check_cache = |states: &[GroupState]| {
+            for state in states {
+                assert_eq!(state.tree_hash(), fresh_tree_hash(state));
+            }
+        };
+
+        // Read the hash before anything happens, so there's a stale value to get rid of
+        check_cache(&states);
+
+        let (init_key, _) = make_user_init_key(b"Dave", &mut rng);
+        let (handshake, _) = states[0].create_add(init_key, &mut rng).unwrap();
+        for state in states.iter_mut().skip(1) {
+            state.process_handshake(&handshake).unwrap();
+        }
+        check_cache(&states);
+
+        update_everyone(&mut states, 1, &mut rng);
+        check_cache(&states);
+
+        let (handshake, _) = states[0].create_remove(2, &mut rng).unwrap();
+        states.remove(2);
+        for state in states.iter_mut().skip(1) {
+            state.process_handshake(&handshake).unwrap();
+        }
+        check_cache(&states);
+
+        // Once computed, the hash is served from the cache until something changes. Swapping in a
+        // bogus value shows that a second read doesn't recompute it.
+        let alice = &mut states[0];
+        let hash = alice.tree_hash();
+        let versions = (alice.tree.version(), alice.roster.version());
+        *alice.tree_hash_cache.borrow_mut() = Some((versions, b"not a hash".to_vec()));
+        assert_eq!(alice.tree_hash(), b"not a hash".to_vec());
+
+        // Touching the tree or the roster in any way, even without changing anything, makes the
+        // next read recompute it
+        alice.tree.get_mut(0);
+        assert_eq!(alice.tree_hash(), hash);
+        let versions = (alice.tree.version(), alice.roster.version());
+        *alice.tree_hash_cache.borrow_mut() = Some((versions, b"not a hash".to_vec()));
+        alice.roster.remove(alice.roster.len());
+        assert_eq!(alice.tree_hash(), hash);
+    }
+
+    // Checks that two members of a group compute the same secrets after advancing the epoch with
+    // the same update secret and handshake, and that those secrets are actually new
+    #[quickcheck]
+    fn advance_epoch_correctness(update_secret: Vec<u8>, rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut states = make_group_states(cs, 2, &mut rng);
+
+        let old_application_secret = states[0].application_secret.clone();
+        let old_transcript_hash = states[0].transcript_hash.clone();
+
+        let handshake = Handshake::from_group_op(cs, &states[0], GroupOperation::Init(GroupInit));
+        for state in states.iter_mut() {
+            state.update_transcript_hash(&handshake).unwrap();
+            state.advance_epoch(&update_secret);
+        }
+
+        let (alice, bob) = (&states[0], &states[1]);
+        assert_eq!(alice.epoch, 1);
+        assert_eq!(bob.epoch, 1);
+        assert_eq!(alice.transcript_hash, bob.transcript_hash);
+        assert_eq!(alice.application_secret, bob.application_secret);
+        assert_eq!(alice.init_secret, bob.init_secret);
+        assert_eq!(alice.confirmation_key, bob.confirmation_key);
+
+        assert_ne!(alice.application_secret, old_application_secret);
+        assert_ne!(alice.transcript_hash, old_transcript_hash);
+    }
+
+    // Checks that Derive-Secret is deterministic in its inputs, and that changing the label or the
+    // group state changes the output
+    #[quickcheck]
+    fn derive_secret_determinism(secret: Vec<u8>, rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut states = make_group_states(cs, 2, &mut rng);
+
+        // Alice and Bob have the same public group state, so they should agree
+        let out = states[0].derive_secret(&secret, "app");
+        assert_eq!(out, states[0].derive_secret(&secret, "app"));
+        assert_eq!(out, states[1].derive_secret(&secret, "app"));
+        assert_eq!(out.len(), cs.hash_len());
+
+        assert_ne!(out, states[0].derive_secret(&secret, "init"));
+
+        states[0].epoch += 1;
+        assert_ne!(out, states[0].derive_secret(&secret, "app"));
+    }
+
+    // Hashes two handshakes into the transcript of a fresh group and checks the result against
+    // hashes computed by hand
+    #[test]
+    fn transcript_hash_kat() {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        let mut state = make_solo_group_state(cs, &mut rng);
+
+        // The transcript starts out as all zeros. An Init operation serializes to [0x00].
+        //     SHA256([0x00; 32] || 0x00 || 0x00000000)
+        let first = Handshake::from_group_op(cs, &state, GroupOperation::Init(GroupInit));
+        state.update_transcript_hash(&first).unwrap();
+        assert_eq!(
+            hex::encode(&state.transcript_hash),
+            "ab24a95f44ceca5d2aed4b6d056adddd8539f44c6cd6ca506534e830c82ea8a8"
+        );
+
+        //     SHA256(previous || 0x00 || 0x00000001)
+        let mut second = Handshake::from_group_op(cs, &state, GroupOperation::Init(GroupInit));
+        second.signer_index = 1;
+        state.update_transcript_hash(&second).unwrap();
+        assert_eq!(
+            hex::encode(&state.transcript_hash),
+            "01f1da5aad2fa0f1dd2591073c2a9caeac2dcaa3187c905134d0af8dbd4f891a"
+        );
+
+        // Changing the operation should change the hash
+        let mut other_state = make_solo_group_state(cs, &mut rng);
+        let tampered_op = GroupOperation::Remove(GroupRemove {
+            removed: 0,
+            path: DirectPathMessage {
+                node_messages: Vec::new(),
+            },
+        });
+        let tampered = Handshake::from_group_op(cs, &other_state, tampered_op);
+        other_state.update_transcript_hash(&tampered).unwrap();
+        assert_ne!(
+            hex::encode(&other_state.transcript_hash),
+            "ab24a95f44ceca5d2aed4b6d056adddd8539f44c6cd6ca506534e830c82ea8a8"
+        );
+    }
+
+    // Checks that hashing an operation as it's serialized gives the same transcript hash as
+    // serializing it into a buffer and hashing that
+    #[quickcheck]
+    fn transcript_hash_streaming(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut state = make_solo_group_state(cs, &mut rng);
+
+        // A long path, with lots of node secrets, makes for a big operation
+        let op = GroupOperation::Update(GroupUpdate {
+            path: make_direct_path_message(&mut rng, 12),
+        });
+        let mut handshake = Handshake::from_group_op(cs, &state, op);
+        handshake.signer_index = 7;
+
+        let expected = {
+            let serialized_op = tls_ser::serialize_to_bytes(&handshake.operation).unwrap();
+            let mut ctx = ring::digest::Context::new(cs.hash_alg);
+            ctx.update(&state.transcript_hash);
+            ctx.update(&serialized_op);
+            ctx.update(&handshake.signer_index.to_be_bytes());
+            ctx.finish().as_ref().to_vec()
+        };
+
+        state.update_transcript_hash(&handshake).unwrap();
+        assert_eq!(state.transcript_hash, expected);
+    }
+
+    // These are NOT the mlswg key schedule test vectors. None could be vendored into this tree, so
+    // the expected secrets in reference/key_schedule.bin come from reference/key_schedule.py, a
+    // Python rewrite of the same derivation that only uses hashlib and hmac. That catches mistakes
+    // in the Rust code, but not a misreading of the spec that both share. Once the upstream
+    // key_schedule.bin is in test_vectors/, this should be replaced with a test against it. The
+    // file is of the form
+    //
+    // struct {
+    //   opaque update_secret<0..255>;
+    //   opaque group_state<0..2^32-1>;
+    //   opaque application_secret<0..255>;
+    //   opaque confirmation_key<0..255>;
+    //   opaque exporter_secret<0..255>;
+    //   opaque init_secret<0..255>;
+    // } KeyScheduleRefEpoch;
+    //
+    // struct {
+    //   CipherSuite suite;
+    //   KeyScheduleRefEpoch epochs<0..2^16-1>;
+    // } KeyScheduleRefCase;
+    //
+    // struct {
+    //   KeyScheduleRefCase cases<0..2^32-1>;
+    // } KeyScheduleRef;
+    //
+    // Each case starts from an all-zero init secret, and each epoch after the first starts from
+    // the init secret of the one before it. group_state is the serialized GroupState of the epoch.
+
+    #[derive(Deserialize)]
+    struct KeyScheduleRefEpoch {
+        #[serde(rename = "update_secret__bound_u8")]
+        update_secret: Vec<u8>,
+        #[serde(rename = "group_state__bound_u32")]
+        group_state: Vec<u8>,
+        #[serde(rename = "application_secret__bound_u8")]
+        application_secret: Vec<u8>,
+        #[serde(rename = "confirmation_key__bound_u8")]
+        confirmation_key: Vec<u8>,
+        #[serde(rename = "exporter_secret__bound_u8")]
+        exporter_secret: Vec<u8>,
+        #[serde(rename = "init_secret__bound_u8")]
+        init_secret: Vec<u8>,
+    }
+
+    #[derive(Deserialize)]
+    struct KeyScheduleRefCase {
+        suite: u16,
+        #[serde(rename = "epochs__bound_u16")]
+        epochs: Vec<KeyScheduleRefEpoch>,
+    }
+
+    #[derive(Deserialize)]
+    struct KeyScheduleRef {
+        #[serde(rename = "cases__bound_u32")]
+        cases: Vec<KeyScheduleRefCase>,
+    }
+
+    // Runs the key schedule through every epoch of every case in reference/key_schedule.bin, and
+    // checks that each derived secret matches the Python reference's
+    #[test]
+    fn key_schedule_reference_check() {
+        let mut f = std::fs::File::open("reference/key_schedule.bin").unwrap();
+        let mut deserializer = TlsDeserializer::from_reader(&mut f);
+        let reference = KeyScheduleRef::deserialize(&mut deserializer).unwrap();
+        assert!(!reference.cases.is_empty());
+
+        for case in reference.cases {
+            let cs = CipherSuite::from_u16(case.suite).expect("unknown cipher suite in reference");
+            assert!(!case.epochs.is_empty());
+
+            let mut init_secret = cs.zero_secret();
+            for (i, epoch) in case.epochs.iter().enumerate() {
+                let secrets = derive_epoch_secrets(
+                    cs,
+                    &init_secret,
+                    &epoch.update_secret,
+                    &epoch.group_state,
+                );
+
+                assert_eq!(
+                    secrets.application_secret, epoch.application_secret,
+                    "application secret mismatch; ciphersuite {}, epoch {}",
+                    cs.name, i
+                );
+                assert_eq!(
+                    secrets.confirmation_key, epoch.confirmation_key,
+                    "confirmation key mismatch; ciphersuite {}, epoch {}",
+                    cs.name, i
+                );
+                assert_eq!(
+                    secrets.exporter_secret, epoch.exporter_secret,
+                    "exporter secret mismatch; ciphersuite {}, epoch {}",
+                    cs.name, i
+                );
+                assert_eq!(
+                    secrets.init_secret, epoch.init_secret,
+                    "init secret mismatch; ciphersuite {}, epoch {}",
+                    cs.name, i
+                );
+
+                // The next epoch builds on this one
+                init_secret = secrets.init_secret;
+            }
+        }
+    }
 }
@@ -1,12 +1,26 @@
 use crate::{
     credential::Credential,
-    crypto::{ciphersuite::CipherSuite, dh::DhPoint, ecies::EciesCiphertext, sig::Signature},
-    group_state::GroupState,
+    crypto::{
+        ciphersuite::CipherSuite,
+        dh::{DhPoint, DhScalar},
+        ecies::{self, EciesCiphertext},
+        rng::CryptoRng,
+        sig::{SigSecretKey, Signature},
+    },
+    error::Error,
+    group_state::{GroupState, WelcomeInfo},
+    ratchet_tree::{self, RatchetTree, RatchetTreeNode},
+    roster::Roster,
+    tls_de::TlsDeserializer,
+    tls_ser, tree_math,
 };
 
+use serde::de::Deserialize;
+use zeroize::Zeroize;
+
 /// This contains the encrypted `WelcomeInfo` for new group participants
-#[derive(Deserialize, Serialize)]
-struct Welcome {
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct Welcome {
     // opaque user_init_key_id<0..255>;
     #[serde(rename = "user_init_key_id__bound_u8")]
     user_init_key_id: Vec<u8>,
@@ -14,93 +28,436 @@ struct Welcome {
     encrypted_welcome_info: EciesCiphertext,
 }
 
-/// Contains a node's new public key and the new node's secret, encrypted for everyone in that
-/// node's resolution
-#[derive(Deserialize, Serialize)]
-struct DirectPathNodeMessage {
-    public_key: DhPoint,
+impl Welcome {
+    /// Encrypts the given `WelcomeInfo` to the init key in `init_key` that corresponds to the
+    /// given cipher suite
+    ///
+    /// Returns: `Ok(welcome)` on success. If `init_key` does not support the given cipher suite,
+    /// returns an `Error::ValidationError`. Otherwise, returns whatever error serialization or
+    /// ECIES encryption returns.
+    pub(crate) fn encrypt<R: CryptoRng>(
+        cs: &'static CipherSuite,
+        init_key: &UserInitKey,
+        welcome_info: &WelcomeInfo,
+        csprng: &mut R,
+    ) -> Result<Welcome, Error> {
+        // Find the init key that corresponds to our cipher suite
+        let init_public_key = init_key
+            .cipher_suites
+            .iter()
+            .position(|other| other.name == cs.name)
+            .and_then(|idx| init_key.init_keys.get(idx))
+            .ok_or(Error::ValidationError(
+                "UserInitKey does not support the given cipher suite",
+            ))?;
+
+        let serialized_welcome_info = tls_ser::serialize_to_bytes(welcome_info)?;
+        let encrypted_welcome_info =
+            ecies::ecies_encrypt(cs, init_public_key, serialized_welcome_info, csprng)?;
+
+        Ok(Welcome {
+            user_init_key_id: init_key.user_init_key_id.clone(),
+            cipher_suite: cs,
+            encrypted_welcome_info: encrypted_welcome_info,
+        })
+    }
+
+    /// Decrypts the `WelcomeInfo` in this `Welcome` with the private key corresponding to the init
+    /// key with the given ID
+    ///
+    /// Returns: `Ok(welcome_info)` on success. If `user_init_key_id` doesn't match the ID this
+    /// `Welcome` was encrypted to, returns an `Error::ValidationError`. Otherwise, returns
+    /// whatever error ECIES decryption or deserialization returns.
+    pub(crate) fn decrypt(
+        self,
+        user_init_key_id: &[u8],
+        init_private_key: &DhScalar,
+    ) -> Result<WelcomeInfo, Error> {
+        // Make sure we're not trying to decrypt with the wrong key
+        if self.user_init_key_id.as_slice() != user_init_key_id {
+            return Err(Error::ValidationError(
+                "Welcome is not encrypted to the given UserInitKey",
+            ));
+        }
+
+        let serialized_welcome_info = ecies::EciesCiphertext::decrypt(
+            self.cipher_suite,
+            init_private_key,
+            self.encrypted_welcome_info,
+        )?;
+
+        let mut buf = serialized_welcome_info.as_slice();
+        let mut deserializer = TlsDeserializer::from_reader(&mut buf);
+        WelcomeInfo::deserialize(&mut deserializer)
+    }
+}
+
+/// Contains a node's new public key, the new node's secret, encrypted for everyone in that node's
+/// resolution, and the node's parent hash in the tree with the path applied
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct DirectPathNodeMessage {
+    pub(crate) public_key: DhPoint,
     // ECIESCiphertext node_secrets<0..2^16-1>;
-    node_secrets: Vec<EciesCiphertext>,
+    #[serde(rename = "node_secrets__bound_u16")]
+    pub(crate) node_secrets: Vec<EciesCiphertext>,
+    // opaque parent_hash<0..255>;
+    #[serde(rename = "parent_hash__bound_u8")]
+    pub(crate) parent_hash: Vec<u8>,
 }
 
 /// Contains a direct path of node messages. The length of `node_secrets` for the first
 /// `DirectPathNodeMessage` MUST be zero.
-#[derive(Deserialize, Serialize)]
-struct DirectPathMessage {
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct DirectPathMessage {
     // DirectPathNodeMessage nodes<0..2^16-1>;
-    node_messages: Vec<DirectPathNodeMessage>,
+    #[serde(rename = "node_messages__bound_u16")]
+    pub(crate) node_messages: Vec<DirectPathNodeMessage>,
+}
+
+impl DirectPathMessage {
+    /// Makes a `DirectPathMessage` that updates every node from the given leaf up to the root. The
+    /// leaf gets the secret `leaf_secret`, and each node above it gets the next secret in the chain
+    /// (see `ratchet_tree::derive_path_secrets`). Every node's new public key is derived from its
+    /// new secret, and every node's secret (besides the leaf's) is encrypted to the resolution of
+    /// the copath node below it. Every node also carries its parent hash in `tree` with the new
+    /// public keys in place, so that recipients can tell we saw the same tree they did.
+    ///
+    /// Returns: `Ok(direct_path_msg)` on success. If `from_leaf` is not a leaf of `tree`, or
+    /// `leaf_secret` isn't `cs.hash_len()` bytes long (see `CipherSuite::generate_leaf_secret`),
+    /// returns an `Error::ValidationError`. Otherwise, returns whatever error key derivation or
+    /// ECIES encryption returns.
+    pub(crate) fn from_path<R: CryptoRng>(
+        cs: &'static CipherSuite,
+        tree: &RatchetTree,
+        from_leaf: usize,
+        leaf_secret: &[u8],
+        csprng: &mut R,
+    ) -> Result<DirectPathMessage, Error> {
+        let num_leaves = tree.num_leaves();
+        if from_leaf >= num_leaves {
+            return Err(Error::ValidationError("Leaf index is out of range"));
+        }
+        if leaf_secret.len() != cs.hash_len() {
+            return Err(Error::ValidationError("Leaf secret is the wrong length"));
+        }
+        let leaf_idx = tree_math::leaf_to_node(from_leaf);
+
+        // The path starts at the leaf and goes all the way up to the root. The secret of the ith
+        // node of the path is encrypted to the resolution of the ith copath node. The leaf has no
+        // copath node, so its recipient list is empty.
+        let path = tree_math::node_full_path(leaf_idx, num_leaves);
+        let recipients = tree_math::copath_resolutions(from_leaf, num_leaves, &tree.blanks());
+        let mut path_secrets = ratchet_tree::derive_path_secrets(cs, leaf_secret, path.len());
+
+        let mut node_messages = Vec::with_capacity(path.len());
+
+        // The first node message is the leaf's. Its node secrets MUST be empty
+        let (leaf_public_key, _) = cs.derive_key_pair(&path_secrets[0])?;
+        node_messages.push(DirectPathNodeMessage {
+            public_key: leaf_public_key,
+            node_secrets: Vec::new(),
+            parent_hash: Vec::new(),
+        });
+
+        for (path_secret, recipients) in path_secrets[1..].iter().zip(recipients[1..].iter()) {
+            let (public_key, _) = cs.derive_key_pair(path_secret)?;
+
+            // Encrypt the secret to everyone in the resolution of the copath node
+            let mut node_secrets = Vec::new();
+            for &recipient_idx in recipients {
+                let recipient_public_key = match tree.get(recipient_idx) {
+                    Some(RatchetTreeNode::Filled { pubkey, .. }) => pubkey,
+                    _ => unreachable!("resolution contained a blank node"),
+                };
+                let ciphertext =
+                    ecies::ecies_encrypt(cs, recipient_public_key, path_secret.clone(), csprng)?;
+                node_secrets.push(ciphertext);
+            }
+
+            node_messages.push(DirectPathNodeMessage {
+                public_key: public_key,
+                node_secrets: node_secrets,
+                parent_hash: Vec::new(),
+            });
+        }
+
+        path_secrets.iter_mut().for_each(Zeroize::zeroize);
+
+        // The parent hashes are those of the tree everyone ends up with, so put the new public
+        // keys into a copy of the tree and hash that
+        let mut new_tree = tree.public_copy();
+        let new_nodes = path
+            .iter()
+            .zip(node_messages.iter())
+            .map(|(&idx, node_msg)| {
+                let node = RatchetTreeNode::Filled {
+                    pubkey: node_msg.public_key.clone(),
+                    privkey: None,
+                    secret: None,
+                    credential: tree.credential(idx).cloned(),
+                    parent_hash: Vec::new(),
+                };
+                (idx, node)
+            })
+            .collect();
+        new_tree.set_nodes(new_nodes);
+        let parent_hashes = new_tree.parent_hashes(cs);
+        for (&idx, node_msg) in path.iter().zip(node_messages.iter_mut()) {
+            node_msg.parent_hash = parent_hashes[idx].clone();
+        }
+
+        Ok(DirectPathMessage {
+            node_messages: node_messages,
+        })
+    }
+}
+
+/// A version of the MLS protocol. On the wire, this is the draft number as a `u8`. Versions are
+/// ordered from oldest to newest.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub(crate) enum ProtocolVersion {
+    Draft02 = 2,
+    Draft03 = 3,
+}
+
+/// The protocol versions this crate speaks. Every `UserInitKey` we make advertises these, and
+/// every group we're in uses one of them.
+pub(crate) const SUPPORTED_PROTOCOL_VERSIONS: &[ProtocolVersion] = &[ProtocolVersion::Draft03];
+
+/// Picks the protocol version to use with the client that made the given `UserInitKey`, out of
+/// the versions that a group supports
+///
+/// Returns: `Some(version)` where `version` is the newest version in both `group_versions` and
+/// `init_key.supported_versions`, or `None` if there is no such version
+pub(crate) fn negotiate_version(
+    group_versions: &[ProtocolVersion],
+    init_key: &UserInitKey,
+) -> Option<ProtocolVersion> {
+    init_key
+        .supported_versions
+        .iter()
+        .filter(|version| group_versions.contains(version))
+        .max()
+        .cloned()
+}
+
+/// An extension to a `UserInitKey`, advertising something about the client that made it. We don't
+/// interpret any extensions ourselves, so every extension is kept exactly as it was received, and
+/// unknown types are never dropped.
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct Extension {
+    pub(crate) extension_type: u16,
+    // opaque extension_data<0..2^16-1>
+    #[serde(rename = "extension_data__bound_u16")]
+    pub(crate) extension_data: Vec<u8>,
 }
 
 /// This is used in lieu of negotiating public keys when a participant is added. This has a bunch
 /// of published ephemeral keys that can be used to initiated communication with a previously
 /// uncontacted participant.
-#[derive(Serialize)]
-struct UserInitKey {
+// Deserialize is implemented by hand in codec.rs, so that malformed UserInitKeys are rejected
+#[derive(Clone, Serialize)]
+pub(crate) struct UserInitKey {
     // opaque user_init_key_id<0..255>
     /// An identifier for this init key. This MUST be unique among the `UserInitKey` generated by
     /// the client
     #[serde(rename = "user_init_key_id__bound_u8")]
-    user_init_key_id: Vec<u8>,
+    pub(crate) user_init_key_id: Vec<u8>,
+    // ProtocolVersion supported_versions<0..255>
+    /// The protocol versions supported by this client
+    #[serde(rename = "supported_versions__bound_u8")]
+    pub(crate) supported_versions: Vec<ProtocolVersion>,
     // CipherSuite cipher_suites<0..255>
     /// The cipher suites supported by this client. Each cipher suite here corresponds uniquely to
     /// a DH public key in `init_keys`. As such, this MUST have the same length as `init_keys`.
     #[serde(rename = "cipher_suites__bound_u8")]
-    cipher_suites: Vec<&'static CipherSuite>,
+    pub(crate) cipher_suites: Vec<&'static CipherSuite>,
     // DHPublicKey init_keys<1..2^16-1>
     /// The DH public keys owned by this client. Each public key corresponds uniquely to a cipher
     /// suite in `cipher_suites`. As such, this MUST have the same length as `cipher_suites`.
     #[serde(rename = "init_keys__bound_u16")]
-    init_keys: Vec<DhPoint>,
+    pub(crate) init_keys: Vec<DhPoint>,
     /// The identity information of this user
-    credential: Credential,
+    pub(crate) credential: Credential,
+    // Extension extensions<0..2^16-1>
+    /// The extensions this client advertises. These are signed along with everything else.
+    #[serde(rename = "extensions__bound_u16")]
+    pub(crate) extensions: Vec<Extension>,
     /// Contains the signature of all the other fields of this struct, under the identity key of
     /// the client.
     // opaque signature<0..2^16-1>
-    signature: Signature,
+    pub(crate) signature: Signature,
+}
+
+impl UserInitKey {
+    /// Generates a fresh DH keypair for every given cipher suite, and signs the resulting
+    /// `UserInitKey`, along with the given extensions, with the given identity key. The
+    /// `UserInitKey` advertises every version in `SUPPORTED_PROTOCOL_VERSIONS`. The `i`th init key
+    /// corresponds to the `i`th cipher suite, as does the `i`th returned private key.
+    ///
+    /// Returns: `Ok((user_init_key, private_keys))` on success. The private keys should be kept
+    /// around until this `UserInitKey` is used or expires. If there isn't enough randomness,
+    /// returns `Error::OutOfEntropy`.
+    fn new_from_random<R: CryptoRng>(
+        id: Vec<u8>,
+        cipher_suites: Vec<&'static CipherSuite>,
+        credential: Credential,
+        extensions: Vec<Extension>,
+        identity_key: &SigSecretKey,
+        csprng: &mut R,
+    ) -> Result<(UserInitKey, Vec<DhScalar>), Error> {
+        let mut init_keys = Vec::with_capacity(cipher_suites.len());
+        let mut private_keys = Vec::with_capacity(cipher_suites.len());
+        for cs in cipher_suites.iter() {
+            let (pubkey, privkey) = cs.dh_impl.generate_keypair(csprng)?;
+            init_keys.push(pubkey);
+            private_keys.push(privkey);
+        }
+
+        // The signature is computed over every other field, so we start with a dummy signature
+        // and fill it in after the fact
+        let sig_impl = credential.signature_scheme();
+        let mut user_init_key = UserInitKey {
+            user_init_key_id: id,
+            supported_versions: SUPPORTED_PROTOCOL_VERSIONS.to_vec(),
+            cipher_suites: cipher_suites,
+            init_keys: init_keys,
+            credential: credential,
+            extensions: extensions,
+            signature: sig_impl.sign(identity_key, &[]),
+        };
+        let msg = user_init_key.signable_bytes()?;
+        user_init_key.signature = sig_impl.sign(identity_key, &msg);
+
+        Ok((user_init_key, private_keys))
+    }
+
+    /// Returns the serialized form of every field of this `UserInitKey` except the signature. This
+    /// is the message that `signature` is computed over.
+    fn signable_bytes(&self) -> Result<Vec<u8>, Error> {
+        // This struct is only used for computing and checking signatures. Its fields must be
+        // identical to those of `UserInitKey`, minus the signature.
+        #[derive(Serialize)]
+        struct PartialUserInitKey<'a> {
+            #[serde(rename = "user_init_key_id__bound_u8")]
+            user_init_key_id: &'a [u8],
+            #[serde(rename = "supported_versions__bound_u8")]
+            supported_versions: &'a [ProtocolVersion],
+            #[serde(rename = "cipher_suites__bound_u8")]
+            cipher_suites: &'a [&'static CipherSuite],
+            #[serde(rename = "init_keys__bound_u16")]
+            init_keys: &'a [DhPoint],
+            credential: &'a Credential,
+            #[serde(rename = "extensions__bound_u16")]
+            extensions: &'a [Extension],
+        }
+
+        let partial = PartialUserInitKey {
+            user_init_key_id: &self.user_init_key_id,
+            supported_versions: &self.supported_versions,
+            cipher_suites: &self.cipher_suites,
+            init_keys: &self.init_keys,
+            credential: &self.credential,
+            extensions: &self.extensions,
+        };
+        crate::tls_ser::serialize_to_bytes(&partial)
+    }
+
+    /// Verifies this `UserInitKey` under the identity key contained in its credential, and checks
+    /// that it has a nonzero number of init keys, each corresponding to exactly one cipher suite.
+    ///
+    /// Returns: `Ok(())` iff all the checks pass. If there are no init keys, returns an
+    /// `Error::ValidationError`. If there are more or fewer init keys than cipher suites, returns
+    /// an `Error::LengthMismatch`. If an init key is the wrong size for its
+    /// cipher suite, returns an `Error::BadPointLength`. If the signature is invalid, returns an
+    /// `Error::SignatureError`.
+    pub(crate) fn verify_signature(&self) -> Result<(), Error> {
+        if self.init_keys.is_empty() {
+            return Err(Error::ValidationError("UserInitKey has no init keys"));
+        }
+        if self.cipher_suites.len() != self.init_keys.len() {
+            return Err(Error::LengthMismatch {
+                expected: String::from("as many init keys as cipher suites"),
+                got: self.init_keys.len(),
+            });
+        }
+        for (cs, init_key) in self.cipher_suites.iter().zip(self.init_keys.iter()) {
+            cs.dh_impl.validate_point(init_key)?;
+        }
+
+        let msg = self.signable_bytes()?;
+        self.credential.verify(&msg, &self.signature)
+    }
 }
 
 /// This is currently not defined by the spec. See open issue in section 7.1
-#[derive(Serialize)]
-struct GroupInit;
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct GroupInit;
 
 /// Operation to add a partcipant to a group
-#[derive(Serialize)]
-struct GroupAdd {
-    init_key: UserInitKey,
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct GroupAdd {
+    pub(crate) init_key: UserInitKey,
+    // opaque welcome_info_hash<0..255>;
+    /// The hash of the `WelcomeInfo` sent to the new member (see `WelcomeInfo::hash`). The
+    /// `Handshake` signature covers this, which is how the new member knows their `WelcomeInfo`
+    /// came from a member of the group.
+    #[serde(rename = "welcome_info_hash__bound_u8")]
+    pub(crate) welcome_info_hash: Vec<u8>,
 }
 
 /// Operation to add entropy to the group
-#[derive(Serialize)]
-struct GroupUpdate {
-    path: DirectPathMessage,
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct GroupUpdate {
+    pub(crate) path: DirectPathMessage,
 }
 
 /// Operation to remove a partcipant from the group
-#[derive(Serialize)]
-struct GroupRemove {
-    removed: u32,
-    path: DirectPathMessage,
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct GroupRemove {
+    pub(crate) removed: u32,
+    pub(crate) path: DirectPathMessage,
 }
 
 /// Enum of possible group operations
-#[derive(Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 #[serde(rename = "GroupOperation__enum_u8")]
-enum GroupOperation {
+pub(crate) enum GroupOperation {
     Init(GroupInit),
     Add(GroupAdd),
     Update(GroupUpdate),
     Remove(GroupRemove),
 }
 
-struct Handshake {
+/// The label prepended to the transcript hash before it's signed in a `Handshake`. This keeps a
+/// `Handshake` signature from being valid in any other context that uses the same identity key.
+const HANDSHAKE_SIGNATURE_LABEL: &[u8] = b"mls10 handshake";
+
+/// Returns the bytes that a `Handshake` signature is over, i.e.,
+/// `"mls10 handshake" || transcript_hash || operation`. Signing the operation too means nobody can
+/// swap it out from under the signature.
+///
+/// Returns: `Ok(data)` on success. Otherwise, returns whatever error serializing the operation
+/// returns.
+fn handshake_signature_data(
+    transcript_hash: &[u8],
+    operation: &GroupOperation,
+) -> Result<Vec<u8>, Error> {
+    let serialized_op = tls_ser::serialize_to_bytes(operation)?;
+    Ok([HANDSHAKE_SIGNATURE_LABEL, transcript_hash, &serialized_op].concat())
+}
+
+#[derive(Clone)]
+pub(crate) struct Handshake {
     /// This is equal to the epoch of the current `GroupState`
-    prior_epoch: u32,
+    pub(crate) prior_epoch: u32,
     /// The operation this `Handshake` is perofrming
-    operation: GroupOperation,
+    pub(crate) operation: GroupOperation,
     /// Position of the signer in the roster
-    signer_index: u32,
-    /// Signature over the `Group`'s history:
-    /// `Handshake.signature = Sign(identity_key, GroupState.transcript_hash)`
+    pub(crate) signer_index: u32,
+    /// Signature over the `Group`'s history and the operation:
+    /// `Handshake.signature = Sign(identity_key, "mls10 handshake" || GroupState.transcript_hash ||
+    /// operation)`
     signature: Signature,
     /// HMAC over the group state and `Handshake` signature
     /// `confirmation_data = GroupState.transcript_hash || Handshake.signature`
@@ -110,15 +467,19 @@ struct Handshake {
 
 impl Handshake {
     /// Creates a `Handshake` message, given a ciphersuite, group state, and group operation
-    fn from_group_op(
+    ///
+    /// Panics: when `op` can't be serialized, i.e., when one of its fields is too long for its
+    /// length prefix
+    pub(crate) fn from_group_op(
         cs: &'static CipherSuite,
         state: &GroupState,
         op: GroupOperation,
     ) -> Handshake {
-        // signature = Sign(identity_key, GroupState.transcript_hash)
-        let signature = cs
-            .sig_impl
-            .sign(&state.identity_key, &state.transcript_hash);
+        // signature = Sign(identity_key, "mls10 handshake" || GroupState.transcript_hash ||
+        //                  operation)
+        let signature_data = handshake_signature_data(&state.transcript_hash, &op)
+            .expect("couldn't serialize group operation");
+        let signature = cs.sig_impl.sign(&state.identity_key, &signature_data);
 
         // confirmation_data = GroupState.transcript_hash || Handshake.signature
         let confirmation_data = [
@@ -127,7 +488,7 @@ impl Handshake {
         ]
         .concat();
         // confirmation = HMAC(confirmation_key, confirmation_data)
-        let confirmation = ring::hmac::sign(&state.confirmation_key, &confirmation_data);
+        let confirmation = ring::hmac::sign(&state.confirmation_signing_key(), &confirmation_data);
 
         Handshake {
             prior_epoch: state.epoch,
@@ -137,4 +498,780 @@ impl Handshake {
             confirmation: confirmation,
         }
     }
+
+    /// Verifies this `Handshake`'s signature and confirmation MAC against the given group state.
+    /// The signer's public key is looked up in the roster by `signer_index`.
+    ///
+    /// Returns: `Ok(())` iff both checks pass. If `signer_index` is out of range or points to a
+    /// blank slot in the roster, returns an `Error::InvalidSigner`. If the confirmation MAC is
+    /// invalid, returns an `Error::ValidationError`. If the signature is invalid, returns an
+    /// `Error::SignatureError`.
+    pub(crate) fn verify(&self, cs: &'static CipherSuite, state: &GroupState) -> Result<(), Error> {
+        self.verify_signature(&state.roster, &state.transcript_hash)?;
+
+        // confirmation_data = GroupState.transcript_hash || Handshake.signature
+        let confirmation_data = [
+            state.transcript_hash.as_slice(),
+            cs.sig_impl.signature_to_bytes(&self.signature).as_slice(),
+        ]
+        .concat();
+        // Check that confirmation = HMAC(confirmation_key, confirmation_data). The confirmation key
+        // is secret, so this must not be a plain `==` on the tags: that would leak, through timing,
+        // how many leading bytes of a forged tag are correct. ring does the comparison in constant
+        // time.
+        ring::hmac::verify_with_own_key(
+            &state.confirmation_signing_key(),
+            &confirmation_data,
+            self.confirmation.as_ref(),
+        )
+        .map_err(|_| Error::ValidationError("Handshake confirmation is invalid"))
+    }
+
+    /// Verifies just this `Handshake`'s signature, given the roster and transcript hash of the
+    /// state it was made in. This is all a new member can check of the `Handshake` that added
+    /// them, since they never had the confirmation key it's MACed with.
+    ///
+    /// Returns: `Ok(())` iff the signature is valid. If `signer_index` is out of range or points
+    /// to a blank slot in the roster, returns an `Error::InvalidSigner`. If the signature is
+    /// invalid, returns an `Error::SignatureError`. If the operation can't be serialized, returns
+    /// that error.
+    pub(crate) fn verify_signature(
+        &self,
+        roster: &Roster,
+        transcript_hash: &[u8],
+    ) -> Result<(), Error> {
+        // The signer has to be an active member. An index past the end of the roster, or one that
+        // points at a removed member, gets rejected before we look at any signatures. Otherwise a
+        // removed member could keep signing Handshakes with their old identity key.
+        let signer_cred = roster
+            .get(self.signer_index as usize)
+            .ok_or(Error::InvalidSigner)?;
+
+        // Check that
+        // signature = Sign(identity_key, "mls10 handshake" || GroupState.transcript_hash ||
+        //                  operation)
+        let signature_data = handshake_signature_data(transcript_hash, &self.operation)?;
+        signer_cred
+            .verify(&signature_data, &self.signature)
+            .map_err(|_| Error::SignatureError("Handshake signature is invalid"))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::{
+        credential::{BasicCredential, Identity},
+        crypto::{
+            ciphersuite::{P256_SHA256_AES128GCM, X25519_SHA256_AES128GCM},
+            ecies::ecies_encrypt,
+            sig::{SignatureScheme, ED25519_IMPL},
+        },
+        group_state::test::make_solo_group_state,
+        ratchet_tree::RatchetTree,
+        roster::Roster,
+    };
+
+    use quickcheck::{Arbitrary, Gen};
+    use quickcheck_macros::quickcheck;
+    use rand::SeedableRng;
+
+    // Makes a UserInitKey with a single init key and a basic credential with the given identity,
+    // signed with a fresh identity key. Also returns the private init key.
+    pub(crate) fn make_user_init_key<R: CryptoRng>(
+        identity: &[u8],
+        rng: &mut R,
+    ) -> (UserInitKey, DhScalar) {
+        let (user_init_key, mut private_keys, _) =
+            make_multi_suite_user_init_key(identity, vec![&X25519_SHA256_AES128GCM], rng);
+        (user_init_key, private_keys.pop().unwrap())
+    }
+
+    // Like make_user_init_key, but with the given ID instead of a random one
+    pub(crate) fn make_user_init_key_with_id<R: CryptoRng>(
+        identity: &[u8],
+        id: Vec<u8>,
+        rng: &mut R,
+    ) -> (UserInitKey, DhScalar) {
+        let (user_init_key, mut private_keys, _) =
+            make_user_init_key_from_parts(identity, id, vec![&X25519_SHA256_AES128GCM], rng);
+        (user_init_key, private_keys.pop().unwrap())
+    }
+
+    // Like make_user_init_key, but with an init key for every given cipher suite. The returned
+    // private keys are in the same order as the cipher suites. This also returns the identity key
+    // that the UserInitKey was signed with.
+    pub(crate) fn make_multi_suite_user_init_key<R: CryptoRng>(
+        identity: &[u8],
+        cipher_suites: Vec<&'static CipherSuite>,
+        rng: &mut R,
+    ) -> (UserInitKey, Vec<DhScalar>, SigSecretKey) {
+        // Init key IDs are supposed to be unique, so pick a random one
+        let mut id = vec![0u8; 16];
+        rng.fill_bytes(&mut id);
+        make_user_init_key_from_parts(identity, id, cipher_suites, rng)
+    }
+
+    // Like make_user_init_key, but advertising the given protocol versions instead of the ones we
+    // support
+    pub(crate) fn make_user_init_key_with_versions<R: CryptoRng>(
+        identity: &[u8],
+        versions: Vec<ProtocolVersion>,
+        rng: &mut R,
+    ) -> (UserInitKey, DhScalar) {
+        let (mut user_init_key, mut private_keys, identity_key) =
+            make_multi_suite_user_init_key(identity, vec![&X25519_SHA256_AES128GCM], rng);
+        user_init_key.supported_versions = versions;
+        let msg = user_init_key.signable_bytes().unwrap();
+        user_init_key.signature = ED25519_IMPL.sign(&identity_key, &msg);
+
+        (user_init_key, private_keys.pop().unwrap())
+    }
+
+    fn make_user_init_key_from_parts<R: CryptoRng>(
+        identity: &[u8],
+        id: Vec<u8>,
+        cipher_suites: Vec<&'static CipherSuite>,
+        rng: &mut R,
+    ) -> (UserInitKey, Vec<DhScalar>, SigSecretKey) {
+        let ss: &dyn SignatureScheme = &ED25519_IMPL;
+
+        let identity_key = ss.secret_key_from_random(rng).unwrap();
+        let credential = Credential::Basic(BasicCredential {
+            identity: Identity(identity.to_vec()),
+            signature_scheme: ss,
+            public_key: ss.public_key_from_secret_key(&identity_key),
+        });
+
+        let (user_init_key, private_keys) = UserInitKey::new_from_random(
+            id,
+            cipher_suites,
+            credential,
+            Vec::new(),
+            &identity_key,
+            rng,
+        )
+        .unwrap();
+
+        (user_init_key, private_keys, identity_key)
+    }
+
+    // Makes a DirectPathMessage of the given length where each node has a fresh public key and
+    // each node secret is encrypted to some random public key
+    pub(crate) fn make_direct_path_message<R: CryptoRng>(
+        rng: &mut R,
+        num_nodes: usize,
+    ) -> DirectPathMessage {
+        let cs = &X25519_SHA256_AES128GCM;
+
+        let mut node_messages = Vec::new();
+        for i in 0..num_nodes {
+            let public_key = {
+                let scalar = cs.dh_impl.scalar_from_random(rng).unwrap();
+                cs.dh_impl.multiply_basepoint(&scalar)
+            };
+            // The first node message has no node secrets
+            let node_secrets = (0..i)
+                .map(|_| {
+                    let scalar = cs.dh_impl.scalar_from_random(rng).unwrap();
+                    let recipient = cs.dh_impl.multiply_basepoint(&scalar);
+                    ecies_encrypt(cs, &recipient, b"node secret".to_vec(), rng).unwrap()
+                })
+                .collect();
+
+            node_messages.push(DirectPathNodeMessage {
+                public_key,
+                node_secrets,
+                parent_hash: Vec::new(),
+            });
+        }
+
+        DirectPathMessage { node_messages }
+    }
+
+    // Arbitrary impls for generating random group operations. These pull a seed out of the
+    // generator and use it to make real keys and ciphertexts, since quickcheck's generator isn't a
+    // CryptoRng.
+
+    impl Arbitrary for GroupAdd {
+        fn arbitrary<G: Gen>(g: &mut G) -> GroupAdd {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(g.next_u64());
+            let identity = vec![0x42; g.next_u32() as usize % 64];
+            // Pick a nonempty subset of the supported cipher suites
+            let cipher_suites = match g.next_u32() % 3 {
+                0 => vec![&X25519_SHA256_AES128GCM],
+                1 => vec![&P256_SHA256_AES128GCM],
+                _ => vec![&X25519_SHA256_AES128GCM, &P256_SHA256_AES128GCM],
+            };
+            let (init_key, _, _) =
+                make_multi_suite_user_init_key(&identity, cipher_suites, &mut rng);
+            let welcome_info_hash = vec![0x42; g.next_u32() as usize % 64];
+            GroupAdd {
+                init_key,
+                welcome_info_hash,
+            }
+        }
+    }
+
+    impl Arbitrary for DirectPathMessage {
+        fn arbitrary<G: Gen>(g: &mut G) -> DirectPathMessage {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(g.next_u64());
+            let num_nodes = g.next_u32() as usize % 6;
+            make_direct_path_message(&mut rng, num_nodes)
+        }
+    }
+
+    impl Arbitrary for GroupUpdate {
+        fn arbitrary<G: Gen>(g: &mut G) -> GroupUpdate {
+            GroupUpdate {
+                path: DirectPathMessage::arbitrary(g),
+            }
+        }
+    }
+
+    impl Arbitrary for GroupRemove {
+        fn arbitrary<G: Gen>(g: &mut G) -> GroupRemove {
+            GroupRemove {
+                removed: g.next_u32(),
+                path: DirectPathMessage::arbitrary(g),
+            }
+        }
+    }
+
+    impl Arbitrary for GroupOperation {
+        fn arbitrary<G: Gen>(g: &mut G) -> GroupOperation {
+            match g.next_u32() % 4 {
+                0 => GroupOperation::Init(GroupInit),
+                1 => GroupOperation::Add(GroupAdd::arbitrary(g)),
+                2 => GroupOperation::Update(GroupUpdate::arbitrary(g)),
+                _ => GroupOperation::Remove(GroupRemove::arbitrary(g)),
+            }
+        }
+    }
+
+    // quickcheck needs to be able to print failing inputs. Most of what's inside an operation
+    // doesn't implement Debug, so just print the variant and its serialization.
+    impl std::fmt::Debug for GroupOperation {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            let variant = match self {
+                GroupOperation::Init(_) => "Init",
+                GroupOperation::Add(_) => "Add",
+                GroupOperation::Update(_) => "Update",
+                GroupOperation::Remove(_) => "Remove",
+            };
+            let bytes = tls_ser::serialize_to_bytes(self).map_err(|_| std::fmt::Error)?;
+            write!(f, "GroupOperation::{}({:02x?})", variant, bytes)
+        }
+    }
+
+    // Checks that serialize(deserialize(serialize(op))) == serialize(op)
+    fn check_roundtrip(op: GroupOperation) {
+        let bytes = tls_ser::serialize_to_bytes(&op).unwrap();
+
+        let mut buf = bytes.as_slice();
+        let mut deserializer = TlsDeserializer::from_reader(&mut buf);
+        let recovered_op = GroupOperation::deserialize(&mut deserializer).unwrap();
+
+        // Make sure we consumed everything
+        assert!(buf.is_empty());
+
+        let recovered_bytes = tls_ser::serialize_to_bytes(&recovered_op).unwrap();
+        assert_eq!(bytes, recovered_bytes);
+    }
+
+    #[test]
+    fn group_init_roundtrip() {
+        let op = GroupOperation::Init(GroupInit);
+        // An Init is just a variant tag with nothing following it
+        assert_eq!(tls_ser::serialize_to_bytes(&op).unwrap(), vec![0x00]);
+        check_roundtrip(op);
+    }
+
+    // Same as the above tests, but over arbitrary operations of every kind. Also checks that the
+    // variant tag comes first, and that a Remove's index comes right after it as a big-endian u32.
+    #[quickcheck]
+    fn group_operation_roundtrip(op: GroupOperation) {
+        let bytes = tls_ser::serialize_to_bytes(&op).unwrap();
+        match op {
+            GroupOperation::Init(_) => assert_eq!(bytes, vec![0x00]),
+            GroupOperation::Add(_) => assert_eq!(bytes[0], 0x01),
+            GroupOperation::Update(_) => assert_eq!(bytes[0], 0x02),
+            GroupOperation::Remove(ref remove) => {
+                assert_eq!(bytes[0], 0x03);
+                assert_eq!(bytes[1..5], remove.removed.to_be_bytes());
+            }
+        }
+
+        check_roundtrip(op);
+    }
+
+    #[quickcheck]
+    fn group_add_roundtrip(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let op = GroupOperation::Add(GroupAdd {
+            init_key: make_user_init_key(b"Alice", &mut rng).0,
+            welcome_info_hash: vec![0x42; 32],
+        });
+        check_roundtrip(op);
+    }
+
+    #[quickcheck]
+    fn group_update_roundtrip(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let op = GroupOperation::Update(GroupUpdate {
+            path: make_direct_path_message(&mut rng, 4),
+        });
+        check_roundtrip(op);
+    }
+
+    #[quickcheck]
+    fn group_remove_roundtrip(removed: u32, rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let op = GroupOperation::Remove(GroupRemove {
+            removed: removed,
+            path: make_direct_path_message(&mut rng, 3),
+        });
+        check_roundtrip(op);
+    }
+
+    #[quickcheck]
+    fn handshake_verify_correctness(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut state = make_solo_group_state(cs, &mut rng);
+
+        let handshake = Handshake::from_group_op(cs, &state, GroupOperation::Init(GroupInit));
+        assert!(handshake.verify(cs, &state).is_ok());
+
+        // A different confirmation key should make the MAC check fail, but not the signature check
+        state.confirmation_key = b"wrong key".to_vec();
+        match handshake.verify(cs, &state) {
+            Err(Error::ValidationError(_)) => (),
+            _ => panic!("expected a confirmation error"),
+        }
+
+        // A confirmation that's a valid MAC of something else should also fail
+        state.confirmation_key = vec![0u8; 32];
+        let mut tampered = Handshake::from_group_op(cs, &state, GroupOperation::Init(GroupInit));
+        assert!(tampered.verify(cs, &state).is_ok());
+        tampered.confirmation =
+            ring::hmac::sign(&state.confirmation_signing_key(), b"something else");
+        match tampered.verify(cs, &state) {
+            Err(Error::ValidationError(_)) => (),
+            _ => panic!("expected a confirmation error"),
+        }
+
+        // So should a different operation, since the signature covers it but the confirmation
+        // doesn't
+        let mut swapped_op = Handshake::from_group_op(cs, &state, GroupOperation::Init(GroupInit));
+        swapped_op.operation = GroupOperation::Remove(GroupRemove {
+            removed: 0,
+            path: make_direct_path_message(&mut rng, 1),
+        });
+        match swapped_op.verify(cs, &state) {
+            Err(Error::SignatureError(_)) => (),
+            _ => panic!("expected a signature error"),
+        }
+
+        // A different transcript hash should make the signature check fail
+        state.transcript_hash[0] ^= 1;
+        match handshake.verify(cs, &state) {
+            Err(Error::SignatureError(_)) => (),
+            _ => panic!("expected a signature error"),
+        }
+    }
+
+    // Checks that a signature over the bare transcript hash and operation, i.e., one without the
+    // domain separation label, isn't accepted as a Handshake signature
+    #[quickcheck]
+    fn handshake_signature_label(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let state = make_solo_group_state(cs, &mut rng);
+
+        let mut handshake = Handshake::from_group_op(cs, &state, GroupOperation::Init(GroupInit));
+        assert!(handshake.verify(cs, &state).is_ok());
+
+        // Swap in an unlabeled signature, with a confirmation that's valid for it, so that the
+        // signature is the only thing wrong
+        let unlabeled_data = [
+            state.transcript_hash.as_slice(),
+            &tls_ser::serialize_to_bytes(&handshake.operation).unwrap(),
+        ]
+        .concat();
+        let unlabeled_sig = cs.sig_impl.sign(&state.identity_key, &unlabeled_data);
+        let confirmation_data = [
+            state.transcript_hash.as_slice(),
+            cs.sig_impl.signature_to_bytes(&unlabeled_sig).as_slice(),
+        ]
+        .concat();
+        handshake.confirmation =
+            ring::hmac::sign(&state.confirmation_signing_key(), &confirmation_data);
+        handshake.signature = unlabeled_sig;
+
+        match handshake.verify(cs, &state) {
+            Err(Error::SignatureError(_)) => (),
+            _ => panic!("expected a signature error"),
+        }
+    }
+
+    #[quickcheck]
+    fn user_init_key_verify_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (mut user_init_key, _) = make_user_init_key(b"Alice", &mut rng);
+        assert!(user_init_key.verify_signature().is_ok());
+
+        // Changing any signed field should invalidate the signature
+        user_init_key.user_init_key_id.push(0xff);
+        match user_init_key.verify_signature() {
+            Err(Error::SignatureError(_)) => (),
+            _ => panic!("expected a signature error"),
+        }
+
+        // A cipher suite without a corresponding init key is malformed
+        user_init_key.cipher_suites.push(&X25519_SHA256_AES128GCM);
+        match user_init_key.verify_signature() {
+            Err(Error::LengthMismatch { .. }) => (),
+            _ => panic!("expected a length mismatch error"),
+        }
+
+        // As is a UserInitKey with no init keys at all
+        user_init_key.cipher_suites.clear();
+        user_init_key.init_keys.clear();
+        match user_init_key.verify_signature() {
+            Err(Error::ValidationError(_)) => (),
+            _ => panic!("expected a validation error"),
+        }
+    }
+
+    // Checks that a UserInitKey with more cipher suites than init keys can't be deserialized
+    #[quickcheck]
+    fn user_init_key_length_mismatch(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (mut user_init_key, _) = make_user_init_key(b"Alice", &mut rng);
+
+        // A well-formed one comes back fine
+        let bytes = tls_ser::serialize_to_bytes(&user_init_key).unwrap();
+        let mut buf = bytes.as_slice();
+        let mut deserializer = TlsDeserializer::from_reader(&mut buf);
+        assert!(UserInitKey::deserialize(&mut deserializer).is_ok());
+
+        // Now make one with 2 cipher suites and 1 init key. The serializer doesn't check this.
+        user_init_key.cipher_suites.push(&P256_SHA256_AES128GCM);
+        let bytes = tls_ser::serialize_to_bytes(&user_init_key).unwrap();
+        let mut buf = bytes.as_slice();
+        let mut deserializer = TlsDeserializer::from_reader(&mut buf);
+        match UserInitKey::deserialize(&mut deserializer) {
+            Err(Error::LengthMismatch { got, .. }) => assert_eq!(got, 1),
+            _ => panic!("expected a length mismatch error"),
+        }
+    }
+
+    // Checks that the newest common version is picked, and that there's none when the sets are
+    // disjoint
+    #[quickcheck]
+    fn negotiate_version_kat(rng_seed: u64) {
+        use ProtocolVersion::*;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        let cases: &[(
+            &[ProtocolVersion],
+            Vec<ProtocolVersion>,
+            Option<ProtocolVersion>,
+        )] = &[
+            (&[Draft03], vec![Draft02, Draft03], Some(Draft03)),
+            (&[Draft02, Draft03], vec![Draft03, Draft02], Some(Draft03)),
+            (&[Draft02, Draft03], vec![Draft02], Some(Draft02)),
+            (&[Draft03], vec![Draft02], None),
+            (&[Draft03], Vec::new(), None),
+            (&[], vec![Draft03], None),
+        ];
+        for (group_versions, init_key_versions, expected) in cases.iter() {
+            let (init_key, _) =
+                make_user_init_key_with_versions(b"Alice", init_key_versions.clone(), &mut rng);
+            assert!(init_key.verify_signature().is_ok());
+            assert_eq!(negotiate_version(group_versions, &init_key), *expected);
+        }
+
+        // The versions are on the wire as their draft numbers, right after the init key ID
+        let (init_key, _) =
+            make_user_init_key_with_versions(b"Alice", vec![Draft02, Draft03], &mut rng);
+        let bytes = tls_ser::serialize_to_bytes(&init_key).unwrap();
+        let id_len = init_key.user_init_key_id.len();
+        assert_eq!(bytes[1 + id_len..1 + id_len + 3], [0x02, 0x02, 0x03]);
+    }
+
+    // Checks that extensions, including ones of types we don't know, survive a roundtrip over the
+    // wire untouched, and are covered by the signature
+    #[quickcheck]
+    fn user_init_key_extensions_roundtrip(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let ss: &dyn SignatureScheme = &ED25519_IMPL;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        let identity_key = ss.secret_key_from_random(&mut rng).unwrap();
+        let credential = Credential::Basic(BasicCredential {
+            identity: Identity(b"Alice".to_vec()),
+            signature_scheme: ss,
+            public_key: ss.public_key_from_secret_key(&identity_key),
+        });
+        let extensions = vec![
+            Extension {
+                extension_type: 0x0001,
+                extension_data: vec![0x01, 0x02, 0x03],
+            },
+            Extension {
+                extension_type: 0xfafa,
+                extension_data: Vec::new(),
+            },
+        ];
+        let (user_init_key, _) = UserInitKey::new_from_random(
+            b"Alice's key".to_vec(),
+            vec![cs],
+            credential,
+            extensions,
+            &identity_key,
+            &mut rng,
+        )
+        .unwrap();
+
+        let bytes = tls_ser::serialize_to_bytes(&user_init_key).unwrap();
+        let mut buf = bytes.as_slice();
+        let mut deserializer = TlsDeserializer::from_reader(&mut buf);
+        let mut recovered = UserInitKey::deserialize(&mut deserializer).unwrap();
+        assert!(buf.is_empty());
+        assert!(recovered.verify_signature().is_ok());
+
+        let recovered_extensions: Vec<(u16, &[u8])> = recovered
+            .extensions
+            .iter()
+            .map(|ext| (ext.extension_type, ext.extension_data.as_slice()))
+            .collect();
+        assert_eq!(
+            recovered_extensions,
+            vec![(0x0001, &[0x01, 0x02, 0x03][..]), (0xfafa, &[][..])]
+        );
+
+        // The extensions are signed
+        recovered.extensions[1].extension_data.push(0x00);
+        match recovered.verify_signature() {
+            Err(Error::SignatureError(_)) => (),
+            _ => panic!("expected a signature error"),
+        }
+    }
+
+    // Checks that new_from_random makes a valid UserInitKey whose init keys line up with the
+    // returned private keys
+    #[quickcheck]
+    fn user_init_key_new_from_random(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let ss: &dyn SignatureScheme = &ED25519_IMPL;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        let identity_key = ss.secret_key_from_random(&mut rng).unwrap();
+        let credential = Credential::Basic(BasicCredential {
+            identity: Identity(b"Bob".to_vec()),
+            signature_scheme: ss,
+            public_key: ss.public_key_from_secret_key(&identity_key),
+        });
+
+        let (user_init_key, private_keys) = UserInitKey::new_from_random(
+            b"another init key".to_vec(),
+            vec![cs, cs, cs],
+            credential,
+            Vec::new(),
+            &identity_key,
+            &mut rng,
+        )
+        .unwrap();
+        assert!(user_init_key.verify_signature().is_ok());
+
+        assert_eq!(user_init_key.init_keys.len(), 3);
+        assert_eq!(private_keys.len(), 3);
+        for ((cs, init_key), privkey) in user_init_key
+            .cipher_suites
+            .iter()
+            .zip(user_init_key.init_keys.iter())
+            .zip(private_keys.iter())
+        {
+            let expected_init_key = cs.dh_impl.multiply_basepoint(privkey);
+            assert_eq!(
+                tls_ser::serialize_to_bytes(init_key).unwrap(),
+                tls_ser::serialize_to_bytes(&expected_init_key).unwrap()
+            );
+        }
+    }
+
+    // Checks that key generation gets all its randomness from the RNG it's given: two runs from the
+    // same seed make byte-for-byte identical UserInitKeys and private keys, and a different seed
+    // doesn't
+    #[quickcheck]
+    fn user_init_key_deterministic(rng_seed: u64) {
+        let make_bytes = |seed: u64| {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+            let (user_init_key, private_keys, _) = make_multi_suite_user_init_key(
+                b"Carol",
+                vec![&X25519_SHA256_AES128GCM, &P256_SHA256_AES128GCM],
+                &mut rng,
+            );
+            let cs_and_privkeys = user_init_key.cipher_suites.iter().zip(private_keys.iter());
+            let privkey_bytes: Vec<Vec<u8>> = cs_and_privkeys
+                .map(|(cs, privkey)| cs.dh_impl.scalar_as_bytes(privkey))
+                .collect();
+            (
+                tls_ser::serialize_to_bytes(&user_init_key).unwrap(),
+                privkey_bytes,
+            )
+        };
+
+        assert_eq!(make_bytes(rng_seed), make_bytes(rng_seed));
+        assert_ne!(make_bytes(rng_seed), make_bytes(rng_seed.wrapping_add(1)));
+    }
+
+    #[quickcheck]
+    fn welcome_correctness(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        let (user_init_key, init_private_key) = make_user_init_key(b"Alice", &mut rng);
+        // A WelcomeInfo has to describe a group with at least one leaf to be deserializable
+        let mut roster = Roster::new();
+        roster.add(user_init_key.credential.clone());
+        let mut tree = RatchetTree::new();
+        tree.add_leaf_node(RatchetTreeNode::Blank);
+        let welcome_info = WelcomeInfo {
+            group_id: b"test group".to_vec(),
+            epoch: 1,
+            roster: roster,
+            tree: tree,
+            transcript_hash: vec![0x01; 32],
+            init_secret: vec![0x02; 32],
+        };
+
+        let welcome = Welcome::encrypt(cs, &user_init_key, &welcome_info, &mut rng).unwrap();
+        let recovered_welcome_info = welcome
+            .decrypt(&user_init_key.user_init_key_id, &init_private_key)
+            .unwrap();
+        assert_eq!(
+            tls_ser::serialize_to_bytes(&welcome_info).unwrap(),
+            tls_ser::serialize_to_bytes(&recovered_welcome_info).unwrap()
+        );
+
+        // Decrypting with the wrong init key ID should fail before we even try to decrypt
+        let welcome = Welcome::encrypt(cs, &user_init_key, &welcome_info, &mut rng).unwrap();
+        match welcome.decrypt(b"some other init key", &init_private_key) {
+            Err(Error::ValidationError(_)) => (),
+            _ => panic!("expected a validation error"),
+        }
+    }
+
+    // Checks that a DirectPathMessage from every leaf of a 4-leaf tree has exactly as many node
+    // secrets at each level as there are recipients in the copath resolution at that level
+    #[quickcheck]
+    fn direct_path_message_layout(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        // Make a tree of 4 leaves whose intermediate nodes are all blank, except for node 5
+        let mut tree = RatchetTree::new();
+        for _ in 0..4 {
+            let privkey = cs.dh_impl.scalar_from_random(&mut rng).unwrap();
+            tree.add_leaf_node(RatchetTreeNode::Filled {
+                pubkey: cs.dh_impl.multiply_basepoint(&privkey),
+                privkey: None,
+                secret: None,
+                credential: None,
+                parent_hash: Vec::new(),
+            });
+        }
+        let privkey = cs.dh_impl.scalar_from_random(&mut rng).unwrap();
+        *tree.get_mut(5).unwrap() = RatchetTreeNode::Filled {
+            pubkey: cs.dh_impl.multiply_basepoint(&privkey),
+            privkey: None,
+            secret: None,
+            credential: None,
+            parent_hash: Vec::new(),
+        };
+
+        // Leaves 0 and 1 see node 5 on their copath, so the root's secret has one recipient.
+        // Leaves 2 and 3 see the blank node 1, so the root's secret goes to leaves 0 and 1.
+        let expected_sizes = [[0, 1, 1], [0, 1, 1], [0, 1, 2], [0, 1, 2]];
+        for from_leaf in 0..4 {
+            let recipients = tree_math::copath_resolutions(from_leaf, 4, &tree.blanks());
+            let leaf_secret = cs.generate_leaf_secret(&mut rng);
+            let direct_path_msg =
+                DirectPathMessage::from_path(cs, &tree, from_leaf, &leaf_secret, &mut rng).unwrap();
+
+            let recipient_sizes: Vec<usize> = recipients.iter().map(Vec::len).collect();
+            let message_sizes: Vec<usize> = direct_path_msg
+                .node_messages
+                .iter()
+                .map(|node_msg| node_msg.node_secrets.len())
+                .collect();
+            assert_eq!(recipient_sizes, message_sizes);
+            assert_eq!(message_sizes, expected_sizes[from_leaf]);
+        }
+    }
+
+    // Checks that every member of a group can decrypt a DirectPathMessage and arrive at the same
+    // root secret as the sender
+    #[quickcheck]
+    fn direct_path_message_correctness(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let leaf_secret = cs.generate_leaf_secret(&mut rng);
+
+        // Make a tree of 4 leaves whose intermediate nodes are all blank
+        let mut tree = RatchetTree::new();
+        let mut leaf_private_keys = Vec::new();
+        for _ in 0..4 {
+            let privkey = cs.dh_impl.scalar_from_random(&mut rng).unwrap();
+            let pubkey = cs.dh_impl.multiply_basepoint(&privkey);
+            tree.add_leaf_node(RatchetTreeNode::Filled {
+                pubkey: pubkey,
+                privkey: None,
+                secret: None,
+                credential: None,
+                parent_hash: Vec::new(),
+            });
+            leaf_private_keys.push(privkey);
+        }
+
+        // Leaf secrets that aren't hash_len() bytes long are refused
+        for &len in &[0, 16, cs.hash_len() - 1, cs.hash_len() + 1] {
+            let bad_leaf_secret = vec![0xabu8; len];
+            assert!(
+                DirectPathMessage::from_path(cs, &tree, 0, &bad_leaf_secret, &mut rng).is_err()
+            );
+        }
+
+        // Leaf 0 updates its path, which consists of nodes 0, 1, and 3
+        let mut direct_path_msg =
+            DirectPathMessage::from_path(cs, &tree, 0, &leaf_secret, &mut rng).unwrap();
+        assert_eq!(direct_path_msg.node_messages.len(), 3);
+        assert!(direct_path_msg.node_messages[0].node_secrets.is_empty());
+
+        let expected_root_secret = ratchet_tree::derive_path_secrets(cs, &leaf_secret, 3).pop();
+
+        // Node 1's secret is encrypted to the resolution of node 2, i.e., just leaf 1. Leaf 1 can
+        // hash this to get the root secret.
+        let root_secret_for_leaf_1 = {
+            let ciphertext = direct_path_msg.node_messages[1].node_secrets.remove(0);
+            let node_secret =
+                ecies::EciesCiphertext::decrypt(cs, &leaf_private_keys[1], ciphertext).unwrap();
+            ratchet_tree::derive_path_secrets(cs, &node_secret, 2).pop()
+        };
+        assert_eq!(root_secret_for_leaf_1, expected_root_secret);
+
+        // The root's secret is encrypted to the resolution of node 5, which is blank. So the
+        // ciphertexts are for node 4 and node 6, i.e., leaves 2 and 3.
+        let root_node_secrets = &mut direct_path_msg.node_messages[2].node_secrets;
+        assert_eq!(root_node_secrets.len(), 2);
+        for leaf in &[2, 3] {
+            let ciphertext = root_node_secrets.remove(0);
+            let root_secret =
+                ecies::EciesCiphertext::decrypt(cs, &leaf_private_keys[*leaf], ciphertext).unwrap();
+            assert_eq!(Some(root_secret), expected_root_secret);
+        }
+    }
 }
@@ -1,18 +1,40 @@
+// tree_math only needs core and alloc, so it's usable without std. Everything else needs std.
+#![cfg_attr(not(feature = "std"), no_std)]
+// Nothing outside the crate can make a GroupState yet, so outside of tests every item behind it is
+// reported as dead. Tests still get the lint.
+#![cfg_attr(not(test), allow(dead_code))]
+
+extern crate alloc;
+
 // Can't make this work using edition 2018 syntax yet
+#[cfg(feature = "std")]
 #[macro_use]
 extern crate serde;
 
 // Internal modules still need macro_use
+#[cfg(feature = "std")]
 #[macro_use]
 mod utils;
 
+#[cfg(feature = "std")]
 mod codec;
+#[cfg(feature = "std")]
 mod credential;
+#[cfg(feature = "std")]
 pub mod crypto;
 pub mod error;
+#[cfg(feature = "std")]
 mod group_state;
+#[cfg(feature = "std")]
 mod handshake;
+#[cfg(feature = "std")]
 pub mod ratchet_tree;
+#[cfg(feature = "std")]
+mod roster;
+#[cfg(feature = "std")]
+mod secret_tree;
+#[cfg(feature = "std")]
 mod tls_de;
+#[cfg(feature = "std")]
 mod tls_ser;
-mod tree_math;
+pub mod tree_math;
@@ -1,14 +1,36 @@
-use crate::crypto::dh::{DhPoint, DhScalar};
-use crate::tree_math;
+use crate::credential::Credential;
+use crate::crypto::{
+    ciphersuite::CipherSuite,
+    dh::{DhPoint, DhScalar},
+};
+use crate::{error::Error, tls_ser, tree_math, utils::next_version};
 
-// Ratchet trees are serialized in DirectPath messages as optional<PublicKey> tree<1..2^32-1>
+use serde::{
+    de::{Deserialize, Deserializer},
+    ser::{Serialize, Serializer},
+};
+use std::cell::RefCell;
+use zeroize::Zeroize;
+
+// Ratchet trees are serialized in WelcomeInfo messages as
+//
+//   struct {
+//       PublicKey public_key;
+//       optional<Credential> credential;
+//       opaque parent_hash<0..255>;
+//   } PublicNode;
+//
+//   optional<PublicNode> tree<1..2^32-1>;
+//
 // So we encode RatchetTree as a Vec<RatchetTreeNode> with length bound u32, and we encode
-// RatchetTreeNode as enum { Blank, Filled { DhPoint } }, which is encoded in the same way as an
-// Option<DhPoint> would be.
+// RatchetTreeNode as enum { Blank, Filled { PublicNode } }, which is encoded in the same way as an
+// Option<PublicNode> would be. That is, a blank node is the single byte 0x00, and a filled node is
+// 0x01 followed by its public key, credential, and parent hash. Only leaves have credentials.
 
 /// A node in a `RatchetTree`. Every node must have a DH pubkey. It may also optionally contain the
-/// corresponding private key and a secret octet string.
-#[derive(Serialize)]
+/// corresponding private key and a secret octet string. A leaf may carry the credential of the
+/// member it belongs to, and any node may carry its parent hash.
+#[derive(Clone)]
 pub(crate) enum RatchetTreeNode {
     Blank,
     Filled {
@@ -20,25 +42,181 @@ pub(crate) enum RatchetTreeNode {
         // Bar had the associated type Baz, then DH::Baz would be ambiguous. Instead, you'd write
         // <DH as Foo>::Baz or <DH as Bar>::Baz.
         pubkey: DhPoint,
-        #[serde(skip)]
         privkey: Option<DhScalar>,
-        #[serde(skip)]
         secret: Option<Vec<u8>>,
+        /// The credential of the member at this leaf. Non-leaf nodes never have one.
+        credential: Option<Credential>,
+        /// This node's parent hash (see `parent_hash`), or empty if it hasn't been set
+        parent_hash: Vec<u8>,
     },
 }
 
+// The private key zeroizes itself, but the node secret is just a Vec, so we have to wipe it here.
+// Note that this runs whenever a node is overwritten, too.
+impl Drop for RatchetTreeNode {
+    fn drop(&mut self) {
+        if let RatchetTreeNode::Filled {
+            secret: Some(secret),
+            ..
+        } = self
+        {
+            secret.zeroize();
+        }
+    }
+}
+
+// The public part of a filled node. This is what goes over the wire.
+#[derive(Serialize)]
+#[serde(rename = "PublicNode")]
+struct PublicNodeRef<'a> {
+    public_key: &'a DhPoint,
+    credential: Option<&'a Credential>,
+    #[serde(rename = "parent_hash__bound_u8")]
+    parent_hash: &'a [u8],
+}
+
+// The owned version of PublicNodeRef, for deserialization
+#[derive(Deserialize)]
+#[serde(rename = "PublicNode")]
+struct PublicNode {
+    public_key: DhPoint,
+    credential: Option<Credential>,
+    #[serde(rename = "parent_hash__bound_u8")]
+    parent_hash: Vec<u8>,
+}
+
+// Only the public part of a node is ever serialized, so a node is encoded exactly as an
+// optional<PublicNode> would be
+impl Serialize for RatchetTreeNode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            RatchetTreeNode::Blank => serializer.serialize_none(),
+            RatchetTreeNode::Filled {
+                pubkey,
+                credential,
+                parent_hash,
+                ..
+            } => serializer.serialize_some(&PublicNodeRef {
+                public_key: pubkey,
+                credential: credential.as_ref(),
+                parent_hash: parent_hash,
+            }),
+        }
+    }
+}
+
+// Likewise, a deserialized node only has its public part. Its secrets are filled in later, if we
+// come to learn them.
+impl<'de> Deserialize<'de> for RatchetTreeNode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<RatchetTreeNode, D::Error> {
+        let node = match Option::<PublicNode>::deserialize(deserializer)? {
+            None => RatchetTreeNode::Blank,
+            Some(public_node) => RatchetTreeNode::Filled {
+                pubkey: public_node.public_key,
+                privkey: None,
+                secret: None,
+                credential: public_node.credential,
+                parent_hash: public_node.parent_hash,
+            },
+        };
+        Ok(node)
+    }
+}
+
+// A tree that comes off the wire has to have a valid shape before anything indexes into it, since
+// the tree math panics on trees with an even number of nodes. So the node count is checked here,
+// rather than left to whoever receives the tree. So is the rule that only leaves have credentials.
+impl<'de> Deserialize<'de> for RatchetTree {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<RatchetTree, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(rename = "RatchetTree")]
+        struct RawRatchetTree {
+            #[serde(rename = "nodes__bound_u32")]
+            nodes: Vec<RatchetTreeNode>,
+        }
+
+        let raw = RawRatchetTree::deserialize(deserializer)?;
+        let tree = RatchetTree::from_nodes(raw.nodes);
+        tree.validate_shape().map_err(serde::de::Error::custom)?;
+        let parent_has_credential = tree.nodes.iter().enumerate().any(|(idx, node)| match node {
+            RatchetTreeNode::Filled {
+                credential: Some(_),
+                ..
+            } => tree_math::node_to_leaf(idx).is_none(),
+            _ => false,
+        });
+        if parent_has_credential {
+            return Err(serde::de::Error::custom("Non-leaf node has a credential"));
+        }
+        Ok(tree)
+    }
+}
+
 /// A left-balanced binary tree of `RatchetTreeNode`s
 // Contains a vector of nodes that could optionally be blanks
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub(crate) struct RatchetTree {
     #[serde(rename = "nodes__bound_u32")]
     nodes: Vec<RatchetTreeNode>,
+    /// The resolutions we've computed so far, as node indices, indexed by node. `None` means the
+    /// resolution hasn't been computed since the last time something under that node changed.
+    /// This is never serialized.
+    #[serde(skip)]
+    resolution_cache: RefCell<Vec<Option<Vec<usize>>>>,
+    /// Changes every time this tree is modified. See `utils::next_version`. This is never
+    /// serialized.
+    #[serde(skip)]
+    version: u64,
 }
 
 impl RatchetTree {
     /// Returns an new empty `RatchetTree`
     pub fn new() -> RatchetTree {
-        RatchetTree { nodes: Vec::new() }
+        RatchetTree::from_nodes(Vec::new())
+    }
+
+    /// Makes a tree whose leaves hold the given members' public keys and credentials, in order,
+    /// where `None` is a blank leaf. Every non-leaf node is blank, which is how the tree looks to a
+    /// member who knows who is in the group but hasn't seen any updates yet. If no leaves are
+    /// given, the tree gets a single blank leaf, since a tree can't have zero leaves.
+    ///
+    /// Returns: `Ok(tree)` on success. If one of the public keys isn't a valid point for the given
+    /// cipher suite, returns whatever error validating it returns.
+    pub(crate) fn from_leaves(
+        cs: &CipherSuite,
+        leaves: Vec<Option<(DhPoint, Credential)>>,
+    ) -> Result<RatchetTree, Error> {
+        let mut tree = RatchetTree::new();
+        for leaf in leaves {
+            let node = match leaf {
+                None => RatchetTreeNode::Blank,
+                Some((pubkey, credential)) => {
+                    cs.dh_impl.validate_point(&pubkey)?;
+                    RatchetTreeNode::Filled {
+                        pubkey: pubkey,
+                        privkey: None,
+                        secret: None,
+                        credential: Some(credential),
+                        parent_hash: Vec::new(),
+                    }
+                }
+            };
+            tree.add_leaf_node(node);
+        }
+        if tree.nodes.is_empty() {
+            tree.add_leaf_node(RatchetTreeNode::Blank);
+        }
+
+        Ok(tree)
+    }
+
+    /// Makes a `RatchetTree` out of the given nodes, with nothing cached
+    fn from_nodes(nodes: Vec<RatchetTreeNode>) -> RatchetTree {
+        RatchetTree {
+            nodes: nodes,
+            resolution_cache: RefCell::new(Vec::new()),
+            version: next_version(),
+        }
     }
 
     // It turns out that appending to the tree in this way preserves the left-balanced property
@@ -56,6 +234,9 @@ impl RatchetTree {
     //                                        A   B   C   D   E
     //                                        0 1 2 3 4 5 6 7 8
     pub fn add_leaf_node(&mut self, node: RatchetTreeNode) {
+        // Growing the tree can move the root, which changes the resolution of everything on the
+        // right edge
+        self.clear_resolution_cache();
         if self.nodes.is_empty() {
             self.nodes.push(node);
             return;
@@ -65,33 +246,365 @@ impl RatchetTree {
         }
     }
 
+    /// Returns a reference to the node at the given index, or `None` if it's out of range
+    pub(crate) fn get(&self, idx: usize) -> Option<&RatchetTreeNode> {
+        self.nodes.get(idx)
+    }
+
+    /// Returns the credential held by the node at the given index, if there is one. Only filled
+    /// leaves have credentials. Re-keying a leaf keeps its credential, so anything that overwrites
+    /// a node on a path should carry this over.
+    pub(crate) fn credential(&self, idx: usize) -> Option<&Credential> {
+        match self.nodes.get(idx) {
+            Some(RatchetTreeNode::Filled { credential, .. }) => credential.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Returns a mutable reference to the node at the given index, or `None` if it's out of range.
+    /// We can't tell whether the caller is going to blank or fill the node, so this forgets every
+    /// cached resolution above it. Use `blank_node` to blank a node.
+    pub(crate) fn get_mut(&mut self, idx: usize) -> Option<&mut RatchetTreeNode> {
+        if idx < self.nodes.len() {
+            self.invalidate_resolutions(idx);
+        }
+        self.nodes.get_mut(idx)
+    }
+
+    /// Blanks the node at the given index. Only the cached resolutions of the node and its
+    /// ancestors are affected by this, so those are the only ones that get recomputed.
+    ///
+    /// Panics: when `idx` is out of range
+    pub(crate) fn blank_node(&mut self, idx: usize) {
+        self.invalidate_resolutions(idx);
+        self.nodes[idx] = RatchetTreeNode::Blank;
+    }
+
+    /// Removes nodes from the right of the tree until it has exactly `num_leaves` many leaves
+    ///
+    /// Panics: when `num_leaves == 0`
+    pub(crate) fn truncate_to_leaves(&mut self, num_leaves: usize) {
+        assert!(num_leaves > 0, "cannot truncate a tree to zero leaves");
+        self.clear_resolution_cache();
+        // A tree with n leaves has 2n - 1 nodes
+        self.nodes.truncate(2 * num_leaves - 1);
+    }
+
+    /// Returns a copy of this tree that only contains public information: public keys, credentials,
+    /// and parent hashes. This is what a new member gets in a `WelcomeInfo`.
+    pub(crate) fn public_copy(&self) -> RatchetTree {
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|node| match node {
+                RatchetTreeNode::Blank => RatchetTreeNode::Blank,
+                RatchetTreeNode::Filled {
+                    pubkey,
+                    credential,
+                    parent_hash,
+                    ..
+                } => RatchetTreeNode::Filled {
+                    pubkey: pubkey.clone(),
+                    privkey: None,
+                    secret: None,
+                    credential: credential.clone(),
+                    parent_hash: parent_hash.clone(),
+                },
+            })
+            .collect();
+        RatchetTree::from_nodes(nodes)
+    }
+
+    /// Returns whether each node in this tree is blank, in order
+    pub(crate) fn blanks(&self) -> Vec<bool> {
+        self.nodes
+            .iter()
+            .map(|node| match node {
+                RatchetTreeNode::Blank => true,
+                RatchetTreeNode::Filled { .. } => false,
+            })
+            .collect()
+    }
+
+    /// Returns the number of nodes in this tree, blank or not
+    pub(crate) fn num_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Checks that this tree has the shape of a nonempty left-balanced binary tree. Call this on
+    /// any tree that came from someone else before calling `num_leaves` or doing tree math on it.
+    ///
+    /// Returns: `Ok(num_leaves)` on success. If the tree is malformed, returns an
+    /// `Error::ValidationError`.
+    pub(crate) fn validate_shape(&self) -> Result<usize, Error> {
+        tree_math::validate_tree_shape(self.nodes.len())
+            .map_err(|_| Error::ValidationError("Tree has an invalid number of nodes"))
+    }
+
+    /// Returns the number of leaves in this tree
+    pub(crate) fn num_leaves(&self) -> usize {
+        tree_math::num_leaves_in_tree(self.nodes.len())
+    }
+
     /// Returns the resolution of a given node: this an ordered list of non-blank nodes that
-    /// collectively cover all non-blank descendants of the given node.
-    fn resolution(&self, idx: usize) -> Vec<&RatchetTreeNode> {
-        fn helper<'a>(
-            i: usize,
-            nodes: &'a Vec<RatchetTreeNode>,
-            acc: &mut Vec<&'a RatchetTreeNode>,
-        ) {
-            let num_leaves = tree_math::num_leaves_in_tree(nodes.len());
-            match &nodes[i] {
-                f @ RatchetTreeNode::Filled { .. } => {
-                    acc.push(f);
-                    return;
-                }
-                _ => (),
+    /// collectively cover all non-blank descendants of the given node. Adding a member blanks
+    /// every node above their leaf, so no node in this tree ever has unmerged leaves.
+    pub(crate) fn resolution(&self, idx: usize) -> Vec<&RatchetTreeNode> {
+        let indices = self.resolution_indices(idx);
+        self.make_node_iter(indices).collect()
+    }
+
+    /// Returns the indices of the nodes in the resolution of the given node. This computes the
+    /// same thing as `tree_math::node_resolution`, but reuses whatever resolutions are cached and
+    /// caches everything it computes.
+    ///
+    /// Panics: when `idx` is out of range
+    fn resolution_indices(&self, idx: usize) -> Vec<usize> {
+        if let Some(Some(cached)) = self.resolution_cache.borrow().get(idx) {
+            return cached.clone();
+        }
+
+        // The resolution of a filled node is itself. Otherwise, it's the resolution of its
+        // children, or nothing if it's a blank leaf.
+        let resolution = match self.nodes[idx] {
+            RatchetTreeNode::Filled { .. } => vec![idx],
+            RatchetTreeNode::Blank if tree_math::node_level(idx) == 0 => Vec::new(),
+            RatchetTreeNode::Blank => {
+                let left = tree_math::node_left_child(idx);
+                let right = tree_math::node_right_child(idx, self.num_leaves());
+                let mut resolution = self.resolution_indices(left);
+                resolution.extend(self.resolution_indices(right));
+                resolution
             }
-            if tree_math::node_level(i) == 0 {
-                return;
+        };
+
+        let mut cache = self.resolution_cache.borrow_mut();
+        if cache.len() < self.nodes.len() {
+            cache.resize(self.nodes.len(), None);
+        }
+        cache[idx] = Some(resolution.clone());
+        resolution
+    }
+
+    /// Returns the version of this tree. This changes every time the tree is modified, so it
+    /// tells whether something computed from the tree is still up to date.
+    pub(crate) fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Forgets the cached resolutions of the given node and all its ancestors. These are the only
+    /// resolutions that can change when the node does. This is called whenever a node changes, so
+    /// it also bumps the tree's version.
+    fn invalidate_resolutions(&mut self, idx: usize) {
+        self.version = next_version();
+        let num_leaves = self.num_leaves();
+        let cache = self.resolution_cache.get_mut();
+        if cache.is_empty() {
+            return;
+        }
+        for i in tree_math::node_full_path(idx, num_leaves) {
+            if let Some(entry) = cache.get_mut(i) {
+                *entry = None;
             }
+        }
+    }
+
+    /// Forgets every cached resolution. This is for when the shape of the tree changes, or when we
+    /// can't tell which nodes changed. Like `invalidate_resolutions`, this bumps the tree's
+    /// version.
+    fn clear_resolution_cache(&mut self) {
+        self.version = next_version();
+        self.resolution_cache.get_mut().clear();
+    }
+
+    /// Returns the indices of the direct path of the given node: its parent, its grandparent, and
+    /// so on, up to but not including the root
+    ///
+    /// Panics: when `idx` is out of range
+    pub(crate) fn direct_path(&self, idx: usize) -> Vec<usize> {
+        tree_math::DirectPathIter::new(idx, self.num_leaves()).collect()
+    }
+
+    /// Returns the indices of the copath of the given node: the siblings of the node and of every
+    /// node on its direct path
+    ///
+    /// Panics: when `idx` is out of range
+    pub(crate) fn copath(&self, idx: usize) -> Vec<usize> {
+        tree_math::CopathIter::new(idx, self.num_leaves()).collect()
+    }
 
-            helper(tree_math::node_left_child(i), nodes, acc);
-            helper(tree_math::node_right_child(i, num_leaves), nodes, acc);
+    /// Returns the indices of the nodes above the given leaf, including the root, whose secrets we
+    /// don't know. This is every such node right after we join, since a `WelcomeInfo` only has
+    /// public keys, and adding us blanked our path anyway. These nodes have to be treated as blank
+    /// until someone re-keys them. Note that `my_leaf` is a leaf index, not a node index.
+    ///
+    /// Panics: when `my_leaf` is out of range
+    pub(crate) fn uninitialized_path_nodes(&self, my_leaf: usize) -> Vec<usize> {
+        let num_leaves = self.num_leaves();
+        let leaf_idx = tree_math::leaf_to_node(my_leaf);
+        tree_math::node_full_path(leaf_idx, num_leaves)
+            .into_iter()
+            .skip(1)
+            .filter(|&idx| match self.nodes[idx] {
+                RatchetTreeNode::Filled {
+                    secret: Some(_), ..
+                } => false,
+                _ => true,
+            })
+            .collect()
+    }
+
+    /// Blanks every node above the given leaf, including the root. The leaf itself is left alone.
+    ///
+    /// Panics: when `leaf` is out of range
+    pub(crate) fn blank_path(&mut self, leaf: usize) {
+        let num_leaves = self.num_leaves();
+        let leaf_idx = tree_math::leaf_to_node(leaf);
+        for idx in tree_math::node_full_path(leaf_idx, num_leaves)
+            .into_iter()
+            .skip(1)
+        {
+            self.blank_node(idx);
         }
+    }
+
+    /// Sets the secret of the given node to `path_secret`, and ratchets it up to the root: every
+    /// node above gets the next secret in the chain (see `derive_path_secrets`), along with the
+    /// keypair derived from it. This is what a member does once they've decrypted the secret of a
+    /// node on someone's path.
+    ///
+    /// Returns: `Ok(root_secret)` on success, where `root_secret` is the secret the root ends up
+    /// with. Otherwise, returns whatever error key derivation returns, and leaves the tree as it
+    /// was.
+    ///
+    /// Panics: when `from_node` is out of range
+    pub(crate) fn merge_path_secret(
+        &mut self,
+        cs: &CipherSuite,
+        from_node: usize,
+        path_secret: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let new_nodes = self.derive_path_nodes(cs, from_node, path_secret)?;
+        let root_secret = match new_nodes.last() {
+            Some((
+                _,
+                RatchetTreeNode::Filled {
+                    secret: Some(secret),
+                    ..
+                },
+            )) => secret.clone(),
+            _ => unreachable!("derive_path_nodes didn't end with a filled root"),
+        };
+        self.set_nodes(new_nodes);
 
-        let mut acc = Vec::new();
-        helper(idx, &self.nodes, &mut acc);
-        acc
+        Ok(root_secret)
+    }
+
+    /// Does the work of `merge_path_secret` without touching the tree, so that nothing is written
+    /// if key derivation fails partway up the path
+    ///
+    /// Returns: `Ok(new_nodes)` on success, where `new_nodes` is a list of `(idx, node)` pairs,
+    /// going from `from_node` up to the root. Otherwise, returns whatever error key derivation
+    /// returns.
+    ///
+    /// Panics: when `from_node` is out of range
+    fn derive_path_nodes(
+        &self,
+        cs: &CipherSuite,
+        from_node: usize,
+        path_secret: &[u8],
+    ) -> Result<Vec<(usize, RatchetTreeNode)>, Error> {
+        let path = tree_math::node_full_path(from_node, self.num_leaves());
+        let mut path_secrets = derive_path_secrets(cs, path_secret, path.len());
+
+        let mut new_nodes = Vec::with_capacity(path.len());
+        for (&idx, path_secret) in path.iter().zip(path_secrets.iter()) {
+            let (pubkey, privkey) = cs.derive_key_pair(path_secret)?;
+            new_nodes.push((
+                idx,
+                RatchetTreeNode::Filled {
+                    pubkey: pubkey,
+                    privkey: Some(privkey),
+                    secret: Some(path_secret.clone()),
+                    credential: self.credential(idx).cloned(),
+                    parent_hash: Vec::new(),
+                },
+            ));
+        }
+
+        // Everything we need is in the new nodes now. Wipe the rest.
+        path_secrets.iter_mut().for_each(Zeroize::zeroize);
+
+        Ok(new_nodes)
+    }
+
+    /// Overwrites the nodes at the given indices with the given nodes
+    ///
+    /// Panics: when any of the indices is out of range
+    pub(crate) fn set_nodes(&mut self, new_nodes: Vec<(usize, RatchetTreeNode)>) {
+        for (idx, node) in new_nodes {
+            self.invalidate_resolutions(idx);
+            self.nodes[idx] = node;
+        }
+    }
+
+    /// Computes the parent hash of every node in this tree, in order. This works top-down, so
+    /// every node's parent hash is computed from its parent's already-computed one. See
+    /// `parent_hash` for the definition.
+    pub(crate) fn parent_hashes(&self, cs: &CipherSuite) -> Vec<Vec<u8>> {
+        let num_leaves = self.num_leaves();
+        let mut hashes = vec![Vec::new(); self.nodes.len()];
+        if num_leaves == 0 {
+            return hashes;
+        }
+
+        // Going by level from the root down guarantees every parent is done before its children
+        let root_level = tree_math::node_level(tree_math::try_root_idx(num_leaves).unwrap());
+        for level in (0..=root_level).rev() {
+            for idx in tree_math::nodes_at_level(level, num_leaves) {
+                let parent = tree_math::node_parent(idx, num_leaves);
+                let parent_hash = if parent == idx {
+                    None
+                } else {
+                    Some(hashes[parent].as_slice())
+                };
+                hashes[idx] = parent_hash_step(cs, self, idx, num_leaves, parent_hash);
+            }
+        }
+
+        hashes
+    }
+
+    /// Recomputes the parent hash of every filled node in this tree and stores it in the node.
+    /// Every node's parent hash depends on the whole path above it, so this has to be called after
+    /// any change to the tree.
+    pub(crate) fn set_parent_hashes(&mut self, cs: &CipherSuite) {
+        let hashes = self.parent_hashes(cs);
+        for (node, hash) in self.nodes.iter_mut().zip(hashes) {
+            if let RatchetTreeNode::Filled { parent_hash, .. } = node {
+                *parent_hash = hash;
+            }
+        }
+        self.version = next_version();
+    }
+
+    /// Checks that every filled node in this tree holds the parent hash computed from the tree
+    /// around it. A tree that passes this is the tree whoever set the parent hashes saw.
+    ///
+    /// Returns: `Ok(())` on success. If some filled node has the wrong parent hash, returns an
+    /// `Error::ParentHashMismatch`.
+    pub(crate) fn verify_parent_hashes(&self, cs: &CipherSuite) -> Result<(), Error> {
+        let hashes = self.parent_hashes(cs);
+        for (node, hash) in self.nodes.iter().zip(hashes.iter()) {
+            match node {
+                RatchetTreeNode::Filled { parent_hash, .. } if parent_hash != hash => {
+                    return Err(Error::ParentHashMismatch)
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
     }
 
     // This has the same functionality as RatchetTreeIter, so one of them's got to go
@@ -103,6 +616,121 @@ impl RatchetTree {
     }
 }
 
+/// Derives the secrets of every node on a path, given the secret of the first node on it. The
+/// secret of each node is derived from the secret of the node below it, i.e.,
+/// `path_secret[n] = HKDF-Expand-Label(path_secret[n-1], "path", "", Hash.length)`. The first
+/// element of the returned vector is `start_secret` itself.
+pub(crate) fn derive_path_secrets(
+    cs: &CipherSuite,
+    start_secret: &[u8],
+    path_len: usize,
+) -> Vec<Vec<u8>> {
+    let mut path_secrets: Vec<Vec<u8>> = Vec::with_capacity(path_len);
+    if path_len == 0 {
+        return path_secrets;
+    }
+
+    path_secrets.push(start_secret.to_vec());
+    for _ in 1..path_len {
+        let prev_secret = path_secrets.last().unwrap();
+        let next_secret = cs.hkdf_expand_label(prev_secret, "path", b"", cs.hash_len());
+        path_secrets.push(next_secret);
+    }
+
+    path_secrets
+}
+
+/// Computes the parent hash of the given node. This binds the node to everything above it and to
+/// the subtree beside it, so that a new member can tell whether the tree they were given was
+/// tampered with. It's defined as
+/// `Hash(public_key || parent_hash(parent) || Hash(public keys of the sibling's resolution))`,
+/// where the parent and sibling parts are empty for the root. A blank node has an absent public
+/// key.
+///
+/// Panics: when `num_leaves` doesn't match the size of `tree`, or `node_idx` is not in the tree
+pub(crate) fn parent_hash(
+    cs: &CipherSuite,
+    tree: &RatchetTree,
+    node_idx: usize,
+    num_leaves: usize,
+) -> Vec<u8> {
+    assert_eq!(
+        num_leaves,
+        tree.num_leaves(),
+        "wrong tree size for parent hash"
+    );
+
+    let parent = tree_math::node_parent(node_idx, num_leaves);
+    if parent == node_idx {
+        // The root has no parent
+        parent_hash_step(cs, tree, node_idx, num_leaves, None)
+    } else {
+        let parent_hash = parent_hash(cs, tree, parent, num_leaves);
+        parent_hash_step(cs, tree, node_idx, num_leaves, Some(&parent_hash))
+    }
+}
+
+/// Computes the parent hash of the given node, given the parent hash of its parent. This is `None`
+/// iff `node_idx` is the root.
+fn parent_hash_step(
+    cs: &CipherSuite,
+    tree: &RatchetTree,
+    node_idx: usize,
+    num_leaves: usize,
+    parent_hash: Option<&[u8]>,
+) -> Vec<u8> {
+    #[derive(Serialize)]
+    struct SiblingResolution<'a> {
+        #[serde(rename = "public_keys__bound_u32")]
+        public_keys: Vec<&'a DhPoint>,
+    }
+
+    #[derive(Serialize)]
+    struct ParentHashInput<'a> {
+        public_key: Option<&'a DhPoint>,
+        #[serde(rename = "parent_hash__bound_u8")]
+        parent_hash: &'a [u8],
+        #[serde(rename = "sibling_hash__bound_u8")]
+        sibling_hash: &'a [u8],
+    }
+
+    let public_key = match tree
+        .get(node_idx)
+        .expect("parent hash of a nonexistent node")
+    {
+        RatchetTreeNode::Blank => None,
+        RatchetTreeNode::Filled { pubkey, .. } => Some(pubkey),
+    };
+
+    // The root has no sibling, so it has an empty sibling hash along with its empty parent hash
+    let sibling_hash = match parent_hash {
+        None => Vec::new(),
+        Some(_) => {
+            let sibling = tree_math::node_sibling(node_idx, num_leaves);
+            let public_keys = tree
+                .resolution(sibling)
+                .into_iter()
+                .map(|node| match node {
+                    RatchetTreeNode::Filled { pubkey, .. } => pubkey,
+                    RatchetTreeNode::Blank => unreachable!("resolutions don't contain blanks"),
+                })
+                .collect();
+            let serialized = tls_ser::serialize_to_bytes(&SiblingResolution { public_keys })
+                .expect("couldn't serialize sibling resolution");
+            cs.hash(&serialized)
+        }
+    };
+
+    let input = ParentHashInput {
+        public_key,
+        parent_hash: parent_hash.unwrap_or(&[]),
+        sibling_hash: &sibling_hash,
+    };
+    let serialized =
+        tls_ser::serialize_to_bytes(&input).expect("couldn't serialize parent hash input");
+    cs.hash(&serialized)
+}
+
 // This has the same functionality as make_node_iter, so one of them's got to go
 /// An iterator that holds a queue of indices into a RatchetTree, and returns references to the
 /// corresponding nodes in the tree.
@@ -153,6 +781,8 @@ impl<'a> RatchetTreeIterMut<'a> {
         // in the clear.
         indices.dedup();
         let index_iter = indices.into_iter();
+        // We can't see what gets done with the nodes we hand out
+        underlying_tree.clear_resolution_cache();
 
         RatchetTreeIterMut {
             underlying_tree,
@@ -197,3 +827,448 @@ impl<'a> Iterator for RatchetTreeIterMut<'a> {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::credential::{BasicCredential, Identity};
+    use crate::crypto::{
+        ciphersuite::{P256_SHA256_AES128GCM, X25519_SHA256_AES128GCM},
+        rng::CryptoRng,
+    };
+    use crate::tls_de::TlsDeserializer;
+
+    use quickcheck_macros::quickcheck;
+    use rand::SeedableRng;
+
+    // Makes a tree with the given number of leaves where every node has a fresh public key, except
+    // for the ones that are marked blank
+    fn make_tree<R: CryptoRng>(
+        cs: &CipherSuite,
+        num_leaves: usize,
+        blanks: &[bool],
+        rng: &mut R,
+    ) -> RatchetTree {
+        let mut tree = RatchetTree::new();
+        for _ in 0..num_leaves {
+            tree.add_leaf_node(RatchetTreeNode::Blank);
+        }
+        for (node, &blank) in tree.nodes.iter_mut().zip(blanks.iter().cycle()) {
+            if !blank {
+                let (pubkey, _) = cs.dh_impl.generate_keypair(rng).unwrap();
+                *node = RatchetTreeNode::Filled {
+                    pubkey,
+                    privkey: None,
+                    secret: None,
+                    credential: None,
+                    parent_hash: Vec::new(),
+                };
+            }
+        }
+
+        tree
+    }
+
+    // Makes a basic credential with the given name and a fresh identity key
+    fn make_credential<R: CryptoRng>(cs: &CipherSuite, name: &[u8], rng: &mut R) -> Credential {
+        let identity_key = cs.sig_impl.secret_key_from_random(rng).unwrap();
+        Credential::Basic(BasicCredential {
+            identity: Identity(name.to_vec()),
+            signature_scheme: cs.sig_impl,
+            public_key: cs.sig_impl.public_key_from_secret_key(&identity_key),
+        })
+    }
+
+    // Checks that a tree with blank nodes, filled leaves with credentials, and filled parents with
+    // parent hashes survives a roundtrip over the wire, that blank nodes take up a single zero
+    // byte, and that trees of the wrong size or with credentials on non-leaf nodes are rejected
+    #[quickcheck]
+    fn tree_serialization_roundtrip(rng_seed: u64) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        // Nodes 1 and 5 are filled parents, and nodes 2, 6, and 8 are filled leaves
+        let blanks = [true, false, false, true, true, false, false, true, false];
+        let mut tree = make_tree(cs, 5, &blanks, &mut rng);
+        let parent_hashes = tree.parent_hashes(cs);
+        for (idx, node) in tree.nodes.iter_mut().enumerate() {
+            if let RatchetTreeNode::Filled {
+                credential,
+                parent_hash,
+                ..
+            } = node
+            {
+                if tree_math::node_to_leaf(idx).is_some() {
+                    *credential = Some(make_credential(cs, &[b'A' + idx as u8], &mut rng));
+                }
+                *parent_hash = parent_hashes[idx].clone();
+            }
+        }
+
+        let bytes = tls_ser::serialize_to_bytes(&tree).unwrap();
+        // The length prefix is followed by node 0, which is blank, and then node 1, which isn't
+        assert_eq!(bytes[4], 0x00);
+        assert_eq!(bytes[5], 0x01);
+
+        let mut buf = bytes.as_slice();
+        let mut deserializer = TlsDeserializer::from_reader(&mut buf);
+        let recovered = RatchetTree::deserialize(&mut deserializer).unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(recovered.num_leaves(), 5);
+        assert_eq!(recovered.blanks(), tree.blanks());
+        assert_eq!(tls_ser::serialize_to_bytes(&recovered).unwrap(), bytes);
+        for (idx, node) in tree.nodes.iter().enumerate() {
+            match (node, recovered.get(idx).unwrap()) {
+                (RatchetTreeNode::Blank, RatchetTreeNode::Blank) => (),
+                (
+                    RatchetTreeNode::Filled {
+                        pubkey: pubkey_a,
+                        credential: credential_a,
+                        parent_hash: parent_hash_a,
+                        ..
+                    },
+                    RatchetTreeNode::Filled {
+                        pubkey: pubkey_b,
+                        credential: credential_b,
+                        parent_hash: parent_hash_b,
+                        ..
+                    },
+                ) => {
+                    assert_eq!(
+                        cs.dh_impl.point_as_bytes(pubkey_a.clone()),
+                        cs.dh_impl.point_as_bytes(pubkey_b.clone())
+                    );
+                    assert_eq!(
+                        credential_a.as_ref().map(Credential::identity),
+                        credential_b.as_ref().map(Credential::identity)
+                    );
+                    assert_eq!(parent_hash_a, parent_hash_b);
+                    assert!(!parent_hash_b.is_empty());
+                }
+                _ => panic!("node {} changed blankness in a roundtrip", idx),
+            }
+        }
+        assert!(recovered.credential(2).is_some());
+        assert!(recovered.credential(5).is_none());
+
+        // An empty tree and a tree with 2 nodes aren't trees
+        for bytes in &[&[0u8, 0, 0, 0][..], &[0, 0, 0, 2, 0, 0]] {
+            let mut buf = *bytes;
+            let mut deserializer = TlsDeserializer::from_reader(&mut buf);
+            assert!(RatchetTree::deserialize(&mut deserializer).is_err());
+        }
+
+        // Only leaves can have credentials
+        if let Some(RatchetTreeNode::Filled { credential, .. }) = tree.get_mut(5) {
+            *credential = Some(make_credential(cs, b"Mallory", &mut rng));
+        }
+        let bytes = tls_ser::serialize_to_bytes(&tree).unwrap();
+        let mut buf = bytes.as_slice();
+        let mut deserializer = TlsDeserializer::from_reader(&mut buf);
+        assert!(RatchetTree::deserialize(&mut deserializer).is_err());
+    }
+
+    // Checks that the recursive parent hash agrees with the top-down computation for every node,
+    // that changing a node's public key changes its parent hash and those of its descendants, and
+    // that a tree with stored parent hashes fails verification once one of its nodes is swapped
+    #[quickcheck]
+    fn parent_hash_correctness(rng_seed: u64, num_leaves: u8, blanks: Vec<bool>) {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let num_leaves = (num_leaves as usize % 20) + 1;
+        // Make sure there's at least one non-blank node
+        let blanks = if blanks.is_empty() {
+            vec![false]
+        } else {
+            blanks
+        };
+
+        let mut tree = make_tree(cs, num_leaves, &blanks, &mut rng);
+        let stored_hashes = tree.parent_hashes(cs);
+        for (idx, stored_hash) in stored_hashes.iter().enumerate() {
+            assert_eq!(&parent_hash(cs, &tree, idx, num_leaves), stored_hash);
+        }
+
+        // Store the hashes. The tree should verify, but not once a filled node's public key is
+        // swapped out from under its parent hash.
+        tree.set_parent_hashes(cs);
+        assert!(tree.verify_parent_hashes(cs).is_ok());
+        let mut tampered_tree = tree.clone();
+        let filled_node = tampered_tree.nodes.iter_mut().find_map(|node| match node {
+            RatchetTreeNode::Filled { pubkey, .. } => Some(pubkey),
+            RatchetTreeNode::Blank => None,
+        });
+        if let Some(pubkey) = filled_node {
+            *pubkey = cs.dh_impl.generate_keypair(&mut rng).unwrap().0;
+            match tampered_tree.verify_parent_hashes(cs) {
+                Err(Error::ParentHashMismatch) => (),
+                _ => panic!("expected a parent hash mismatch"),
+            }
+        }
+
+        // Now swap out the root's public key. Every node is a descendant of the root, so every
+        // parent hash should change.
+        let root = tree_math::try_root_idx(num_leaves).unwrap();
+        let (new_pubkey, _) = cs.dh_impl.generate_keypair(&mut rng).unwrap();
+        *tree.get_mut(root).unwrap() = RatchetTreeNode::Filled {
+            pubkey: new_pubkey,
+            privkey: None,
+            secret: None,
+            credential: None,
+            parent_hash: Vec::new(),
+        };
+        for (idx, stored_hash) in stored_hashes.iter().enumerate() {
+            assert_ne!(&parent_hash(cs, &tree, idx, num_leaves), stored_hash);
+        }
+    }
+
+    // The tree relations from tree_math's tree_relations_kat, as seen through a RatchetTree. We use
+    // the same 5-leaf tree:
+    //               7
+    //         _____/ \
+    //        /        |
+    //       3         |
+    //     /   \       |
+    //    /     \      |
+    //   1       5     |
+    //  / \     / \    |
+    // 0   2   4   6   8
+    #[test]
+    fn tree_relations_kat() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let cs = &X25519_SHA256_AES128GCM;
+        // Node indices are 0 through 8 here. Blank out nodes 1 and 6.
+        let blanks = [false, true, false, false, false, false, true, false, false];
+        let mut tree = make_tree(cs, 5, &blanks, &mut rng);
+        assert_eq!(tree.num_leaves(), 5);
+
+        assert_eq!(tree.direct_path(0), vec![1, 3]);
+        assert_eq!(tree.direct_path(4), vec![5, 3]);
+        assert_eq!(tree.direct_path(8), Vec::<usize>::new());
+        assert_eq!(tree.direct_path(7), Vec::<usize>::new());
+
+        assert_eq!(tree.copath(0), vec![2, 5, 8]);
+        assert_eq!(tree.copath(4), vec![6, 1, 8]);
+        assert_eq!(tree.copath(8), vec![3]);
+        assert_eq!(tree.copath(7), Vec::<usize>::new());
+
+        // The resolution of a blank node is the resolution of its children. Compare by address,
+        // since the nodes are the ones in the tree.
+        let resolution_indices = |tree: &RatchetTree, idx: usize| -> Vec<usize> {
+            tree.resolution(idx)
+                .into_iter()
+                .map(|node| {
+                    (0..tree.num_nodes())
+                        .find(|&i| std::ptr::eq(tree.get(i).unwrap(), node))
+                        .unwrap()
+                })
+                .collect()
+        };
+        assert_eq!(resolution_indices(&tree, 1), vec![0, 2]);
+        assert_eq!(resolution_indices(&tree, 6), Vec::<usize>::new());
+        assert_eq!(resolution_indices(&tree, 3), vec![3]);
+
+        // Blanking the path above leaf 2 (node 4) blanks nodes 5, 3, and 7, and nothing else
+        tree.blank_path(2);
+        assert_eq!(
+            tree.blanks(),
+            vec![false, true, false, true, false, true, true, true, false]
+        );
+        assert_eq!(resolution_indices(&tree, 7), vec![0, 2, 4, 8]);
+
+        // Adding a leaf puts a blank parent between the old leaves and the new one
+        tree.add_leaf_node(RatchetTreeNode::Blank);
+        assert_eq!(tree.num_leaves(), 6);
+        assert_eq!(tree.direct_path(8), vec![9]);
+        assert_eq!(tree.copath(10), vec![8, 3]);
+    }
+
+    // Builds trees out of lists of leaves and checks that the leaves land where they should with
+    // their credentials, that everything else is blank, and that a bad public key is an error
+    #[test]
+    fn from_leaves_kat() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let cs = &X25519_SHA256_AES128GCM;
+        let pubkeys: Vec<DhPoint> = (0..2)
+            .map(|_| cs.dh_impl.generate_keypair(&mut rng).unwrap().0)
+            .collect();
+        let alice_cred = make_credential(cs, b"Alice", &mut rng);
+        let carol_cred = make_credential(cs, b"Carol", &mut rng);
+
+        // 3 leaves, the middle one blank, makes the 5-node tree
+        //     3
+        //    / \
+        //   1   |
+        //  / \  |
+        // 0   2 4
+        let tree = RatchetTree::from_leaves(
+            cs,
+            vec![
+                Some((pubkeys[0].clone(), alice_cred.clone())),
+                None,
+                Some((pubkeys[1].clone(), carol_cred)),
+            ],
+        )
+        .unwrap();
+        assert_eq!(tree.num_nodes(), 5);
+        assert_eq!(tree.num_leaves(), 3);
+        assert_eq!(tree.validate_shape().unwrap(), 3);
+        assert_eq!(tree.blanks(), vec![false, true, true, true, false]);
+        for (idx, pubkey, name) in &[(0, &pubkeys[0], b"Alice"), (4, &pubkeys[1], b"Carol")] {
+            match tree.get(*idx) {
+                Some(RatchetTreeNode::Filled {
+                    pubkey: leaf_pubkey,
+                    privkey: None,
+                    secret: None,
+                    credential: Some(credential),
+                    ..
+                }) => {
+                    assert_eq!(
+                        cs.dh_impl.point_as_bytes(leaf_pubkey.clone()),
+                        cs.dh_impl.point_as_bytes((*pubkey).clone())
+                    );
+                    assert_eq!(credential.identity(), &name[..]);
+                }
+                _ => panic!(
+                    "leaf at node {} should only have a public key and credential",
+                    idx
+                ),
+            }
+        }
+        assert_eq!(tree.resolution(3).len(), 2);
+
+        // No leaves at all still makes a tree
+        let tree = RatchetTree::from_leaves(cs, Vec::new()).unwrap();
+        assert_eq!(tree.num_leaves(), 1);
+        assert_eq!(tree.blanks(), vec![true]);
+
+        // A P-256 key doesn't go in an X25519 tree
+        let (p256_pubkey, _) = P256_SHA256_AES128GCM
+            .dh_impl
+            .generate_keypair(&mut rng)
+            .unwrap();
+        match RatchetTree::from_leaves(cs, vec![Some((p256_pubkey, alice_cred))]) {
+            Err(Error::BadPointLength { expected, got }) => {
+                assert_eq!(expected, 32);
+                assert_eq!(got, 65);
+            }
+            _ => panic!("expected a bad point length error"),
+        }
+    }
+
+    // Known-answer test for the path secret chain. Like the HKDF-Expand-Label KAT in
+    // ciphersuite.rs, the expected secrets were computed independently with Python's hmac module,
+    // using the hkdf_expand_label function in reference/key_schedule.py.
+    #[test]
+    fn derive_path_secrets_kat() {
+        let cs = &X25519_SHA256_AES128GCM;
+        let start_secret: Vec<u8> = (0u8..32).collect();
+        let expected = [
+            "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+            "ce3cc5b8a99226587b74feda1beeba36581917837e2359243a5faa2cd03d8866",
+            "3b2737d92bd815182a305b48ae8870197ec48ed6a1566c2b5a39b61109de6e9a",
+            "c3ac50a63cf8bb409d562dbd42fa623e3e1cd240b4b58f9e2b8033d168ecb530",
+        ];
+
+        let path_secrets = derive_path_secrets(cs, &start_secret, expected.len());
+        assert_eq!(path_secrets.len(), expected.len());
+        for (path_secret, expected) in path_secrets.iter().zip(expected.iter()) {
+            assert_eq!(hex::encode(path_secret), *expected);
+        }
+        assert!(derive_path_secrets(cs, &start_secret, 0).is_empty());
+    }
+
+    // Has the sender of a path and two receivers, each of whom decrypted a different node's
+    // secret, merge what they know into their own trees. Checks that they end up agreeing on the
+    // root secret, and on every node they have in common.
+    #[quickcheck]
+    fn merge_path_secret_agreement(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let cs = &X25519_SHA256_AES128GCM;
+
+        // The sender is at leaf 0 of a 4-leaf tree, so their path is 0, 1, 3. The member at leaf 1
+        // (node 2) gets the secret of node 1, and the member at leaf 3 (node 6) gets the secret of
+        // the root, node 3.
+        let leaf_secret = cs.generate_leaf_secret(&mut rng);
+        let path_secrets = derive_path_secrets(cs, &leaf_secret, 3);
+
+        let mut sender_tree = make_tree(cs, 4, &[false], &mut rng);
+        let mut near_tree = sender_tree.public_copy();
+        let mut far_tree = sender_tree.public_copy();
+
+        let sender_root_secret = sender_tree
+            .merge_path_secret(cs, 0, &path_secrets[0])
+            .unwrap();
+        let near_root_secret = near_tree
+            .merge_path_secret(cs, 1, &path_secrets[1])
+            .unwrap();
+        let far_root_secret = far_tree.merge_path_secret(cs, 3, &path_secrets[2]).unwrap();
+
+        assert_eq!(sender_root_secret, path_secrets[2]);
+        assert_eq!(near_root_secret, sender_root_secret);
+        assert_eq!(far_root_secret, sender_root_secret);
+
+        let node_secret = |tree: &RatchetTree, idx: usize| match tree.get(idx) {
+            Some(RatchetTreeNode::Filled {
+                secret: Some(secret),
+                privkey: Some(_),
+                ..
+            }) => secret.clone(),
+            _ => panic!("node {} doesn't have a secret and private key", idx),
+        };
+        let node_pubkey = |tree: &RatchetTree, idx: usize| match tree.get(idx) {
+            Some(RatchetTreeNode::Filled { pubkey, .. }) => {
+                cs.dh_impl.point_as_bytes(pubkey.clone())
+            }
+            _ => panic!("node {} is blank", idx),
+        };
+
+        for &idx in &[1, 3] {
+            assert_eq!(node_secret(&sender_tree, idx), node_secret(&near_tree, idx));
+            assert_eq!(node_pubkey(&sender_tree, idx), node_pubkey(&near_tree, idx));
+        }
+        assert_eq!(node_secret(&sender_tree, 3), node_secret(&far_tree, 3));
+        assert_eq!(node_pubkey(&sender_tree, 3), node_pubkey(&far_tree, 3));
+
+        // Nothing below where each member started was touched
+        assert!(near_tree.get(0).map_or(false, |node| match node {
+            RatchetTreeNode::Filled { secret, .. } => secret.is_none(),
+            RatchetTreeNode::Blank => false,
+        }));
+    }
+
+    // Blanks nodes in a random order, asking for resolutions along the way so that there's
+    // something cached, and checks that every resolution always agrees with the one computed from
+    // scratch
+    #[quickcheck]
+    fn resolution_cache_correctness(rng_seed: u64, num_leaves: u8, blank_order: Vec<u16>) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let cs = &X25519_SHA256_AES128GCM;
+        let num_leaves = (num_leaves as usize % 16) + 1;
+
+        let mut tree = make_tree(cs, num_leaves, &[false], &mut rng);
+        let num_nodes = tree.num_nodes();
+        let check_all = |tree: &RatchetTree| {
+            for idx in 0..num_nodes {
+                let expected = tree_math::node_resolution(idx, num_leaves, &tree.blanks(), None);
+                assert_eq!(tree.resolution_indices(idx), expected);
+            }
+        };
+
+        check_all(&tree);
+        for idx in blank_order.into_iter().map(|i| i as usize % num_nodes) {
+            tree.blank_node(idx);
+            check_all(&tree);
+        }
+
+        // Filling a node back in through get_mut is seen too
+        let (pubkey, _) = cs.dh_impl.generate_keypair(&mut rng).unwrap();
+        *tree.get_mut(0).unwrap() = RatchetTreeNode::Filled {
+            pubkey,
+            privkey: None,
+            secret: None,
+            credential: None,
+            parent_hash: Vec::new(),
+        };
+        check_all(&tree);
+    }
+}
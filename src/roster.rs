@@ -0,0 +1,183 @@
+use crate::credential::Credential;
+use crate::utils::next_version;
+
+// The roster is serialized in GroupState and WelcomeInfo as optional<Credential> roster<1..2^32-1>
+// So we encode Roster as a Vec<Option<Credential>> with length bound u32.
+
+/// The credentials of the members of a group, indexed by leaf. Slot `i` of the roster belongs to
+/// leaf `i` of the ratchet tree, and a `None` is a blank slot, i.e., a member who was removed. The
+/// add and remove operations here place and drop slots in exactly the same way the tree places and
+/// drops leaves, so that the two stay in sync.
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct Roster {
+    #[serde(rename = "credentials__bound_u32")]
+    credentials: Vec<Option<Credential>>,
+    /// Changes every time this roster is modified. See `utils::next_version`. This is never
+    /// serialized.
+    #[serde(skip, default = "next_version")]
+    version: u64,
+}
+
+impl Roster {
+    /// Returns a new empty `Roster`
+    pub(crate) fn new() -> Roster {
+        Roster {
+            credentials: Vec::new(),
+            version: next_version(),
+        }
+    }
+
+    /// Returns the credential at the given index, or `None` if the index is out of range or the
+    /// slot is blank
+    pub(crate) fn get(&self, index: usize) -> Option<&Credential> {
+        self.credentials.get(index).and_then(|cred| cred.as_ref())
+    }
+
+    /// Returns the number of slots in the roster, blank or not. This is always equal to the number
+    /// of leaves in the group's tree.
+    pub(crate) fn len(&self) -> usize {
+        self.credentials.len()
+    }
+
+    /// Returns the number of slots in the roster that aren't blank, i.e., the number of members
+    pub(crate) fn len_active(&self) -> usize {
+        self.credentials
+            .iter()
+            .filter(|cred| cred.is_some())
+            .count()
+    }
+
+    /// Returns the version of this roster. This changes every time the roster is modified, so it
+    /// tells whether something computed from the roster is still up to date.
+    pub(crate) fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Returns an iterator over every slot in the roster, in order of index
+    pub(crate) fn iter(&self) -> impl Iterator<Item = Option<&Credential>> {
+        self.credentials.iter().map(Option::as_ref)
+    }
+
+    /// Puts the given credential in the leftmost blank slot, if there is one. Otherwise, appends
+    /// it to the end of the roster. This is where the tree puts a new member's leaf.
+    ///
+    /// Returns: the index of the slot the credential went into
+    pub(crate) fn add(&mut self, credential: Credential) -> usize {
+        self.version = next_version();
+        match self.credentials.iter().position(Option::is_none) {
+            Some(index) => {
+                self.credentials[index] = Some(credential);
+                index
+            }
+            None => {
+                self.credentials.push(Some(credential));
+                self.credentials.len() - 1
+            }
+        }
+    }
+
+    /// Blanks the slot at the given index, and then removes the blank slots on the right edge of
+    /// the roster. Like the tree, the roster never shrinks below one slot. This does nothing if
+    /// the index is out of range.
+    ///
+    /// Returns: the credential that was removed, or `None` if the slot was out of range or already
+    /// blank
+    pub(crate) fn remove(&mut self, index: usize) -> Option<Credential> {
+        self.version = next_version();
+        let removed = self.credentials.get_mut(index).and_then(Option::take);
+
+        let new_len = self
+            .credentials
+            .iter()
+            .rposition(Option::is_some)
+            .map(|last| last + 1)
+            .unwrap_or(1);
+        self.credentials.truncate(new_len);
+
+        removed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::credential::{BasicCredential, Identity};
+    use crate::crypto::sig::{SignatureScheme, ED25519_IMPL};
+
+    use rand::SeedableRng;
+
+    fn make_credential(name: &[u8]) -> Credential {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let secret_key = ED25519_IMPL.secret_key_from_random(&mut rng).unwrap();
+        Credential::Basic(BasicCredential {
+            identity: Identity(name.to_vec()),
+            signature_scheme: &ED25519_IMPL,
+            public_key: ED25519_IMPL.public_key_from_secret_key(&secret_key),
+        })
+    }
+
+    fn identities(roster: &Roster) -> Vec<Option<&[u8]>> {
+        roster
+            .iter()
+            .map(|cred| cred.map(Credential::identity))
+            .collect()
+    }
+
+    // New members go into the leftmost gap before the roster grows
+    #[test]
+    fn roster_add_into_gap() {
+        let mut roster = Roster::new();
+        for name in &[&b"Alice"[..], b"Bob", b"Carol", b"Dave"] {
+            roster.add(make_credential(name));
+        }
+
+        assert!(roster.remove(1).is_some());
+        assert!(roster.remove(2).is_some());
+        assert_eq!(roster.len(), 4);
+        assert_eq!(roster.len_active(), 2);
+        assert!(roster.get(1).is_none());
+
+        assert_eq!(roster.add(make_credential(b"Eve")), 1);
+        assert_eq!(roster.add(make_credential(b"Frank")), 2);
+        assert_eq!(roster.add(make_credential(b"Grace")), 4);
+        assert_eq!(
+            identities(&roster),
+            vec![
+                Some(&b"Alice"[..]),
+                Some(b"Eve"),
+                Some(b"Frank"),
+                Some(b"Dave"),
+                Some(b"Grace")
+            ]
+        );
+    }
+
+    // Removing the rightmost member drops every trailing blank, the same way the tree is truncated
+    #[test]
+    fn roster_remove_then_truncate() {
+        let mut roster = Roster::new();
+        for name in &[&b"Alice"[..], b"Bob", b"Carol", b"Dave"] {
+            roster.add(make_credential(name));
+        }
+
+        // A gap in the middle stays put
+        roster.remove(2);
+        assert_eq!(roster.len(), 4);
+
+        // Removing Dave makes Carol's blank slot trailing, so both go
+        roster.remove(3);
+        assert_eq!(identities(&roster), vec![Some(&b"Alice"[..]), Some(b"Bob")]);
+        assert_eq!(roster.len_active(), 2);
+
+        // Removing a blank or out-of-range slot does nothing
+        assert!(roster.remove(2).is_none());
+        assert!(roster.remove(7).is_none());
+        assert_eq!(roster.len(), 2);
+
+        // The roster never goes below one slot
+        roster.remove(0);
+        roster.remove(1);
+        assert_eq!(identities(&roster), vec![None]);
+        assert_eq!(roster.len_active(), 0);
+    }
+}
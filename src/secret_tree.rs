@@ -0,0 +1,535 @@
+use crate::crypto::{
+    aead::{AeadKey, AeadNonce},
+    ciphersuite::CipherSuite,
+};
+use crate::error::Error;
+use crate::tree_math;
+
+use zeroize::Zeroize;
+
+/// The default number of generations behind a sender's newest one whose keys we hang on to. See
+/// `SecretTree::key_nonce_for_generation`.
+const DEFAULT_OUT_OF_ORDER_WINDOW: u32 = 5;
+
+/// The most generations a sender's chain can be ratcheted forward by a single call to
+/// `SecretTree::key_nonce_for_generation`. Without a bound, a message claiming a huge generation
+/// would make us do billions of key derivations.
+const MAX_FORWARD_SKIP: u32 = 1000;
+
+// opaque secret<0..255>
+/// A secret in the tree: either the secret of a node or the chain secret of a sender. It's wiped
+/// when it's dropped.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename = "TreeSecret__bound_u8")]
+struct TreeSecret(Vec<u8>);
+
+impl Drop for TreeSecret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// The key and nonce of a single generation of a sender's chain. These are kept as bytes, rather
+/// than as an `AeadKey` and `AeadNonce`, so that the keys of skipped generations can be cloned and
+/// persisted along with the rest of the tree.
+#[derive(Clone, Deserialize, Serialize)]
+struct GenerationKeyNonce {
+    generation: u32,
+    // opaque key<0..255>
+    #[serde(rename = "key__bound_u8")]
+    key: Vec<u8>,
+    // opaque nonce<0..255>
+    #[serde(rename = "nonce__bound_u8")]
+    nonce: Vec<u8>,
+}
+
+impl GenerationKeyNonce {
+    /// Makes the AEAD key and nonce of this generation
+    ///
+    /// Returns: `Ok((key, nonce))` on success. Otherwise, returns whatever error the cipher suite's
+    /// AEAD returns.
+    fn to_aead(&self, cs: &CipherSuite) -> Result<(AeadKey, AeadNonce), Error> {
+        let key = cs.aead_impl.key_from_bytes(&self.key)?;
+        let nonce = cs.aead_impl.nonce_from_bytes(&self.nonce)?;
+        Ok((key, nonce))
+    }
+}
+
+impl Drop for GenerationKeyNonce {
+    fn drop(&mut self) {
+        self.key.zeroize();
+        self.nonce.zeroize();
+    }
+}
+
+/// The state of a single sender's ratchet: the current chain secret and the generation it
+/// corresponds to
+#[derive(Clone, Deserialize, Serialize)]
+struct SenderRatchet {
+    secret: TreeSecret,
+    generation: u32,
+    /// Keys and nonces of generations that were skipped over and haven't been used yet
+    #[serde(rename = "skipped__bound_u32")]
+    skipped: Vec<GenerationKeyNonce>,
+}
+
+impl SenderRatchet {
+    /// Derives the key and nonce of the current generation and moves the chain forward by one.
+    ///
+    /// Returns: `Ok(key_nonce)` on success. If the chain has been exhausted, returns an
+    /// `Error::SecretTreeError`.
+    fn step(&mut self, cs: &CipherSuite) -> Result<GenerationKeyNonce, Error> {
+        if self.generation == std::u32::MAX {
+            return Err(Error::SecretTreeError("Sender has exhausted its ratchet"));
+        }
+
+        // key = HKDF-Expand-Label(secret, "key", "", key_length)
+        // nonce = HKDF-Expand-Label(secret, "nonce", "", nonce_length)
+        // secret_[n+1] = HKDF-Expand-Label(secret_[n], "secret", "", Hash.length)
+        let aead = cs.aead_impl;
+        let key_nonce = GenerationKeyNonce {
+            generation: self.generation,
+            key: cs.hkdf_expand_label(&self.secret.0, "key", b"", aead.key_size()),
+            nonce: cs.hkdf_expand_label(&self.secret.0, "nonce", b"", aead.nonce_size()),
+        };
+        let next_secret = cs.hkdf_expand_label(&self.secret.0, "secret", b"", cs.hash_len());
+
+        // The old chain secret is wiped as it's dropped
+        self.secret = TreeSecret(next_secret);
+        self.generation += 1;
+
+        Ok(key_nonce)
+    }
+}
+
+/// A tree of secrets from which every member of the group derives per-sender chains of
+/// application message keys. The root is the application secret of the current epoch. Every node
+/// secret is consumed (and wiped) the first time it's used to derive its children, so that old
+/// keys can't be recovered from the tree once they've been used.
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct SecretTree {
+    cs: &'static CipherSuite,
+    num_leaves: usize,
+    /// The secrets of the interior and leaf nodes that haven't been consumed yet, indexed by node
+    #[serde(rename = "node_secrets__bound_u32")]
+    node_secrets: Vec<Option<TreeSecret>>,
+    /// The ratchets of the senders whose leaf secrets have been consumed, indexed by leaf
+    #[serde(rename = "sender_ratchets__bound_u32")]
+    sender_ratchets: Vec<Option<SenderRatchet>>,
+    /// How many generations behind a sender's newest one we keep skipped keys around for
+    out_of_order_window: u32,
+}
+
+impl SecretTree {
+    /// Makes a new `SecretTree` for a group with `num_leaves` many leaves, rooted at the given
+    /// application secret
+    ///
+    /// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES`
+    pub(crate) fn new(
+        cs: &'static CipherSuite,
+        application_secret: &[u8],
+        num_leaves: usize,
+    ) -> SecretTree {
+        let num_nodes =
+            tree_math::try_num_nodes_in_tree(num_leaves).expect("invalid secret tree size");
+        let root = tree_math::try_root_idx(num_leaves).expect("invalid secret tree size");
+
+        let mut node_secrets = vec![None; num_nodes];
+        node_secrets[root] = Some(TreeSecret(application_secret.to_vec()));
+
+        SecretTree {
+            cs,
+            num_leaves,
+            node_secrets,
+            sender_ratchets: vec![None; num_leaves],
+            out_of_order_window: DEFAULT_OUT_OF_ORDER_WINDOW,
+        }
+    }
+
+    /// Checks that this tree, which was deserialized from local storage, is a secret tree for the
+    /// given cipher suite with `num_leaves` many leaves. Everything in the tree is indexed by its
+    /// shape, so this has to be done before it's used.
+    ///
+    /// Returns: `Ok(())` if it is. Otherwise, returns an `Error::ValidationError`.
+    pub(crate) fn validate_persisted(
+        &self,
+        cs: &'static CipherSuite,
+        num_leaves: usize,
+    ) -> Result<(), Error> {
+        let expected_num_nodes = tree_math::try_num_nodes_in_tree(num_leaves)
+            .map_err(|_| Error::ValidationError("Persisted secret tree has an invalid size"))?;
+        if self.cs.name != cs.name
+            || self.num_leaves != num_leaves
+            || self.node_secrets.len() != expected_num_nodes
+            || self.sender_ratchets.len() != num_leaves
+        {
+            return Err(Error::ValidationError(
+                "Persisted secret tree doesn't match the group",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the key and nonce for the next message sent by the member at the given leaf,
+    /// along with the generation of that key. This ratchets the sender's chain forward, so every
+    /// call returns a fresh key/nonce with a generation one greater than the last.
+    ///
+    /// The key and nonce are both derived from the chain secret of their generation, never drawn
+    /// at random, so two members can't disagree on them and a nonce is never repeated under a
+    /// key. The flip side is that each key is single-use: it must encrypt exactly one message,
+    /// since its nonce is fixed.
+    ///
+    /// Returns: `Ok((key, nonce, generation))` on success. If `sender_leaf` is out of range, or
+    /// the sender's chain has been exhausted, returns an `Error::SecretTreeError`. Otherwise,
+    /// returns whatever error the cipher suite's AEAD returns.
+    pub(crate) fn next_key_nonce(
+        &mut self,
+        sender_leaf: usize,
+    ) -> Result<(AeadKey, AeadNonce, u32), Error> {
+        let cs = self.cs;
+        let key_nonce = self.sender_ratchet(sender_leaf)?.step(cs)?;
+        let (key, nonce) = key_nonce.to_aead(cs)?;
+
+        Ok((key, nonce, key_nonce.generation))
+    }
+
+    /// Returns the key and nonce of the given generation of the given sender's chain. This is how
+    /// a receiver gets the key for a message, since messages can arrive out of order. If the
+    /// generation is ahead of the sender's chain, the chain is ratcheted up to it, and the keys of
+    /// every generation skipped along the way are kept until they're used. Keys more than
+    /// `out_of_order_window` generations behind the newest one are thrown away, so that a
+    /// compromise doesn't reveal the keys of old messages. Every key is handed out at most once.
+    /// The chain won't be ratcheted more than `MAX_FORWARD_SKIP` generations ahead.
+    ///
+    /// Returns: `Ok((key, nonce))` on success. If `sender_leaf` is out of range, the key for the
+    /// generation was already handed out or thrown away, the generation is more than
+    /// `MAX_FORWARD_SKIP` ahead of the sender's chain, or the sender's chain has been exhausted,
+    /// returns an `Error::SecretTreeError`. Otherwise, returns whatever error the cipher suite's
+    /// AEAD returns.
+    pub(crate) fn key_nonce_for_generation(
+        &mut self,
+        sender_leaf: usize,
+        generation: u32,
+    ) -> Result<(AeadKey, AeadNonce), Error> {
+        let cs = self.cs;
+        let window = self.out_of_order_window;
+        let ratchet = self.sender_ratchet(sender_leaf)?;
+
+        if generation < ratchet.generation {
+            let pos = ratchet
+                .skipped
+                .iter()
+                .position(|skipped| skipped.generation == generation)
+                .ok_or(Error::SecretTreeError(
+                    "Key generation was already used or has expired",
+                ))?;
+            return ratchet.skipped.remove(pos).to_aead(cs);
+        }
+
+        // Check this before touching the chain, so that a bogus generation doesn't cost us anything
+        if generation - ratchet.generation > MAX_FORWARD_SKIP {
+            return Err(Error::SecretTreeError(
+                "Key generation is too far ahead of the sender's chain",
+            ));
+        }
+
+        // Only keep the skipped keys that are going to be in the window once we're done
+        let key_nonce = loop {
+            let key_nonce = ratchet.step(cs)?;
+            if key_nonce.generation == generation {
+                break key_nonce;
+            } else if generation - key_nonce.generation <= window {
+                ratchet.skipped.push(key_nonce);
+            }
+        };
+
+        // Drop the older skipped keys that have now fallen out of the window. The generation we
+        // just returned is the newest one.
+        ratchet
+            .skipped
+            .retain(|skipped| generation - skipped.generation <= window);
+
+        key_nonce.to_aead(cs)
+    }
+
+    /// Returns the ratchet of the given sender, starting it from the sender's leaf secret if this
+    /// is the first time it's been asked for
+    fn sender_ratchet(&mut self, sender_leaf: usize) -> Result<&mut SenderRatchet, Error> {
+        if sender_leaf >= self.num_leaves {
+            return Err(Error::SecretTreeError("Secret tree sender is out of range"));
+        }
+
+        if self.sender_ratchets[sender_leaf].is_none() {
+            let leaf_secret = self.take_leaf_secret(sender_leaf);
+            self.sender_ratchets[sender_leaf] = Some(SenderRatchet {
+                secret: leaf_secret,
+                generation: 0,
+                skipped: Vec::new(),
+            });
+        }
+
+        Ok(self.sender_ratchets[sender_leaf].as_mut().unwrap())
+    }
+
+    /// Walks down from the root to the given leaf, deriving the children of every node along the
+    /// way whose secret hasn't been consumed yet. Returns the leaf's secret and removes it from the
+    /// tree.
+    fn take_leaf_secret(&mut self, leaf: usize) -> TreeSecret {
+        let leaf_node = tree_math::leaf_to_node(leaf);
+        let path = tree_math::node_full_path(leaf_node, self.num_leaves);
+
+        // Go from the root down to the leaf's parent. Leaves have no children. Each parent secret
+        // is wiped as it's dropped at the end of its iteration.
+        for &node in path[1..].iter().rev() {
+            if let Some(secret) = self.node_secrets[node].take() {
+                // left_secret = HKDF-Expand-Label(secret, "tree", "left", Hash.length)
+                // right_secret = HKDF-Expand-Label(secret, "tree", "right", Hash.length)
+                let hash_len = self.cs.hash_len();
+                let left = tree_math::node_left_child(node);
+                let right = tree_math::node_right_child(node, self.num_leaves);
+                self.node_secrets[left] = Some(TreeSecret(
+                    self.cs
+                        .hkdf_expand_label(&secret.0, "tree", b"left", hash_len),
+                ));
+                self.node_secrets[right] = Some(TreeSecret(
+                    self.cs
+                        .hkdf_expand_label(&secret.0, "tree", b"right", hash_len),
+                ));
+            }
+        }
+
+        // Every leaf secret is derived exactly once, and only taken once, since the sender's
+        // ratchet lives on afterwards
+        self.node_secrets[leaf_node]
+            .take()
+            .expect("secret tree leaf was never derived")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::crypto::ciphersuite::{P256_SHA256_AES128GCM, X25519_SHA256_AES128GCM};
+    use crate::{tls_de::TlsDeserializer, tls_ser};
+
+    use quickcheck_macros::quickcheck;
+    use rand::{RngCore, SeedableRng};
+    use serde::de::Deserialize;
+
+    fn assert_secret_tree_error<T>(res: Result<T, Error>) {
+        match res {
+            Err(Error::SecretTreeError(_)) => (),
+            _ => panic!("expected a secret tree error"),
+        }
+    }
+
+    // Checks that two members who start from the same application secret agree on every key and
+    // nonce for every (sender, generation) pair, and that generations count up from 0
+    #[quickcheck]
+    fn secret_tree_agreement(rng_seed: u64, num_leaves: u8, num_messages: u8) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let num_leaves = (num_leaves as usize % 20) + 1;
+        let num_messages = num_messages as usize % 50;
+
+        for cs in &[&X25519_SHA256_AES128GCM, &P256_SHA256_AES128GCM] {
+            let application_secret = cs.generate_leaf_secret(&mut rng);
+
+            let mut tree_a = SecretTree::new(cs, &application_secret, num_leaves);
+            let mut tree_b = SecretTree::new(cs, &application_secret, num_leaves);
+            let mut last_generations = vec![None; num_leaves];
+
+            for _ in 0..num_messages {
+                // Senders are picked in random order, so the two trees derive their interior
+                // nodes in whatever order the senders first show up
+                let sender = rng.next_u32() as usize % num_leaves;
+                let (key_a, nonce_a, gen_a) = tree_a.next_key_nonce(sender).unwrap();
+                let (key_b, nonce_b, gen_b) = tree_b.next_key_nonce(sender).unwrap();
+
+                assert_eq!(gen_a, gen_b);
+                let expected_gen = last_generations[sender].map(|g| g + 1).unwrap_or(0);
+                assert_eq!(gen_a, expected_gen);
+                last_generations[sender] = Some(gen_a);
+
+                // AeadKey doesn't expose its bytes, so check that the keys agree by sealing with
+                // one and opening with the other
+                let plaintext = b"hello from the secret tree".to_vec();
+                let mut buf = plaintext.clone();
+                buf.extend(vec![0u8; cs.aead_impl.tag_size()]);
+                cs.aead_impl.seal(&key_a, nonce_a, &mut buf).unwrap();
+                let opened = cs.aead_impl.open(&key_b, nonce_b, &mut buf).unwrap();
+                assert_eq!(opened, plaintext.as_slice());
+            }
+        }
+    }
+
+    // Checks that the nonces are deterministic, and that a sender's chain never repeats one
+    #[test]
+    fn secret_tree_nonces_differ_across_generations() {
+        let cs = &X25519_SHA256_AES128GCM;
+        let application_secret = [0x17u8; 32];
+        let mut tree_a = SecretTree::new(cs, &application_secret, 2);
+        let mut tree_b = SecretTree::new(cs, &application_secret, 2);
+
+        let mut nonces = Vec::new();
+        for expected_gen in 0..2 {
+            let (_, nonce_a, gen) = tree_a.next_key_nonce(1).unwrap();
+            let (_, nonce_b, _) = tree_b.next_key_nonce(1).unwrap();
+            assert_eq!(gen, expected_gen);
+
+            let nonce_a = enum_variant!(nonce_a, AeadNonce::Aes128GcmNonce);
+            let nonce_b = enum_variant!(nonce_b, AeadNonce::Aes128GcmNonce);
+            assert_eq!(nonce_a.as_ref(), nonce_b.as_ref());
+            nonces.push(*nonce_a.as_ref());
+        }
+
+        assert_ne!(nonces[0], nonces[1]);
+    }
+
+    // Checks that different senders and different generations never get the same key
+    #[test]
+    fn secret_tree_distinct_keys() {
+        let cs = &X25519_SHA256_AES128GCM;
+        let application_secret = [0x42u8; 32];
+        let mut tree = SecretTree::new(cs, &application_secret, 3);
+
+        // Seal the same plaintext under a bunch of (sender, generation) keys and make sure all
+        // the ciphertexts differ
+        let mut ciphertexts = Vec::new();
+        for sender in 0..3 {
+            for _ in 0..3 {
+                let (key, nonce, _) = tree.next_key_nonce(sender).unwrap();
+                let mut buf = vec![0u8; 16 + cs.aead_impl.tag_size()];
+                cs.aead_impl.seal(&key, nonce, &mut buf).unwrap();
+                ciphertexts.push(buf);
+            }
+        }
+
+        for (i, c1) in ciphertexts.iter().enumerate() {
+            for c2 in ciphertexts[i + 1..].iter() {
+                assert_ne!(c1, c2);
+            }
+        }
+
+        // Out-of-range senders are rejected
+        assert_secret_tree_error(tree.next_key_nonce(3));
+    }
+
+    // Delivers a sender's messages to a receiver out of order, and checks that they all open, and
+    // that a key which fell out of the window or was already used can't be gotten again
+    #[test]
+    fn secret_tree_out_of_order() {
+        let cs = &X25519_SHA256_AES128GCM;
+        let application_secret = [0x5au8; 32];
+        let mut sender_tree = SecretTree::new(cs, &application_secret, 2);
+        let mut receiver_tree = SecretTree::new(cs, &application_secret, 2);
+        receiver_tree.out_of_order_window = 2;
+
+        // Seal a message for each of the generations 0 through 3
+        let mut ciphertexts = Vec::new();
+        for expected_gen in 0..4 {
+            let (key, nonce, gen) = sender_tree.next_key_nonce(0).unwrap();
+            assert_eq!(gen, expected_gen);
+
+            let mut buf = vec![gen as u8; 16];
+            buf.extend(vec![0u8; cs.aead_impl.tag_size()]);
+            cs.aead_impl.seal(&key, nonce, &mut buf).unwrap();
+            ciphertexts.push(buf);
+        }
+
+        for &gen in &[2u32, 1, 3] {
+            let (key, nonce) = receiver_tree.key_nonce_for_generation(0, gen).unwrap();
+            let buf = &mut ciphertexts[gen as usize];
+            let opened = cs.aead_impl.open(&key, nonce, buf).unwrap();
+            assert_eq!(opened, vec![gen as u8; 16].as_slice());
+        }
+
+        // Generation 0 is more than 2 behind generation 3, so its key is gone. Generation 1's key
+        // was already handed out.
+        assert_secret_tree_error(receiver_tree.key_nonce_for_generation(0, 0));
+        assert_secret_tree_error(receiver_tree.key_nonce_for_generation(0, 1));
+
+        // Out-of-range senders are rejected
+        assert_secret_tree_error(receiver_tree.key_nonce_for_generation(2, 0));
+    }
+
+    // A generation more than MAX_FORWARD_SKIP ahead of the chain should be refused without
+    // moving the chain, and one exactly MAX_FORWARD_SKIP ahead should be fine
+    #[test]
+    fn secret_tree_max_forward_skip() {
+        let cs = &X25519_SHA256_AES128GCM;
+        let application_secret = [0x5au8; 32];
+        let mut sender_tree = SecretTree::new(cs, &application_secret, 1);
+        let mut receiver_tree = SecretTree::new(cs, &application_secret, 1);
+
+        assert_secret_tree_error(receiver_tree.key_nonce_for_generation(0, MAX_FORWARD_SKIP + 1));
+        assert_secret_tree_error(receiver_tree.key_nonce_for_generation(0, std::u32::MAX));
+
+        // The chain didn't move, so the first message still opens
+        let (key, nonce, gen) = sender_tree.next_key_nonce(0).unwrap();
+        assert_eq!(gen, 0);
+        let mut buf = vec![0xffu8; 16];
+        buf.extend(vec![0u8; cs.aead_impl.tag_size()]);
+        cs.aead_impl.seal(&key, nonce, &mut buf).unwrap();
+        let (key, nonce) = receiver_tree.key_nonce_for_generation(0, 0).unwrap();
+        assert_eq!(
+            cs.aead_impl.open(&key, nonce, &mut buf).unwrap(),
+            &[0xffu8; 16]
+        );
+
+        // We're now at generation 1, so this is as far as we can go
+        assert_secret_tree_error(receiver_tree.key_nonce_for_generation(0, MAX_FORWARD_SKIP + 2));
+        assert!(receiver_tree
+            .key_nonce_for_generation(0, MAX_FORWARD_SKIP + 1)
+            .is_ok());
+    }
+
+    // Persists a receiver's tree partway through a sender's chain, and checks that the restored
+    // tree picks up where the old one left off, including the keys it kept for skipped generations
+    #[test]
+    fn secret_tree_persistence() {
+        let cs = &X25519_SHA256_AES128GCM;
+        let application_secret = [0x33u8; 32];
+        let mut sender_tree = SecretTree::new(cs, &application_secret, 3);
+        let mut receiver_tree = SecretTree::new(cs, &application_secret, 3);
+
+        let mut ciphertexts = Vec::new();
+        for _ in 0..3 {
+            let (key, nonce, gen) = sender_tree.next_key_nonce(1).unwrap();
+            let mut buf = vec![gen as u8; 16];
+            buf.extend(vec![0u8; cs.aead_impl.tag_size()]);
+            cs.aead_impl.seal(&key, nonce, &mut buf).unwrap();
+            ciphertexts.push(buf);
+        }
+
+        // Generation 2 arrives first, so the receiver keeps the keys of generations 0 and 1
+        let (key, nonce) = receiver_tree.key_nonce_for_generation(1, 2).unwrap();
+        cs.aead_impl.open(&key, nonce, &mut ciphertexts[2]).unwrap();
+
+        let bytes = tls_ser::serialize_to_bytes(&receiver_tree).unwrap();
+        let mut restored = {
+            let mut cursor = bytes.as_slice();
+            let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
+            SecretTree::deserialize(&mut deserializer).unwrap()
+        };
+        restored.validate_persisted(cs, 3).unwrap();
+        match restored.validate_persisted(cs, 4) {
+            Err(Error::ValidationError(_)) => (),
+            _ => panic!("expected a validation error"),
+        }
+
+        for gen in 0..2 {
+            let (key, nonce) = restored.key_nonce_for_generation(1, gen).unwrap();
+            let opened = cs
+                .aead_impl
+                .open(&key, nonce, &mut ciphertexts[gen as usize])
+                .unwrap();
+            assert_eq!(opened, vec![gen as u8; 16].as_slice());
+        }
+
+        // Generation 2 was used before the tree was persisted, and the chain goes on after it
+        assert_secret_tree_error(restored.key_nonce_for_generation(1, 2));
+        let (_, _, gen) = restored.next_key_nonce(1).unwrap();
+        assert_eq!(gen, 3);
+    }
+}
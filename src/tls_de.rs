@@ -3,11 +3,12 @@ use crate::error::Error;
 use std::io::Read;
 
 use byteorder::{BigEndian, ReadBytesExt};
-use serde::de::{Deserializer, Visitor};
+use serde::de::{Deserializer, IntoDeserializer, Visitor};
 
-// TODO: Make this parser more conservative in what it accepts. Currently, it will happily return
-// incomplete vectors (i.e., it'll read a length, get to the end of a buffer that's too short, and
-// then return what it has instead of blocking or erroring).
+/// The default cap on the declared length of any single variable-length field, in bytes. This is
+/// far bigger than anything MLS should send, but small enough that a malicious length prefix can't
+/// make us buffer gigabytes.
+pub(crate) const DEFAULT_MAX_ALLOC: u64 = 1 << 24;
 
 /// Given a reader and the name of a field or unit struct, find the length of the upcoming data.
 /// This only makes sense for variable-length data types. So for example if we were parsing the `v`
@@ -51,17 +52,80 @@ where
     Ok(res)
 }
 
+/// A reader that keeps track of how many bytes have been read through it
+struct CountingReader<'a, R: std::io::Read> {
+    inner: &'a mut R,
+    bytes_read: usize,
+}
+
+impl<'a, R: std::io::Read> std::io::Read for CountingReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bytes_read += n;
+        Ok(n)
+    }
+}
+
 /// This implements some subset of the Tls wire format. I still don't have a good source on the
 /// format, but it seems as though the idea is "concat everything, and specify length in the
 /// prefix".
 pub(crate) struct TlsDeserializer<'a, R: std::io::Read> {
-    reader: &'a mut R,
+    reader: CountingReader<'a, R>,
+    /// The largest length prefix we'll accept on a variable-length field
+    max_alloc: u64,
 }
 
 impl<'a, R: std::io::Read> TlsDeserializer<'a, R> {
-    /// Makes a new `TlsDeserializer` from the given byte reader
+    /// Makes a new `TlsDeserializer` from the given byte reader. The declared lengths of
+    /// variable-length fields are capped at `DEFAULT_MAX_ALLOC` bytes.
     pub(crate) fn from_reader(reader: &'a mut R) -> TlsDeserializer<R> {
-        TlsDeserializer { reader: reader }
+        TlsDeserializer {
+            reader: CountingReader {
+                inner: reader,
+                bytes_read: 0,
+            },
+            max_alloc: DEFAULT_MAX_ALLOC,
+        }
+    }
+
+    /// Returns the number of bytes this deserializer has consumed from its reader so far. After
+    /// deserializing a value, this is where the next value in the stream starts.
+    pub(crate) fn bytes_read(&self) -> usize {
+        self.reader.bytes_read
+    }
+
+    /// Sets the largest length prefix, in bytes, that this deserializer will accept on a
+    /// variable-length field. Anything with a bigger declared length is rejected before we read
+    /// any of it.
+    pub(crate) fn with_max_alloc(mut self, max_alloc: u64) -> TlsDeserializer<'a, R> {
+        self.max_alloc = max_alloc;
+        self
+    }
+
+    /// Checks that a declared length doesn't exceed `max_alloc`
+    fn check_len(&self, len: u64) -> Result<(), Error> {
+        if len > self.max_alloc {
+            Err(invalid_data("declared length exceeds max_alloc"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Makes an error for malformed input. This can't be an `UnexpectedEof`, since `TlsVecSeq` takes
+/// that to mean the end of a vector.
+fn invalid_data(msg: &'static str) -> Error {
+    Error::SerdeError(std::io::Error::new(std::io::ErrorKind::InvalidData, msg))
+}
+
+/// Checks that a sub-reader made for a length-prefixed field was read to the end. If it wasn't,
+/// then the underlying reader ran out before the declared length did, i.e., the declared length
+/// exceeds the remaining input.
+fn check_fully_read<R: std::io::Read>(sub_reader: &std::io::Take<R>) -> Result<(), Error> {
+    if sub_reader.limit() != 0 {
+        Err(invalid_data("declared length exceeds remaining input"))
+    } else {
+        Ok(())
     }
 }
 
@@ -123,12 +187,17 @@ impl<'de, 'a, 'b, R: std::io::Read> Deserializer<'de> for &'b mut TlsDeserialize
         // deserialize the contents normally. It will finish when it runs out of things to read.
         // This is guaranteed by the logic in TlsVecSeq.
         if let Some(len) = field_len {
+            self.check_len(len)?;
+
             // Make a new deserializer with a sub-buffer
-            let mut sub_reader = self.reader.take(len);
-            let mut sub_deserializer = TlsDeserializer::from_reader(&mut sub_reader);
+            let mut sub_reader = (&mut self.reader).take(len);
+            let mut sub_deserializer =
+                TlsDeserializer::from_reader(&mut sub_reader).with_max_alloc(self.max_alloc);
 
             // Deserialize the contents normally
-            visitor.visit_newtype_struct(&mut sub_deserializer)
+            let value = visitor.visit_newtype_struct(&mut sub_deserializer)?;
+            check_fully_read(&sub_reader)?;
+            Ok(value)
         } else {
             // Otherwise, if the inner type is not variable-length, deserialize the contents
             // normally
@@ -162,6 +231,54 @@ impl<'de, 'a, 'b, R: std::io::Read> Deserializer<'de> for &'b mut TlsDeserialize
         visitor.visit_seq(s)
     }
 
+    /// Hint that the `Deserialize` type is expecting a unit struct. These have no contents, so we
+    /// don't read anything.
+    fn deserialize_unit_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    /// Hint that the `Deserialize` type is expecting an enum. If the name of the enum ends with
+    /// `__enum_u8`, then we expect a single byte representing the variant index, followed by the
+    /// contents of the variant. This will make a new `TlsEnumAccess` object and run
+    /// `Visitor::visit_enum` on that.
+    fn deserialize_enum<V>(
+        self,
+        name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        if name.ends_with("__enum_u8") {
+            visitor.visit_enum(TlsEnumAccess::new(self))
+        } else {
+            unimplemented!()
+        }
+    }
+
+    /// Hint that the `Deserialize` type is expecting an `optional<T>` value. This is a single byte
+    /// indicating presence (1) or absence (0), followed by the value if it is present.
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.reader.read_u8()? {
+            0 => visitor.visit_none(),
+            1 => visitor.visit_some(self),
+            _ => Err(serde::de::Error::custom(
+                "invalid optional<T> presence byte",
+            )),
+        }
+    }
+
     /// I don't care who you are. This is not a human-readable format.
     #[inline]
     fn is_human_readable(&self) -> bool {
@@ -217,33 +334,9 @@ impl<'de, 'a, 'b, R: std::io::Read> Deserializer<'de> for &'b mut TlsDeserialize
     fn deserialize_byte_buf<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
         unimplemented!()
     }
-    fn deserialize_option<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
-        unimplemented!()
-    }
     fn deserialize_unit<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
         unimplemented!()
     }
-    fn deserialize_unit_struct<V>(
-        self,
-        _name: &'static str,
-        _visitor: V,
-    ) -> Result<V::Value, Self::Error>
-    where
-        V: Visitor<'de>,
-    {
-        unimplemented!()
-    }
-    fn deserialize_enum<V>(
-        self,
-        _name: &'static str,
-        _variants: &'static [&'static str],
-        _visitor: V,
-    ) -> Result<V::Value, Self::Error>
-    where
-        V: Visitor<'de>,
-    {
-        unimplemented!()
-    }
     fn deserialize_tuple<V>(self, _len: usize, _visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
@@ -325,12 +418,17 @@ impl<'de, 'a, 'b, R: std::io::Read> serde::de::SeqAccess<'de> for TlsStructSeq<'
         // will finish when it runs out of things to read. This is guaranteed by the logic in
         // TlsVecSeq.
         if let Some(len) = field_len {
+            self.de.check_len(len)?;
+
             // Make a sub-buffer to read from
-            let mut sub_reader = self.de.reader.take(len);
-            let mut sub_deserializer = TlsDeserializer::from_reader(&mut sub_reader);
+            let mut sub_reader = (&mut self.de.reader).take(len);
+            let mut sub_deserializer =
+                TlsDeserializer::from_reader(&mut sub_reader).with_max_alloc(self.de.max_alloc);
 
             // Deserialize from it normally
-            seed.deserialize(&mut sub_deserializer).map(Some)
+            let value = seed.deserialize(&mut sub_deserializer)?;
+            check_fully_read(&sub_reader)?;
+            Ok(Some(value))
         } else {
             // If no length is specified, do the natural thing
             seed.deserialize(&mut *self.de).map(Some)
@@ -374,16 +472,80 @@ impl<'de, 'a, 'b, R: std::io::Read> serde::de::SeqAccess<'de> for TlsVecSeq<'a,
                     Err(Error::SerdeError(io_err))
                 }
             }
-            // We can't receive a non-serde error from a deserialize method
-            _ => unreachable!(),
+            // Anything else is the item rejecting what it read, e.g., with a length mismatch
+            Err(e) => Err(e),
         }
     }
 }
 
+/// This deals with the logic of deserializing enums. The variant is determined by a single byte
+/// tag, and the contents of the variant immediately follow it.
+struct TlsEnumAccess<'a, 'b, R: std::io::Read> {
+    de: &'a mut TlsDeserializer<'b, R>,
+}
+
+impl<'a, 'b, R: std::io::Read> TlsEnumAccess<'a, 'b, R> {
+    /// Makes a new `TlsEnumAccess` object from the given deserializer
+    fn new(de: &'a mut TlsDeserializer<'b, R>) -> TlsEnumAccess<'a, 'b, R> {
+        TlsEnumAccess { de: de }
+    }
+}
+
+impl<'de, 'a, 'b, R: std::io::Read> serde::de::EnumAccess<'de> for TlsEnumAccess<'a, 'b, R> {
+    type Error = Error;
+    type Variant = Self;
+
+    /// Reads the variant tag and figures out which variant it corresponds to
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self), Error>
+    where
+        V: serde::de::DeserializeSeed<'de>,
+    {
+        // The variant is identified by its index, which is exactly the tag we read
+        let tag = self.de.reader.read_u8()?;
+        let tag_deserializer: serde::de::value::U8Deserializer<Error> = tag.into_deserializer();
+        let variant = seed.deserialize(tag_deserializer)?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de, 'a, 'b, R: std::io::Read> serde::de::VariantAccess<'de> for TlsEnumAccess<'a, 'b, R> {
+    type Error = Error;
+
+    /// Unit variants have no contents
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// The contents of a newtype variant are just deserialized normally
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Error>
+    where
+        T: serde::de::DeserializeSeed<'de>,
+    {
+        seed.deserialize(&mut *self.de)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, _visitor: V) -> Result<V::Value, Error> {
+        unimplemented!()
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Error> {
+        unimplemented!()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::crypto::dh::{DhPoint, DiffieHellman, X25519_IMPL};
+    use crate::handshake::{test::make_user_init_key, UserInitKey};
+    use crate::tls_ser;
 
+    use quickcheck_macros::quickcheck;
+    use rand::SeedableRng;
     use serde::de::Deserialize;
 
     // I'm bad at naming things. These are just structs that I'm using to test deserialization
@@ -476,4 +638,110 @@ mod test {
 
         assert_eq!(biff, expected);
     }
+
+    #[derive(Debug, Deserialize)]
+    struct Zorp {
+        #[serde(rename = "v__bound_u32")]
+        v: Vec<u8>,
+    }
+
+    // A length prefix that claims way more data than there is should be an error, not an attempt
+    // to allocate 4GiB
+    #[test]
+    fn truncated_input() {
+        let bytes = [0xff, 0xff, 0xff, 0xff, 0x01, 0x02, 0x03];
+
+        // Under the default cap, this gets rejected before we read anything
+        let mut buf = &bytes[..];
+        let mut deserializer = TlsDeserializer::from_reader(&mut buf);
+        assert!(Zorp::deserialize(&mut deserializer).is_err());
+
+        // Without a cap, we run out of input before we run out of declared length
+        let mut buf = &bytes[..];
+        let mut deserializer = TlsDeserializer::from_reader(&mut buf).with_max_alloc(std::u64::MAX);
+        assert!(Zorp::deserialize(&mut deserializer).is_err());
+
+        // Same thing inside a newtype struct
+        let mut buf = &[0x00, 0x08, 0xaa, 0xbb][..];
+        let mut deserializer = TlsDeserializer::from_reader(&mut buf);
+        assert!(Shake::deserialize(&mut deserializer).is_err());
+    }
+
+    // Declared lengths above max_alloc are rejected even when the data is all there
+    #[test]
+    fn max_alloc_enforced() {
+        let bytes = [0x00, 0x00, 0x00, 0x03, 0x01, 0x02, 0x03];
+
+        let mut buf = &bytes[..];
+        let mut deserializer = TlsDeserializer::from_reader(&mut buf).with_max_alloc(2);
+        assert!(Zorp::deserialize(&mut deserializer).is_err());
+
+        let mut buf = &bytes[..];
+        let mut deserializer = TlsDeserializer::from_reader(&mut buf).with_max_alloc(3);
+        assert_eq!(
+            Zorp::deserialize(&mut deserializer).unwrap().v,
+            vec![1, 2, 3]
+        );
+    }
+
+    // optional<T> is a presence byte followed by the value if it's there. This is how blank nodes
+    // are encoded in a ratchet tree, so check both states with a DhPoint.
+    #[test]
+    fn option_roundtrip_kat() {
+        let point_bytes = vec![0x09; 32];
+        let some_point = Some(X25519_IMPL.point_from_bytes(point_bytes.clone()));
+        let none_point: Option<DhPoint> = None;
+
+        let none_bytes = tls_ser::serialize_to_bytes(&none_point).unwrap();
+        assert_eq!(none_bytes, vec![0x00]);
+        let some_bytes = tls_ser::serialize_to_bytes(&some_point).unwrap();
+        assert_eq!(some_bytes, [&[0x01, 0x00, 0x20][..], &point_bytes].concat());
+
+        let mut buf = none_bytes.as_slice();
+        let mut deserializer = TlsDeserializer::from_reader(&mut buf);
+        assert!(Option::<DhPoint>::deserialize(&mut deserializer)
+            .unwrap()
+            .is_none());
+
+        let mut buf = some_bytes.as_slice();
+        let mut deserializer = TlsDeserializer::from_reader(&mut buf);
+        let point = Option::<DhPoint>::deserialize(&mut deserializer)
+            .unwrap()
+            .unwrap();
+        assert_eq!(X25519_IMPL.point_as_bytes(point), point_bytes);
+
+        // Anything but 0 or 1 is not a presence byte
+        let bad_bytes = [&[0x02, 0x00, 0x20][..], &point_bytes].concat();
+        let mut buf = bad_bytes.as_slice();
+        let mut deserializer = TlsDeserializer::from_reader(&mut buf);
+        assert!(Option::<DhPoint>::deserialize(&mut deserializer).is_err());
+    }
+
+    // Deserializes two UserInitKeys sent back to back, and checks that the bytes read after each
+    // one tell us exactly where it ended
+    #[quickcheck]
+    fn bytes_read_stream(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (alice_key, _) = make_user_init_key(b"Alice", &mut rng);
+        let (bob_key, _) = make_user_init_key(b"Bob", &mut rng);
+
+        let alice_bytes = tls_ser::serialize_to_bytes(&alice_key).unwrap();
+        let bob_bytes = tls_ser::serialize_to_bytes(&bob_key).unwrap();
+        let stream = [alice_bytes.as_slice(), bob_bytes.as_slice()].concat();
+
+        let mut buf = stream.as_slice();
+        let mut deserializer = TlsDeserializer::from_reader(&mut buf);
+        assert_eq!(deserializer.bytes_read(), 0);
+
+        let first = UserInitKey::deserialize(&mut deserializer).unwrap();
+        assert_eq!(deserializer.bytes_read(), alice_bytes.len());
+        let second = UserInitKey::deserialize(&mut deserializer).unwrap();
+        assert_eq!(deserializer.bytes_read(), stream.len());
+
+        assert!(first.verify_signature().is_ok());
+        assert!(second.verify_signature().is_ok());
+        assert_eq!(tls_ser::serialize_to_bytes(&first).unwrap(), alice_bytes);
+        assert_eq!(tls_ser::serialize_to_bytes(&second).unwrap(), bob_bytes);
+        assert!(buf.is_empty());
+    }
 }
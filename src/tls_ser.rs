@@ -3,24 +3,114 @@ use crate::error::Error;
 use byteorder::{BigEndian, WriteBytesExt};
 use doc_comment::doc_comment;
 use serde::ser::{Serialize, Serializer};
-
-// TODO: Add more helpful panic messages
+use std::io::Write;
 
 /// Uses `TlsSerializer` to serialize the input to a vector of bytes
 pub(crate) fn serialize_to_bytes<T: Serialize>(value: &T) -> Result<Vec<u8>, Error> {
     let mut serializer = TlsSerializer::new();
     value.serialize(&mut serializer)?;
-    Ok(serializer.buf.into_inner())
+    Ok(serializer.into_vec())
+}
+
+/// Serializes `v` into a fresh region buffer instead of the output, and returns the resulting
+/// bytes. This is how we find out how long a length-prefixed value is before we write its prefix.
+fn serialize_region<W: Write, T: Serialize + ?Sized>(
+    v: &T,
+    s: &mut TlsSerializer<W>,
+) -> Result<Vec<u8>, Error> {
+    s.regions.push(Vec::new());
+    let res = v.serialize(&mut *s);
+    let region = s
+        .regions
+        .pop()
+        .expect("serializer region stack underflowed");
+    res.map(|_| region)
+}
+
+/// A writer that throws away everything written to it and just counts the bytes
+struct ByteCounter(u64);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns the number of bytes that `v` serializes to, without keeping any of them around. The
+/// length-prefixed values inside `v` are measured the same way, so nothing gets buffered at any
+/// depth.
+fn serialized_len<T: Serialize + ?Sized>(v: &T) -> Result<u64, Error> {
+    let mut serializer = TlsSerializer::streaming_from_writer(ByteCounter(0));
+    v.serialize(&mut serializer)?;
+    Ok(serializer.into_inner().0)
+}
+
+/// Finds out how long the length-prefixed value `v` is. A buffering serializer serializes it into
+/// a region, which is returned so it can be written out after the length. A streaming serializer
+/// only measures it, and returns `None`.
+fn measure_region<W: Write, T: Serialize + ?Sized>(
+    v: &T,
+    s: &mut TlsSerializer<W>,
+) -> Result<(u64, Option<Vec<u8>>), Error> {
+    if s.streaming {
+        Ok((serialized_len(v)?, None))
+    } else {
+        let region = serialize_region(v, s)?;
+        Ok((region.len() as u64, Some(region)))
+    }
+}
+
+/// Writes out a length-prefixed value after its length has been written. This is the region that
+/// `measure_region` returned if there is one. Otherwise, `v` is serialized again, this time
+/// straight to the output.
+fn write_region<W: Write, T: Serialize + ?Sized>(
+    v: &T,
+    region: Option<Vec<u8>>,
+    s: &mut TlsSerializer<W>,
+) -> Result<(), Error> {
+    match region {
+        Some(region) => s.out().write_all(&region)?,
+        None => v.serialize(&mut *s)?,
+    }
+
+    Ok(())
+}
+
+/// Checks that a value of `len` bytes fits in a length prefix whose largest value is `max`.
+/// Writing the length anyway would silently truncate it and produce a message that can't be
+/// parsed.
+///
+/// Returns: `Ok(())` if it fits. Otherwise, returns an `Error::LengthExceedsBound`.
+fn check_length_bound(len: u64, max: u64) -> Result<(), Error> {
+    if len > max {
+        Err(Error::LengthExceedsBound { max, got: len })
+    } else {
+        Ok(())
+    }
 }
 
 // This macro gives us a way of serializing things with Tls notation like <1..2^16-1>. Here's how
 // it works: we're given some serializable value: &T and we want to encode it so that we can
-// specify its length in bytes as a prefix. So we first write 0 to the serialization buffer (that's
-// TlsSerializer.buf), then serialize the whole value out. Once it's serialized, we now know how
-// many bytes its serialization takes, so we seek back to the prefix location, and put that in as
-// the length. One downside of this: we have to serialize the whole thing before we can reject it
-// as too long. But this is nice and simple and I don't think it'll backfire unless the local
-// participant is actively trying to take up a ton of memory.
+// specify its length in bytes as a prefix. We can't seek backwards in an arbitrary writer, so we
+// first serialize the whole value into its own buffer (see `serialize_region`). Once it's
+// serialized, we know how many bytes its serialization takes, so we write that as the length, and
+// then write the buffer out after it. This means every length-prefixed value gets buffered in
+// memory in its entirety (and nested ones get copied once per level of nesting), but everything
+// that isn't inside a length-prefixed value goes straight to the writer. One downside of this: we
+// have to serialize the whole thing before we can reject it as too long. But this is nice and
+// simple and I don't think it'll backfire unless the local participant is actively trying to take
+// up a ton of memory. A value that's too long for its prefix is an error, not a truncation.
+//
+// A streaming serializer (see `TlsSerializer::streaming_from_writer`) doesn't buffer anything.
+// Instead, it serializes the value once into a byte counter to get its length, writes the length,
+// and then serializes the value again straight into the writer. Nested values get measured the
+// same way, so a value nested d prefixes deep is serialized d + 1 times. That's more work, but
+// the memory use no longer depends on the size of the value.
 macro_rules! serialize_with_bound {
     ($t:ty, $ti:ident, $fn_name:ident, $write_fn:ident, $endianness:ty ) => {
         doc_comment! {
@@ -29,33 +119,20 @@ macro_rules! serialize_with_bound {
                 stringify!($t),
                 "`",
             ),
-            pub(crate) fn $fn_name<'a, T: Serialize + ?Sized>(
+            pub(crate) fn $fn_name<'a, W: Write, T: Serialize + ?Sized>(
                 v: &T,
-                s: &mut &'a mut TlsSerializer,
+                s: &mut &'a mut TlsSerializer<W>,
             ) -> Result<
-                <&'a mut TlsSerializer as Serializer>::Ok,
-                <&'a mut TlsSerializer as Serializer>::Error,
+                <&'a mut TlsSerializer<W> as Serializer>::Ok,
+                <&'a mut TlsSerializer<W> as Serializer>::Error,
             > {
-                // Starting position
-                let len_pos = s.buf.position();
-                // Write a dummy zero here, then serialize everything we get, then rewrite the
-                // correct length in the position of the dummy zero.
-                s.buf.$write_fn::<$endianness>(0)?;
-                v.serialize(&mut **s)?;
-                // End position - start position - size of length tag = length of serialized output
-                let len: u64 = s.buf.position() - len_pos - (std::mem::size_of::<$t>() as u64);
-
-                if len > (std::$ti::MAX as u64) {
-                    panic!(
-                        "tried to serialize a {}-bounded object that was too long",
-                        stringify!($t),
-                    )
-                }
+                let (len, region) = measure_region(v, *s)?;
 
-                // If we haven't panicked yet, we're within the bound
-                let len: $t = len as $t;
-                s.buf.set_position(len_pos);
-                s.buf.$write_fn::<$endianness>(len)?;
+                check_length_bound(len, std::$ti::MAX as u64)?;
+
+                // We're within the bound. Write the length, then the contents.
+                s.out().$write_fn::<$endianness>(len as $t)?;
+                write_region(v, region, *s)?;
 
                 Ok(())
             }
@@ -71,77 +148,133 @@ serialize_with_bound!(u64, u64, serialize_with_bound_u64, write_u64, BigEndian);
 // any type, and u24 is not a type.
 
 /// Serializes an object with a length in bytes that must be representable by `u8`
-pub(crate) fn serialize_with_bound_u8<'a, T: Serialize + ?Sized>(
+pub(crate) fn serialize_with_bound_u8<'a, W: Write, T: Serialize + ?Sized>(
     v: &T,
-    s: &mut &'a mut TlsSerializer,
-) -> Result<<&'a mut TlsSerializer as Serializer>::Ok, <&'a mut TlsSerializer as Serializer>::Error>
-{
-    // Starting position
-    let len_pos = s.buf.position();
-    // Write a dummy zero here, then serialize everything we get, then rewrite the correct
-    // length in the position of the dummy zero.
-    s.buf.write_u8(0)?;
-    v.serialize(&mut **s)?;
-    // End position - start position - size of length tag = length of serialized output
-    let len: u64 = s.buf.position() - len_pos - 1;
-
-    if len > (std::u8::MAX as u64) {
-        panic!("tried to serialize a u8-bounded object that was too long")
-    }
-
-    // If we haven't panicked yet, we're within the bound
-    let len: u8 = len as u8;
-    s.buf.set_position(len_pos);
-    s.buf.write_u8(len)?;
+    s: &mut &'a mut TlsSerializer<W>,
+) -> Result<
+    <&'a mut TlsSerializer<W> as Serializer>::Ok,
+    <&'a mut TlsSerializer<W> as Serializer>::Error,
+> {
+    let (len, region) = measure_region(v, *s)?;
+
+    check_length_bound(len, std::u8::MAX as u64)?;
+
+    // We're within the bound. Write the length, then the contents.
+    s.out().write_u8(len as u8)?;
+    write_region(v, region, *s)?;
 
     Ok(())
 }
 
 /// Serializes an object with a length in bytes that must be representable by `u24` (i.e. 3 bytes)
-pub(crate) fn serialize_with_bound_u24<'a, T: Serialize + ?Sized>(
+pub(crate) fn serialize_with_bound_u24<'a, W: Write, T: Serialize + ?Sized>(
     v: &T,
-    s: &mut &'a mut TlsSerializer,
-) -> Result<<&'a mut TlsSerializer as Serializer>::Ok, <&'a mut TlsSerializer as Serializer>::Error>
-{
-    // Starting position
-    let len_pos = s.buf.position();
-    // Write a dummy zero here, then serialize everything we get, then rewrite the correct
-    // length in the position of the dummy zero.
-    s.buf.write_u24::<BigEndian>(0)?;
-    v.serialize(&mut **s)?;
-    // End position - start position - size of length tag = length of serialized output
-    let len: u64 = s.buf.position() - len_pos - 3;
-
-    if len >= (1u64 << 24) {
-        panic!("tried to serialize a u24-bounded object that was too long")
-    }
-
-    // If we haven't panicked yet, we're within the bound
-    let len: u32 = len as u32;
-    s.buf.set_position(len_pos);
-    s.buf.write_u24::<BigEndian>(len)?;
+    s: &mut &'a mut TlsSerializer<W>,
+) -> Result<
+    <&'a mut TlsSerializer<W> as Serializer>::Ok,
+    <&'a mut TlsSerializer<W> as Serializer>::Error,
+> {
+    let (len, region) = measure_region(v, *s)?;
+
+    check_length_bound(len, (1u64 << 24) - 1)?;
+
+    // We're within the bound. Write the length, then the contents.
+    s.out().write_u24::<BigEndian>(len as u32)?;
+    write_region(v, region, *s)?;
 
     Ok(())
 }
 
+/// Serializes an object, prefixing it with its length in bytes if `name` ends with `__bound_uX`
+/// where X = 8, 16, 24, 32, or 64. The length tag will be the width of the specified X. This is
+/// used for both newtype structs and struct fields, since serde gives us a name for both.
+fn serialize_with_name_bound<'a, W: Write, T: Serialize + ?Sized>(
+    name: &'static str,
+    v: &T,
+    s: &mut &'a mut TlsSerializer<W>,
+) -> Result<
+    <&'a mut TlsSerializer<W> as Serializer>::Ok,
+    <&'a mut TlsSerializer<W> as Serializer>::Error,
+> {
+    if name.ends_with("__bound_u8") {
+        serialize_with_bound_u8(v, s)
+    } else if name.ends_with("__bound_u16") {
+        serialize_with_bound_u16(v, s)
+    } else if name.ends_with("__bound_u24") {
+        serialize_with_bound_u24(v, s)
+    } else if name.ends_with("__bound_u32") {
+        serialize_with_bound_u32(v, s)
+    } else if name.ends_with("__bound_u64") {
+        serialize_with_bound_u64(v, s)
+    } else {
+        v.serialize(&mut **s)
+    }
+}
+
 /// This implements some subset of the Tls wire format. I still don't have a good source on the
 /// format, but it seems as though the idea is "concat everything, and specify length in the
 /// prefix". The output of this is verified against known serializations.
-pub(crate) struct TlsSerializer {
-    buf: std::io::Cursor<Vec<u8>>,
+///
+/// Output is streamed to the underlying writer as it's produced, except for length-prefixed values,
+/// which are buffered until they're complete, unless this is a streaming serializer (see
+/// `serialize_with_bound`).
+pub(crate) struct TlsSerializer<W: Write = Vec<u8>> {
+    writer: W,
+    /// The buffers of the length-prefixed values we're currently in the middle of serializing,
+    /// from outermost to innermost. When this is empty, we write straight to `writer`. This is
+    /// always empty for a streaming serializer.
+    regions: Vec<Vec<u8>>,
+    /// Whether length-prefixed values are measured and then serialized straight to `writer`,
+    /// rather than buffered
+    streaming: bool,
 }
 
-impl TlsSerializer {
-    /// Makes a new empty `TlsSerializer` object
-    pub(crate) fn new() -> TlsSerializer {
-        TlsSerializer {
-            buf: std::io::Cursor::new(Vec::new()),
-        }
+impl TlsSerializer<Vec<u8>> {
+    /// Makes a new empty `TlsSerializer` object that serializes to a vector of bytes
+    pub(crate) fn new() -> TlsSerializer<Vec<u8>> {
+        TlsSerializer::from_writer(Vec::new())
     }
 
     /// Returns this objects internal buffer
     pub(crate) fn into_vec(self) -> Vec<u8> {
-        self.buf.into_inner()
+        self.into_inner()
+    }
+}
+
+impl<W: Write> TlsSerializer<W> {
+    /// Makes a new `TlsSerializer` that writes its output to the given writer
+    pub(crate) fn from_writer(writer: W) -> TlsSerializer<W> {
+        TlsSerializer {
+            writer: writer,
+            regions: Vec::new(),
+            streaming: false,
+        }
+    }
+
+    /// Makes a new `TlsSerializer` that writes its output to the given writer without buffering
+    /// anything. Length-prefixed values are serialized twice: once to find their length, and once
+    /// to write them out. This is for when the output is too big to keep around, e.g., when
+    /// hashing a large message.
+    pub(crate) fn streaming_from_writer(writer: W) -> TlsSerializer<W> {
+        TlsSerializer {
+            writer: writer,
+            regions: Vec::new(),
+            streaming: true,
+        }
+    }
+
+    /// Returns the underlying writer
+    pub(crate) fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Returns the thing we should be writing to right now. This is the innermost length-prefixed
+    /// value being serialized if there is one, and the underlying writer otherwise.
+    fn out(&mut self) -> &mut dyn Write {
+        match self.regions.last_mut() {
+            Some(region) => region,
+            None => &mut self.writer,
+        }
     }
 }
 
@@ -149,7 +282,7 @@ impl TlsSerializer {
 // for us, we don't actually need that much functionality out of our serializer. So we're going to
 // leave most things unimplemented, and then implement them if we ever end up needing them.
 
-impl<'a> Serializer for &'a mut TlsSerializer {
+impl<'a, W: Write> Serializer for &'a mut TlsSerializer<W> {
     type Ok = ();
     type Error = crate::error::Error;
 
@@ -166,22 +299,22 @@ impl<'a> Serializer for &'a mut TlsSerializer {
     //
 
     fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
-        self.buf.write_u8(v)?;
+        self.out().write_u8(v)?;
         Ok(())
     }
 
     fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
-        self.buf.write_u16::<BigEndian>(v)?;
+        self.out().write_u16::<BigEndian>(v)?;
         Ok(())
     }
 
     fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
-        self.buf.write_u32::<BigEndian>(v)?;
+        self.out().write_u32::<BigEndian>(v)?;
         Ok(())
     }
 
     fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
-        self.buf.write_u64::<BigEndian>(v)?;
+        self.out().write_u64::<BigEndian>(v)?;
         Ok(())
     }
 
@@ -196,21 +329,54 @@ impl<'a> Serializer for &'a mut TlsSerializer {
     where
         T: ?Sized + Serialize,
     {
-        if name.ends_with("__bound_u8") {
-            serialize_with_bound_u8(value, &mut self)
-        } else if name.ends_with("__bound_u16") {
-            serialize_with_bound_u16(value, &mut self)
-        } else if name.ends_with("__bound_u24") {
-            serialize_with_bound_u24(value, &mut self)
-        } else if name.ends_with("__bound_u32") {
-            serialize_with_bound_u32(value, &mut self)
-        } else if name.ends_with("__bound_u64") {
-            serialize_with_bound_u64(value, &mut self)
-        } else {
+        serialize_with_name_bound(name, value, &mut self)
+    }
+
+    /// Serializes an enum variant that contains a value. If the name of the enum ends with
+    /// `__enum_u8`, then we write the variant index as a single byte, followed by the serialized
+    /// inner value. This is how `select` statements in the TLS presentation language are encoded.
+    fn serialize_newtype_variant<T>(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        if name.ends_with("__enum_u8") {
+            if variant_index > std::u8::MAX as u32 {
+                return Err(Error::SerdeError(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "tried to serialize a u8-tagged enum with too many variants",
+                )));
+            }
+            self.out().write_u8(variant_index as u8)?;
             value.serialize(self)
+        } else {
+            unimplemented!()
         }
     }
 
+    /// Unit structs have no contents, so they serialize to nothing
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> {
+        Ok(())
+    }
+
+    /// Serializes an absent `optional<T>` value. This is a single zero byte.
+    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+        self.out().write_u8(0)?;
+        Ok(())
+    }
+
+    /// Serializes a present `optional<T>` value. This is a single one byte followed by the
+    /// serialized value.
+    fn serialize_some<T: ?Sized + Serialize>(self, v: &T) -> Result<Self::Ok, Self::Error> {
+        self.out().write_u8(1)?;
+        v.serialize(self)
+    }
+
     /// `TlsSerializer` is also a `SerializeSeq` (see impl below)
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
         Ok(self)
@@ -259,18 +425,9 @@ impl<'a> Serializer for &'a mut TlsSerializer {
     fn serialize_bytes(self, _v: &[u8]) -> Result<Self::Ok, Self::Error> {
         unimplemented!()
     }
-    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
-    }
-    fn serialize_some<T: ?Sized + Serialize>(self, _v: &T) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
-    }
     fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
         unimplemented!()
     }
-    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
-    }
     fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
         unimplemented!()
     }
@@ -292,18 +449,6 @@ impl<'a> Serializer for &'a mut TlsSerializer {
     ) -> Result<Self::SerializeTupleStruct, Self::Error> {
         unimplemented!()
     }
-    fn serialize_newtype_variant<T>(
-        self,
-        _name: &'static str,
-        _variant_index: u32,
-        _variant: &'static str,
-        _value: &T,
-    ) -> Result<Self::Ok, Self::Error>
-    where
-        T: ?Sized + Serialize,
-    {
-        unimplemented!()
-    }
     fn serialize_tuple_variant(
         self,
         _name: &'static str,
@@ -325,7 +470,7 @@ impl<'a> Serializer for &'a mut TlsSerializer {
 }
 
 /// Serializes slices, vecs, etc.
-impl<'a> serde::ser::SerializeSeq for &'a mut TlsSerializer {
+impl<'a, W: Write> serde::ser::SerializeSeq for &'a mut TlsSerializer<W> {
     type Ok = ();
     type Error = Error;
 
@@ -344,17 +489,18 @@ impl<'a> serde::ser::SerializeSeq for &'a mut TlsSerializer {
 }
 
 /// Serializes structs. This does the same thing as `TlsSerializer as SerializeSeq`
-impl<'a> serde::ser::SerializeStruct for &'a mut TlsSerializer {
+impl<'a, W: Write> serde::ser::SerializeStruct for &'a mut TlsSerializer<W> {
     type Ok = ();
     type Error = crate::error::Error;
 
     /// Structs are serialized sequentially as well, without any delimiters between fields, since
-    /// variable-sized fields are length-prefixed
-    fn serialize_field<T>(&mut self, _key: &'static str, value: &T) -> Result<Self::Ok, Self::Error>
+    /// variable-sized fields are length-prefixed. As with newtype structs, a field whose name ends
+    /// with `__bound_uX` gets a length prefix.
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<Self::Ok, Self::Error>
     where
         T: ?Sized + Serialize,
     {
-        value.serialize(&mut **self)
+        serialize_with_name_bound(key, value, self)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
@@ -366,7 +512,7 @@ impl<'a> serde::ser::SerializeStruct for &'a mut TlsSerializer {
 // More unimplemented stuff
 //
 
-impl<'a> serde::ser::SerializeTuple for &'a mut TlsSerializer {
+impl<'a, W: Write> serde::ser::SerializeTuple for &'a mut TlsSerializer<W> {
     type Ok = ();
     type Error = Error;
 
@@ -381,7 +527,7 @@ impl<'a> serde::ser::SerializeTuple for &'a mut TlsSerializer {
     }
 }
 
-impl<'a> serde::ser::SerializeTupleStruct for &'a mut TlsSerializer {
+impl<'a, W: Write> serde::ser::SerializeTupleStruct for &'a mut TlsSerializer<W> {
     type Ok = ();
     type Error = crate::error::Error;
 
@@ -396,7 +542,7 @@ impl<'a> serde::ser::SerializeTupleStruct for &'a mut TlsSerializer {
     }
 }
 
-impl<'a> serde::ser::SerializeTupleVariant for &'a mut TlsSerializer {
+impl<'a, W: Write> serde::ser::SerializeTupleVariant for &'a mut TlsSerializer<W> {
     type Ok = ();
     type Error = crate::error::Error;
 
@@ -411,7 +557,7 @@ impl<'a> serde::ser::SerializeTupleVariant for &'a mut TlsSerializer {
     }
 }
 
-impl<'a> serde::ser::SerializeMap for &'a mut TlsSerializer {
+impl<'a, W: Write> serde::ser::SerializeMap for &'a mut TlsSerializer<W> {
     type Ok = ();
     type Error = crate::error::Error;
 
@@ -432,7 +578,7 @@ impl<'a> serde::ser::SerializeMap for &'a mut TlsSerializer {
     }
 }
 
-impl<'a> serde::ser::SerializeStructVariant for &'a mut TlsSerializer {
+impl<'a, W: Write> serde::ser::SerializeStructVariant for &'a mut TlsSerializer<W> {
     type Ok = ();
     type Error = crate::error::Error;
 
@@ -450,3 +596,108 @@ impl<'a> serde::ser::SerializeStructVariant for &'a mut TlsSerializer {
         unimplemented!()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        handshake::{test::make_user_init_key, UserInitKey},
+        tls_de::TlsDeserializer,
+    };
+
+    use quickcheck_macros::quickcheck;
+    use rand::SeedableRng;
+    use serde::de::Deserialize;
+
+    // Serializes a UserInitKey straight into a writer, and checks that we get the same bytes as the
+    // in-memory serializer, whether or not length-prefixed values are buffered, and that they
+    // deserialize to something that reserializes identically
+    #[quickcheck]
+    fn streaming_serialization_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (user_init_key, _) = make_user_init_key(b"Rick Sanchez", &mut rng);
+
+        // Write to a borrowed vector, the way we'd write to a socket or a file
+        let mut written = Vec::new();
+        {
+            let mut serializer = TlsSerializer::from_writer(&mut written);
+            user_init_key.serialize(&mut serializer).unwrap();
+        }
+        assert_eq!(written, serialize_to_bytes(&user_init_key).unwrap());
+
+        // The UserInitKey has length-prefixed values inside length-prefixed values, which a
+        // streaming serializer measures instead of buffering
+        let mut streamed = Vec::new();
+        {
+            let mut serializer = TlsSerializer::streaming_from_writer(&mut streamed);
+            user_init_key.serialize(&mut serializer).unwrap();
+            assert!(serializer.regions.is_empty());
+        }
+        assert_eq!(streamed, written);
+        assert_eq!(
+            serialized_len(&user_init_key).unwrap(),
+            written.len() as u64
+        );
+
+        let mut buf = written.as_slice();
+        let mut deserializer = TlsDeserializer::from_reader(&mut buf);
+        let recovered = UserInitKey::deserialize(&mut deserializer).unwrap();
+        // Make sure we read everything
+        assert!(buf.is_empty());
+
+        let mut rewritten = Vec::new();
+        {
+            let mut serializer = TlsSerializer::from_writer(&mut rewritten);
+            recovered.serialize(&mut serializer).unwrap();
+        }
+        assert_eq!(written, rewritten);
+    }
+
+    #[derive(Serialize)]
+    struct Zorp {
+        v__bound_u8: Vec<u8>,
+    }
+
+    // Checks that a value that doesn't fit in its length prefix is an error instead of a
+    // truncated length
+    #[test]
+    fn length_bound_enforced() {
+        let fits = Zorp {
+            v__bound_u8: vec![0xAB; 255],
+        };
+        let bytes = serialize_to_bytes(&fits).unwrap();
+        assert_eq!(bytes[0], 255);
+        assert_eq!(bytes.len(), 256);
+
+        let too_long = Zorp {
+            v__bound_u8: vec![0xAB; 256],
+        };
+        match serialize_to_bytes(&too_long) {
+            Err(Error::LengthExceedsBound { max, got }) => {
+                assert_eq!(max, 255);
+                assert_eq!(got, 256);
+            }
+            _ => panic!("expected a length bound error"),
+        }
+
+        // Nothing gets written to the underlying writer when the bound is violated, whether or not
+        // the serializer buffers
+        let mut written = Vec::new();
+        {
+            let mut serializer = TlsSerializer::from_writer(&mut written);
+            assert!(too_long.serialize(&mut serializer).is_err());
+        }
+        assert!(written.is_empty());
+        {
+            let mut serializer = TlsSerializer::streaming_from_writer(&mut written);
+            match too_long.serialize(&mut serializer) {
+                Err(Error::LengthExceedsBound { max, got }) => {
+                    assert_eq!(max, 255);
+                    assert_eq!(got, 256);
+                }
+                _ => panic!("expected a length bound error"),
+            }
+        }
+        assert!(written.is_empty());
+    }
+}
@@ -1,10 +1,16 @@
+use crate::error::TreeMathError;
+
+#[cfg(test)]
+use alloc::{format, string::String};
+use alloc::{vec, vec::Vec};
+
 // Suppose usize is u64. If there are k := 2^(63)+1 leaves, then there are a total of 2(k-1) + 1 =
 // 2(2^(63))+1 = 2^(64)+1 nodes in the tree, which is outside the representable range. So our upper
 // bound is 2^(63) leaves, which gives a tree with 2^(64)-1 nodes.
-const MAX_LEAVES: usize = (std::usize::MAX >> 1) + 1;
+const MAX_LEAVES: usize = (core::usize::MAX >> 1) + 1;
 
 /// Returns `Some(floor(log2(x))` when `x != 0`, and `None` otherwise
-fn log2(x: usize) -> Option<usize> {
+const fn log2(x: usize) -> Option<usize> {
     // The log2 of x is the position of its most significant bit
     let bitlen = (0usize).leading_zeros() as usize;
     (bitlen - x.leading_zeros() as usize).checked_sub(1)
@@ -13,25 +19,40 @@ fn log2(x: usize) -> Option<usize> {
 /// Computes the level of a given node in a binary left-balanced tree. Leaves are level 0, their
 /// parents are level 1, etc. If a node's children are at different level, then its level is the
 /// max level of its children plus one.
-pub(crate) fn node_level(idx: usize) -> usize {
+pub const fn node_level(idx: usize) -> usize {
     // The level of idx is equal to the number of trialing 1s in its binary representation.
     // Equivalently, this is just the number of trailing zeros of (NOT idx)
     (!idx).trailing_zeros() as usize
 }
 
+/// Computes the number of nodes needed to represent a tree with `num_leaves` many leaves. The
+/// arithmetic is checked, so this is correct for any width of `usize`: it returns `None` exactly
+/// when `num_leaves == 0` or `num_leaves > MAX_LEAVES`.
+const fn checked_num_nodes_in_tree(num_leaves: usize) -> Option<usize> {
+    if num_leaves == 0 {
+        return None;
+    }
+    match (num_leaves - 1).checked_mul(2) {
+        Some(n) => n.checked_add(1),
+        None => None,
+    }
+}
+
 /// Computes the number of nodes needed to represent a tree with `num_leaves` many leaves
 ///
 /// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES`
-fn num_nodes_in_tree(num_leaves: usize) -> usize {
-    assert!(num_leaves > 0 && num_leaves <= MAX_LEAVES);
-    2 * (num_leaves - 1) + 1
+const fn num_nodes_in_tree(num_leaves: usize) -> usize {
+    match checked_num_nodes_in_tree(num_leaves) {
+        Some(num_nodes) => num_nodes,
+        None => panic!("invalid number of leaves"),
+    }
 }
 
 /// Computes the number of leaves in a tree of `num_nodes` many nodes
 ///
-/// Panics: when `num_nodes` is odd, since all left-balanced binary trees have an odd number of
+/// Panics: when `num_nodes` is even, since all left-balanced binary trees have an odd number of
 /// nodes
-pub(crate) fn num_leaves_in_tree(num_nodes: usize) -> usize {
+pub(crate) const fn num_leaves_in_tree(num_nodes: usize) -> usize {
     assert!(num_nodes % 2 == 1);
     // Inverting the formula for num_nodes_in_tree, we get num_leaves = (num_nodes-1)/2 + 1
     ((num_nodes - 1) >> 1) + 1
@@ -48,6 +69,18 @@ fn root_idx(num_leaves: usize) -> usize {
     (1 << log2(n).unwrap()) - 1
 }
 
+/// Returns whether the given node is a leaf. This does not depend on the size of the tree.
+pub(crate) fn is_leaf(idx: usize) -> bool {
+    node_level(idx) == 0
+}
+
+/// Returns whether the given node is the root of a tree with `num_leaves` many leaves
+///
+/// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES`
+pub(crate) fn is_root(idx: usize, num_leaves: usize) -> bool {
+    idx == root_idx(num_leaves)
+}
+
 /// Computes the index of the left child of a given node. This does not depend on the size of the
 /// tree. The child of a leaf is itself.
 pub(crate) fn node_left_child(idx: usize) -> usize {
@@ -95,60 +128,73 @@ pub(crate) fn node_right_child(idx: usize, num_leaves: usize) -> usize {
     }
 }
 
+/// Steps up to the parent of the given node, assuming it's in a full tree. The result may be
+/// beyond the right edge of the actual tree.
+///
+/// Returns: `Some(parent)` on success. If the parent's index isn't representable by a `usize`
+/// (i.e., `i` is on the topmost level a `usize` can index), returns `None` rather than wrapping.
+fn checked_parent_step(i: usize) -> Option<usize> {
+    // Recall that the children of xyz...0111...1 are xyz...0011...1 and xyz...1011...1 Working
+    // backwards, this means that the parent of something that ends with 0011...1 or
+    // 1011...1 is 0111...1. So if i is the index of the least significant 0, we must clear the
+    // (i+1)-th bit and set the i-th bit.
+    // This might be off the edge of the tree, since if, say, we have a tree on 3 leaves, the
+    // rightmost leaf is idx 4, whose parent according to this algorithm would be idx 5, which
+    // doesn't exist.
+    let lvl = node_level(i) as u32;
+    let bit_to_clear = i & 1usize.checked_shl(lvl.checked_add(1)?)?;
+    let bit_to_set = 1usize.checked_shl(lvl)?;
+
+    Some((i | bit_to_set) ^ bit_to_clear)
+}
+
 /// Computes the index of the parent of a given node. The parent of the root is the root.
 ///
-/// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES` or
-/// `idx >= num_nodes_in_tree(num_leaves)`
-fn node_parent(idx: usize, num_leaves: usize) -> usize {
-    // The immediate parent of a node. May be beyond the right edge of the tree. This means weird
-    // overflowing behavior when i == usize::MAX. However, this case is caught by the check below
-    // that idx == root_idx(num_leaves). We hit the overflowing case iff idx is usize::MAX, which
-    // is of the form 2^n - 1 for some n, which means that it's the root of a completely full tree
-    // or it's the root of a subtree with more than `MAX_LEAVES` elements. The former case is
-    // handled by the first if-statement below, and the latter is handled by the assert below.
-    fn parent_step(i: usize) -> usize {
-        // Recall that the children of xyz...0111...1 are xyz...0011...1 and xyz...1011...1 Working
-        // backwards, this means that the parent of something that ends with 0011...1 or
-        // 1011...1 is 0111...1. So if i is the index of the least significant 0, we must clear the
-        // (i+1)-th bit and set the i-th bit.
-        // This might be off the edge of the tree, since if, say, we have a tree on 3 leaves, the
-        // rightmost leaf is idx 4, whose parent according to this algorithm would be idx 5, which
-        // doesn't exist.
-        let lvl = node_level(i);
-        let bit_to_clear = i & (0x01 << (lvl + 1));
-        let bit_to_set = 0x01 << lvl;
-
-        (i | bit_to_set) ^ bit_to_clear
+/// Returns: `Some(parent)` on success. Returns `None` if `num_leaves == 0` or
+/// `num_leaves > MAX_LEAVES` or `idx >= num_nodes_in_tree(num_leaves)`.
+pub(crate) fn checked_node_parent(idx: usize, num_leaves: usize) -> Option<usize> {
+    if num_leaves == 0 || num_leaves > MAX_LEAVES || idx >= num_nodes_in_tree(num_leaves) {
+        return None;
     }
 
-    assert!(num_leaves > 0 && num_leaves <= MAX_LEAVES);
-    assert!(idx < num_nodes_in_tree(num_leaves));
-
     if idx == root_idx(num_leaves) {
-        idx
+        Some(idx)
     } else {
         // First assume we're in a full tree. This means we're assuming the direct path of this
         // node is maximally long.
-        let mut p = parent_step(idx);
+        let mut p = checked_parent_step(idx)?;
         let idx_threshold = num_nodes_in_tree(num_leaves);
         // This must terminate, since stepping up will eventually land us at the root node of the
-        // tree, and parent_step increases the level at every step. The algorithm is correct, since
-        // the direct path of the node of index i ocurring in a non-full subtree is a subpath of
-        // the node of index i ocurring in a full subtree. Since they share an ancestor, we'll
-        // eventually reach it if we start from the bottom and work our way up.
+        // tree, and the parent step increases the level at every step. The algorithm is correct,
+        // since the direct path of the node of index i ocurring in a non-full subtree is a subpath
+        // of the node of index i ocurring in a full subtree. Since they share an ancestor, we'll
+        // eventually reach it if we start from the bottom and work our way up. In a tree of at most
+        // `MAX_LEAVES` leaves, no step can run out of bits. If one somehow did, we'd bail out
+        // instead of wrapping.
         while p >= idx_threshold {
-            p = parent_step(p);
+            p = checked_parent_step(p)?;
         }
 
-        p
+        Some(p)
     }
 }
 
+/// Computes the index of the parent of a given node. The parent of the root is the root.
+///
+/// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES` or
+/// `idx >= num_nodes_in_tree(num_leaves)`
+pub(crate) fn node_parent(idx: usize, num_leaves: usize) -> usize {
+    assert!(num_leaves > 0 && num_leaves <= MAX_LEAVES);
+    assert!(idx < num_nodes_in_tree(num_leaves));
+
+    checked_node_parent(idx, num_leaves).expect("node parent computation overflowed")
+}
+
 /// Computes the index of the sibling of a given node. The sibling of the root is the root.
 ///
 /// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES` or
 /// `idx >= num_nodes_in_tree(num_leaves)`
-fn node_sibling(idx: usize, num_leaves: usize) -> usize {
+pub(crate) fn node_sibling(idx: usize, num_leaves: usize) -> usize {
     assert!(num_leaves > 0 && num_leaves <= MAX_LEAVES);
     assert!(idx < num_nodes_in_tree(num_leaves));
 
@@ -168,61 +214,562 @@ fn node_sibling(idx: usize, num_leaves: usize) -> usize {
     }
 }
 
-// TODO: Consider making direct_path and copath into iterators so that we don't have to allocate
+/// An iterator over the direct path of a given node. This yields `i_1, i_2, ..., i_n` where `i_1`
+/// is the parent of the starting node and `i_n` is a child of the root node. Nothing is allocated;
+/// each index is computed on the fly from the previous one.
+pub(crate) struct DirectPathIter {
+    /// The most recently yielded node, or the starting node if nothing has been yielded yet
+    current_idx: usize,
+    /// The root of the tree. We stop once the next step would land us here.
+    root_idx: usize,
+    /// The number of leaves in the tree we're walking
+    num_leaves: usize,
+}
+
+impl DirectPathIter {
+    /// Makes a new `DirectPathIter` starting at the given node. The starting node itself is not
+    /// yielded.
+    ///
+    /// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES` or
+    /// `start_idx >= num_nodes_in_tree(num_leaves)`
+    pub(crate) fn new(start_idx: usize, num_leaves: usize) -> DirectPathIter {
+        assert!(num_leaves > 0 && num_leaves <= MAX_LEAVES);
+        assert!(start_idx < num_nodes_in_tree(num_leaves));
+
+        DirectPathIter {
+            current_idx: start_idx,
+            root_idx: root_idx(num_leaves),
+            num_leaves,
+        }
+    }
+}
+
+impl Iterator for DirectPathIter {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        // Recall that the parent of the root is itself, so if we're at the root or at a child of
+        // the root, this is where we stop. Since we don't move in that case, every subsequent call
+        // also returns None.
+        let p = node_parent(self.current_idx, self.num_leaves);
+        if p == self.root_idx {
+            None
+        } else {
+            self.current_idx = p;
+            Some(p)
+        }
+    }
+
+    /// The hint is exact. We find it by stepping up the tree until we hit the root, which takes
+    /// at most `log2(num_leaves)` steps and doesn't allocate.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let mut remaining = 0;
+        let mut p = node_parent(self.current_idx, self.num_leaves);
+        while p != self.root_idx {
+            remaining += 1;
+            p = node_parent(p, self.num_leaves);
+        }
+
+        (remaining, Some(remaining))
+    }
+}
 
 /// Returns the direct path of a given node in the form `[i_1, i_2, ..., i_n]` where
-/// `i_1` is the parent of the given node and `i_n` is a child of the root node.
+/// `i_1` is the parent of the given node and `i_n` is a child of the root node. This is just
+/// `DirectPathIter` collected into a `Vec`.
 ///
 /// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES` or
 /// `start_idx >= num_nodes_in_tree(num_leaves)`
 fn node_direct_path(start_idx: usize, num_leaves: usize) -> Vec<usize> {
-    assert!(num_leaves > 0 && num_leaves <= MAX_LEAVES);
-    assert!(start_idx < num_nodes_in_tree(num_leaves));
+    DirectPathIter::new(start_idx, num_leaves).collect()
+}
 
-    let mut path = Vec::new();
+/// Returns the full path from a given node up to the root, in the form `[start_idx, i_1, ..., i_n,
+/// root]` where `i_1, ..., i_n` is the direct path of `start_idx`. Unlike `node_direct_path`, this
+/// includes both the starting node and the root. If `start_idx` is the root, this is just
+/// `[start_idx]`.
+///
+/// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES` or
+/// `start_idx >= num_nodes_in_tree(num_leaves)`
+pub(crate) fn node_full_path(start_idx: usize, num_leaves: usize) -> Vec<usize> {
     let root = root_idx(num_leaves);
-    let mut p = node_parent(start_idx, num_leaves);
 
-    // Start on the parent of the given node. Recall that the parent of the root is itself, so if
-    // we're at the root, we return the empty vector. Similarly, if we're the child of the root, we
-    // still return the empty vector.
-    while p != root {
-        path.push(p);
-        p = node_parent(p, num_leaves);
+    let mut path = vec![start_idx];
+    path.extend(DirectPathIter::new(start_idx, num_leaves));
+    if start_idx != root {
+        path.push(root);
     }
 
     path
 }
 
+/// Returns the nodes that have to be blanked when the member at the given leaf index is removed:
+/// the member's leaf, followed by its direct path, followed by the root. These are exactly the
+/// nodes whose secrets the removed member knew, so they must all be blanked before the path is
+/// re-keyed. Note that `leaf` is a leaf index, not a node index.
+///
+/// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES` or `leaf >= num_leaves`
+pub(crate) fn nodes_to_blank_on_remove(leaf: usize, num_leaves: usize) -> Vec<usize> {
+    assert!(leaf < num_leaves, "removed leaf is out of range");
+    node_full_path(leaf_to_node(leaf), num_leaves)
+}
+
+/// Returns the union of the direct paths of the given leaves, without duplicates, sorted by
+/// increasing level and then by increasing index. Direct paths near the root overlap heavily, so
+/// this lets callers process every affected node exactly once, and always after its children. Note
+/// that `leaves` are leaf indices, not node indices.
+///
+/// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES` or any element of `leaves` is
+/// `>= num_leaves`
+pub(crate) fn merged_direct_paths(leaves: &[usize], num_leaves: usize) -> Vec<usize> {
+    let mut merged: Vec<usize> = leaves
+        .iter()
+        .flat_map(|&leaf| DirectPathIter::new(leaf_to_node(leaf), num_leaves))
+        .collect();
+
+    merged.sort_by_key(|&idx| (node_level(idx), idx));
+    merged.dedup();
+    merged
+}
+
+/// Returns every node in the subtree rooted at `root`, including `root` itself, in order (i.e.,
+/// sorted by index). This is what needs blanking when everything below a node is removed.
+///
+/// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES` or `root >= num_nodes_in_tree`
+pub(crate) fn subtree_nodes(root: usize, num_leaves: usize) -> Vec<usize> {
+    let mut nodes = Vec::new();
+    push_subtree_nodes(root, num_leaves, &mut nodes);
+    nodes
+}
+
+/// Pushes the subtree rooted at `idx` onto `nodes` in order: left subtree, then `idx`, then right
+/// subtree
+fn push_subtree_nodes(idx: usize, num_leaves: usize, nodes: &mut Vec<usize>) {
+    let left = node_left_child(idx);
+    // The child of a leaf is itself
+    if left == idx {
+        nodes.push(idx);
+        return;
+    }
+
+    push_subtree_nodes(left, num_leaves, nodes);
+    nodes.push(idx);
+    push_subtree_nodes(node_right_child(idx, num_leaves), num_leaves, nodes);
+}
+
+/// An iterator over the copath of a given node. This yields `i_1, i_2, ..., i_n` where `i_1` is
+/// the sibling of the starting node and `i_n` is a child of the root node. Like `DirectPathIter`,
+/// this does not allocate.
+pub(crate) struct CopathIter {
+    /// The node whose sibling will be yielded next
+    current_idx: usize,
+    /// The root of the tree. The root has no sibling, so we stop once we get here.
+    root_idx: usize,
+    /// The number of leaves in the tree we're walking
+    num_leaves: usize,
+}
+
+impl CopathIter {
+    /// Makes a new `CopathIter` starting at the given node
+    ///
+    /// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES` or
+    /// `start_idx >= num_nodes_in_tree(num_leaves)`
+    pub(crate) fn new(start_idx: usize, num_leaves: usize) -> CopathIter {
+        assert!(num_leaves > 0 && num_leaves <= MAX_LEAVES);
+        assert!(start_idx < num_nodes_in_tree(num_leaves));
+
+        CopathIter {
+            current_idx: start_idx,
+            root_idx: root_idx(num_leaves),
+            num_leaves,
+        }
+    }
+}
+
+impl Iterator for CopathIter {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.current_idx == self.root_idx {
+            None
+        } else {
+            // Recall that p has no siblings iff it is the root node, so it's guaranteed that
+            // sibling != p here.
+            let sibling = node_sibling(self.current_idx, self.num_leaves);
+            self.current_idx = node_parent(self.current_idx, self.num_leaves);
+            Some(sibling)
+        }
+    }
+
+    /// The hint is exact. Every step up the tree before the root contributes one sibling.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let mut remaining = 0;
+        let mut p = self.current_idx;
+        while p != self.root_idx {
+            remaining += 1;
+            p = node_parent(p, self.num_leaves);
+        }
+
+        (remaining, Some(remaining))
+    }
+}
+
+// Both of these have exact size hints, so the default len() is correct
+impl ExactSizeIterator for DirectPathIter {}
+impl ExactSizeIterator for CopathIter {}
+
 /// Returns the copath path of a given node in the form `[i_1, i_2, ..., i_n]` where
-/// `i_1` is the sibling of the given node and `i_n` is a child of the root node.
+/// `i_1` is the sibling of the given node and `i_n` is a child of the root node. This is just
+/// `CopathIter` collected into a `Vec`.
 ///
 /// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES` or
 /// `start_idx >= num_nodes_in_tree(num_leaves)`
 fn node_copath(start_idx: usize, num_leaves: usize) -> Vec<usize> {
+    CopathIter::new(start_idx, num_leaves).collect()
+}
+
+/// Returns an iterator over the nodes on the path from the given node up to (but not including)
+/// the root, each paired with its sibling: `(start_idx, sibling(start_idx)), (i_1, sibling(i_1)),
+/// ...`, where `i_1, ...` is the direct path of `start_idx`. The second elements are exactly the
+/// copath of `start_idx`. Note that the copath is one longer than the direct path, since it starts
+/// at the sibling of `start_idx`, not the sibling of its parent. So the first elements are the
+/// direct path with `start_idx` in front. At the root, this is empty.
+///
+/// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES` or
+/// `start_idx >= num_nodes_in_tree(num_leaves)`
+pub(crate) fn path_with_copath(
+    start_idx: usize,
+    num_leaves: usize,
+) -> impl Iterator<Item = (usize, usize)> {
+    let path = core::iter::once(start_idx).chain(DirectPathIter::new(start_idx, num_leaves));
+    // This stops at the end of the copath, which cuts off start_idx when it's the root
+    path.zip(CopathIter::new(start_idx, num_leaves))
+}
+
+/// Returns whether `ancestor` is a proper ancestor of `descendant`, i.e., whether `ancestor` is
+/// in the direct path of `descendant` or is the root. A node is not its own ancestor.
+///
+/// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES` or
+/// `ancestor >= num_nodes_in_tree(num_leaves)` or `descendant >= num_nodes_in_tree(num_leaves)`
+pub(crate) fn is_ancestor(ancestor: usize, descendant: usize, num_leaves: usize) -> bool {
     assert!(num_leaves > 0 && num_leaves <= MAX_LEAVES);
-    assert!(start_idx < num_nodes_in_tree(num_leaves));
+    assert!(ancestor < num_nodes_in_tree(num_leaves));
+    assert!(descendant < num_nodes_in_tree(num_leaves));
 
-    let mut copath = Vec::new();
-    let root = root_idx(num_leaves);
-    let mut p = start_idx;
-
-    // Iterate up the direct path starting at the given node, taking siblings along the way.
-    while p != root {
-        // Recall that p has no siblings iff it is the root node, so it's guaranteed that
-        // sibling != p here.
-        let sibling = node_sibling(p, num_leaves);
-        copath.push(sibling);
+    // Levels strictly increase as we walk up the tree, so once we're at or above the level of the
+    // supposed ancestor, we either found it or we never will. This terminates, since nothing in
+    // the tree has a higher level than the root.
+    let ancestor_level = node_level(ancestor);
+    let mut p = descendant;
+    while node_level(p) < ancestor_level {
         p = node_parent(p, num_leaves);
     }
 
-    copath
+    p == ancestor && p != descendant
 }
 
-/// Returns a list of root node indices for maximal subtrees of a tree of a given size
+/// Computes the index of the lowest common ancestor of the two given nodes, i.e., the deepest node
+/// that has both `a` and `b` in its subtree. Every node is considered to be in its own subtree, so
+/// if `a` is an ancestor of `b` then the result is `a`.
+///
+/// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES` or
+/// `a >= num_nodes_in_tree(num_leaves)` or `b >= num_nodes_in_tree(num_leaves)`
+pub(crate) fn node_common_ancestor(a: usize, b: usize, num_leaves: usize) -> usize {
+    assert!(num_leaves > 0 && num_leaves <= MAX_LEAVES);
+    assert!(a < num_nodes_in_tree(num_leaves));
+    assert!(b < num_nodes_in_tree(num_leaves));
+
+    // A node on level n has the form xyz..01111...1 with n-many ones at the end, and every node in
+    // its subtree agrees with it on the bits xyz..0 above the lowest n+1 bits. So if the nodes
+    // agree above the lowest level(a)+1 bits, then a is an ancestor of b. Same goes for b.
+    let shift_a = node_level(a) + 1;
+    let shift_b = node_level(b) + 1;
+    if shift_b <= shift_a && a.checked_shr(shift_a as u32) == b.checked_shr(shift_a as u32) {
+        return a;
+    }
+    if shift_a <= shift_b && a.checked_shr(shift_b as u32) == b.checked_shr(shift_b as u32) {
+        return b;
+    }
+
+    // Otherwise, the nodes are in distinct subtrees of their common ancestor. Chop off bits until
+    // we find the longest common prefix. If that prefix is k bits shorter than the indices, then
+    // the common ancestor is on level k-1, so it's the prefix followed by a 0 and k-1 ones.
+    let (mut pa, mut pb) = (a, b);
+    let mut k = 0;
+    while pa != pb {
+        pa >>= 1;
+        pb >>= 1;
+        k += 1;
+    }
+
+    // This is always within the bounds of the tree, since it lies strictly between a and b. And
+    // since parents in a left-balanced tree are found by skipping over nonexistent nodes on the
+    // way up a full tree (see node_parent), an in-bounds ancestor in the full tree is also an
+    // ancestor in ours.
+    (pa << k) | ((1 << (k - 1)) - 1)
+}
+
+/// Computes the number of edges on the path between the two given nodes in the tree. This is the
+/// number of steps from `a` up to the lowest common ancestor of `a` and `b`, plus the number of
+/// steps from there back down to `b`.
+///
+/// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES` or
+/// `a >= num_nodes_in_tree(num_leaves)` or `b >= num_nodes_in_tree(num_leaves)`
+pub(crate) fn node_distance(a: usize, b: usize, num_leaves: usize) -> usize {
+    // Counts the number of steps it takes to get from a node to one of its ancestors. We can't
+    // just take the difference in levels, since the tree isn't necessarily full. E.g., in a tree
+    // of 5 leaves, leaf 8 is on level 0 and its parent, the root, is on level 3.
+    fn steps_up(from: usize, to: usize, num_leaves: usize) -> usize {
+        let mut steps = 0;
+        let mut p = from;
+        while p != to {
+            p = node_parent(p, num_leaves);
+            steps += 1;
+        }
+        steps
+    }
+
+    let lca = node_common_ancestor(a, b, num_leaves);
+    steps_up(a, lca, num_leaves) + steps_up(b, lca, num_leaves)
+}
+
+/// Returns the resolution of a given node: this is an ordered list of non-blank nodes that
+/// collectively cover all non-blank descendants of the given node. `blanks[i]` says whether node
+/// `i` is blank, and `unmerged_leaves[i]`, if given, lists the leaf indices (not node indices) of
+/// the leaves under node `i` that were added after node `i` was last set, and so don't know its
+/// secret. Concretely, the resolution of a non-blank node is `[idx]` followed by the nodes of its
+/// unmerged leaves, the resolution of a blank leaf is empty, and the resolution of a blank
+/// non-leaf node is the resolution of its left child followed by the resolution of its right
+/// child.
+///
+/// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES` or
+/// `idx >= num_nodes_in_tree(num_leaves)` or `blanks.len() != num_nodes_in_tree(num_leaves)` or
+/// `unmerged_leaves` is given and `unmerged_leaves.len() != num_nodes_in_tree(num_leaves)`
+pub(crate) fn node_resolution(
+    idx: usize,
+    num_leaves: usize,
+    blanks: &[bool],
+    unmerged_leaves: Option<&[Vec<usize>]>,
+) -> Vec<usize> {
+    fn helper(
+        i: usize,
+        num_leaves: usize,
+        blanks: &[bool],
+        unmerged_leaves: Option<&[Vec<usize>]>,
+        acc: &mut Vec<usize>,
+    ) {
+        if !blanks[i] {
+            acc.push(i);
+            // A non-blank node still doesn't cover the leaves that were added under it since it
+            // was set, so they have to be listed separately
+            if let Some(unmerged_leaves) = unmerged_leaves {
+                acc.extend(unmerged_leaves[i].iter().map(|&leaf| leaf_to_node(leaf)));
+            }
+        } else if node_level(i) > 0 {
+            helper(node_left_child(i), num_leaves, blanks, unmerged_leaves, acc);
+            helper(
+                node_right_child(i, num_leaves),
+                num_leaves,
+                blanks,
+                unmerged_leaves,
+                acc,
+            );
+        }
+    }
+
+    assert!(num_leaves > 0 && num_leaves <= MAX_LEAVES);
+    assert!(idx < num_nodes_in_tree(num_leaves));
+    assert_eq!(blanks.len(), num_nodes_in_tree(num_leaves));
+    if let Some(unmerged_leaves) = unmerged_leaves {
+        assert_eq!(unmerged_leaves.len(), num_nodes_in_tree(num_leaves));
+    }
+
+    let mut acc = Vec::new();
+    helper(idx, num_leaves, blanks, unmerged_leaves, &mut acc);
+    acc
+}
+
+/// Returns, for each node on the direct path of the given leaf (starting at the leaf and ending at
+/// the root), the resolution of the corresponding copath node. These are exactly the recipients of
+/// the `node_secrets` in each `DirectPathNodeMessage` of a `DirectPathMessage` sent from this leaf.
+/// The leaf has no corresponding copath node, so the first entry is always empty. `blanks[i]` says
+/// whether node `i` is blank.
+///
+/// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES` or `from_leaf >= num_leaves` or
+/// `blanks.len() != num_nodes_in_tree(num_leaves)`
+pub(crate) fn copath_resolutions(
+    from_leaf: usize,
+    num_leaves: usize,
+    blanks: &[bool],
+) -> Vec<Vec<usize>> {
+    assert!(from_leaf < num_leaves);
+    let leaf_idx = leaf_to_node(from_leaf);
+
+    // The ith node of the path (for i > 0) corresponds to the sibling of the (i-1)th node
+    let mut resolutions = vec![Vec::new()];
+    resolutions.extend(
+        CopathIter::new(leaf_idx, num_leaves)
+            .map(|copath_idx| node_resolution(copath_idx, num_leaves, blanks, None)),
+    );
+    resolutions
+}
+
+/// Returns the number of leaves the tree should have once all the blank leaves on its right edge
+/// are removed. `blanks[i]` says whether node `i` is blank. If every leaf is blank, this returns 1,
+/// since a tree can't have zero leaves.
+///
+/// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES` or
+/// `blanks.len() != num_nodes_in_tree(num_leaves)`
+pub(crate) fn truncated_num_leaves(num_leaves: usize, blanks: &[bool]) -> usize {
+    assert!(num_leaves > 0 && num_leaves <= MAX_LEAVES);
+    assert_eq!(blanks.len(), num_nodes_in_tree(num_leaves));
+
+    // Walk leftwards from the rightmost leaf until we find one that isn't blank. Everything to the
+    // right of that leaf gets chopped off.
+    (0..num_leaves)
+        .rev()
+        .find(|&leaf_idx| !blanks[leaf_to_node(leaf_idx)])
+        .map(|leaf_idx| leaf_idx + 1)
+        .unwrap_or(1)
+}
+
+/// Returns the index of the leftmost blank leaf, where `blanks[i]` says whether node `i` is blank.
+/// This is where a new member goes. If no leaf is blank, returns `None`, meaning the tree has to
+/// grow by a leaf instead. Every member must place new members identically, or their trees
+/// diverge, so this is the only place the rule lives. Note that the return value is a leaf index,
+/// not a node index.
+pub(crate) fn leftmost_blank_leaf(blanks: &[bool]) -> Option<usize> {
+    // The leaves are just all the even indices
+    blanks.iter().step_by(2).position(|&is_blank| is_blank)
+}
+
+/// Returns the node indices of the leaves that aren't blank, from left to right, where `blanks[i]`
+/// says whether node `i` is blank. These are the current members. After a removal, the tree can
+/// still have blank leaves in the middle, or on the right edge until it's truncated, so this can
+/// be shorter than the number of leaves in the tree.
+///
+/// Panics: when `blanks.len()` is even, or is 0 or too big to be the size of a tree
+pub(crate) fn active_leaves(blanks: &[bool]) -> Vec<usize> {
+    let num_leaves = num_leaves_in_tree(blanks.len());
+    tree_leaves(num_leaves)
+        .into_iter()
+        .filter(|&leaf_idx| !blanks[leaf_idx])
+        .collect()
+}
+
+/// Returns the number of leaves that aren't blank, i.e., `active_leaves(blanks).len()`
+///
+/// Panics: when `blanks.len()` is even, or is 0 or too big to be the size of a tree
+pub(crate) fn active_leaf_count(blanks: &[bool]) -> usize {
+    let num_leaves = num_leaves_in_tree(blanks.len());
+    tree_leaves_iter(num_leaves)
+        .filter(|&leaf_idx| !blanks[leaf_idx])
+        .count()
+}
+
+/// Draws a tree with `num_leaves` many leaves as ASCII art. This is only built for tests, where
+/// it's handy for debugging. There's one row of nodes per level, with the root on top, and each
+/// row is labeled with its level. Nodes sit in columns by index, which for a left-balanced tree is
+/// exactly left-to-right order. If `blanks` is given, `blanks[i]` says whether node `i` is blank,
+/// and blank nodes are drawn in parentheses. For example, a tree with 3 leaves whose node 3 is
+/// blank looks like
+///
+/// ```text
+/// L2                 (3)
+///            ________/ \___
+/// L1        1              |
+///       ___/ \___          |
+/// L0   0         2         4
+/// ```
+///
+/// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES` or `blanks` is given and
+/// `blanks.len() != num_nodes_in_tree(num_leaves)`
+#[cfg(test)]
+pub(crate) fn render_ascii(num_leaves: usize, blanks: Option<&[bool]>) -> String {
+    /// Draws the subtree rooted at `idx` onto `grid`, whose row `2*(root_level - l)` holds the
+    /// nodes of level `l`, and whose odd rows hold the edges between levels
+    fn draw(
+        idx: usize,
+        num_leaves: usize,
+        blanks: Option<&[bool]>,
+        cell_width: usize,
+        root_level: usize,
+        grid: &mut Vec<Vec<u8>>,
+    ) {
+        let level = node_level(idx);
+        let row = 2 * (root_level - level);
+        let center = |i: usize| i * cell_width + cell_width / 2;
+
+        // Center the label in the node's cell
+        let label = match blanks {
+            Some(blanks) if blanks[idx] => format!("({})", idx),
+            _ => format!("{}", idx),
+        };
+        let start = center(idx) - label.len() / 2;
+        grid[row][start..start + label.len()].copy_from_slice(label.as_bytes());
+
+        if level == 0 {
+            return;
+        }
+
+        // The left subtree of a node is always full, so the left child is exactly one level down.
+        // The right child might be several levels down, in which case a vertical line drops down
+        // to it.
+        let left = node_left_child(idx);
+        let right = node_right_child(idx, num_leaves);
+        let edge_row = row + 1;
+        grid[edge_row][center(idx) - 1] = b'/';
+        for col in center(left) + 1..center(idx) - 1 {
+            grid[edge_row][col] = b'_';
+        }
+        grid[edge_row][center(idx) + 1] = b'\\';
+        for col in center(idx) + 2..center(right) {
+            grid[edge_row][col] = b'_';
+        }
+        let right_row = 2 * (root_level - node_level(right));
+        for drop_row in edge_row + 1..right_row {
+            grid[drop_row][center(right)] = b'|';
+        }
+
+        draw(left, num_leaves, blanks, cell_width, root_level, grid);
+        draw(right, num_leaves, blanks, cell_width, root_level, grid);
+    }
+
+    let num_nodes = num_nodes_in_tree(num_leaves);
+    if let Some(blanks) = blanks {
+        assert_eq!(blanks.len(), num_nodes);
+    }
+
+    // Every cell fits the widest label, parentheses included, plus a space on either side
+    let cell_width = format!("({})", num_nodes - 1).len() + 2;
+    let root = root_idx(num_leaves);
+    let root_level = node_level(root);
+    let mut grid = vec![vec![b' '; num_nodes * cell_width]; 2 * root_level + 1];
+    draw(root, num_leaves, blanks, cell_width, root_level, &mut grid);
+
+    // Label the node rows with their levels, and drop the trailing whitespace
+    let margin_width = format!("L{} ", root_level).len();
+    let mut out = String::new();
+    for (row_idx, row) in grid.iter().enumerate() {
+        let margin = if row_idx % 2 == 0 {
+            format!("L{}", root_level - row_idx / 2)
+        } else {
+            String::new()
+        };
+        out.push_str(&format!("{:width$}", margin, width = margin_width));
+        // The grid is all ASCII, so this can't fail
+        out.push_str(core::str::from_utf8(row).unwrap().trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// Returns a list of root node indices for maximal full subtrees of a tree of a given size, from
+/// left to right. A new member needs this to interpret the public tree in a `WelcomeInfo`, since
+/// every leaf of the tree is under exactly one of these subtrees.
 ///
 /// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES`
-fn tree_frontier(num_leaves: usize) -> Vec<usize> {
+pub(crate) fn tree_frontier(num_leaves: usize) -> Vec<usize> {
     assert!(num_leaves > 0 && num_leaves <= MAX_LEAVES);
 
     // The given tree has a maximal subtree of size 2^(i+1)-1 exists iff the i-th bit (indexing at
@@ -251,87 +798,418 @@ fn tree_frontier(num_leaves: usize) -> Vec<usize> {
     frontier
 }
 
-/// Returns a list of indices for leaf nodes in a tree of given size
+/// Returns the indices of the leaves under the full subtree rooted at the given node, from left to
+/// right. This assumes the subtree is full, i.e., it doesn't run off the right edge of the tree.
+fn full_subtree_leaves(root: usize) -> impl Iterator<Item = usize> {
+    // A full subtree whose root is on level n has 2^n leaves, and the root sits right in the middle
+    // of them. So the leftmost leaf is 2^n - 1 to the left of the root.
+    let half_width = (1 << node_level(root)) - 1;
+    (root - half_width..=root + half_width).step_by(2)
+}
+
+/// Checks that the subtrees given by `tree_frontier(num_leaves)` cover every leaf of the tree
+/// exactly once, in order
+///
+/// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES`
+pub(crate) fn frontier_covers(num_leaves: usize) -> bool {
+    let covered_leaves = tree_frontier(num_leaves)
+        .into_iter()
+        .flat_map(full_subtree_leaves)
+        .collect::<Vec<usize>>();
+
+    covered_leaves == tree_leaves(num_leaves)
+}
+
+/// Returns a list of indices for leaf nodes in a tree of given size. This is just
+/// `tree_leaves_iter` collected into a `Vec`.
 ///
 /// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES`
 fn tree_leaves(num_leaves: usize) -> Vec<usize> {
+    tree_leaves_iter(num_leaves).collect()
+}
+
+/// Returns an iterator over the indices of the leaf nodes in a tree of given size, from left to
+/// right. Unlike `tree_leaves`, this does not allocate.
+///
+/// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES`
+pub(crate) fn tree_leaves_iter(num_leaves: usize) -> impl ExactSizeIterator<Item = usize> {
     assert!(num_leaves > 0 && num_leaves <= MAX_LEAVES);
     // The leaves are just all the even indices
-    (0..num_leaves).map(|i| 2 * i).collect()
+    (0..num_leaves).map(leaf_to_node)
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::tls_de::TlsDeserializer;
-
-    use quickcheck::TestResult;
-    use quickcheck_macros::quickcheck;
-    use rand::Rng;
-    use serde::de::Deserialize;
-
-    #[test]
-    fn log2_kat() {
-        assert_eq!(log2(0), None);
-        assert_eq!(log2(1), Some(0));
-        assert_eq!(log2(2), Some(1));
-        assert_eq!(log2(3), Some(1));
-        assert_eq!(log2(127), Some(6));
-        assert_eq!(log2(128), Some(7));
-        assert_eq!(log2(129), Some(7));
-        assert_eq!(log2(255), Some(7));
+/// Returns an iterator over the indices of all the nodes on the given level of a tree of the given
+/// size, from left to right. Level 0 is the leaves.
+///
+/// Panics: when `num_leaves == 0` or `num_leaves > MAX_LEAVES`
+pub(crate) fn nodes_at_level(level: usize, num_leaves: usize) -> impl Iterator<Item = usize> {
+    assert!(num_leaves > 0 && num_leaves <= MAX_LEAVES);
 
-        // Check log2(x/2) == log2(x/4) + 1 where x == 2^n for the biggest possible n for usize
-        let bigboi = std::usize::MAX;
-        assert_eq!(
-            log2((bigboi >> 1) + 1),
-            log2((bigboi >> 2) + 1).map(|i| i + 1)
-        );
+    // The nodes on level n are exactly the ones of the form xyz..01111...1 with n-many ones at the
+    // end. So the first is 2^n - 1, and every subsequent one is 2^(n+1) further along. A node on
+    // this level may be conjectured to exist past the right edge of the tree (e.g., in a tree of 5
+    // leaves, node 9 would be on level 1 if it existed), so we stop at the end of the tree. Nothing
+    // lives above the root, so for those levels we return an empty range, which also keeps the
+    // shifts below from overflowing.
+    let num_nodes = num_nodes_in_tree(num_leaves);
+    if level > node_level(root_idx(num_leaves)) {
+        return (num_nodes..num_nodes).step_by(1);
     }
+    let first = (1usize << level) - 1;
+    let step = 1usize << (level + 1);
 
-    // We'll use this tree for known-answer tests
-    //               7
-    //         _____/ \
-    //        /        |
-    //       3         |
-    //     /   \       |
-    //    /     \      |
-    //   1       5     |
-    //  / \     / \    |
-    // 0   2   4   6   8
+    (first..num_nodes).step_by(step)
+}
 
-    // See above tree for a diagram
-    #[test]
-    fn node_level_simple_kat() {
-        assert_eq!(node_level(0), 0);
-        assert_eq!(node_level(1), 1);
-        assert_eq!(node_level(2), 0);
-        assert_eq!(node_level(3), 2);
-        assert_eq!(node_level(4), 0);
-        assert_eq!(node_level(5), 1);
-        assert_eq!(node_level(6), 0);
-        assert_eq!(node_level(7), 3);
-        assert_eq!(node_level(8), 0);
+/// Converts the index of a leaf (e.g., a position in the roster) into its node index in the tree.
+/// The leaves are the even-indexed nodes, so leaf `i` is node `2*i`.
+///
+/// Panics: when `leaf_idx >= MAX_LEAVES`
+pub(crate) fn leaf_to_node(leaf_idx: usize) -> usize {
+    assert!(leaf_idx < MAX_LEAVES);
+    2 * leaf_idx
+}
+
+/// Converts a node index into the index of the leaf it represents. This is the inverse of
+/// `leaf_to_node`.
+///
+/// Returns: `Some(node_idx / 2)` if `node_idx` is even, and `None` if it's odd, since odd indices
+/// are not leaves
+pub(crate) fn node_to_leaf(node_idx: usize) -> Option<usize> {
+    if node_idx % 2 == 0 {
+        Some(node_idx >> 1)
+    } else {
+        None
     }
+}
 
-    #[test]
-    fn num_nodes_in_tree_kat() {
-        assert_eq!(num_nodes_in_tree(1), 1);
-        assert_eq!(num_nodes_in_tree(4), 7);
-        assert_eq!(num_nodes_in_tree(5), 9);
+//
+// Public API
+//
+// Everything above panics on invalid input, since internally we only ever call it with indices we
+// computed ourselves. The functions below check their inputs first and return a `TreeMathError`
+// instead of panicking, so they're safe to call on indices that came off the wire.
+//
 
-        // For explanation, see comments by definition of MAX_LEAVES
-        assert_eq!(num_nodes_in_tree(MAX_LEAVES), std::usize::MAX);
+/// Checks that a tree with `num_leaves` many leaves is representable
+fn validate_num_leaves(num_leaves: usize) -> Result<(), TreeMathError> {
+    if num_leaves == 0 {
+        Err(TreeMathError::ZeroLeaves)
+    } else if num_leaves > MAX_LEAVES {
+        Err(TreeMathError::TooManyLeaves)
+    } else {
+        Ok(())
     }
+}
 
-    #[test]
-    fn num_leaves_in_tree_kat() {
-        assert_eq!(num_leaves_in_tree(1), 1);
-        assert_eq!(num_leaves_in_tree(7), 4);
-        assert_eq!(num_leaves_in_tree(9), 5);
-
-        // For explanation, see comments by definition of MAX_LEAVES
-        assert_eq!(num_leaves_in_tree(std::usize::MAX), MAX_LEAVES);
+/// Checks that a tree with `num_leaves` many leaves is representable and that `idx` is a node in
+/// that tree
+fn validate_idx(idx: usize, num_leaves: usize) -> Result<(), TreeMathError> {
+    validate_num_leaves(num_leaves)?;
+    if idx >= num_nodes_in_tree(num_leaves) {
+        Err(TreeMathError::IndexOutOfRange)
+    } else {
+        Ok(())
+    }
+}
+
+/// Computes the number of nodes needed to represent a tree with `num_leaves` many leaves
+///
+/// Returns: `Ok(num_nodes)` on success. Returns `Err(TreeMathError::ZeroLeaves)` or
+/// `Err(TreeMathError::TooManyLeaves)` if `num_leaves` is out of range.
+pub fn try_num_nodes_in_tree(num_leaves: usize) -> Result<usize, TreeMathError> {
+    validate_num_leaves(num_leaves)?;
+    Ok(num_nodes_in_tree(num_leaves))
+}
+
+/// Computes the index of the root node of a tree with `num_leaves` many leaves
+///
+/// Returns: `Ok(root)` on success. Returns `Err(TreeMathError::ZeroLeaves)` or
+/// `Err(TreeMathError::TooManyLeaves)` if `num_leaves` is out of range.
+pub fn try_root_idx(num_leaves: usize) -> Result<usize, TreeMathError> {
+    validate_num_leaves(num_leaves)?;
+    Ok(root_idx(num_leaves))
+}
+
+/// Computes the index of the left child of the given node. The child of a leaf is itself.
+///
+/// Returns: `Ok(left_child)` on success. Returns a `TreeMathError` if `num_leaves` is out of range
+/// or `idx` is not in the tree.
+pub fn try_node_left_child(idx: usize, num_leaves: usize) -> Result<usize, TreeMathError> {
+    validate_idx(idx, num_leaves)?;
+    Ok(node_left_child(idx))
+}
+
+/// Computes the index of the right child of the given node. The child of a leaf is itself.
+///
+/// Returns: `Ok(right_child)` on success. Returns a `TreeMathError` if `num_leaves` is out of
+/// range or `idx` is not in the tree.
+pub fn try_node_right_child(idx: usize, num_leaves: usize) -> Result<usize, TreeMathError> {
+    validate_idx(idx, num_leaves)?;
+    Ok(node_right_child(idx, num_leaves))
+}
+
+/// Computes the index of the parent of the given node. The parent of the root is the root.
+///
+/// Returns: `Ok(parent)` on success. Returns a `TreeMathError` if `num_leaves` is out of range or
+/// `idx` is not in the tree.
+pub fn try_node_parent(idx: usize, num_leaves: usize) -> Result<usize, TreeMathError> {
+    validate_idx(idx, num_leaves)?;
+    Ok(node_parent(idx, num_leaves))
+}
+
+/// Computes the index of the sibling of the given node. The sibling of the root is the root.
+///
+/// Returns: `Ok(sibling)` on success. Returns a `TreeMathError` if `num_leaves` is out of range or
+/// `idx` is not in the tree.
+pub fn try_node_sibling(idx: usize, num_leaves: usize) -> Result<usize, TreeMathError> {
+    validate_idx(idx, num_leaves)?;
+    Ok(node_sibling(idx, num_leaves))
+}
+
+/// Returns the direct path of a given node in the form `[i_1, i_2, ..., i_n]` where `i_1` is the
+/// parent of the given node and `i_n` is a child of the root node.
+///
+/// Returns: `Ok(direct_path)` on success. Returns a `TreeMathError` if `num_leaves` is out of
+/// range or `start_idx` is not in the tree.
+pub fn try_node_direct_path(
+    start_idx: usize,
+    num_leaves: usize,
+) -> Result<Vec<usize>, TreeMathError> {
+    validate_idx(start_idx, num_leaves)?;
+    Ok(node_direct_path(start_idx, num_leaves))
+}
+
+/// Returns the copath of a given node in the form `[i_1, i_2, ..., i_n]` where `i_1` is the
+/// sibling of the given node and `i_n` is a child of the root node.
+///
+/// Returns: `Ok(copath)` on success. Returns a `TreeMathError` if `num_leaves` is out of range or
+/// `start_idx` is not in the tree.
+pub fn try_node_copath(start_idx: usize, num_leaves: usize) -> Result<Vec<usize>, TreeMathError> {
+    validate_idx(start_idx, num_leaves)?;
+    Ok(node_copath(start_idx, num_leaves))
+}
+
+/// Returns a list of root node indices for maximal subtrees of a tree of a given size
+///
+/// Returns: `Ok(frontier)` on success. Returns `Err(TreeMathError::ZeroLeaves)` or
+/// `Err(TreeMathError::TooManyLeaves)` if `num_leaves` is out of range.
+pub fn try_tree_frontier(num_leaves: usize) -> Result<Vec<usize>, TreeMathError> {
+    validate_num_leaves(num_leaves)?;
+    Ok(tree_frontier(num_leaves))
+}
+
+/// Checks that a tree of `num_nodes` many nodes is a left-balanced binary tree we can represent.
+/// Use this on trees that came off the wire before calling anything that takes a number of
+/// leaves.
+///
+/// Returns: `Ok(num_leaves)` on success, where `num_leaves` is the number of leaves of the tree.
+/// Returns `Err(TreeMathError::EvenNumNodes)` if `num_nodes` is even, and
+/// `Err(TreeMathError::ZeroLeaves)` if `num_nodes == 0`.
+pub fn validate_tree_shape(num_nodes: usize) -> Result<usize, TreeMathError> {
+    if num_nodes == 0 {
+        return Err(TreeMathError::ZeroLeaves);
+    }
+    if num_nodes % 2 == 0 {
+        return Err(TreeMathError::EvenNumNodes);
+    }
+
+    let num_leaves = num_leaves_in_tree(num_nodes);
+    validate_num_leaves(num_leaves)?;
+    Ok(num_leaves)
+}
+
+//
+// Public API over u32 indices
+//
+// All the on-wire structures (and the MLS test vectors) use uint32 node indices. These functions
+// check that the given tree is small enough for every one of its node indices to fit in a u32, so
+// the results can't be silently truncated.
+//
+
+/// The largest number of leaves a tree can have such that every node index fits in a `u32`. A tree
+/// with 2^31 leaves has 2^32 - 1 nodes, so the largest node index is 2^32 - 2.
+const MAX_LEAVES_U32: u32 = 1 << 31;
+
+/// Checks that every node in a tree with `num_leaves` many leaves has a `u32` index, and that
+/// `idx` is a node in that tree
+fn validate_idx_u32(idx: u32, num_leaves: u32) -> Result<(), TreeMathError> {
+    if num_leaves > MAX_LEAVES_U32 {
+        Err(TreeMathError::TooManyLeaves)
+    } else {
+        validate_idx(idx as usize, num_leaves as usize)
+    }
+}
+
+/// Computes the index of the root node of a tree with `num_leaves` many leaves
+///
+/// Returns: `Ok(root)` on success. Returns `Err(TreeMathError::ZeroLeaves)` or
+/// `Err(TreeMathError::TooManyLeaves)` if `num_leaves` is out of range.
+pub fn root_idx_u32(num_leaves: u32) -> Result<u32, TreeMathError> {
+    if num_leaves > MAX_LEAVES_U32 {
+        Err(TreeMathError::TooManyLeaves)
+    } else {
+        // The root is a node in the tree, so it fits in a u32
+        try_root_idx(num_leaves as usize).map(|i| i as u32)
+    }
+}
+
+/// Computes the index of the left child of the given node. The child of a leaf is itself.
+///
+/// Returns: `Ok(left_child)` on success. Returns a `TreeMathError` if `num_leaves` is out of range
+/// or `idx` is not in the tree.
+pub fn node_left_child_u32(idx: u32, num_leaves: u32) -> Result<u32, TreeMathError> {
+    validate_idx_u32(idx, num_leaves)?;
+    Ok(node_left_child(idx as usize) as u32)
+}
+
+/// Computes the index of the right child of the given node. The child of a leaf is itself.
+///
+/// Returns: `Ok(right_child)` on success. Returns a `TreeMathError` if `num_leaves` is out of
+/// range or `idx` is not in the tree.
+pub fn node_right_child_u32(idx: u32, num_leaves: u32) -> Result<u32, TreeMathError> {
+    validate_idx_u32(idx, num_leaves)?;
+    Ok(node_right_child(idx as usize, num_leaves as usize) as u32)
+}
+
+/// Computes the index of the parent of the given node. The parent of the root is the root.
+///
+/// Returns: `Ok(parent)` on success. Returns a `TreeMathError` if `num_leaves` is out of range or
+/// `idx` is not in the tree.
+pub fn node_parent_u32(idx: u32, num_leaves: u32) -> Result<u32, TreeMathError> {
+    validate_idx_u32(idx, num_leaves)?;
+    Ok(node_parent(idx as usize, num_leaves as usize) as u32)
+}
+
+/// Computes the index of the sibling of the given node. The sibling of the root is the root.
+///
+/// Returns: `Ok(sibling)` on success. Returns a `TreeMathError` if `num_leaves` is out of range or
+/// `idx` is not in the tree.
+pub fn node_sibling_u32(idx: u32, num_leaves: u32) -> Result<u32, TreeMathError> {
+    validate_idx_u32(idx, num_leaves)?;
+    Ok(node_sibling(idx as usize, num_leaves as usize) as u32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[cfg(feature = "std")]
+    use crate::tls_de::TlsDeserializer;
+    use alloc::boxed::Box;
+
+    use quickcheck::{Arbitrary, Gen, TestResult};
+    use quickcheck_macros::quickcheck;
+    #[cfg(feature = "std")]
+    use serde::de::Deserialize;
+
+    /// A tree size and a node index that's guaranteed to be in a tree of that size. Generating
+    /// these directly means property tests (and fuzz targets) don't waste runs on out-of-range
+    /// inputs that they'd otherwise have to discard.
+    #[derive(Clone, Copy, Debug)]
+    pub(crate) struct TreeCoord {
+        pub(crate) num_leaves: usize,
+        pub(crate) idx: usize,
+    }
+
+    impl Arbitrary for TreeCoord {
+        fn arbitrary<G: Gen>(g: &mut G) -> TreeCoord {
+            // Most of the time, pick a tree whose size scales with the generator size. Every so
+            // often, pick one right up against MAX_LEAVES, since that's where the overflows live.
+            let size = core::cmp::max(g.size(), 1) as u64;
+            let offset = (g.next_u64() % size) as usize;
+            let num_leaves = if g.next_u32() % 4 == 0 {
+                MAX_LEAVES - offset
+            } else {
+                offset + 1
+            };
+
+            let idx = (g.next_u64() as usize) % num_nodes_in_tree(num_leaves);
+            TreeCoord { num_leaves, idx }
+        }
+
+        // Shrink towards smaller trees, keeping the index in range
+        fn shrink(&self) -> Box<dyn Iterator<Item = TreeCoord>> {
+            let idx = self.idx;
+            Box::new(
+                self.num_leaves
+                    .shrink()
+                    .filter(|&n| n > 0)
+                    .map(move |num_leaves| TreeCoord {
+                        num_leaves,
+                        idx: idx % num_nodes_in_tree(num_leaves),
+                    }),
+            )
+        }
+    }
+
+    // The size helpers are const fns, so these are checked at compile time
+    const _: () = assert!(num_nodes_in_tree(4) == 7);
+    const _: () = assert!(num_leaves_in_tree(9) == 5);
+    const _: () = assert!(node_level(7) == 3);
+    const _: () = assert!(matches!(log2(128), Some(7)));
+
+    #[test]
+    fn log2_kat() {
+        assert_eq!(log2(0), None);
+        assert_eq!(log2(1), Some(0));
+        assert_eq!(log2(2), Some(1));
+        assert_eq!(log2(3), Some(1));
+        assert_eq!(log2(127), Some(6));
+        assert_eq!(log2(128), Some(7));
+        assert_eq!(log2(129), Some(7));
+        assert_eq!(log2(255), Some(7));
+
+        // Check log2(x/2) == log2(x/4) + 1 where x == 2^n for the biggest possible n for usize
+        let bigboi = core::usize::MAX;
+        assert_eq!(
+            log2((bigboi >> 1) + 1),
+            log2((bigboi >> 2) + 1).map(|i| i + 1)
+        );
+    }
+
+    // We'll use this tree for known-answer tests
+    //               7
+    //         _____/ \
+    //        /        |
+    //       3         |
+    //     /   \       |
+    //    /     \      |
+    //   1       5     |
+    //  / \     / \    |
+    // 0   2   4   6   8
+
+    // See above tree for a diagram
+    #[test]
+    fn node_level_simple_kat() {
+        assert_eq!(node_level(0), 0);
+        assert_eq!(node_level(1), 1);
+        assert_eq!(node_level(2), 0);
+        assert_eq!(node_level(3), 2);
+        assert_eq!(node_level(4), 0);
+        assert_eq!(node_level(5), 1);
+        assert_eq!(node_level(6), 0);
+        assert_eq!(node_level(7), 3);
+        assert_eq!(node_level(8), 0);
+    }
+
+    #[test]
+    fn num_nodes_in_tree_kat() {
+        assert_eq!(num_nodes_in_tree(1), 1);
+        assert_eq!(num_nodes_in_tree(4), 7);
+        assert_eq!(num_nodes_in_tree(5), 9);
+
+        // For explanation, see comments by definition of MAX_LEAVES
+        assert_eq!(num_nodes_in_tree(MAX_LEAVES), core::usize::MAX);
+    }
+
+    #[test]
+    fn num_leaves_in_tree_kat() {
+        assert_eq!(num_leaves_in_tree(1), 1);
+        assert_eq!(num_leaves_in_tree(7), 4);
+        assert_eq!(num_leaves_in_tree(9), 5);
+
+        // For explanation, see comments by definition of MAX_LEAVES
+        assert_eq!(num_leaves_in_tree(core::usize::MAX), MAX_LEAVES);
     }
 
     // num_leaves_in_tree and num_nodes_in_tree are inverses of each other
@@ -398,20 +1276,46 @@ mod test {
         assert_eq!(node_sibling(7, num_leaves), 7);
     }
 
-    #[quickcheck]
-    fn tree_relations_correctness(num_leaves: usize) {
-        if num_leaves == 0 || num_leaves > MAX_LEAVES {
-            // This is an invalid input. Do nothing.
-            return;
+    // Checks that the parent computation doesn't wrap around in the biggest trees we allow
+    #[test]
+    fn checked_node_parent_near_max_leaves() {
+        // The topmost level can't be stepped past
+        assert_eq!(checked_parent_step(core::usize::MAX), None);
+        assert_eq!(checked_parent_step(core::usize::MAX >> 1), None);
+
+        for &num_leaves in &[MAX_LEAVES, MAX_LEAVES - 1, (MAX_LEAVES >> 1) + 1] {
+            let root = root_idx(num_leaves);
+            let num_nodes = num_nodes_in_tree(num_leaves);
+            assert_eq!(checked_node_parent(root, num_leaves), Some(root));
+
+            // Walk up from the nodes at the edges of the tree. Every step should go up a level
+            // and stay inside the tree, until we hit the root.
+            for &start in &[0, num_nodes - 1, num_nodes - 2, root - 1, root + 1] {
+                let mut cur = start;
+                while cur != root {
+                    let parent = checked_node_parent(cur, num_leaves).unwrap();
+                    assert!(parent < num_nodes);
+                    assert!(node_level(parent) > node_level(cur));
+                    assert_eq!(parent, node_parent(cur, num_leaves));
+                    cur = parent;
+                }
+            }
+
+            // Out of range indices don't have parents
+            if num_nodes < core::usize::MAX {
+                assert_eq!(checked_node_parent(num_nodes, num_leaves), None);
+            }
         }
 
-        let num_nodes = num_nodes_in_tree(num_leaves);
+        assert_eq!(checked_node_parent(0, 0), None);
+        assert_eq!(checked_node_parent(0, MAX_LEAVES + 1), None);
+    }
 
+    #[quickcheck]
+    fn tree_relations_correctness(coord: TreeCoord) {
+        let num_leaves = coord.num_leaves;
         // This is our starting node
-        let me: usize = {
-            let mut rng = rand::thread_rng();
-            rng.gen_range(0, num_nodes)
-        };
+        let me = coord.idx;
         let my_sibling = node_sibling(me, num_leaves);
         let my_parent = node_parent(my_sibling, num_leaves);
 
@@ -419,17 +1323,17 @@ mod test {
 
         // Recall left_child < parent < right_child
         match me.cmp(&my_parent) {
-            std::cmp::Ordering::Less => {
+            core::cmp::Ordering::Less => {
                 // I am the left child of my parent
                 assert_eq!(node_left_child(my_parent), me);
                 assert_eq!(node_right_child(my_parent, num_leaves), my_sibling);
             }
-            std::cmp::Ordering::Greater => {
+            core::cmp::Ordering::Greater => {
                 // I am the left child of my parent
                 assert_eq!(node_left_child(my_parent), my_sibling);
                 assert_eq!(node_right_child(my_parent, num_leaves), me);
             }
-            std::cmp::Ordering::Equal => {
+            core::cmp::Ordering::Equal => {
                 // I am my own parent. I must be the root node
                 assert_eq!(root_idx(num_leaves), me);
             }
@@ -452,7 +1356,906 @@ mod test {
         }
     }
 
-    // TODO: Add Panic tests
+    // The direct path iterator should agree with a naive walk up the tree that collects every
+    // parent until it reaches the root
+    #[quickcheck]
+    fn direct_path_iter_correctness(num_leaves: usize, start_idx: usize) -> TestResult {
+        if num_leaves == 0 || num_leaves > MAX_LEAVES {
+            return TestResult::discard();
+        }
+        let start_idx = start_idx % num_nodes_in_tree(num_leaves);
+
+        let mut expected = Vec::new();
+        let root = root_idx(num_leaves);
+        let mut p = node_parent(start_idx, num_leaves);
+        while p != root {
+            expected.push(p);
+            p = node_parent(p, num_leaves);
+        }
+
+        // Make sure the size hint is exact at every step of the way
+        let mut iter = DirectPathIter::new(start_idx, num_leaves);
+        let mut yielded = Vec::new();
+        loop {
+            let remaining = expected.len() - yielded.len();
+            assert_eq!(iter.size_hint(), (remaining, Some(remaining)));
+            match iter.next() {
+                Some(i) => yielded.push(i),
+                None => break,
+            }
+        }
+
+        assert_eq!(yielded, expected);
+        assert_eq!(node_direct_path(start_idx, num_leaves), expected);
+        TestResult::passed()
+    }
+
+    // The copath iterator should agree with a naive walk up the tree that collects every sibling
+    // along the way. Check this exhaustively for every node in every tree of up to 64 leaves.
+    #[test]
+    fn copath_iter_correctness() {
+        for num_leaves in 1..=64 {
+            let root = root_idx(num_leaves);
+            for start_idx in 0..num_nodes_in_tree(num_leaves) {
+                let mut expected = Vec::new();
+                let mut p = start_idx;
+                while p != root {
+                    expected.push(node_sibling(p, num_leaves));
+                    p = node_parent(p, num_leaves);
+                }
+
+                let iter = CopathIter::new(start_idx, num_leaves);
+                assert_eq!(iter.len(), expected.len());
+                assert_eq!(iter.collect::<Vec<usize>>(), expected);
+                assert_eq!(node_copath(start_idx, num_leaves), expected);
+
+                // The copath is one longer than the direct path, except at the root where both
+                // are empty
+                let direct_path_len = DirectPathIter::new(start_idx, num_leaves).len();
+                if start_idx == root {
+                    assert_eq!(expected.len(), 0);
+                    assert_eq!(direct_path_len, 0);
+                } else {
+                    assert_eq!(expected.len(), direct_path_len + 1);
+                }
+            }
+        }
+    }
+
+    // See above tree for a diagram
+    #[test]
+    fn render_ascii_kat() {
+        // Reading the rendering top to bottom, left to right, should give the nodes level by
+        // level, and each row should be labeled with its level
+        let rendering = render_ascii(5, None);
+        let node_rows: Vec<Vec<&str>> = rendering
+            .lines()
+            .filter(|line| line.starts_with('L'))
+            .map(|line| line.split_whitespace().filter(|&tok| tok != "|").collect())
+            .collect();
+        assert_eq!(
+            node_rows,
+            vec![
+                vec!["L3", "7"],
+                vec!["L2", "3"],
+                vec!["L1", "1", "5"],
+                vec!["L0", "0", "2", "4", "6", "8"],
+            ]
+        );
+
+        // Now check the whole drawing, with some blanks
+        let mut blanks = [false; 9];
+        blanks[3] = true;
+        blanks[8] = true;
+        let expected = [
+            r"L3                                      7",
+            r"                     __________________/ \___",
+            r"L2                 (3)                       |",
+            r"           ________/ \________               |",
+            r"L1        1                   5              |",
+            r"      ___/ \___           ___/ \___          |",
+            r"L0   0         2         4         6        (8)",
+        ];
+        assert_eq!(
+            render_ascii(5, Some(&blanks)).lines().collect::<Vec<_>>(),
+            expected
+        );
+
+        // A single leaf is just a single row
+        assert_eq!(render_ascii(1, None), "L0   0\n");
+    }
+
+    // Well-formed trees have a nonzero, odd number of nodes
+    #[test]
+    fn validate_tree_shape_kat() {
+        assert_eq!(validate_tree_shape(1), Ok(1));
+        assert_eq!(validate_tree_shape(9), Ok(5));
+        assert_eq!(validate_tree_shape(15), Ok(8));
+        assert_eq!(validate_tree_shape(0), Err(TreeMathError::ZeroLeaves));
+        assert_eq!(validate_tree_shape(8), Err(TreeMathError::EvenNumNodes));
+        assert_eq!(
+            validate_tree_shape(core::usize::MAX - 1),
+            Err(TreeMathError::EvenNumNodes)
+        );
+
+        for num_leaves in 1..200 {
+            let num_nodes = num_nodes_in_tree(num_leaves);
+            assert_eq!(validate_tree_shape(num_nodes), Ok(num_leaves));
+        }
+    }
+
+    // Every pair is a node and its sibling, the nodes are the start followed by the direct path,
+    // and the siblings are the copath
+    #[quickcheck]
+    fn path_with_copath_correctness(coord: TreeCoord) {
+        let TreeCoord { num_leaves, idx } = coord;
+        let pairs: Vec<(usize, usize)> = path_with_copath(idx, num_leaves).collect();
+
+        let copath = node_copath(idx, num_leaves);
+        assert_eq!(pairs.len(), copath.len());
+        assert_eq!(pairs.iter().map(|&(_, c)| c).collect::<Vec<_>>(), copath);
+
+        let mut path = vec![idx];
+        path.extend(node_direct_path(idx, num_leaves));
+        if idx == root_idx(num_leaves) {
+            assert!(pairs.is_empty());
+        } else {
+            assert_eq!(pairs.iter().map(|&(p, _)| p).collect::<Vec<_>>(), path);
+        }
+
+        for &(p, c) in pairs.iter() {
+            assert_eq!(c, node_sibling(p, num_leaves));
+            assert_eq!(node_parent(p, num_leaves), node_parent(c, num_leaves));
+        }
+    }
+
+    // Make sure the public wrappers reject exactly the inputs that the internal functions panic on
+    #[test]
+    fn try_fns_input_validation() {
+        assert_eq!(try_root_idx(0), Err(TreeMathError::ZeroLeaves));
+        assert_eq!(
+            try_num_nodes_in_tree(MAX_LEAVES + 1),
+            Err(TreeMathError::TooManyLeaves)
+        );
+        assert_eq!(try_tree_frontier(0), Err(TreeMathError::ZeroLeaves));
+        assert_eq!(try_node_parent(0, 0), Err(TreeMathError::ZeroLeaves));
+        assert_eq!(
+            try_node_sibling(0, MAX_LEAVES + 1),
+            Err(TreeMathError::TooManyLeaves)
+        );
+
+        // A tree of 5 leaves has 9 nodes, so 9 is the first bad index
+        assert_eq!(
+            try_node_left_child(9, 5),
+            Err(TreeMathError::IndexOutOfRange)
+        );
+        assert_eq!(
+            try_node_right_child(9, 5),
+            Err(TreeMathError::IndexOutOfRange)
+        );
+        assert_eq!(try_node_parent(9, 5), Err(TreeMathError::IndexOutOfRange));
+        assert_eq!(try_node_sibling(9, 5), Err(TreeMathError::IndexOutOfRange));
+        assert_eq!(
+            try_node_direct_path(9, 5),
+            Err(TreeMathError::IndexOutOfRange)
+        );
+        assert_eq!(
+            try_node_copath(core::usize::MAX, 5),
+            Err(TreeMathError::IndexOutOfRange)
+        );
+
+        // See the tree diagram above
+        assert_eq!(try_num_nodes_in_tree(5), Ok(9));
+        assert_eq!(try_root_idx(5), Ok(7));
+        assert_eq!(try_node_left_child(7, 5), Ok(3));
+        assert_eq!(try_node_right_child(7, 5), Ok(8));
+        assert_eq!(try_node_parent(8, 5), Ok(7));
+        assert_eq!(try_node_sibling(8, 5), Ok(3));
+        assert_eq!(try_node_direct_path(0, 5), Ok(vec![1, 3]));
+        assert_eq!(try_node_copath(0, 5), Ok(vec![2, 5, 8]));
+        assert_eq!(try_tree_frontier(5), Ok(vec![3, 8]));
+    }
+
+    // See above tree for a diagram
+    #[test]
+    fn node_common_ancestor_kat() {
+        let num_leaves = 5;
+
+        assert_eq!(node_common_ancestor(0, 2, num_leaves), 1);
+        assert_eq!(node_common_ancestor(0, 6, num_leaves), 3);
+        assert_eq!(node_common_ancestor(4, 6, num_leaves), 5);
+        assert_eq!(node_common_ancestor(0, 8, num_leaves), 7);
+        assert_eq!(node_common_ancestor(6, 8, num_leaves), 7);
+        assert_eq!(node_common_ancestor(3, 8, num_leaves), 7);
+        assert_eq!(node_common_ancestor(1, 5, num_leaves), 3);
+
+        // Ancestors of a node are common ancestors with it
+        assert_eq!(node_common_ancestor(2, 3, num_leaves), 3);
+        assert_eq!(node_common_ancestor(7, 4, num_leaves), 7);
+        assert_eq!(node_common_ancestor(6, 6, num_leaves), 6);
+        assert_eq!(node_common_ancestor(7, 7, num_leaves), 7);
+    }
+
+    // See above tree for a diagram
+    #[test]
+    fn merged_direct_paths_kat() {
+        let num_leaves = 5;
+
+        // Leaves 0 and 1 (nodes 0 and 2) meet at node 1, which should only show up once
+        let merged = merged_direct_paths(&[0, 1], num_leaves);
+        assert_eq!(merged, vec![1, 3]);
+        assert_eq!(merged.iter().filter(|&&i| i == 1).count(), 1);
+
+        // Level 1 nodes come before level 2 nodes, regardless of index
+        assert_eq!(merged_direct_paths(&[3, 0], num_leaves), vec![1, 5, 3]);
+
+        // The rightmost leaf's direct path is empty, since its parent is the root
+        assert_eq!(merged_direct_paths(&[4], num_leaves), Vec::<usize>::new());
+        assert_eq!(merged_direct_paths(&[], num_leaves), Vec::<usize>::new());
+    }
+
+    // See above tree for a diagram
+    #[test]
+    fn nodes_to_blank_on_remove_kat() {
+        let num_leaves = 5;
+
+        // Leaf 2 is node 4, whose direct path is [5, 3]
+        assert_eq!(nodes_to_blank_on_remove(2, num_leaves), vec![4, 5, 3, 7]);
+        // Leaf 4 is node 8, whose parent is the root
+        assert_eq!(nodes_to_blank_on_remove(4, num_leaves), vec![8, 7]);
+        // In a one-leaf tree, the leaf is the root
+        assert_eq!(nodes_to_blank_on_remove(0, 1), vec![0]);
+    }
+
+    // The nodes to blank are the removed leaf, everything on its direct path, and the root, with
+    // nothing else
+    #[quickcheck]
+    fn nodes_to_blank_on_remove_correctness(coord: TreeCoord) -> TestResult {
+        let TreeCoord { num_leaves, idx } = coord;
+        let leaf = match node_to_leaf(idx) {
+            Some(leaf) => leaf,
+            None => return TestResult::discard(),
+        };
+
+        let blanked = nodes_to_blank_on_remove(leaf, num_leaves);
+        let root = try_root_idx(num_leaves).unwrap();
+        assert_eq!(blanked[0], idx);
+        assert!(blanked.contains(&root));
+        for i in DirectPathIter::new(idx, num_leaves) {
+            assert!(blanked.contains(&i));
+        }
+        for &i in blanked.iter() {
+            assert!(i == idx || i == root || is_ancestor(i, idx, num_leaves));
+        }
+
+        TestResult::passed()
+    }
+
+    // See above tree for a diagram
+    #[test]
+    fn subtree_nodes_kat() {
+        let num_leaves = 5;
+
+        assert_eq!(subtree_nodes(3, num_leaves), vec![0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(subtree_nodes(5, num_leaves), vec![4, 5, 6]);
+        // Leaves are their own subtrees
+        assert_eq!(subtree_nodes(6, num_leaves), vec![6]);
+        assert_eq!(subtree_nodes(8, num_leaves), vec![8]);
+        // The root's right child is a leaf, since the tree is truncated on the right
+        assert_eq!(subtree_nodes(7, num_leaves), (0..9).collect::<Vec<usize>>());
+    }
+
+    // A single leaf's merged path is just its direct path, and the merged path of several leaves is
+    // the sorted, deduplicated union of their direct paths
+    #[quickcheck]
+    fn merged_direct_paths_correctness(num_leaves: usize, leaves: Vec<usize>) -> TestResult {
+        if num_leaves == 0 || num_leaves > MAX_LEAVES {
+            return TestResult::discard();
+        }
+        let leaves: Vec<usize> = leaves.into_iter().map(|l| l % num_leaves).collect();
+
+        for &leaf in leaves.iter() {
+            let node = leaf_to_node(leaf);
+            assert_eq!(
+                merged_direct_paths(&[leaf], num_leaves),
+                node_direct_path(node, num_leaves)
+            );
+        }
+
+        let merged = merged_direct_paths(&leaves, num_leaves);
+        for (i, &idx) in merged.iter().enumerate() {
+            // Every node is on the direct path of at least one of the leaves
+            assert!(leaves
+                .iter()
+                .any(|&leaf| node_direct_path(leaf_to_node(leaf), num_leaves).contains(&idx)));
+            // Nodes are strictly increasing in (level, index), so there are no duplicates
+            if let Some(&next) = merged.get(i + 1) {
+                assert!((node_level(idx), idx) < (node_level(next), next));
+            }
+        }
+        // Every node on every direct path made it in
+        for &leaf in leaves.iter() {
+            for idx in DirectPathIter::new(leaf_to_node(leaf), num_leaves) {
+                assert!(merged.contains(&idx));
+            }
+        }
+
+        TestResult::passed()
+    }
+
+    // The common ancestor should be the first node that appears in both of the chains going from
+    // a and b up to the root
+    #[quickcheck]
+    fn node_common_ancestor_correctness(num_leaves: usize, a: usize, b: usize) -> TestResult {
+        if num_leaves == 0 || num_leaves > MAX_LEAVES {
+            return TestResult::discard();
+        }
+        let num_nodes = num_nodes_in_tree(num_leaves);
+        let (a, b) = (a % num_nodes, b % num_nodes);
+
+        // Make the chains [x, parent(x), ..., root]
+        let chain = |x: usize| {
+            let mut c = vec![x];
+            c.extend(DirectPathIter::new(x, num_leaves));
+            if x != root_idx(num_leaves) {
+                c.push(root_idx(num_leaves));
+            }
+            c
+        };
+        let chain_a = chain(a);
+        let chain_b = chain(b);
+        let expected = *chain_a.iter().find(|i| chain_b.contains(i)).unwrap();
+
+        assert_eq!(node_common_ancestor(a, b, num_leaves), expected);
+        assert_eq!(node_common_ancestor(b, a, num_leaves), expected);
+        TestResult::passed()
+    }
+
+    // See above tree for a diagram
+    #[test]
+    fn leaf_node_conversion_kat() {
+        assert_eq!(leaf_to_node(0), 0);
+        assert_eq!(leaf_to_node(1), 2);
+        assert_eq!(leaf_to_node(2), 4);
+        assert_eq!(leaf_to_node(4), 8);
+
+        assert_eq!(node_to_leaf(0), Some(0));
+        assert_eq!(node_to_leaf(2), Some(1));
+        assert_eq!(node_to_leaf(8), Some(4));
+        assert_eq!(node_to_leaf(1), None);
+        assert_eq!(node_to_leaf(7), None);
+        assert_eq!(node_to_leaf(core::usize::MAX), None);
+    }
+
+    // leaf_to_node and node_to_leaf are inverses of each other
+    #[quickcheck]
+    fn leaf_node_conversion_correctness(leaf_idx: usize) {
+        let leaf_idx = leaf_idx % MAX_LEAVES;
+        let node_idx = leaf_to_node(leaf_idx);
+
+        assert_eq!(node_level(node_idx), 0);
+        assert_eq!(node_to_leaf(node_idx), Some(leaf_idx));
+    }
+
+    // See above tree for a diagram
+    #[test]
+    fn node_resolution_kat() {
+        let num_leaves = 5;
+
+        // With no blanks, everything resolves to itself
+        let no_blanks = [false; 9];
+        for i in 0..9 {
+            assert_eq!(node_resolution(i, num_leaves, &no_blanks, None), vec![i]);
+        }
+
+        // With everything blank, everything resolves to nothing
+        let all_blanks = [true; 9];
+        for i in 0..9 {
+            assert_eq!(
+                node_resolution(i, num_leaves, &all_blanks, None),
+                Vec::<usize>::new()
+            );
+        }
+
+        // Blank out the root, node 3, node 1, leaf 4, and leaf 8
+        //               _
+        //         _____/ \
+        //        /        |
+        //       _         |
+        //     /   \       |
+        //    /     \      |
+        //   _       5     |
+        //  / \     / \    |
+        // 0   2   _   6   _
+        let blanks = [false, true, false, true, true, false, false, true, true];
+        assert_eq!(node_resolution(7, num_leaves, &blanks, None), vec![0, 2, 5]);
+        assert_eq!(node_resolution(3, num_leaves, &blanks, None), vec![0, 2, 5]);
+        assert_eq!(node_resolution(1, num_leaves, &blanks, None), vec![0, 2]);
+        assert_eq!(node_resolution(5, num_leaves, &blanks, None), vec![5]);
+        assert_eq!(
+            node_resolution(4, num_leaves, &blanks, None),
+            Vec::<usize>::new()
+        );
+        assert_eq!(
+            node_resolution(8, num_leaves, &blanks, None),
+            Vec::<usize>::new()
+        );
+    }
+
+    // This is the example from the spec where a leaf was just added under non-blank ancestors
+    //
+    //        3
+    //      /   \
+    //     1     5
+    //    / \   / \
+    //   0   2 4   6
+    //
+    // Leaf 3 (node 6) is new, so it's an unmerged leaf of nodes 5 and 3. The resolutions of its
+    // ancestors include it, even though the ancestors aren't blank.
+    #[test]
+    fn node_resolution_unmerged_leaves_kat() {
+        let num_leaves = 4;
+        let no_blanks = [false; 7];
+        let mut unmerged_leaves = vec![Vec::new(); 7];
+        unmerged_leaves[5] = vec![3];
+        unmerged_leaves[3] = vec![3];
+        let unmerged_leaves = Some(unmerged_leaves.as_slice());
+
+        assert_eq!(
+            node_resolution(3, num_leaves, &no_blanks, unmerged_leaves),
+            vec![3, 6]
+        );
+        assert_eq!(
+            node_resolution(5, num_leaves, &no_blanks, unmerged_leaves),
+            vec![5, 6]
+        );
+        assert_eq!(
+            node_resolution(1, num_leaves, &no_blanks, unmerged_leaves),
+            vec![1]
+        );
+        assert_eq!(
+            node_resolution(6, num_leaves, &no_blanks, unmerged_leaves),
+            vec![6]
+        );
+
+        // Unmerged leaves of a blank node don't matter, since its children get resolved instead
+        let mut blanks = no_blanks;
+        blanks[5] = true;
+        assert_eq!(
+            node_resolution(5, num_leaves, &blanks, unmerged_leaves),
+            vec![4, 6]
+        );
+        assert_eq!(
+            node_resolution(3, num_leaves, &blanks, unmerged_leaves),
+            vec![3, 6]
+        );
+
+        // Without unmerged leaves, a non-blank node resolves to itself
+        assert_eq!(node_resolution(3, num_leaves, &no_blanks, None), vec![3]);
+    }
+
+    #[test]
+    fn copath_resolutions_kat() {
+        //        3
+        //      /   \
+        //     1     5
+        //    / \   / \
+        //   0   2 4   6
+        let num_leaves = 4;
+
+        // With no blanks, every copath node is its own resolution
+        let no_blanks = [false; 7];
+        assert_eq!(
+            copath_resolutions(0, num_leaves, &no_blanks),
+            vec![vec![], vec![2], vec![5]]
+        );
+        assert_eq!(
+            copath_resolutions(3, num_leaves, &no_blanks),
+            vec![vec![], vec![4], vec![1]]
+        );
+
+        // With the intermediate nodes blank, the copath nodes resolve to the leaves under them
+        let blanks = [false, true, false, true, false, true, false];
+        assert_eq!(
+            copath_resolutions(0, num_leaves, &blanks),
+            vec![vec![], vec![2], vec![4, 6]]
+        );
+
+        // A blank leaf on the copath has nobody to encrypt to
+        let blanks = [false, true, true, true, false, true, false];
+        assert_eq!(
+            copath_resolutions(0, num_leaves, &blanks),
+            vec![vec![], vec![], vec![4, 6]]
+        );
+
+        // A lone leaf has an empty path
+        assert_eq!(
+            copath_resolutions(0, 1, &[false]),
+            vec![Vec::<usize>::new()]
+        );
+    }
+
+    #[test]
+    fn truncated_num_leaves_kat() {
+        let num_leaves = 5;
+
+        // No trailing blanks means no truncation, even if there are blanks elsewhere
+        let blanks = [true, true, true, true, false, true, true, true, false];
+        assert_eq!(truncated_num_leaves(num_leaves, &blanks), 5);
+
+        // Blank out leaves 6 and 8. Node 5 being non-blank doesn't matter; only leaves count.
+        let blanks = [false, false, false, false, false, false, true, false, true];
+        assert_eq!(truncated_num_leaves(num_leaves, &blanks), 3);
+
+        // Only the leftmost leaf is left
+        let blanks = [false, true, true, true, true, true, true, true, true];
+        assert_eq!(truncated_num_leaves(num_leaves, &blanks), 1);
+
+        // A fully blank tree collapses to a single leaf
+        assert_eq!(truncated_num_leaves(num_leaves, &[true; 9]), 1);
+        assert_eq!(truncated_num_leaves(1, &[true]), 1);
+    }
+
+    // See above tree for a diagram
+    #[test]
+    fn leftmost_blank_leaf_kat() {
+        // Every leaf is occupied. Blank parents don't matter.
+        let blanks = [false, true, false, true, false, true, false, true, false];
+        assert_eq!(leftmost_blank_leaf(&blanks), None);
+
+        // Leaves 2 and 4 (nodes 4 and 8) are blank, so the gap at leaf 2 gets filled first
+        let blanks = [false, false, false, false, true, true, false, true, true];
+        assert_eq!(leftmost_blank_leaf(&blanks), Some(2));
+
+        // Everything is blank
+        assert_eq!(leftmost_blank_leaf(&[true; 9]), Some(0));
+        assert_eq!(leftmost_blank_leaf(&[true]), Some(0));
+        assert_eq!(leftmost_blank_leaf(&[false]), None);
+    }
+
+    // See above tree for a diagram
+    #[test]
+    fn active_leaves_kat() {
+        // Leaves 1 and 3 (nodes 2 and 6) were removed, along with their direct paths. Leaf 4 is on
+        // the right edge and hasn't been truncated, so it still counts.
+        let blanks = [false, true, true, true, false, true, true, true, false];
+        assert_eq!(active_leaves(&blanks), vec![0, 4, 8]);
+        assert_eq!(active_leaf_count(&blanks), 3);
+
+        // Blank parents don't matter, only leaves do
+        let blanks = [false, true, false, true, false, true, false, true, false];
+        assert_eq!(active_leaves(&blanks), vec![0, 2, 4, 6, 8]);
+        assert_eq!(active_leaf_count(&blanks), 5);
+
+        // Interior nodes that aren't blank don't make their blank leaves active
+        let blanks = [true, false, true, false, true, false, true, false, false];
+        assert_eq!(active_leaves(&blanks), vec![8]);
+        assert_eq!(active_leaf_count(&blanks), 1);
+
+        assert_eq!(active_leaves(&[true; 9]), Vec::<usize>::new());
+        assert_eq!(active_leaf_count(&[true]), 0);
+        assert_eq!(active_leaves(&[false]), vec![0]);
+    }
+
+    // The leaves under the frontier subtrees, taken from left to right, should be exactly the
+    // leaves of the tree
+    #[test]
+    fn frontier_covers_leaves() {
+        for num_leaves in 1..=1000 {
+            let covered_leaves = tree_frontier(num_leaves)
+                .into_iter()
+                .flat_map(full_subtree_leaves)
+                .collect::<Vec<usize>>();
+            assert_eq!(covered_leaves, tree_leaves(num_leaves));
+            assert!(frontier_covers(num_leaves));
+        }
+
+        // Spot-check a tree with 5 leaves. The frontier is [3, 8]
+        assert_eq!(
+            full_subtree_leaves(3).collect::<Vec<usize>>(),
+            vec![0, 2, 4, 6]
+        );
+        assert_eq!(full_subtree_leaves(8).collect::<Vec<usize>>(), vec![8]);
+    }
+
+    #[test]
+    fn tree_leaves_iter_kat() {
+        for &num_leaves in &[1, 2, 3, 5, 8, 13, 100] {
+            let iter = tree_leaves_iter(num_leaves);
+            assert_eq!(iter.len(), num_leaves);
+            assert_eq!(iter.collect::<Vec<usize>>(), tree_leaves(num_leaves));
+        }
+
+        assert_eq!(
+            tree_leaves_iter(5).collect::<Vec<usize>>(),
+            vec![0, 2, 4, 6, 8]
+        );
+    }
+
+    // See above tree for a diagram
+    #[test]
+    fn nodes_at_level_kat() {
+        let num_leaves = 5;
+        let level = |l| nodes_at_level(l, num_leaves).collect::<Vec<usize>>();
+
+        assert_eq!(level(0), vec![0, 2, 4, 6, 8]);
+        assert_eq!(level(0), tree_leaves(num_leaves));
+        assert_eq!(level(1), vec![1, 5]);
+        assert_eq!(level(2), vec![3]);
+        assert_eq!(level(3), vec![7]);
+        assert_eq!(level(4), Vec::<usize>::new());
+        assert_eq!(level(1000), Vec::<usize>::new());
+        // Levels too big for a u32 are higher than any root, so they must not wrap around to lower
+        // levels
+        assert_eq!(level((1 << 32) + 1), Vec::<usize>::new());
+        assert_eq!(level(core::usize::MAX), Vec::<usize>::new());
+    }
+
+    // Every node in the tree should be yielded exactly once by nodes_at_level(node_level(i))
+    #[test]
+    fn nodes_at_level_correctness() {
+        for num_leaves in 1..=64 {
+            let num_nodes = num_nodes_in_tree(num_leaves);
+            let max_level = node_level(root_idx(num_leaves));
+
+            let mut seen = Vec::new();
+            for level in 0..=max_level + 1 {
+                for i in nodes_at_level(level, num_leaves) {
+                    assert!(i < num_nodes);
+                    assert_eq!(node_level(i), level);
+                    seen.push(i);
+                }
+            }
+
+            seen.sort();
+            assert_eq!(seen, (0..num_nodes).collect::<Vec<usize>>());
+        }
+    }
+
+    #[test]
+    fn u32_fns_input_validation() {
+        // The biggest tree whose indices all fit in a u32
+        let num_leaves = MAX_LEAVES_U32;
+        let last_idx = core::u32::MAX - 1;
+        assert_eq!(root_idx_u32(num_leaves), Ok(core::u32::MAX >> 1));
+        assert_eq!(node_left_child_u32(last_idx, num_leaves), Ok(last_idx));
+        assert_eq!(node_right_child_u32(last_idx, num_leaves), Ok(last_idx));
+        assert!(node_parent_u32(last_idx, num_leaves).is_ok());
+        assert!(node_sibling_u32(last_idx, num_leaves).is_ok());
+
+        // One node too far
+        assert_eq!(
+            node_parent_u32(core::u32::MAX, num_leaves),
+            Err(TreeMathError::IndexOutOfRange)
+        );
+        // One leaf too many
+        assert_eq!(
+            root_idx_u32(num_leaves + 1),
+            Err(TreeMathError::TooManyLeaves)
+        );
+        assert_eq!(
+            node_sibling_u32(0, num_leaves + 1),
+            Err(TreeMathError::TooManyLeaves)
+        );
+        // No leaves at all
+        assert_eq!(root_idx_u32(0), Err(TreeMathError::ZeroLeaves));
+        assert_eq!(node_left_child_u32(0, 0), Err(TreeMathError::ZeroLeaves));
+    }
+
+    // See above tree for a diagram
+    #[test]
+    fn predicates_kat() {
+        let num_leaves = 5;
+
+        for &i in &[0, 2, 4, 6, 8] {
+            assert!(is_leaf(i));
+            assert!(!is_root(i, num_leaves));
+        }
+        for &i in &[1, 3, 5] {
+            assert!(!is_leaf(i));
+            assert!(!is_root(i, num_leaves));
+        }
+        assert!(!is_leaf(7));
+        assert!(is_root(7, num_leaves));
+
+        assert!(is_ancestor(1, 0, num_leaves));
+        assert!(is_ancestor(3, 0, num_leaves));
+        assert!(is_ancestor(7, 0, num_leaves));
+        assert!(is_ancestor(3, 6, num_leaves));
+        assert!(is_ancestor(7, 8, num_leaves));
+        assert!(is_ancestor(7, 5, num_leaves));
+
+        assert!(!is_ancestor(5, 0, num_leaves));
+        assert!(!is_ancestor(3, 8, num_leaves));
+        assert!(!is_ancestor(0, 1, num_leaves));
+        assert!(!is_ancestor(1, 1, num_leaves));
+        assert!(!is_ancestor(7, 7, num_leaves));
+    }
+
+    // Everything on a node's direct path, as well as the root, is an ancestor of that node.
+    // Nothing else is.
+    #[quickcheck]
+    fn is_ancestor_correctness(num_leaves: usize, descendant: usize) -> TestResult {
+        // Keep the trees small enough that we can check every node
+        let num_leaves = num_leaves % 256;
+        if num_leaves == 0 {
+            return TestResult::discard();
+        }
+        let num_nodes = num_nodes_in_tree(num_leaves);
+        let descendant = descendant % num_nodes;
+
+        let mut ancestors: Vec<usize> = DirectPathIter::new(descendant, num_leaves).collect();
+        if !is_root(descendant, num_leaves) {
+            ancestors.push(root_idx(num_leaves));
+        }
+
+        for i in 0..num_nodes {
+            assert_eq!(
+                is_ancestor(i, descendant, num_leaves),
+                ancestors.contains(&i)
+            );
+        }
+        TestResult::passed()
+    }
+
+    // See above tree for a diagram
+    #[test]
+    fn node_distance_kat() {
+        let num_leaves = 5;
+
+        assert_eq!(node_distance(0, 8, num_leaves), 4);
+        assert_eq!(node_distance(0, 2, num_leaves), 2);
+        assert_eq!(node_distance(0, 6, num_leaves), 4);
+        assert_eq!(node_distance(2, 5, num_leaves), 3);
+        assert_eq!(node_distance(7, 8, num_leaves), 1);
+        assert_eq!(node_distance(7, 0, num_leaves), 3);
+        assert_eq!(node_distance(4, 4, num_leaves), 0);
+    }
+
+    // Distance is symmetric, and is zero iff the nodes are the same
+    #[quickcheck]
+    fn node_distance_correctness(num_leaves: usize, a: usize, b: usize) -> TestResult {
+        if num_leaves == 0 || num_leaves > MAX_LEAVES {
+            return TestResult::discard();
+        }
+        let num_nodes = num_nodes_in_tree(num_leaves);
+        let (a, b) = (a % num_nodes, b % num_nodes);
+
+        let d = node_distance(a, b, num_leaves);
+        assert_eq!(d, node_distance(b, a, num_leaves));
+        assert_eq!(d == 0, a == b);
+        assert_eq!(node_distance(a, a, num_leaves), 0);
+        TestResult::passed()
+    }
+
+    // See above tree for a diagram
+    #[test]
+    fn node_full_path_kat() {
+        let num_leaves = 5;
+
+        assert_eq!(node_full_path(0, num_leaves), vec![0, 1, 3, 7]);
+        assert_eq!(node_full_path(6, num_leaves), vec![6, 5, 3, 7]);
+        assert_eq!(node_full_path(8, num_leaves), vec![8, 7]);
+        assert_eq!(node_full_path(3, num_leaves), vec![3, 7]);
+        assert_eq!(node_full_path(7, num_leaves), vec![7]);
+    }
+
+    // The full path of x is [x] ++ direct_path(x) ++ [root] for non-root x
+    #[quickcheck]
+    fn node_full_path_correctness(num_leaves: usize, start_idx: usize) -> TestResult {
+        if num_leaves == 0 || num_leaves > MAX_LEAVES {
+            return TestResult::discard();
+        }
+        let start_idx = start_idx % num_nodes_in_tree(num_leaves);
+        let root = root_idx(num_leaves);
+
+        let expected = if start_idx == root {
+            vec![root]
+        } else {
+            [
+                vec![start_idx],
+                node_direct_path(start_idx, num_leaves),
+                vec![root],
+            ]
+            .concat()
+        };
+
+        assert_eq!(node_full_path(start_idx, num_leaves), expected);
+        TestResult::passed()
+    }
+
+    //
+    // Panic tests. Everything in this crate relies on these bounds checks, so make sure they stay.
+    //
+
+    #[test]
+    #[should_panic]
+    fn num_nodes_in_tree_zero_leaves() {
+        num_nodes_in_tree(0);
+    }
+
+    // MAX_LEAVES + 1 leaves would need 2^(64)+1 nodes (see definition of MAX_LEAVES), which
+    // overflows
+    #[test]
+    #[should_panic]
+    fn num_nodes_in_tree_too_many_leaves() {
+        num_nodes_in_tree(MAX_LEAVES + 1);
+    }
+
+    // The checked node count gives up just past MAX_LEAVES, whatever the width of usize
+    #[test]
+    fn checked_num_nodes_in_tree_kat() {
+        assert_eq!(checked_num_nodes_in_tree(0), None);
+        assert_eq!(checked_num_nodes_in_tree(1), Some(1));
+        assert_eq!(checked_num_nodes_in_tree(5), Some(9));
+        assert_eq!(
+            checked_num_nodes_in_tree(MAX_LEAVES),
+            Some(core::usize::MAX)
+        );
+        assert_eq!(checked_num_nodes_in_tree(MAX_LEAVES + 1), None);
+        assert_eq!(checked_num_nodes_in_tree(core::usize::MAX), None);
+    }
+
+    // On a 32-bit platform, MAX_LEAVES is 2^31, and a tree of that many leaves has 2^32 - 1 nodes
+    #[cfg(target_pointer_width = "32")]
+    #[test]
+    fn checked_num_nodes_in_tree_32_bit() {
+        assert_eq!(MAX_LEAVES, 1 << 31);
+        assert_eq!(
+            checked_num_nodes_in_tree(1 << 31),
+            Some(core::u32::MAX as usize)
+        );
+        assert_eq!(checked_num_nodes_in_tree((1 << 31) + 1), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn num_leaves_in_tree_even_nodes() {
+        num_leaves_in_tree(8);
+    }
+
+    #[test]
+    #[should_panic]
+    fn root_idx_zero_leaves() {
+        root_idx(0);
+    }
+
+    // A tree of 5 leaves has 9 nodes, so 9 is the first bad index
+    #[test]
+    #[should_panic]
+    fn node_right_child_out_of_range() {
+        node_right_child(9, 5);
+    }
+
+    #[test]
+    #[should_panic]
+    fn node_parent_out_of_range() {
+        node_parent(9, 5);
+    }
+
+    #[test]
+    #[should_panic]
+    fn node_sibling_out_of_range() {
+        node_sibling(9, 5);
+    }
+
+    #[test]
+    #[should_panic]
+    fn node_direct_path_out_of_range() {
+        node_direct_path(9, 5);
+    }
+
+    #[test]
+    #[should_panic]
+    fn node_copath_out_of_range() {
+        node_copath(9, 5);
+    }
+
+    #[test]
+    #[should_panic]
+    fn node_parent_zero_leaves() {
+        node_parent(0, 0);
+    }
 
     // The following test vector is from
     // https://github.com/mlswg/mls-implementations/tree/master/test_vectors
@@ -477,6 +2280,8 @@ mod test {
     //   * parent[i] is the index of the parent of node i
     //   * sibling[i] is the index of the sibling of node i
 
+    // Reading the vectors needs the TLS deserializer, which needs std
+    #[cfg(feature = "std")]
     #[derive(Deserialize)]
     struct TreeMathTestVectors {
         #[serde(rename = "root__bound_u32")]
@@ -491,6 +2296,7 @@ mod test {
         sibling: Vec<u32>,
     }
 
+    #[cfg(feature = "std")]
     #[test]
     fn deserialize_test_vec() {
         let mut f = std::fs::File::open("test_vectors/tree_math.bin").unwrap();
@@ -504,18 +2310,20 @@ mod test {
         let num_parent_ops = test_vec.parent.len();
         let num_sibling_ops = test_vec.sibling.len();
 
-        let mut root: Vec<u32> = (1..=num_root_ops).map(|i| root_idx(i) as u32).collect();
-        let mut left: Vec<u32> = (0..num_left_ops)
-            .map(|i| node_left_child(i) as u32)
+        let root: Vec<u32> = (1..=num_root_ops as u32)
+            .map(|i| root_idx_u32(i).unwrap())
+            .collect();
+        let left: Vec<u32> = (0..num_left_ops as u32)
+            .map(|i| node_left_child_u32(i, size).unwrap())
             .collect();
-        let mut right: Vec<u32> = (0..num_right_ops)
-            .map(|i| node_right_child(i, size) as u32)
+        let right: Vec<u32> = (0..num_right_ops as u32)
+            .map(|i| node_right_child_u32(i, size).unwrap())
             .collect();
-        let mut parent: Vec<u32> = (0..num_parent_ops)
-            .map(|i| node_parent(i, size) as u32)
+        let parent: Vec<u32> = (0..num_parent_ops as u32)
+            .map(|i| node_parent_u32(i, size).unwrap())
             .collect();
-        let mut sibling: Vec<u32> = (0..num_sibling_ops)
-            .map(|i| node_sibling(i, size) as u32)
+        let sibling: Vec<u32> = (0..num_sibling_ops as u32)
+            .map(|i| node_sibling_u32(i, size).unwrap())
             .collect();
 
         assert_eq!(root, test_vec.root);
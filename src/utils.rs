@@ -77,3 +77,13 @@ macro_rules! make_enum_u8_discriminant {
         }
     }
 }
+
+/// Returns a number that this function has never returned before. A `RatchetTree` or `Roster`
+/// takes a new one of these as its version every time it's modified, so two of them with the same
+/// version are copies of each other, and anything computed from one is still good for the other.
+pub(crate) fn next_version() -> u64 {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    static NEXT_VERSION: AtomicU64 = AtomicU64::new(0);
+    NEXT_VERSION.fetch_add(1, Ordering::Relaxed)
+}